@@ -0,0 +1,2 @@
+-- arbitrary per-bookmark metadata as JSON object
+ALTER TABLE bookmarks ADD COLUMN extra VARCHAR not null default '{}';
@@ -0,0 +1,11 @@
+-- files (HTML snapshots, PDFs, screenshots, ...) attached to a bookmark
+create table attachments
+(
+    id          INTEGER not null primary key,
+    bookmark_id INTEGER not null,
+    name        VARCHAR not null,
+    content     BLOB not null,
+    created_ts  DATETIME not null default CURRENT_TIMESTAMP
+);
+
+CREATE INDEX attachments_bookmark_id ON attachments (bookmark_id);
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER [UpdateLastTime];
+CREATE TRIGGER [UpdateLastTime]
+    AFTER UPDATE
+    ON bookmarks
+    FOR EACH ROW
+    WHEN NEW.last_update_ts <= OLD.last_update_ts
+BEGIN
+    update bookmarks set last_update_ts=CURRENT_TIMESTAMP where id = OLD.id;
+END;
+
+ALTER TABLE bookmarks DROP COLUMN last_visited;
+ALTER TABLE bookmarks DROP COLUMN visits;
@@ -0,0 +1,14 @@
+-- usage of a bookmark: number of opens and last open
+ALTER TABLE bookmarks ADD COLUMN visits INTEGER not null default 0;
+ALTER TABLE bookmarks ADD COLUMN last_visited DATETIME;
+
+-- recording a visit is not an update of the bookmark
+DROP TRIGGER [UpdateLastTime];
+CREATE TRIGGER [UpdateLastTime]
+    AFTER UPDATE
+    ON bookmarks
+    FOR EACH ROW
+    WHEN NEW.last_update_ts <= OLD.last_update_ts AND NEW.visits = OLD.visits
+BEGIN
+    update bookmarks set last_update_ts=CURRENT_TIMESTAMP where id = OLD.id;
+END;
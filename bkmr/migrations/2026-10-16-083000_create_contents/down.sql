@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER contents_au;
+DROP TRIGGER contents_ai;
+DROP TRIGGER contents_ad;
+DROP TABLE contents_fts;
+DROP TABLE contents;
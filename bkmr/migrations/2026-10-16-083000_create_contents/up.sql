@@ -0,0 +1,40 @@
+-- readable text of bookmarked web pages, searched together with the bookmarks
+create table contents
+(
+    bookmark_id INTEGER not null primary key,
+    content     TEXT not null
+);
+
+create virtual table contents_fts using fts5
+(
+    content,
+    content= 'contents',
+    content_rowid= 'bookmark_id',
+    tokenize= "porter unicode61"
+);
+
+CREATE TRIGGER contents_ad
+    AFTER DELETE
+    ON contents
+BEGIN
+    INSERT INTO contents_fts (contents_fts, rowid, content)
+    VALUES ('delete', old.bookmark_id, old.content);
+END;
+
+CREATE TRIGGER contents_ai
+    AFTER INSERT
+    ON contents
+BEGIN
+    INSERT INTO contents_fts (rowid, content)
+    VALUES (new.bookmark_id, new.content);
+END;
+
+CREATE TRIGGER contents_au
+    AFTER UPDATE
+    ON contents
+BEGIN
+    INSERT INTO contents_fts (contents_fts, rowid, content)
+    VALUES ('delete', old.bookmark_id, old.content);
+    INSERT INTO contents_fts (rowid, content)
+    VALUES (new.bookmark_id, new.content);
+END;
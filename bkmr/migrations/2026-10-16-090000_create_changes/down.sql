@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER changes_au_url;
+DROP TRIGGER changes_au;
+DROP TRIGGER changes_ad;
+DROP TRIGGER changes_ai;
+DROP TABLE changes;
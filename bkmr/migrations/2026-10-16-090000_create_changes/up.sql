@@ -0,0 +1,49 @@
+-- log of the mutations of bookmarks for `bkmr changes`, by URL since ids are compacted on deletion
+create table changes
+(
+    seq INTEGER not null primary key autoincrement,
+    op  TEXT not null,
+    URL TEXT not null,
+    ts  DATETIME not null default CURRENT_TIMESTAMP
+);
+
+-- the existing bookmarks, so that replaying the feed from the start yields all of them
+INSERT INTO changes (op, URL)
+SELECT 'add', URL
+FROM bookmarks
+ORDER BY id;
+
+CREATE TRIGGER changes_ai
+    AFTER INSERT
+    ON bookmarks
+BEGIN
+    INSERT INTO changes (op, URL) VALUES ('add', new.URL);
+END;
+
+CREATE TRIGGER changes_ad
+    AFTER DELETE
+    ON bookmarks
+BEGIN
+    INSERT INTO changes (op, URL) VALUES ('delete', old.URL);
+END;
+
+-- visits, timestamps and the compaction of ids are no changes
+CREATE TRIGGER changes_au
+    AFTER UPDATE OF URL, metadata, tags, desc, flags, extra
+    ON bookmarks
+    FOR EACH ROW
+    WHEN NEW.URL = OLD.URL
+BEGIN
+    INSERT INTO changes (op, URL) VALUES ('update', new.URL);
+END;
+
+-- a new URL is a new bookmark for mirrors keyed by URL
+CREATE TRIGGER changes_au_url
+    AFTER UPDATE OF URL
+    ON bookmarks
+    FOR EACH ROW
+    WHEN NEW.URL != OLD.URL
+BEGIN
+    INSERT INTO changes (op, URL) VALUES ('delete', old.URL);
+    INSERT INTO changes (op, URL) VALUES ('add', new.URL);
+END;
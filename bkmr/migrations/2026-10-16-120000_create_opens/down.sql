@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER opens_ad;
+DROP TRIGGER opens_au_url;
+DROP TRIGGER opens_visit;
+DROP TABLE opens;
@@ -0,0 +1,35 @@
+-- log of the opens of bookmarks for `bkmr stats top`, by URL since ids are compacted on deletion
+create table opens
+(
+    id  INTEGER not null primary key,
+    URL TEXT not null,
+    ts  DATETIME not null default CURRENT_TIMESTAMP
+);
+
+CREATE INDEX opens_url ON opens (URL);
+
+-- opening a bookmark counts a visit
+CREATE TRIGGER opens_visit
+    AFTER UPDATE OF visits
+    ON bookmarks
+    FOR EACH ROW
+    WHEN NEW.visits > OLD.visits
+BEGIN
+    INSERT INTO opens (URL) VALUES (new.URL);
+END;
+
+CREATE TRIGGER opens_au_url
+    AFTER UPDATE OF URL
+    ON bookmarks
+    FOR EACH ROW
+    WHEN NEW.URL != OLD.URL
+BEGIN
+    UPDATE opens SET URL = new.URL WHERE URL = old.URL;
+END;
+
+CREATE TRIGGER opens_ad
+    AFTER DELETE
+    ON bookmarks
+BEGIN
+    DELETE FROM opens WHERE URL = old.URL;
+END;
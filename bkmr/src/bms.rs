@@ -1,10 +1,9 @@
 use std::collections::HashSet;
 
-use log::debug;
-use stdext::function_name;
+use diesel::result::Error as DieselError;
+use tracing::debug;
 
 use crate::dal::Dal;
-use crate::environment::CONFIG;
 use crate::models::Bookmark;
 use crate::tag::Tags;
 
@@ -18,32 +17,28 @@ pub struct Bookmarks {
 
 // #[allow(dead_code)]
 impl Bookmarks {
+    /// panics on invalid queries, use `try_new` for queries given by the user
     pub fn new(fts_query: String) -> Self {
-        let mut dal = Dal::new(CONFIG.db_url.clone());
-        Bookmarks {
+        Self::try_new(fts_query).expect("Error getting bookmarks")
+    }
+    /// fails e.g. if `fts_query` is no valid FTS syntax
+    pub fn try_new(fts_query: String) -> Result<Self, DieselError> {
+        let mut dal = Dal::default();
+        Ok(Bookmarks {
             fts_query: fts_query.clone(),
-            bms: dal
-                .get_bookmarks(&fts_query)
-                .expect("Error getting bookmarks"),
+            bms: dal.get_bookmarks(&fts_query)?,
             dal,
-        }
+        })
     }
     pub fn check_tags(&mut self, tags: Vec<String>) -> Vec<String> {
         let all_tags: HashSet<String> = HashSet::from_iter(self.dal.get_all_tags_as_vec());
         let tags = HashSet::from_iter(tags.into_iter().filter(|s| !s.is_empty()));
-        debug!("({}:{}) {:?}", function_name!(), line!(), all_tags);
+        debug!("{:?}", all_tags);
         tags.difference(&all_tags).cloned().collect()
     }
 
     pub fn match_all(tags: Vec<String>, bms: Vec<Bookmark>, not: bool) -> Vec<Bookmark> {
-        debug!(
-            "({}:{}) {:?} {:?} {:?}",
-            function_name!(),
-            line!(),
-            tags,
-            bms,
-            not
-        );
+        debug!("{:?} {:?} {:?}", tags, bms, not);
         match not {
             false => bms
                 .into_iter()
@@ -57,14 +52,7 @@ impl Bookmarks {
         }
     }
     pub fn match_any(tags: Vec<String>, bms: Vec<Bookmark>, not: bool) -> Vec<Bookmark> {
-        debug!(
-            "({}:{}) {:?} {:?} {:?}",
-            function_name!(),
-            line!(),
-            tags,
-            bms,
-            not
-        );
+        debug!("{:?} {:?} {:?}", tags, bms, not);
         match not {
             false => bms
                 .into_iter()
@@ -77,14 +65,7 @@ impl Bookmarks {
         }
     }
     pub fn match_exact(tags: Vec<String>, bms: Vec<Bookmark>, not: bool) -> Vec<Bookmark> {
-        debug!(
-            "({}:{}) {:?} {:?} {:?}",
-            function_name!(),
-            line!(),
-            tags,
-            bms,
-            not
-        );
+        debug!("{:?} {:?} {:?}", tags, bms, not);
         match not {
             false => bms
                 .into_iter()
@@ -126,7 +107,7 @@ impl Bookmarks {
                 self.bms = Bookmarks::match_all(tags_all_not_, self.bms.clone(), true);
             }
         }
-        debug!("({}:{}) {:?}", function_name!(), line!(), self.bms);
+        debug!("{:?}", self.bms);
     }
 }
 
@@ -0,0 +1,76 @@
+//! Change feed of `bkmr changes` and `GET /changes` of `bkmr serve`: the mutations after a cursor,
+//! so that other systems can mirror the bookmarks incrementally, keyed by URL.
+//!
+//! The log is written by triggers of the database. It starts with an `add` of every bookmark
+//! existing when it was created, so replaying it from the start yields all bookmarks.
+use anyhow::anyhow;
+use diesel::result::Error as DieselError;
+use serde::Serialize;
+
+use crate::dal::Dal;
+use crate::models::{Bookmark, Change};
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Feed {
+    pub changes: Vec<Entry>,
+    /// cursor of the next request, the given one if there are no changes
+    pub next: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Entry {
+    #[serde(flatten)]
+    pub change: Change,
+    /// current state of added and updated bookmarks, None if deleted since
+    pub bookmark: Option<Bookmark>,
+}
+
+/// cursor of a previous feed, empty: from the start
+pub fn parse_token(token: &str) -> anyhow::Result<i32> {
+    match token.trim() {
+        "" => Ok(0),
+        token => token
+            .parse()
+            .ok()
+            .filter(|seq| *seq >= 0)
+            .ok_or_else(|| anyhow!("Invalid change token {:?}", token)),
+    }
+}
+
+/// the changes after the cursor `since`, at most `limit`
+pub fn feed(dal: &mut Dal, since: &str, limit: Option<i64>) -> anyhow::Result<Feed> {
+    let since = parse_token(since)?;
+    let changes = dal.get_changes(since, limit)?;
+    let next = changes
+        .last()
+        .map_or(since, |change| change.seq)
+        .to_string();
+    let changes = changes
+        .into_iter()
+        .map(|change| {
+            let bookmark = match change.op.as_str() {
+                "delete" => None,
+                _ => dal.get_bookmark_by_url(&change.URL)?,
+            };
+            Ok(Entry { change, bookmark })
+        })
+        .collect::<Result<_, DieselError>>()?;
+    Ok(Feed { changes, next })
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("", Some(0))]
+    #[case("42", Some(42))]
+    #[case(" 7\n", Some(7))]
+    #[case("-1", None)]
+    #[case("abc", None)]
+    fn test_parse_token(#[case] token: &str, #[case] expected: Option<i32>) {
+        assert_eq!(parse_token(token).ok(), expected);
+    }
+}
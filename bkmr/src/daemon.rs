@@ -0,0 +1,61 @@
+//! `bkmr daemon`: one long running process instead of one per request. It serves the web UI and
+//! endpoints of `bkmr serve` and, on Linux, the DBus service of `bkmr serve --dbus`, with the
+//! database connections kept open.
+//!
+//! The change log of the database is watched, so changes by other bkmr processes are noticed too:
+//! DBus clients get the signal `Changed`, HTTP clients wait for changes with
+//! `GET /changes?since=<token>&wait=<seconds>`.
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::dal::Dal;
+use crate::serve;
+
+type Notify = Box<dyn Fn(&str) + Send>;
+
+/// serves `listener` until the process ends, `dbus`: also on the session bus.
+/// Changes are checked every `interval`.
+pub fn run(listener: TcpListener, dbus: bool, interval: Duration) -> anyhow::Result<()> {
+    let notify = notifier(dbus)?;
+    let mut dal = Dal::default();
+    let mut last = dal.get_last_change()?;
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match dal.get_last_change() {
+            Ok(seq) if seq != last => {
+                last = seq;
+                info!("Database changed, next: {}", seq);
+                notify(&seq.to_string());
+            }
+            Ok(_) => {}
+            Err(e) => error!("Cannot read the change log: {}", e),
+        }
+    });
+    serve::serve(listener)
+}
+
+#[cfg(target_os = "linux")]
+fn notifier(dbus: bool) -> anyhow::Result<Notify> {
+    if !dbus {
+        return Ok(Box::new(|_| {}));
+    }
+    let signals = crate::dbus::spawn()?;
+    eprintln!(
+        "Serving bookmarks on the session bus as {}",
+        crate::dbus::NAME
+    );
+    Ok(Box::new(move |next| {
+        if let Err(e) = signals.changed(next) {
+            error!("Cannot signal the change: {:#}", e);
+        }
+    }))
+}
+
+/// no DBus
+#[cfg(not(target_os = "linux"))]
+fn notifier(_dbus: bool) -> anyhow::Result<Notify> {
+    Ok(Box::new(|_| {}))
+}
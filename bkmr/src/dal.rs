@@ -1,44 +1,144 @@
+use std::collections::HashMap;
+use std::error::Error;
 use std::fmt;
 use std::fmt::Debug;
+use std::sync::Mutex;
 
-use diesel::connection::SimpleConnection;
+use chrono::NaiveDateTime;
+use diesel::connection::{AnsiTransactionManager, SimpleConnection, TransactionManager};
 use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection};
 use diesel::result::Error as DieselError;
-use diesel::sql_types::{Integer, Text};
-use diesel::{sql_query, Connection, RunQueryDsl, SqliteConnection};
-use log::debug;
-use stdext::function_name;
+use diesel::sql_types::{BigInt, Integer, Text, Timestamp};
+use diesel::{sql_query, RunQueryDsl, SqliteConnection};
+use diesel_migrations::MigrationHarness;
+use lazy_static::lazy_static;
+use tracing::debug;
 
-use crate::models::{Bookmark, NewBookmark, TagsFrequency};
+use crate::environment::CONFIG;
+use crate::helper::MIGRATIONS;
+use crate::models::{
+    Attachment, AttachmentInfo, Bookmark, Change, DayActivity, MonthCount, NewAttachment,
+    NewBookmark, Opened, SchemaObject, TagsFrequency, TextRow,
+};
+use crate::schema::attachments;
 use crate::schema::bookmarks::dsl::bookmarks;
-use crate::schema::bookmarks::{desc, flags, id, metadata, tags, URL};
+use crate::schema::bookmarks::{
+    desc, extra, flags, id, last_update_ts, last_visited, metadata, tags, visits, URL,
+};
+use crate::schema::changes;
+use crate::schema::contents;
+use crate::webhook;
+use crate::webhook::Event;
 
 // use crate::schema::bookmarks;
 
+pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+pub type DbConnection = PooledConnection<ConnectionManager<SqliteConnection>>;
+
+lazy_static! {
+    /// one pool per database url, so all `Dal` instances of a process share their connections
+    static ref POOLS: Mutex<HashMap<String, DbPool>> = Mutex::new(HashMap::new());
+}
+
+/// returns the (lazily created) connection pool for `database_url`
+pub fn get_pool(database_url: &str) -> DbPool {
+    let mut pools = POOLS.lock().expect("Connection pool registry poisoned");
+    pools
+        .entry(database_url.to_string())
+        .or_insert_with(|| {
+            debug!("New pool {:?}", database_url);
+            let pool = Pool::builder()
+                .max_size(8)
+                .min_idle(Some(1))
+                .connection_customizer(Box::new(BusyTimeout))
+                .build(ConnectionManager::<SqliteConnection>::new(database_url))
+                .unwrap_or_else(|e| panic!("Error connecting to {}: {:?}", database_url, e));
+            upgrade_schema(&pool)
+                .unwrap_or_else(|e| panic!("Error upgrading {}: {:?}", database_url, e));
+            pool
+        })
+        .clone()
+}
+
+/// connections wait for the locks of others instead of failing, e.g. of the threads of `bkmr daemon`
+#[derive(Debug)]
+struct BusyTimeout;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for BusyTimeout {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute("PRAGMA busy_timeout = 5000;")
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// applies migrations added since the database was created. Databases not created by
+/// `bkmr create-db` have no migration history and are left alone.
+fn upgrade_schema(pool: &DbPool) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let mut conn = pool.get()?;
+    let history: Vec<TextRow> = sql_query(
+        "SELECT name AS text FROM sqlite_master \
+        WHERE type = 'table' AND name = '__diesel_schema_migrations';",
+    )
+    .load(&mut conn)?;
+    if !history.is_empty() && conn.has_pending_migration(MIGRATIONS)? {
+        debug!("Upgrading schema");
+        conn.run_pending_migrations(MIGRATIONS)?;
+    }
+    Ok(())
+}
+
 // #[derive(Debug)]
 pub struct Dal {
     // #[allow(dead_code)]
     url: String,
-    pub conn: SqliteConnection,
+    pub conn: DbConnection,
+    /// webhook events of the open transactions, sent when the outermost one commits
+    events: Vec<(Event, Bookmark)>,
+    /// number of open transactions
+    depth: usize,
 }
 
 impl Dal {
     pub fn new(url: String) -> Self {
-        debug!("({}:{}) {:?}", function_name!(), line!(), url);
+        debug!("{:?}", url);
         Self {
             conn: Dal::establish_connection(&url),
             url,
+            events: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// queues `event` for the webhooks, sends it right away outside of transactions
+    fn notify(&mut self, event: Event, bm: &Bookmark) {
+        if !webhook::enabled() {
+            return;
+        }
+        self.events.push((event, bm.clone()));
+        if self.depth == 0 {
+            for (event, bm) in self.events.drain(..) {
+                webhook::fire(event, &bm);
+            }
         }
     }
 
-    fn establish_connection(database_url: &str) -> SqliteConnection {
-        SqliteConnection::establish(database_url)
+    /// checks out a connection from the shared pool instead of opening a new one
+    fn establish_connection(database_url: &str) -> DbConnection {
+        get_pool(database_url)
+            .get()
             .unwrap_or_else(|e| panic!("Error connecting to {}: {:?}", database_url, e))
     }
 
     pub fn delete_bookmark(&mut self, id_: i32) -> Result<Vec<Bookmark>, DieselError> {
         // diesel::delete(bookmarks.filter(id.eq(1))).execute(&mut self.conn)
-        diesel::delete(bookmarks.filter(id.eq(id_))).get_results(&mut self.conn)
+        diesel::delete(attachments::table.filter(attachments::bookmark_id.eq(id_)))
+            .execute(&mut self.conn)?;
+        diesel::delete(contents::table.find(id_)).execute(&mut self.conn)?;
+        let deleted: Vec<Bookmark> =
+            diesel::delete(bookmarks.filter(id.eq(id_))).get_results(&mut self.conn)?;
+        deleted.iter().for_each(|bm| self.notify(Event::Delete, bm));
+        Ok(deleted)
     }
     /// POC for multiple statements, not used in application
     pub fn batch_execute(&mut self, id_: i32) -> Result<(), DieselError> {
@@ -49,81 +149,313 @@ impl Dal {
             COMMIT;
         ";
         self.conn.batch_execute(query)?;
-        debug!(
-            "({}:{}) Deleted and Compacted {:?}",
-            function_name!(),
-            line!(),
-            id_
-        );
+        debug!("Deleted and Compacted {:?}", id_);
         Ok(())
     }
     pub fn delete_bookmark2(&mut self, id_: i32) -> Result<usize, DieselError> {
-        sql_query("BEGIN TRANSACTION;").execute(&mut self.conn)?;
+        let n = self.transaction(|dal| -> Result<usize, DieselError> {
+            if webhook::enabled() {
+                if let Ok(bm) = dal.get_bookmark_by_id(id_) {
+                    dal.notify(Event::Delete, &bm);
+                }
+            }
+            // Gotcha: 'returning *' not working within transaction
+            let n = sql_query(
+                "
+                DELETE FROM bookmarks
+                WHERE id = ?;
+            ",
+            )
+            .bind::<Integer, _>(id_)
+            .execute(&mut dal.conn)?;
+            debug!("Deleting {:?}", id_);
+            sql_query("DELETE FROM attachments WHERE bookmark_id = ?;")
+                .bind::<Integer, _>(id_)
+                .execute(&mut dal.conn)?;
+            sql_query("DELETE FROM contents WHERE bookmark_id = ?;")
+                .bind::<Integer, _>(id_)
+                .execute(&mut dal.conn)?;
 
-        // Gotcha: 'returning *' not working within transaction
-        let n = sql_query(
-            "
-            DELETE FROM bookmarks
-            WHERE id = ?;
-        ",
-        )
-        .bind::<Integer, _>(id_)
-        .execute(&mut self.conn);
-        debug!("({}:{}) Deleting {:?}", function_name!(), line!(), id_);
+            // database compaction
+            sql_query(
+                "
+                UPDATE bookmarks
+                SET id = id - 1
+                WHERE id > ?;
+            ",
+            )
+            .bind::<Integer, _>(id_)
+            .execute(&mut dal.conn)?;
+            sql_query(
+                "
+                UPDATE attachments
+                SET bookmark_id = bookmark_id - 1
+                WHERE bookmark_id > ?;
+            ",
+            )
+            .bind::<Integer, _>(id_)
+            .execute(&mut dal.conn)?;
+            sql_query(
+                "
+                UPDATE contents
+                SET bookmark_id = bookmark_id - 1
+                WHERE bookmark_id > ?;
+            ",
+            )
+            .bind::<Integer, _>(id_)
+            .execute(&mut dal.conn)?;
+            debug!("{:?}", "Compacting");
+            Ok(n)
+        })?;
+        debug!("Deleted and Compacted, n: {:?}", n);
+        Ok(n)
+    }
 
-        // database compaction
-        sql_query(
-            "
-            UPDATE bookmarks
-            SET id = id - 1
-            WHERE id > ?;
-        ",
-        )
-        .bind::<Integer, _>(id_)
-        .execute(&mut self.conn)?;
-        debug!("({}:{}) {:?}", function_name!(), line!(), "Compacting");
-
-        sql_query("COMMIT;").execute(&mut self.conn)?;
-        debug!(
-            "({}:{}) Deleted and Compacted, n: {:?}",
-            function_name!(),
-            line!(),
-            n
-        );
-        n
+    /// runs `f` in a transaction: commits if `f` succeeds, rolls back all its changes otherwise.
+    /// Nested calls use savepoints.
+    pub fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Dal) -> Result<T, E>,
+        E: From<DieselError>,
+    {
+        AnsiTransactionManager::begin_transaction(&mut *self.conn)?;
+        let queued = self.events.len();
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        let result = match result {
+            Ok(value) => AnsiTransactionManager::commit_transaction(&mut *self.conn)
+                .map(|_| value)
+                .map_err(E::from),
+            Err(e) => {
+                debug!("Rolling back");
+                AnsiTransactionManager::rollback_transaction(&mut *self.conn)?;
+                Err(e)
+            }
+        };
+        match result {
+            Ok(_) if self.depth == 0 => {
+                for (event, bm) in self.events.drain(..) {
+                    webhook::fire(event, &bm);
+                }
+            }
+            Ok(_) => {}
+            Err(_) => self.events.truncate(queued),
+        }
+        result
+    }
+
+    /// runs `f` in a transaction which is always rolled back: `f` sees its own changes,
+    /// the database stays untouched.
+    pub fn dry_run<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Dal) -> Result<T, E>,
+        E: From<DieselError>,
+    {
+        AnsiTransactionManager::begin_transaction(&mut *self.conn)?;
+        let queued = self.events.len();
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        self.events.truncate(queued);
+        debug!("Dry run: rolling back");
+        AnsiTransactionManager::rollback_transaction(&mut *self.conn)?;
+        result
+    }
+
+    /// [`Dal::transaction`] or [`Dal::dry_run`]
+    pub fn transaction_or_dry_run<T, E, F>(&mut self, dry_run: bool, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Dal) -> Result<T, E>,
+        E: From<DieselError>,
+    {
+        if dry_run {
+            self.dry_run(f)
+        } else {
+            self.transaction(f)
+        }
     }
     pub fn clean_table(&mut self) -> Result<(), DieselError> {
         sql_query("DELETE FROM bookmarks WHERE id != 1;").execute(&mut self.conn)?;
-        debug!("({}:{}) {:?}", function_name!(), line!(), "Cleaned table.");
+        sql_query("DELETE FROM attachments WHERE bookmark_id != 1;").execute(&mut self.conn)?;
+        sql_query("DELETE FROM contents WHERE bookmark_id != 1;").execute(&mut self.conn)?;
+        debug!("{:?}", "Cleaned table.");
         Ok(())
     }
     pub fn update_bookmark(&mut self, bm: Bookmark) -> Result<Vec<Bookmark>, DieselError> {
-        diesel::update(bookmarks.find(bm.id))
+        let updated: Vec<Bookmark> = diesel::update(bookmarks.find(bm.id))
             .set((
                 URL.eq(bm.URL),
                 metadata.eq(bm.metadata),
                 tags.eq(bm.tags),
                 desc.eq(bm.desc),
                 flags.eq(bm.flags),
+                extra.eq(bm.extra),
+            ))
+            .get_results(&mut self.conn)?;
+        updated.iter().for_each(|bm| self.notify(Event::Update, bm));
+        Ok(updated)
+    }
+
+    /// counts an open of the bookmark, does not change its last update
+    pub fn record_visit(&mut self, id_: i32) -> Result<usize, DieselError> {
+        let n = diesel::update(bookmarks.find(id_))
+            .set((
+                visits.eq(visits + 1),
+                last_visited.eq(diesel::dsl::now.nullable()),
             ))
-            .get_results(&mut self.conn)
+            .execute(&mut self.conn)?;
+        if n > 0 && webhook::enabled() {
+            let bm = self.get_bookmark_by_id(id_)?;
+            self.notify(Event::Open, &bm);
+        }
+        Ok(n)
     }
 
     pub fn insert_bookmark(&mut self, bm: NewBookmark) -> Result<Vec<Bookmark>, DieselError> {
-        diesel::insert_into(bookmarks)
+        let inserted: Vec<Bookmark> = diesel::insert_into(bookmarks)
             .values(bm)
-            .get_results(&mut self.conn)
+            .get_results(&mut self.conn)?;
+        inserted.iter().for_each(|bm| self.notify(Event::Add, bm));
+        Ok(inserted)
+    }
+    /// inserts `bm` keeping its last update time and extra fields, e.g. synced from another machine
+    pub fn import_bookmark(&mut self, bm: &Bookmark) -> Result<usize, DieselError> {
+        let n = diesel::insert_into(bookmarks)
+            .values((
+                URL.eq(&bm.URL),
+                metadata.eq(&bm.metadata),
+                tags.eq(&bm.tags),
+                desc.eq(&bm.desc),
+                flags.eq(bm.flags),
+                extra.eq(&bm.extra),
+                last_update_ts.eq(bm.last_update_ts),
+            ))
+            .execute(&mut self.conn)?;
+        if webhook::enabled() {
+            let imported: Bookmark = bookmarks.filter(URL.eq(&bm.URL)).first(&mut self.conn)?;
+            self.notify(Event::Add, &imported);
+        }
+        Ok(n)
+    }
+    /// [`Dal::update_bookmark`] setting the last update time of `bm` instead of the current time
+    pub fn replace_bookmark(&mut self, bm: &Bookmark) -> Result<usize, DieselError> {
+        let n = diesel::update(bookmarks.find(bm.id))
+            .set((
+                URL.eq(&bm.URL),
+                metadata.eq(&bm.metadata),
+                tags.eq(&bm.tags),
+                desc.eq(&bm.desc),
+                flags.eq(bm.flags),
+                extra.eq(&bm.extra),
+                last_update_ts.eq(bm.last_update_ts),
+            ))
+            .execute(&mut self.conn)?;
+        if n > 0 {
+            self.notify(Event::Update, bm);
+        }
+        Ok(n)
     }
 
     pub fn get_bookmark_by_id(&mut self, id_: i32) -> Result<Bookmark, DieselError> {
         // Ok(sql_query("SELECT id, URL, metadata, tags, desc, flags, last_update_ts FROM bookmarks").load::<Bookmark2>(conn)?)
         let bms = sql_query(
-            "SELECT id, URL, metadata, tags, desc, flags, last_update_ts FROM bookmarks \
+            "SELECT id, URL, metadata, tags, desc, flags, last_update_ts, extra, visits, last_visited \
+            FROM bookmarks \
             where id = ?;",
         );
         let bm = bms.bind::<Integer, _>(id_).get_result(&mut self.conn);
         bm
     }
+    pub fn get_bookmark_by_url(&mut self, url: &str) -> Result<Option<Bookmark>, DieselError> {
+        bookmarks
+            .filter(URL.eq(url))
+            .first(&mut self.conn)
+            .optional()
+    }
+    /// entries of the change log after `since`, oldest first
+    pub fn get_changes(
+        &mut self,
+        since: i32,
+        limit: Option<i64>,
+    ) -> Result<Vec<Change>, DieselError> {
+        let mut query = changes::table
+            .filter(changes::seq.gt(since))
+            .order(changes::seq)
+            .into_boxed();
+        if let Some(limit) = limit {
+            query = query.limit(limit);
+        }
+        query.load(&mut self.conn)
+    }
+    /// position of the last entry of the change log, 0 if it is empty
+    pub fn get_last_change(&mut self) -> Result<i32, DieselError> {
+        let seq: Option<i32> = changes::table
+            .select(diesel::dsl::max(changes::seq))
+            .first(&mut self.conn)?;
+        Ok(seq.unwrap_or_default())
+    }
+    /// number of `add` entries of the change log per month, oldest first. Bookmarks older than
+    /// the log count for the month it was started, changes of the URL as additions.
+    pub fn get_additions_per_month(&mut self) -> Result<Vec<MonthCount>, DieselError> {
+        sql_query(
+            "SELECT strftime('%Y-%m', ts) AS month, count(*) AS n \
+            FROM changes WHERE op = 'add' \
+            GROUP BY month ORDER BY month;",
+        )
+        .load(&mut self.conn)
+    }
+    /// the most opened bookmarks since `since`, with `tag` if not empty; ties by title
+    pub fn get_most_opened(
+        &mut self,
+        tag: &str,
+        since: NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<Opened>, DieselError> {
+        sql_query(
+            "SELECT b.id, b.URL, b.metadata, b.tags, b.desc, b.flags, b.last_update_ts, b.extra, \
+            b.visits, b.last_visited, count(*) AS opens \
+            FROM opens o JOIN bookmarks b ON b.URL = o.URL \
+            WHERE o.ts >= ? AND (? = '' OR instr(b.tags, ',' || ? || ',') > 0) \
+            GROUP BY b.id ORDER BY opens DESC, lower(b.metadata) LIMIT ?;",
+        )
+        .bind::<Timestamp, _>(since)
+        .bind::<Text, _>(tag)
+        .bind::<Text, _>(tag)
+        .bind::<BigInt, _>(limit)
+        .load(&mut self.conn)
+    }
+    /// additions of the change log and opens per local day since `since`, days without any
+    /// are missing
+    pub fn get_activity(&mut self, since: NaiveDateTime) -> Result<Vec<DayActivity>, DieselError> {
+        sql_query(
+            "SELECT day, sum(added) AS added, sum(opened) AS opened FROM ( \
+                SELECT date(ts, 'localtime') AS day, 1 AS added, 0 AS opened \
+                FROM changes WHERE op = 'add' AND ts >= ? \
+                UNION ALL \
+                SELECT date(ts, 'localtime'), 0, 1 FROM opens WHERE ts >= ?) \
+            GROUP BY day ORDER BY day;",
+        )
+        .bind::<Timestamp, _>(since)
+        .bind::<Timestamp, _>(since)
+        .load(&mut self.conn)
+    }
+    /// size of the database file in bytes
+    pub fn get_db_size(&mut self) -> Result<i64, DieselError> {
+        diesel::select(diesel::dsl::sql::<BigInt>(
+            "(SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size())",
+        ))
+        .get_result(&mut self.conn)
+    }
+    /// most recently added or updated bookmark
+    pub fn get_last_bookmark(&mut self) -> Result<Bookmark, DieselError> {
+        sql_query(
+            "SELECT id, URL, metadata, tags, desc, flags, last_update_ts, extra, visits, last_visited \
+            FROM bookmarks \
+            order by last_update_ts desc, id desc limit 1;",
+        )
+        .get_result(&mut self.conn)
+    }
     pub fn get_bookmarks(&mut self, query: &str) -> Result<Vec<Bookmark>, DieselError> {
         if query.is_empty() {
             // select all
@@ -135,17 +467,49 @@ impl Dal {
     pub fn get_bookmarks_fts(&mut self, fts_query: &str) -> Result<Vec<Bookmark>, DieselError> {
         // Ok(sql_query("SELECT id, URL, metadata, tags, desc, flags, last_update_ts FROM bookmarks").load::<Bookmark2>(conn)?)
         let bms = sql_query(
-            "SELECT id, URL, metadata, tags, desc, flags, last_update_ts FROM bookmarks_fts \
+            "SELECT b.id, b.URL, b.metadata, b.tags, b.desc, b.flags, b.last_update_ts, b.extra, \
+            b.visits, b.last_visited FROM bookmarks_fts JOIN bookmarks b ON b.id = bookmarks_fts.rowid \
             where bookmarks_fts match ? \
-            order by rank",
+            order by bookmarks_fts.rank",
         );
-        let bms = bms.bind::<Text, _>(fts_query).get_results(&mut self.conn);
-        bms
+        let mut bms: Vec<Bookmark> = bms.bind::<Text, _>(fts_query).get_results(&mut self.conn)?;
+        // matches in page contents follow, the query may use columns the contents do not have
+        let content_bms: Vec<Bookmark> = sql_query(
+            "SELECT b.id, b.URL, b.metadata, b.tags, b.desc, b.flags, b.last_update_ts, b.extra, \
+            b.visits, b.last_visited FROM contents_fts JOIN bookmarks b ON b.id = contents_fts.rowid \
+            where contents_fts match ? \
+            order by contents_fts.rank",
+        )
+        .bind::<Text, _>(fts_query)
+        .get_results(&mut self.conn)
+        .unwrap_or_else(|e| {
+            debug!("Contents not searched: {:?}", e);
+            Vec::new()
+        });
+        for bm in content_bms {
+            if !bms.iter().any(|b| b.id == bm.id) {
+                bms.push(bm);
+            }
+        }
+        Ok(bms)
+    }
+
+    /// stores the readable text of the page of bookmark `id_`, replacing an existing one
+    pub fn set_content(&mut self, id_: i32, content: &str) -> Result<usize, DieselError> {
+        // an upsert, REPLACE would not fire the delete trigger keeping the index in sync
+        sql_query(
+            "INSERT INTO contents (bookmark_id, content) VALUES (?, ?) \
+            ON CONFLICT (bookmark_id) DO UPDATE SET content = excluded.content;",
+        )
+        .bind::<Integer, _>(id_)
+        .bind::<Text, _>(content)
+        .execute(&mut self.conn)
     }
 
     pub fn bm_exists(&mut self, url: &str) -> Result<bool, DieselError> {
         let bms = sql_query(
-            "SELECT id, URL, metadata, tags, desc, flags, last_update_ts FROM bookmarks \
+            "SELECT id, URL, metadata, tags, desc, flags, last_update_ts, extra, visits, last_visited \
+            FROM bookmarks \
             where URL = ?;",
         );
         let bms = bms
@@ -182,7 +546,7 @@ impl Dal {
     pub fn get_all_tags_as_vec(&mut self) -> Vec<String> {
         let all_tags = self.get_all_tags().unwrap(); //todo handle error
         let mut all_tags: Vec<String> = all_tags.into_iter().map(|t| t.tag).collect();
-        debug!("({}:{}) {:?}", function_name!(), line!(), all_tags);
+        debug!("{:?}", all_tags);
         all_tags.sort();
         all_tags
     }
@@ -214,6 +578,183 @@ impl Dal {
             .get_results(&mut self.conn);
         tags_result
     }
+
+    pub fn insert_attachment(&mut self, attachment: NewAttachment) -> Result<i32, DieselError> {
+        diesel::insert_into(attachments::table)
+            .values(attachment)
+            .returning(attachments::id)
+            .get_result(&mut self.conn)
+    }
+
+    /// attachments of a bookmark without their content, newest last
+    pub fn get_attachments(
+        &mut self,
+        bookmark_id: i32,
+    ) -> Result<Vec<AttachmentInfo>, DieselError> {
+        sql_query(
+            "SELECT id, bookmark_id, name, length(content) AS size, created_ts FROM attachments \
+            WHERE bookmark_id = ? ORDER BY id;",
+        )
+        .bind::<Integer, _>(bookmark_id)
+        .get_results(&mut self.conn)
+    }
+
+    pub fn get_attachment(&mut self, id_: i32) -> Result<Attachment, DieselError> {
+        attachments::table.find(id_).first(&mut self.conn)
+    }
+
+    pub fn delete_attachment(&mut self, id_: i32) -> Result<usize, DieselError> {
+        diesel::delete(attachments::table.find(id_)).execute(&mut self.conn)
+    }
+
+    fn get_schema_objects(&mut self) -> Result<Vec<SchemaObject>, DieselError> {
+        sql_query(
+            "SELECT type AS kind, name, sql FROM sqlite_master \
+            WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' \
+            ORDER BY rowid;",
+        )
+        .get_results(&mut self.conn)
+    }
+
+    /// tables with their data, excluding virtual tables and their shadow tables
+    fn get_data_tables(objects: &[SchemaObject]) -> Vec<&SchemaObject> {
+        let virtual_tables: Vec<&str> = objects
+            .iter()
+            .filter(|o| o.kind == "table" && o.sql.to_uppercase().starts_with("CREATE VIRTUAL"))
+            .map(|o| o.name.as_str())
+            .collect();
+        objects
+            .iter()
+            .filter(|o| o.kind == "table")
+            .filter(|o| !virtual_tables.contains(&o.name.as_str()))
+            .filter(|o| {
+                !virtual_tables
+                    .iter()
+                    .any(|v| o.name.starts_with(&format!("{}_", v)))
+            })
+            .collect()
+    }
+
+    /// creates a plain-text SQL dump (schema and data) which can be loaded with `load`
+    pub fn dump(&mut self) -> Result<String, DieselError> {
+        let objects = self.get_schema_objects()?;
+        let tables = Dal::get_data_tables(&objects);
+        let mut out = vec![
+            String::from("-- bkmr database dump"),
+            String::from("PRAGMA foreign_keys=OFF;"),
+            String::from("BEGIN TRANSACTION;"),
+        ];
+
+        for table in &tables {
+            out.push(format!("{};", table.sql));
+            let columns: Vec<TextRow> = sql_query("SELECT name AS text FROM pragma_table_info(?);")
+                .bind::<Text, _>(&table.name)
+                .get_results(&mut self.conn)?;
+            let values = columns
+                .iter()
+                .map(|c| format!("quote({})", quote_identifier(&c.text)))
+                .collect::<Vec<_>>()
+                .join(" || ',' || ");
+            let inserts: Vec<TextRow> = sql_query(format!(
+                "SELECT 'INSERT INTO {} VALUES(' || {} || ');' AS text FROM {} ORDER BY rowid;",
+                quote_identifier(&table.name).replace('\'', "''"),
+                values,
+                quote_identifier(&table.name)
+            ))
+            .get_results(&mut self.conn)?;
+            out.extend(inserts.into_iter().map(|r| r.text));
+        }
+        // virtual tables are rebuilt from their content tables instead of dumping shadow tables
+        for vtable in objects
+            .iter()
+            .filter(|o| o.kind == "table" && !tables.iter().any(|t| t.name == o.name))
+            .filter(|o| o.sql.to_uppercase().starts_with("CREATE VIRTUAL"))
+        {
+            out.push(format!("{};", vtable.sql));
+            if vtable.sql.to_lowercase().contains("fts5") {
+                let name = quote_identifier(&vtable.name);
+                out.push(format!("INSERT INTO {}({}) VALUES('rebuild');", name, name));
+            }
+        }
+        // indices and triggers last, so loading the data does not fire any trigger
+        for kind in ["index", "view", "trigger"] {
+            for object in objects.iter().filter(|o| o.kind == kind) {
+                out.push(format!("{};", object.sql));
+            }
+        }
+        out.push(String::from("COMMIT;"));
+        debug!("Dumped {} tables", tables.len());
+        Ok(out.join("\n") + "\n")
+    }
+
+    /// true if the database does not contain any schema objects yet
+    pub fn is_empty_db(&mut self) -> Result<bool, DieselError> {
+        Ok(self.get_schema_objects()?.is_empty())
+    }
+
+    /// loads a dump created by `dump`, `force` replaces all existing tables
+    pub fn load(&mut self, dump: &str, force: bool) -> Result<(), DieselError> {
+        if force {
+            let objects = self.get_schema_objects()?;
+            let data_tables: Vec<String> = Dal::get_data_tables(&objects)
+                .iter()
+                .map(|t| t.name.clone())
+                .collect();
+            // virtual tables first, they own their shadow tables
+            let mut drops: Vec<String> = objects
+                .iter()
+                .filter(|o| o.kind == "table" && !data_tables.contains(&o.name))
+                .filter(|o| o.sql.to_uppercase().starts_with("CREATE VIRTUAL"))
+                .map(|o| format!("DROP TABLE IF EXISTS {};", quote_identifier(&o.name)))
+                .collect();
+            drops.extend(
+                objects
+                    .iter()
+                    .filter(|o| o.kind == "view")
+                    .map(|o| format!("DROP VIEW IF EXISTS {};", quote_identifier(&o.name))),
+            );
+            drops.extend(
+                data_tables
+                    .iter()
+                    .map(|t| format!("DROP TABLE IF EXISTS {};", quote_identifier(t))),
+            );
+            debug!("{:?}", drops);
+            // the drops run in the dump's transaction, so a failing dump keeps the old tables
+            let script = match dump.split_once("BEGIN TRANSACTION;\n") {
+                Some((head, body)) => {
+                    format!("{}BEGIN TRANSACTION;\n{}\n{}", head, drops.join("\n"), body)
+                }
+                None => format!(
+                    "BEGIN TRANSACTION;\n{}\n{}\nCOMMIT;\n",
+                    drops.join("\n"),
+                    dump
+                ),
+            };
+            return self.execute_dump(&script);
+        }
+        self.execute_dump(dump)
+    }
+
+    fn execute_dump(&mut self, dump: &str) -> Result<(), DieselError> {
+        if let Err(e) = self.conn.batch_execute(dump) {
+            // a failing statement leaves the dump's transaction open
+            let _ = self.conn.batch_execute("ROLLBACK;");
+            return Err(e);
+        }
+        debug!("Loaded dump into {:?}", self.url);
+        Ok(())
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// `Dal` on the configured database (`BKMR_DB_URL`)
+impl Default for Dal {
+    fn default() -> Self {
+        Dal::new(CONFIG.db_url.clone())
+    }
 }
 
 impl Debug for Dal {
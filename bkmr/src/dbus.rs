@@ -0,0 +1,837 @@
+//! DBus service of `bkmr serve --dbus` on the session bus, e.g. for krunner or ulauncher.
+//! Bus name and interface `de.sysid.Bkmr`, object path `/de/sysid/Bkmr`:
+//! - `Search(s query, as tags, u limit) -> a(isss)`: id, URL, title and tags of the matching
+//!   bookmarks, an empty query matches all, limit 0 is no limit
+//! - `Add(s url, s title, as tags) -> i`: id of the new bookmark, an empty title is fetched
+//! - `Open(i id) -> s`: opens the bookmark, the text of snippets is returned instead
+//! - signal `Changed(s next)` of `bkmr daemon`: the database changed, `next` is the cursor of the
+//!   change feed
+//!
+//! Only the parts of the DBus wire protocol these need: EXTERNAL authentication on a unix socket,
+//! marshalling of basic types, arrays, structs and variants.
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, Context};
+use serde_json::{json, Value};
+use tracing::{debug, error};
+
+use crate::rpc;
+
+pub const NAME: &str = "de.sysid.Bkmr";
+const PATH: &str = "/de/sysid/Bkmr";
+const ERROR: &str = "de.sysid.Bkmr.Error";
+const BUS: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+const PEER: &str = "org.freedesktop.DBus.Peer";
+const UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const METHOD_ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+const NO_REPLY_EXPECTED: u8 = 0x1;
+/// RequestName flag: fail instead of waiting for the name
+const DO_NOT_QUEUE: u32 = 0x4;
+const PRIMARY_OWNER: u32 = 1;
+
+const PATH_FIELD: u8 = 1;
+const INTERFACE_FIELD: u8 = 2;
+const MEMBER_FIELD: u8 = 3;
+const ERROR_NAME_FIELD: u8 = 4;
+const REPLY_SERIAL_FIELD: u8 = 5;
+const DESTINATION_FIELD: u8 = 6;
+const SENDER_FIELD: u8 = 7;
+const SIGNATURE_FIELD: u8 = 8;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="de.sysid.Bkmr">
+    <method name="Search">
+      <arg name="query" type="s" direction="in"/>
+      <arg name="tags" type="as" direction="in"/>
+      <arg name="limit" type="u" direction="in"/>
+      <arg name="bookmarks" type="a(isss)" direction="out"/>
+    </method>
+    <method name="Add">
+      <arg name="url" type="s" direction="in"/>
+      <arg name="title" type="s" direction="in"/>
+      <arg name="tags" type="as" direction="in"/>
+      <arg name="id" type="i" direction="out"/>
+    </method>
+    <method name="Open">
+      <arg name="id" type="i" direction="in"/>
+      <arg name="text" type="s" direction="out"/>
+    </method>
+    <signal name="Changed">
+      <arg name="next" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+/// a marshalled value
+#[derive(Debug, Clone, PartialEq)]
+enum Arg {
+    Byte(u8),
+    Bool(bool),
+    Int16(i16),
+    Uint16(u16),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Double(f64),
+    Str(String),
+    ObjectPath(String),
+    Signature(String),
+    /// signature of the elements and the elements
+    Array(String, Vec<Arg>),
+    /// also dict entries
+    Struct(Vec<Arg>),
+    Variant(Box<Arg>),
+}
+
+impl Arg {
+    fn signature(&self) -> String {
+        match self {
+            Arg::Byte(_) => "y".into(),
+            Arg::Bool(_) => "b".into(),
+            Arg::Int16(_) => "n".into(),
+            Arg::Uint16(_) => "q".into(),
+            Arg::Int32(_) => "i".into(),
+            Arg::Uint32(_) => "u".into(),
+            Arg::Int64(_) => "x".into(),
+            Arg::Uint64(_) => "t".into(),
+            Arg::Double(_) => "d".into(),
+            Arg::Str(_) => "s".into(),
+            Arg::ObjectPath(_) => "o".into(),
+            Arg::Signature(_) => "g".into(),
+            Arg::Array(element, _) => format!("a{}", element),
+            Arg::Struct(fields) => format!(
+                "({})",
+                fields.iter().map(Arg::signature).collect::<String>()
+            ),
+            Arg::Variant(_) => "v".into(),
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Arg::Str(s) | Arg::ObjectPath(s) | Arg::Signature(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// alignment of the values of the complete type `signature`
+fn alignment(signature: &str) -> usize {
+    match signature.as_bytes().first() {
+        Some(b'n' | b'q') => 2,
+        Some(b'b' | b'i' | b'u' | b'h' | b's' | b'o' | b'a') => 4,
+        Some(b'x' | b't' | b'd' | b'(' | b'{') => 8,
+        _ => 1,
+    }
+}
+
+/// the first complete type of `signature` and the rest
+fn next_type(signature: &str) -> anyhow::Result<(&str, &str)> {
+    let end = match signature.as_bytes().first() {
+        None => return Err(anyhow!("Empty signature")),
+        Some(b'a') => 1 + next_type(&signature[1..])?.0.len(),
+        Some(open @ (b'(' | b'{')) => {
+            let close = if *open == b'(' { b')' } else { b'}' };
+            let mut depth = 0;
+            let end = signature.bytes().position(|c| {
+                depth += (c == *open) as i32 - (c == close) as i32;
+                depth == 0
+            });
+            end.ok_or_else(|| anyhow!("Unbalanced signature {:?}", signature))? + 1
+        }
+        Some(_) => 1,
+    };
+    Ok(signature.split_at(end))
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, alignment: usize) {
+        while !self.buf.len().is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    fn put(&mut self, alignment: usize, bytes: &[u8]) {
+        self.align(alignment);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn write(&mut self, arg: &Arg) {
+        match arg {
+            Arg::Byte(b) => self.buf.push(*b),
+            Arg::Bool(b) => self.put(4, &(*b as u32).to_le_bytes()),
+            Arg::Int16(n) => self.put(2, &n.to_le_bytes()),
+            Arg::Uint16(n) => self.put(2, &n.to_le_bytes()),
+            Arg::Int32(n) => self.put(4, &n.to_le_bytes()),
+            Arg::Uint32(n) => self.put(4, &n.to_le_bytes()),
+            Arg::Int64(n) => self.put(8, &n.to_le_bytes()),
+            Arg::Uint64(n) => self.put(8, &n.to_le_bytes()),
+            Arg::Double(d) => self.put(8, &d.to_le_bytes()),
+            Arg::Str(s) | Arg::ObjectPath(s) => {
+                self.put(4, &(s.len() as u32).to_le_bytes());
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+            }
+            Arg::Signature(s) => {
+                self.buf.push(s.len() as u8);
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+            }
+            Arg::Array(element, items) => {
+                self.put(4, &[0; 4]);
+                let length_at = self.buf.len() - 4;
+                // the padding to the first element does not count
+                self.align(alignment(element));
+                let start = self.buf.len();
+                items.iter().for_each(|item| self.write(item));
+                let length = (self.buf.len() - start) as u32;
+                self.buf[length_at..length_at + 4].copy_from_slice(&length.to_le_bytes());
+            }
+            Arg::Struct(fields) => {
+                self.align(8);
+                fields.iter().for_each(|field| self.write(field));
+            }
+            Arg::Variant(value) => {
+                self.write(&Arg::Signature(value.signature()));
+                self.write(value);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, alignment: usize, n: usize) -> anyhow::Result<&'a [u8]> {
+        self.pos = self.pos.div_ceil(alignment) * alignment;
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| anyhow!("Truncated message"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let mut bytes: [u8; N] = self.take(N, N)?.try_into()?;
+        if self.big_endian {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.fixed()?))
+    }
+
+    fn string(&mut self, length: usize) -> anyhow::Result<String> {
+        let s = String::from_utf8(self.take(1, length)?.to_vec())?;
+        self.take(1, 1)?;
+        Ok(s)
+    }
+
+    /// value of the complete type `signature`
+    fn read(&mut self, signature: &str) -> anyhow::Result<Arg> {
+        Ok(match signature.as_bytes()[0] {
+            b'y' => Arg::Byte(self.take(1, 1)?[0]),
+            b'b' => Arg::Bool(self.u32()? != 0),
+            b'n' => Arg::Int16(i16::from_le_bytes(self.fixed()?)),
+            b'q' => Arg::Uint16(u16::from_le_bytes(self.fixed()?)),
+            b'i' => Arg::Int32(i32::from_le_bytes(self.fixed()?)),
+            b'u' | b'h' => Arg::Uint32(self.u32()?),
+            b'x' => Arg::Int64(i64::from_le_bytes(self.fixed()?)),
+            b't' => Arg::Uint64(u64::from_le_bytes(self.fixed()?)),
+            b'd' => Arg::Double(f64::from_le_bytes(self.fixed()?)),
+            b's' | b'o' => {
+                let length = self.u32()? as usize;
+                let s = self.string(length)?;
+                match signature {
+                    "s" => Arg::Str(s),
+                    _ => Arg::ObjectPath(s),
+                }
+            }
+            b'g' => {
+                let length = self.take(1, 1)?[0] as usize;
+                Arg::Signature(self.string(length)?)
+            }
+            b'v' => {
+                let Arg::Signature(inner) = self.read("g")? else {
+                    unreachable!("signatures are read as such")
+                };
+                Arg::Variant(Box::new(self.read(next_type(&inner)?.0)?))
+            }
+            b'a' => {
+                let length = self.u32()? as usize;
+                let element = &signature[1..];
+                self.take(alignment(element), 0)?;
+                let end = self.pos + length;
+                let mut items = Vec::new();
+                while self.pos < end {
+                    items.push(self.read(element)?);
+                }
+                Arg::Array(element.to_string(), items)
+            }
+            b'(' | b'{' => {
+                self.take(8, 0)?;
+                let mut inner = &signature[1..signature.len() - 1];
+                let mut fields = Vec::new();
+                while !inner.is_empty() {
+                    let (field, rest) = next_type(inner)?;
+                    fields.push(self.read(field)?);
+                    inner = rest;
+                }
+                Arg::Struct(fields)
+            }
+            _ => return Err(anyhow!("Unsupported signature {:?}", signature)),
+        })
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    body: Vec<Arg>,
+}
+
+impl Message {
+    fn call(destination: &str, path: &str, interface: &str, member: &str, body: Vec<Arg>) -> Self {
+        Message {
+            kind: METHOD_CALL,
+            path: Some(path.to_string()),
+            interface: Some(interface.to_string()),
+            member: Some(member.to_string()),
+            destination: Some(destination.to_string()),
+            body,
+            ..Default::default()
+        }
+    }
+
+    /// a signal of the service to all listeners
+    fn signal(member: &str, body: Vec<Arg>) -> Self {
+        Message {
+            kind: SIGNAL,
+            path: Some(PATH.to_string()),
+            interface: Some(NAME.to_string()),
+            member: Some(member.to_string()),
+            body,
+            ..Default::default()
+        }
+    }
+
+    fn reply(&self, body: Vec<Arg>) -> Self {
+        Message {
+            kind: METHOD_RETURN,
+            reply_serial: Some(self.serial),
+            destination: self.sender.clone(),
+            body,
+            ..Default::default()
+        }
+    }
+
+    fn error(&self, name: &str, message: String) -> Self {
+        Message {
+            kind: METHOD_ERROR,
+            error_name: Some(name.to_string()),
+            ..self.reply(vec![Arg::Str(message)])
+        }
+    }
+
+    fn signature(&self) -> String {
+        self.body.iter().map(Arg::signature).collect()
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Writer::default();
+        self.body.iter().for_each(|arg| body.write(arg));
+        let text = |code: u8, value: &Option<String>, object_path: bool| {
+            value.as_ref().map(|v| match object_path {
+                true => (code, Arg::ObjectPath(v.clone())),
+                false => (code, Arg::Str(v.clone())),
+            })
+        };
+        let signature = Some(self.signature()).filter(|s| !s.is_empty());
+        let fields: Vec<Arg> = [
+            text(PATH_FIELD, &self.path, true),
+            text(INTERFACE_FIELD, &self.interface, false),
+            text(MEMBER_FIELD, &self.member, false),
+            text(ERROR_NAME_FIELD, &self.error_name, false),
+            self.reply_serial
+                .map(|serial| (REPLY_SERIAL_FIELD, Arg::Uint32(serial))),
+            text(DESTINATION_FIELD, &self.destination, false),
+            signature.map(|s| (SIGNATURE_FIELD, Arg::Signature(s))),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|(code, value)| Arg::Struct(vec![Arg::Byte(code), Arg::Variant(Box::new(value))]))
+        .collect();
+        let mut message = Writer::default();
+        message.buf.extend([b'l', self.kind, self.flags, 1]);
+        message.write(&Arg::Uint32(body.buf.len() as u32));
+        message.write(&Arg::Uint32(self.serial));
+        message.write(&Arg::Array("(yv)".to_string(), fields));
+        message.align(8);
+        message.buf.extend(body.buf);
+        message.buf
+    }
+
+    /// total length of the message starting with the 16 bytes of `start`
+    fn length(start: &[u8; 16]) -> usize {
+        let u32_at = |i: usize| {
+            let bytes: [u8; 4] = start[i..i + 4].try_into().expect("4 bytes");
+            match start[0] {
+                b'B' => u32::from_be_bytes(bytes),
+                _ => u32::from_le_bytes(bytes),
+            }
+        };
+        let header = (16 + u32_at(12) as usize).div_ceil(8) * 8;
+        header + u32_at(4) as usize
+    }
+
+    fn decode(buf: &[u8]) -> anyhow::Result<Message> {
+        let mut reader = Reader {
+            buf,
+            pos: 12,
+            big_endian: buf.first() == Some(&b'B'),
+        };
+        let mut message = Message {
+            kind: buf[1],
+            flags: buf[2],
+            serial: Reader { pos: 8, ..reader }.u32()?,
+            ..Default::default()
+        };
+        let mut signature = String::new();
+        let Arg::Array(_, fields) = reader.read("a(yv)")? else {
+            unreachable!("arrays are read as such")
+        };
+        for field in fields {
+            let Arg::Struct(field) = field else { continue };
+            let (Some(Arg::Byte(code)), Some(Arg::Variant(value))) = (field.first(), field.get(1))
+            else {
+                continue;
+            };
+            let text = value.as_str().map(String::from);
+            match *code {
+                PATH_FIELD => message.path = text,
+                INTERFACE_FIELD => message.interface = text,
+                MEMBER_FIELD => message.member = text,
+                ERROR_NAME_FIELD => message.error_name = text,
+                REPLY_SERIAL_FIELD => {
+                    if let Arg::Uint32(serial) = **value {
+                        message.reply_serial = Some(serial)
+                    }
+                }
+                DESTINATION_FIELD => message.destination = text,
+                SENDER_FIELD => message.sender = text,
+                SIGNATURE_FIELD => signature = text.unwrap_or_default(),
+                _ => {}
+            }
+        }
+        reader.take(8, 0)?;
+        let mut rest = signature.as_str();
+        while !rest.is_empty() {
+            let (arg, tail) = next_type(rest)?;
+            message.body.push(reader.read(arg)?);
+            rest = tail;
+        }
+        Ok(message)
+    }
+}
+
+/// the writing half of a connection, shared by the threads sending on it
+#[derive(Clone)]
+struct Sender {
+    writer: Arc<Mutex<UnixStream>>,
+    serial: Arc<AtomicU32>,
+}
+
+impl Sender {
+    fn write(&self, bytes: &[u8]) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().expect("DBus writer poisoned");
+        writer.write_all(bytes)
+    }
+
+    fn send(&self, mut message: Message) -> anyhow::Result<u32> {
+        message.serial = self.serial.fetch_add(1, Ordering::Relaxed) + 1;
+        self.write(&message.encode())?;
+        Ok(message.serial)
+    }
+}
+
+struct Connection {
+    reader: BufReader<UnixStream>,
+    sender: Sender,
+}
+
+impl Connection {
+    /// authenticated connection to the session bus
+    fn session() -> anyhow::Result<Connection> {
+        let path = session_bus_path()?;
+        let stream = UnixStream::connect(&path)
+            .with_context(|| format!("Cannot connect to the session bus {}", path))?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            sender: Sender {
+                writer: Arc::new(Mutex::new(stream)),
+                serial: Arc::new(AtomicU32::new(0)),
+            },
+        };
+        connection.authenticate()?;
+        Ok(connection)
+    }
+
+    /// EXTERNAL authentication, the bus checks the credentials of the socket
+    fn authenticate(&mut self) -> anyhow::Result<()> {
+        self.sender.write(b"\0AUTH EXTERNAL\r\n")?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        if line.starts_with("DATA") {
+            self.sender.write(b"DATA\r\n")?;
+            line.clear();
+            self.reader.read_line(&mut line)?;
+        }
+        if !line.starts_with("OK ") {
+            return Err(anyhow!(
+                "Session bus refused authentication: {}",
+                line.trim()
+            ));
+        }
+        self.sender.write(b"BEGIN\r\n")?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> anyhow::Result<Message> {
+        let mut start = [0; 16];
+        self.reader
+            .read_exact(&mut start)
+            .context("Session bus closed the connection")?;
+        let mut buf = start.to_vec();
+        buf.resize(Message::length(&start), 0);
+        self.reader.read_exact(&mut buf[16..])?;
+        Message::decode(&buf)
+    }
+
+    /// body of the reply to a call of the bus, other messages meanwhile are dropped
+    fn call_bus(&mut self, member: &str, body: Vec<Arg>) -> anyhow::Result<Vec<Arg>> {
+        let serial = self
+            .sender
+            .send(Message::call(BUS, BUS_PATH, BUS, member, body))?;
+        loop {
+            let message = self.receive()?;
+            if message.reply_serial != Some(serial) {
+                continue;
+            }
+            return match message.kind {
+                METHOD_ERROR => Err(anyhow!(
+                    "{}: {:?}",
+                    message.error_name.unwrap_or_default(),
+                    message
+                        .body
+                        .first()
+                        .and_then(Arg::as_str)
+                        .unwrap_or_default()
+                )),
+                _ => Ok(message.body),
+            };
+        }
+    }
+}
+
+/// socket of $DBUS_SESSION_BUS_ADDRESS, `$XDG_RUNTIME_DIR/bus` if it is not set
+fn session_bus_path() -> anyhow::Result<String> {
+    let Ok(address) = env::var("DBUS_SESSION_BUS_ADDRESS") else {
+        let runtime_dir = env::var("XDG_RUNTIME_DIR").context(
+            "No session bus: neither $DBUS_SESSION_BUS_ADDRESS nor $XDG_RUNTIME_DIR is set",
+        )?;
+        return Ok(format!("{}/bus", runtime_dir));
+    };
+    address
+        .split(';')
+        .filter_map(|address| address.strip_prefix("unix:"))
+        .flat_map(|options| options.split(','))
+        .find_map(|option| option.strip_prefix("path="))
+        .map(String::from)
+        .ok_or_else(|| {
+            anyhow!(
+                "Unsupported session bus address {:?}, unix:path=... expected",
+                address
+            )
+        })
+}
+
+/// the service, registered on the session bus
+pub struct Service {
+    connection: Connection,
+}
+
+/// emits the signals of a service, also while it runs in another thread
+pub struct Signals {
+    sender: Sender,
+}
+
+impl Signals {
+    /// the database changed, `next` is the cursor of the change feed
+    pub fn changed(&self, next: &str) -> anyhow::Result<()> {
+        self.sender
+            .send(Message::signal("Changed", vec![Arg::Str(next.to_string())]))?;
+        Ok(())
+    }
+}
+
+/// registers the service and answers its calls in a thread
+pub fn spawn() -> anyhow::Result<Signals> {
+    let service = Service::register()?;
+    let signals = service.signals();
+    thread::spawn(move || {
+        if let Err(e) = service.run() {
+            error!("DBus service stopped: {:#}", e);
+        }
+    });
+    Ok(signals)
+}
+
+impl Service {
+    /// connects and takes the bus name, fails if another bkmr has it
+    pub fn register() -> anyhow::Result<Service> {
+        let mut connection = Connection::session()?;
+        connection.call_bus("Hello", vec![])?;
+        let reply = connection.call_bus(
+            "RequestName",
+            vec![Arg::Str(NAME.to_string()), Arg::Uint32(DO_NOT_QUEUE)],
+        )?;
+        if reply != [Arg::Uint32(PRIMARY_OWNER)] {
+            return Err(anyhow!("{} is taken, is another bkmr serving it?", NAME));
+        }
+        Ok(Service { connection })
+    }
+
+    pub fn signals(&self) -> Signals {
+        Signals {
+            sender: self.connection.sender.clone(),
+        }
+    }
+
+    /// answers method calls until the bus closes the connection
+    pub fn run(mut self) -> anyhow::Result<()> {
+        loop {
+            let call = self.connection.receive()?;
+            if call.kind != METHOD_CALL {
+                continue;
+            }
+            debug!("{:?}.{:?}", call.interface, call.member);
+            let reply = match handle(&call) {
+                Ok(body) => call.reply(body),
+                Err((name, message)) => call.error(name, message),
+            };
+            if call.flags & NO_REPLY_EXPECTED == 0 {
+                self.connection.sender.send(reply)?;
+            }
+        }
+    }
+}
+
+/// the strings of an `as` argument
+fn strings(arg: &Arg) -> Vec<&str> {
+    match arg {
+        Arg::Array(_, items) => items.iter().filter_map(Arg::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn handle(call: &Message) -> Result<Vec<Arg>, (&'static str, String)> {
+    let member = call.member.as_deref().unwrap_or_default();
+    let unknown = || (UNKNOWN_METHOD, format!("Unknown method {}", member));
+    if call.path.as_deref() != Some(PATH) {
+        return Err(unknown());
+    }
+    let invalid = || {
+        let message = format!("Invalid arguments ({}) of {}", call.signature(), member);
+        (INVALID_ARGS, message)
+    };
+    let failed = |e: rpc::RpcError| (ERROR, e.message);
+    match (call.interface.as_deref().unwrap_or(NAME), member) {
+        (INTROSPECTABLE, "Introspect") => Ok(vec![Arg::Str(INTROSPECTION.to_string())]),
+        (PEER, "Ping") => Ok(vec![]),
+        (NAME, "Search") => {
+            let [Arg::Str(query), tags @ Arg::Array(..), Arg::Uint32(limit)] = &call.body[..]
+            else {
+                return Err(invalid());
+            };
+            let params = json!({
+                "query": Some(query).filter(|q| !q.is_empty()),
+                "tags": strings(tags),
+                "limit": Some(limit).filter(|l| **l > 0),
+            });
+            let result = rpc::call("search", params).map_err(failed)?;
+            let bms = result.as_array().into_iter().flatten().map(|bm| {
+                let text = |key: &str| Arg::Str(bm[key].as_str().unwrap_or_default().to_string());
+                let tags = bm["tags"].as_str().unwrap_or_default().trim_matches(',');
+                Arg::Struct(vec![
+                    Arg::Int32(bm["id"].as_i64().unwrap_or_default() as i32),
+                    text("URL"),
+                    text("metadata"),
+                    Arg::Str(tags.to_string()),
+                ])
+            });
+            Ok(vec![Arg::Array("(isss)".to_string(), bms.collect())])
+        }
+        (NAME, "Add") => {
+            let [Arg::Str(url), Arg::Str(title), tags @ Arg::Array(..)] = &call.body[..] else {
+                return Err(invalid());
+            };
+            let params = json!({
+                "url": url,
+                "title": Some(title).filter(|t| !t.is_empty()),
+                "tags": strings(tags),
+                "fetch": title.is_empty(),
+            });
+            let bm = rpc::call("add", params).map_err(failed)?;
+            Ok(vec![Arg::Int32(
+                bm["id"].as_i64().unwrap_or_default() as i32
+            )])
+        }
+        (NAME, "Open") => {
+            let [Arg::Int32(id)] = &call.body[..] else {
+                return Err(invalid());
+            };
+            let result = rpc::call("open", json!({ "id": id })).map_err(failed)?;
+            let text = result
+                .get("text")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            Ok(vec![Arg::Str(text.to_string())])
+        }
+        _ => Err(unknown()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("s", ("s", ""))]
+    #[case("asu", ("as", "u"))]
+    #[case("a(isss)i", ("a(isss)", "i"))]
+    #[case("a{sv}", ("a{sv}", ""))]
+    #[case("(a(yv)s)u", ("(a(yv)s)", "u"))]
+    fn test_next_type(#[case] signature: &str, #[case] expected: (&str, &str)) {
+        assert_eq!(next_type(signature).unwrap(), expected);
+    }
+
+    #[rstest]
+    fn test_encode_decode() {
+        let mut call = Message::call(
+            NAME,
+            PATH,
+            NAME,
+            "Search",
+            vec![
+                Arg::Str("rust".to_string()),
+                Arg::Array("s".to_string(), vec![Arg::Str("dev".to_string())]),
+                Arg::Uint32(5),
+            ],
+        );
+        call.serial = 7;
+        let encoded = call.encode();
+        assert_eq!(
+            Message::length(encoded[..16].try_into().unwrap()),
+            encoded.len()
+        );
+        assert_eq!(Message::decode(&encoded).unwrap(), call);
+
+        let reply = Message {
+            sender: Some(":1.7".to_string()),
+            ..call
+        }
+        .reply(vec![Arg::Array(
+            "(isss)".to_string(),
+            vec![Arg::Struct(vec![
+                Arg::Int32(1),
+                Arg::Str("https://www.sysid.de".to_string()),
+                Arg::Str("sysid".to_string()),
+                Arg::Str("blog".to_string()),
+            ])],
+        )]);
+        let decoded = Message::decode(&reply.encode()).unwrap();
+        assert_eq!(decoded.reply_serial, Some(7));
+        assert_eq!(decoded.destination.as_deref(), Some(":1.7"));
+        assert_eq!(decoded.body, reply.body);
+
+        let signal = Message::signal("Changed", vec![Arg::Str("42".to_string())]);
+        let decoded = Message::decode(&signal.encode()).unwrap();
+        assert_eq!((decoded.kind, decoded.destination), (SIGNAL, None));
+        assert_eq!(decoded.member.as_deref(), Some("Changed"));
+    }
+
+    #[rstest]
+    fn test_handle_errors() {
+        let call = |path: &str, member: &str, body: Vec<Arg>| Message {
+            sender: Some(":1.7".to_string()),
+            ..Message::call(NAME, path, NAME, member, body)
+        };
+        assert_eq!(
+            handle(&call(PATH, "Delete", vec![])).unwrap_err().0,
+            UNKNOWN_METHOD
+        );
+        assert_eq!(
+            handle(&call("/", "Search", vec![])).unwrap_err().0,
+            UNKNOWN_METHOD
+        );
+        let wrong = vec![Arg::Str("1".to_string())];
+        assert_eq!(
+            handle(&call(PATH, "Open", wrong)).unwrap_err().0,
+            INVALID_ARGS
+        );
+        let introspect = Message {
+            interface: Some(INTROSPECTABLE.to_string()),
+            ..call(PATH, "Introspect", vec![])
+        };
+        assert!(
+            matches!(&handle(&introspect).unwrap()[0], Arg::Str(xml) if xml.contains("Search"))
+        );
+    }
+}
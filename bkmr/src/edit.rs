@@ -0,0 +1,513 @@
+//! Editor buffer of bookmarks: a TOML document with one `[[bookmark]]` table per bookmark,
+//! so that multi-line descriptions and blank lines survive the round trip.
+//! The body of the tables can be customized by a template file, see [`set_template`].
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::format::Template;
+use crate::models::{Bookmark, NewBookmark};
+use crate::tag::Tags;
+
+const HEADER: &str = r##"# Lines beginning with "#" are comments.
+# title: leave empty to fetch title (and empty description) from the web
+# tags: comma separated, description: multi-line text within """ and """
+# Do not change the ids, removing a [[bookmark]] leaves it unchanged.
+"##;
+const HEADER_NEW: &str = r##"# Lines beginning with "#" are comments.
+# url: any URI, e.g. https://..., a file path or "shell::<command>"
+# tags: comma separated, description: multi-line text within """ and """
+# Add a [[bookmark]] table per bookmark to create several at once.
+"##;
+const HEADER_CONFLICT: &str = r##"# Lines beginning with "#" are comments.
+# Changed on both machines since the last sync: keep one [[bookmark]] and edit it, remove the other.
+# tags: comma separated, description: multi-line text within """ and """
+"##;
+
+/// file in the config directory overriding the body of the `[[bookmark]]` tables
+pub const TEMPLATE_FILE: &str = "edit-template.toml";
+
+static TEMPLATE: OnceLock<Template> = OnceLock::new();
+
+/// user template for the body of each `[[bookmark]]` table, the `id` line is always prepended
+pub fn set_template(template: Template) {
+    let _ = TEMPLATE.set(template);
+}
+
+/// parses an edit template: it must render a valid record for an empty bookmark,
+/// custom fields go into a `[bookmark.extra]` table
+pub fn parse_template(template: &str) -> anyhow::Result<Template> {
+    let template = Template::toml(template)?;
+    let buffer = format!("[[bookmark]]\n{}", template.render(&Bookmark::default()));
+    toml::from_str::<EditBuffer>(&buffer).context("Template does not render a valid bookmark")?;
+    Ok(template)
+}
+
+/// the edit was cancelled: editor failed, buffer unchanged or emptied. Nothing is written.
+#[derive(Debug, PartialEq)]
+pub struct EditAborted(pub String);
+
+impl fmt::Display for EditAborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Edit aborted: {}", self.0)
+    }
+}
+
+impl std::error::Error for EditAborted {}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+struct EditBuffer {
+    #[serde(default)]
+    bookmark: Vec<EditRecord>,
+}
+
+/// editable fields of a bookmark, missing fields are left unchanged
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EditRecord {
+    /// None: new bookmark
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// user defined fields, an empty value removes the field
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+}
+
+impl From<&Bookmark> for EditRecord {
+    fn from(bm: &Bookmark) -> Self {
+        EditRecord {
+            id: Some(bm.id),
+            url: bm.URL.clone(),
+            title: Some(bm.metadata.clone()),
+            tags: Some(bm.get_tags().join(",")),
+            description: Some(bm.desc.clone()),
+            extra: bm
+                .get_extra()
+                .keys()
+                .filter_map(|key| Some((key.clone(), bm.get_extra_value(key)?)))
+                .collect(),
+        }
+    }
+}
+
+impl EditRecord {
+    /// `bm` with the edited fields, tags normalized
+    pub fn apply(self, bm: &Bookmark) -> Bookmark {
+        let mut bm = Bookmark {
+            URL: self.url.trim().to_string(),
+            metadata: self.title.unwrap_or_else(|| bm.metadata.clone()),
+            tags: match self.tags {
+                Some(tags) => Tags::create_normalized_tag_string(Some(tags)),
+                None => bm.tags.clone(),
+            },
+            desc: self.description.unwrap_or_else(|| bm.desc.clone()),
+            ..bm.clone()
+        };
+        for (key, value) in self.extra {
+            if value.is_empty() {
+                if bm.get_extra_value(&key).is_some() {
+                    bm.remove_extra_value(&key);
+                }
+            } else {
+                bm.set_extra_value(&key, &value);
+            }
+        }
+        bm
+    }
+
+    /// new bookmark from the edited fields, tags normalized. Extra fields need an [`EditRecord::apply`] after the insert.
+    pub fn into_new(self) -> NewBookmark {
+        NewBookmark {
+            URL: self.url.trim().to_string(),
+            metadata: self.title.unwrap_or_default(),
+            tags: Tags::create_normalized_tag_string(self.tags),
+            desc: self.description.unwrap_or_default(),
+            flags: 0,
+        }
+    }
+}
+
+/// editor buffer for `bms`
+pub fn render(bms: &[Bookmark]) -> anyhow::Result<String> {
+    match TEMPLATE.get() {
+        Some(template) => Ok(render_template(HEADER, template, bms, true)),
+        None => render_records(HEADER, bms.iter().map(EditRecord::from).collect()),
+    }
+}
+
+/// editor buffer with one empty record for a new bookmark
+pub fn render_new() -> anyhow::Result<String> {
+    match TEMPLATE.get() {
+        Some(template) => Ok(render_template(
+            HEADER_NEW,
+            template,
+            &[Bookmark::default()],
+            false,
+        )),
+        None => render_records(
+            HEADER_NEW,
+            vec![EditRecord {
+                id: None,
+                ..EditRecord::from(&Bookmark::default())
+            }],
+        ),
+    }
+}
+
+/// editor buffer of a sync conflict: the `local` and the `remote` version of a bookmark
+pub fn render_conflict(local: &Bookmark, remote: &Bookmark) -> anyhow::Result<String> {
+    let version = |bm: &Bookmark| {
+        render_records(
+            "",
+            vec![EditRecord {
+                id: None,
+                ..EditRecord::from(bm)
+            }],
+        )
+    };
+    Ok(format!(
+        "{}\n# local, updated {}\n{}\n# remote, updated {}\n{}",
+        HEADER_CONFLICT,
+        local.last_update_ts,
+        version(local)?,
+        remote.last_update_ts,
+        version(remote)?
+    ))
+}
+
+/// the version kept in an edited conflict buffer: a single record of the same URL.
+/// Fields removed from it are those of `local`, except the extra ones.
+pub fn parse_conflict(buffer: &str, local: &Bookmark) -> anyhow::Result<Bookmark> {
+    let mut records = check_new(parse(buffer)?)?;
+    if records.len() != 1 {
+        return Err(anyhow!(
+            "Keep one version of {}, not {}",
+            local.URL,
+            records.len()
+        ));
+    }
+    let record = records.remove(0);
+    if record.url.trim() != local.URL {
+        return Err(anyhow!("The URL of {} must not change", local.URL));
+    }
+    Ok(record.apply(&Bookmark {
+        extra: String::from("{}"),
+        ..local.clone()
+    }))
+}
+
+fn render_template(header: &str, template: &Template, bms: &[Bookmark], with_id: bool) -> String {
+    let records: Vec<_> = bms
+        .iter()
+        .map(|bm| {
+            let id = if with_id {
+                format!("id = {}\n", bm.id)
+            } else {
+                String::new()
+            };
+            format!("[[bookmark]]\n{}{}", id, template.render(bm))
+        })
+        .collect();
+    format!("{}{}", header, records.join("\n"))
+}
+
+fn render_records(header: &str, records: Vec<EditRecord>) -> anyhow::Result<String> {
+    let buffer = EditBuffer { bookmark: records };
+    let records = toml::to_string(&buffer).context("Cannot render bookmarks")?;
+    // blank line between the records
+    Ok(format!(
+        "{}{}",
+        header,
+        records.replace("\n[[bookmark]]", "\n\n[[bookmark]]")
+    ))
+}
+
+/// parses an edited buffer, errors point to the offending line
+pub fn parse(buffer: &str) -> anyhow::Result<Vec<EditRecord>> {
+    let buffer: EditBuffer = toml::from_str(buffer).context(
+        "Invalid bookmarks: expected [[bookmark]] tables with fields id, url, title, tags, description",
+    )?;
+    debug!("{:?}", buffer);
+    for record in &buffer.bookmark {
+        if record.url.trim().is_empty() {
+            return Err(anyhow!(
+                "Invalid bookmark {}: url must not be empty",
+                record.id.map(|id| id.to_string()).unwrap_or_default()
+            ));
+        }
+    }
+    Ok(buffer.bookmark)
+}
+
+/// field of a bookmark changed by an edit
+#[derive(Debug, PartialEq)]
+pub struct FieldChange {
+    /// field name, `extra.<key>` for user defined fields
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// changed fields from `old` to `new`, empty if nothing changed
+pub fn diff(old: &Bookmark, new: &Bookmark) -> Vec<FieldChange> {
+    let (mut old, mut new) = (EditRecord::from(old), EditRecord::from(new));
+    let keys: BTreeSet<_> = old.extra.keys().chain(new.extra.keys()).cloned().collect();
+    let extra = keys.into_iter().map(|key| {
+        (
+            format!("extra.{}", key),
+            old.extra.remove(&key).unwrap_or_default(),
+            new.extra.remove(&key).unwrap_or_default(),
+        )
+    });
+    [
+        ("url", Some(old.url), Some(new.url)),
+        ("title", old.title, new.title),
+        ("tags", old.tags, new.tags),
+        ("description", old.description, new.description),
+    ]
+    .into_iter()
+    .map(|(field, old, new)| {
+        (
+            field.to_string(),
+            old.unwrap_or_default(),
+            new.unwrap_or_default(),
+        )
+    })
+    .chain(extra)
+    .filter(|(_, old, new)| old != new)
+    .map(|(field, old, new)| FieldChange { field, old, new })
+    .collect()
+}
+
+/// true if nothing but comments and blanks are left, like an emptied commit message
+pub fn is_empty(buffer: &str) -> bool {
+    buffer
+        .lines()
+        .map(str::trim)
+        .all(|line| line.is_empty() || line.starts_with('#'))
+}
+
+/// records of new bookmarks: they must not have ids
+pub fn check_new(records: Vec<EditRecord>) -> anyhow::Result<Vec<EditRecord>> {
+    if let Some((url, id)) = records.iter().find_map(|r| Some((&r.url, r.id?))) {
+        return Err(anyhow!("New bookmark {} must not have an id: {}", url, id));
+    }
+    Ok(records)
+}
+
+/// the edited bookmarks: each record must belong to one of `bms`
+pub fn apply(records: Vec<EditRecord>, bms: &[Bookmark]) -> anyhow::Result<Vec<Bookmark>> {
+    let mut seen = HashSet::new();
+    records
+        .into_iter()
+        .map(|record| {
+            let id = record
+                .id
+                .with_context(|| format!("Bookmark {} has no id", record.url))?;
+            if !seen.insert(id) {
+                return Err(anyhow!("Bookmark {} is edited twice", id));
+            }
+            let bm = bms
+                .iter()
+                .find(|bm| bm.id == id)
+                .with_context(|| format!("Bookmark {} was not opened for editing", id))?;
+            Ok(record.apply(bm))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    fn bms() -> Vec<Bookmark> {
+        vec![
+            Bookmark {
+                id: 7,
+                URL: String::from("https://www.sysid.de"),
+                metadata: String::from("sysid \"blog\""),
+                tags: String::from(",aaa,xxx,"),
+                desc: String::from("line 1\n\nline 3 after a blank line"),
+                ..Default::default()
+            },
+            Bookmark {
+                id: 8,
+                URL: String::from("https://www.google.com"),
+                tags: String::from(",,"),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[rstest]
+    fn test_round_trip(bms: Vec<Bookmark>) {
+        let buffer = render(&bms).unwrap();
+        assert!(buffer.starts_with("# Lines beginning"));
+        assert!(buffer.contains("\n\n[[bookmark]]\nid = 8"));
+        let edited = apply(parse(&buffer).unwrap(), &bms).unwrap();
+        assert_eq!(edited, bms);
+    }
+
+    #[rstest]
+    #[case("", true)]
+    #[case("# comment\n\n  # indented\n", true)]
+    #[case("# comment\n[[bookmark]]\n", false)]
+    fn test_is_empty(#[case] buffer: &str, #[case] expected: bool) {
+        assert_eq!(is_empty(buffer), expected);
+    }
+
+    #[rstest]
+    fn test_parse_normalizes_tags(bms: Vec<Bookmark>) {
+        let buffer = "[[bookmark]]\nid = 8\nurl = 'https://a.org'\ntags = 'B, a,,b'\n";
+        let edited = apply(parse(buffer).unwrap(), &bms).unwrap();
+        assert_eq!(edited.len(), 1);
+        assert_eq!(edited[0].URL, "https://a.org");
+        assert_eq!(edited[0].tags, ",a,b,");
+        assert_eq!(edited[0].metadata, "");
+        assert_eq!(edited[0].id, 8);
+    }
+
+    #[rstest]
+    #[case("[[bookmark]]\nid = 7\ntitle = 'x'\n", "url")]
+    #[case("[[bookmark]]\nid = 7\nurl = ''\n", "url must not be empty")]
+    #[case("[[bookmark]]\nid = 7\nurl = 'https://a.org'\ntitel = 'x'\n", "titel")]
+    #[case("[[bookmark]]\nid = 7\nurl = 'https://a.org\n", "line 3")]
+    fn test_parse_invalid(#[case] buffer: &str, #[case] expected: &str) {
+        let e = parse(buffer).unwrap_err();
+        assert!(format!("{:?}", e).contains(expected), "{:?}", e);
+    }
+
+    #[rstest]
+    #[case("[[bookmark]]\nid = 9\nurl = 'https://a.org'\n", "not opened")]
+    #[case("[[bookmark]]\nurl = 'https://a.org'\n", "no id")]
+    #[case(
+        "[[bookmark]]\nid = 7\nurl = 'https://a.org'\n[[bookmark]]\nid = 7\nurl = 'https://b.org'\n",
+        "twice"
+    )]
+    fn test_apply_invalid(bms: Vec<Bookmark>, #[case] buffer: &str, #[case] expected: &str) {
+        let e = apply(parse(buffer).unwrap(), &bms).unwrap_err();
+        assert!(e.to_string().contains(expected), "{:?}", e);
+    }
+
+    #[rstest]
+    fn test_new() {
+        let buffer = render_new().unwrap();
+        assert!(buffer.contains("[[bookmark]]\nurl = \"\"\n"));
+        assert!(!buffer.contains("id ="));
+        let edited = buffer.replace("url = \"\"", "url = \"shell::ls -l\"");
+        let records = check_new(parse(&edited).unwrap()).unwrap();
+        assert_eq!(records.len(), 1);
+        let bm = records[0].clone().into_new();
+        assert_eq!(bm.URL, "shell::ls -l");
+        assert_eq!(bm.tags, ",,");
+        assert!(check_new(parse("[[bookmark]]\nid = 1\nurl = 'x'\n").unwrap()).is_err());
+    }
+
+    #[rstest]
+    fn test_diff(bms: Vec<Bookmark>) {
+        let old = &bms[0];
+        assert!(diff(old, old).is_empty());
+        let new = Bookmark {
+            tags: String::from(",aaa,"),
+            desc: String::new(),
+            ..old.clone()
+        };
+        assert_eq!(
+            diff(old, &new),
+            vec![
+                FieldChange {
+                    field: String::from("tags"),
+                    old: String::from("aaa,xxx"),
+                    new: String::from("aaa"),
+                },
+                FieldChange {
+                    field: String::from("description"),
+                    old: String::from("line 1\n\nline 3 after a blank line"),
+                    new: String::new(),
+                },
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_extra(mut bms: Vec<Bookmark>) {
+        bms[0].set_extra_value("author", "sysid");
+        bms[0].set_extra_value("year", "2023");
+        let buffer = render(&bms[..1]).unwrap();
+        assert!(
+            buffer.contains("[bookmark.extra]\nauthor = \"sysid\"\n"),
+            "{}",
+            buffer
+        );
+        let edited = buffer
+            .replace("author = \"sysid\"", "author = \"\"")
+            .replace("year = \"2023\"", "year = \"2024\"\nvenue = \"rustconf\"");
+        let edited = apply(parse(&edited).unwrap(), &bms).unwrap();
+        assert_eq!(edited[0].get_extra_value("author"), None);
+        assert_eq!(
+            edited[0].get_extra_value("venue").as_deref(),
+            Some("rustconf")
+        );
+        let fields: Vec<_> = diff(&bms[0], &edited[0])
+            .into_iter()
+            .map(|change| change.field)
+            .collect();
+        assert_eq!(fields, vec!["extra.author", "extra.venue", "extra.year"]);
+    }
+
+    #[rstest]
+    fn test_conflict(bms: Vec<Bookmark>) {
+        let local = &bms[0];
+        let mut remote = local.clone();
+        remote.metadata = String::from("remote title");
+        remote.set_extra_value("author", "sysid");
+        let buffer = render_conflict(local, &remote).unwrap();
+        assert!(buffer.contains("# local, updated"));
+        assert!(!buffer.contains("id = 7"));
+        assert!(parse_conflict(&buffer, local)
+            .unwrap_err()
+            .to_string()
+            .contains("Keep one version"));
+        // the remote version kept
+        let kept = buffer[..buffer.find("# local").unwrap()].to_string()
+            + &buffer[buffer.find("# remote").unwrap()..];
+        assert_eq!(parse_conflict(&kept, local).unwrap(), remote);
+        let moved = "[[bookmark]]\nurl = 'https://a.org'\n";
+        assert!(parse_conflict(moved, local).is_err());
+    }
+
+    #[rstest]
+    fn test_render_template(mut bms: Vec<Bookmark>) {
+        bms[0].set_extra_value("author", "sysid");
+        let template = parse_template(
+            "title = {title}\nurl = {url}\n# help\n[bookmark.extra]\nauthor = {extra.author}\n",
+        )
+        .unwrap();
+        let buffer = render_template(HEADER, &template, &bms, true);
+        assert!(
+            buffer.contains("[[bookmark]]\nid = 7\ntitle = 'sysid \"blog\"'\n"),
+            "{}",
+            buffer
+        );
+        let edited = apply(parse(&buffer).unwrap(), &bms).unwrap();
+        // fields missing in the template are left unchanged
+        assert_eq!(edited, bms);
+        assert!(parse_template("url = {url}\ntitel = {title}\n").is_err());
+        assert!(parse_template("url = {unknown}\n").is_err());
+    }
+}
@@ -1,6 +1,16 @@
-use std::{env, process};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+use anyhow::Context;
 use clap::Parser;
 use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::exitcode;
+use crate::fetch::Canonical;
+use crate::handler::Pane;
+use crate::sync::Conflict;
+use crate::webhook::Event;
 
 // #[allow(dead_code)]
 #[derive(Debug)]
@@ -18,6 +28,9 @@ pub struct FzfEnvOpts {
     pub reverse: bool,
     #[clap(long, default_value_t = false)]
     pub show_tags: bool,
+    /// position and size of the preview pane, e.g. "down:30%", "right:50%:hidden" to hide it
+    #[clap(long, default_value = "right:50%")]
+    pub preview_window: String,
 }
 
 impl Config {
@@ -27,7 +40,7 @@ impl Config {
         let path = std::path::Path::new(&db_url);
         if !path.exists() {
             eprintln!("Error: db_url path does not exist: {:?}", db_url);
-            process::exit(1);
+            process::exit(exitcode::DB);
         }
         let port = env::var("BKMR_PORT")
             .unwrap_or_else(|_| "9999".to_string())
@@ -54,13 +67,292 @@ impl Config {
 
         let Ok(fzf_opts) = FzfEnvOpts::try_parse_from(&fzf_opts_args) else {
             eprintln!("Error: Failed to parse BKMR_FZF_OPTS: {:?} \nPlease check bkmr documentation.", fzf_opts_args);
-            process::exit(1)
+            process::exit(exitcode::USAGE)
         };
 
         Config { db_url, port, fzf_opts }
     }
 }
 
+pub const DEFAULT_CONFIG_PATH: &str = "~/.config/bkmr/config.toml";
+
+/// directory of the config file, further files like the edit template are looked up there
+pub fn config_dir(config: Option<&Path>) -> PathBuf {
+    let config = match config {
+        Some(path) => path.to_path_buf(),
+        None => PathBuf::from(shellexpand::tilde(DEFAULT_CONFIG_PATH).as_ref()),
+    };
+    config.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// Settings from the TOML config file (`--config`, default: `~/.config/bkmr/config.toml`)
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub log: LogSettings,
+    /// user defined subcommands, e.g. `work = "search -t work --fzf"`
+    pub aliases: BTreeMap<String, String>,
+    pub display: DisplaySettings,
+    /// editor command if neither $VISUAL nor $EDITOR is set, e.g. "code --wait"
+    pub editor: Option<String>,
+    /// command templates per URI prefix, e.g. `"ssh::" = "kitty +kitten ssh {}"`
+    pub handlers: BTreeMap<String, String>,
+    /// command templates per tag for web pages and files, e.g. `video = "mpv {}"`
+    pub tag_handlers: BTreeMap<String, String>,
+    pub open: OpenSettings,
+    pub fetch: FetchSettings,
+    pub http: HttpSettings,
+    pub screenshot: ScreenshotSettings,
+    pub fzf: FzfSettings,
+    pub theme: ThemeSettings,
+    pub tui: TuiSettings,
+    pub sync: SyncSettings,
+    pub serve: ServeSettings,
+    /// POST requests on bookmark events, `[[webhooks]]`
+    pub webhooks: Vec<WebhookSettings>,
+}
+
+/// fuzzy picker of `--fzf`, its layout is set by $BKMR_FZF_OPTS
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct FzfSettings {
+    /// keys of actions on the selected bookmarks, e.g. `delete = "ctrl-d"`:
+    /// open, edit, delete, yank (copy URLs), ids (print them), menu (choose one of them),
+    /// reload (full-text search of the typed query)
+    pub keys: BTreeMap<String, String>,
+    /// fields of the picker lines in their order: id, title, url, domain, tags.
+    /// Default: id, title, url with the tags after the id if $BKMR_FZF_OPTS has `--show-tags`
+    pub columns: Vec<String>,
+    /// skim color scheme in its `--color` syntax, e.g. "light" or "dark,matched:108"
+    pub color: Option<String>,
+    /// installed fzf used instead of the embedded picker, e.g. "fzf" or "fzf --exact".
+    /// Its layout, theme and keys come from $FZF_DEFAULT_OPTS, not from $BKMR_FZF_OPTS
+    pub external: Option<String>,
+}
+
+/// `bkmr tui`
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct TuiSettings {
+    /// keys of the list commands, e.g. `down = "ctrl-n"`, see `?` in the TUI for their names.
+    /// A character, ctrl-<char>, alt-<char>, space or a sequence of characters like "gg"
+    pub keys: BTreeMap<String, String>,
+}
+
+/// `bkmr sync`
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SyncSettings {
+    /// bookmarks changed on both sides: "newest-wins" (default), "manual-merge" in the editor
+    /// or "field-merge" (union of tags, local title and fields if changed on both sides)
+    pub conflict: Option<Conflict>,
+    pub git: GitSyncSettings,
+    pub ssh: SshSyncSettings,
+    pub cloud: CloudSyncSettings,
+}
+
+/// `bkmr sync git`
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct GitSyncSettings {
+    /// git repository of the export, e.g. "~/bkmr-sync"
+    pub dir: Option<String>,
+    /// remote to fetch from and push to (default: origin if the repository has it)
+    pub remote: Option<String>,
+}
+
+/// `bkmr sync ssh`
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SshSyncSettings {
+    /// command connecting to the peer, e.g. "ssh -p 2222" (default: ssh)
+    pub ssh: Option<String>,
+    /// bkmr command on the peer, e.g. "BKMR_DB_URL=~/bkmr.db ~/.cargo/bin/bkmr" (default: bkmr)
+    pub bkmr: Option<String>,
+}
+
+/// `bkmr sync cloud`: an age encrypted snapshot on S3-compatible or WebDAV storage
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CloudSyncSettings {
+    /// "s3://<bucket>/<key>" or the https URL of a WebDAV file
+    pub url: Option<String>,
+    /// S3 endpoint of providers other than AWS, e.g. "https://s3.eu-central-003.backblazeb2.com"
+    pub endpoint: Option<String>,
+    /// S3 region (default: us-east-1)
+    pub region: Option<String>,
+    /// WebDAV user, the password is read from $BKMR_SYNC_PASSWORD
+    pub user: Option<String>,
+    /// age key file decrypting the snapshot, e.g. "~/.config/bkmr/sync.key" by `age-keygen -o`
+    pub identity: Option<String>,
+    /// age public keys the snapshot is encrypted to, e.g. of all machines (default: of `identity`)
+    pub recipients: Vec<String>,
+}
+
+/// `bkmr serve`
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ServeSettings {
+    /// names the server is reached by besides localhost and IP addresses, e.g. "pc.local".
+    /// Requests for other names are refused, they come from web pages rebinding their domain
+    pub hosts: Vec<String>,
+    /// API tokens, `[[serve.users]]`. If any are configured, requests need one of them
+    pub users: Vec<UserSettings>,
+}
+
+/// a user of `bkmr serve`, e.g. of a household or team sharing one instance
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct UserSettings {
+    /// for the log
+    pub name: String,
+    /// sent as `Authorization: Bearer <token>` or `?token=<token>`, e.g. by `openssl rand -hex 32`
+    pub token: String,
+    /// tag of the user's private bookmarks, they are hidden from the other users.
+    /// Bookmarks added by the user get it, bookmarks without any namespace tag are shared.
+    /// Default: no namespace, all bookmarks are visible
+    pub namespace: Option<String>,
+}
+
+/// a webhook, fired on the events of bookmarks having one of the tags
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct WebhookSettings {
+    /// target of the POST requests
+    pub url: String,
+    /// add, update, delete, open (default: all)
+    pub events: Vec<Event>,
+    /// only bookmarks with one of these tags (default: all bookmarks)
+    pub tags: Vec<String>,
+    /// request body as output template with `{event}`, values are escaped for JSON strings,
+    /// e.g. `{{"text": "{event}: {title} {url}"}}` (default: event and bookmark as JSON)
+    pub payload: Option<String>,
+    /// e.g. `Authorization = "Bearer ..."`, Content-Type defaults to application/json
+    pub headers: BTreeMap<String, String>,
+}
+
+/// colors of bookmark listings and of `bkmr tui`
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ThemeSettings {
+    /// dark (default), light or solarized
+    pub preset: Option<String>,
+    /// colors overriding the preset by element: title, url, id, date, desc, tags, mark, dim.
+    /// Color names like "green", ANSI numbers like "208" or "r,g,b"
+    pub colors: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct LogSettings {
+    /// log level per module, e.g. `"bkmr::dal" = "debug"`, applied on top of the `-d` level
+    pub filters: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct DisplaySettings {
+    /// timestamps in listings: "relative" (default, e.g. "3 days ago") or a chrono format
+    pub time_format: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct OpenSettings {
+    /// opening more bookmarks at once asks for confirmation (default: 5)
+    pub confirm_above: Option<usize>,
+    /// web bookmarks opened at the same time (default: 4)
+    pub concurrency: Option<usize>,
+    /// pause between opening web bookmarks in milliseconds (default: 100)
+    pub delay_ms: Option<u64>,
+    /// run shell commands in a new tmux window/pane or wezterm tab (default: current terminal)
+    pub pane: Option<Pane>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct FetchSettings {
+    /// page metadata requests running at the same time when adding many bookmarks (default: 8)
+    pub concurrency: Option<usize>,
+    /// canonical URL differing from the added one: "keep" in extra.canonical_url (default), "replace" or "ignore"
+    pub canonical: Option<Canonical>,
+    /// store the readable text of pages on add and refresh, so searches match their content (default: false)
+    pub index_content: Option<bool>,
+    /// tag added bookmarks with the language of the page, e.g. "lang:de" (default: false)
+    pub detect_language: Option<bool>,
+    /// policy per host, e.g. "localhost" or "*.intranet.corp" for the domain and all its subdomains
+    pub hosts: BTreeMap<String, HostSettings>,
+}
+
+/// web requests to one host, the most specific matching host pattern applies
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct HostSettings {
+    /// never fetch page data on add and refresh, as with `bkmr add --no-web`
+    pub skip: bool,
+    /// replaces `[http] user_agent` for the host
+    pub user_agent: Option<String>,
+    /// sent in addition to `[http] headers`, e.g. a session cookie
+    pub headers: BTreeMap<String, String>,
+}
+
+/// HTTP client of all web requests: fetching page data, checking links, archiving
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct HttpSettings {
+    /// some sites block unknown clients, e.g. "Mozilla/5.0 (X11; Linux x86_64)" (default: bkmr/<version>)
+    pub user_agent: Option<String>,
+    /// sent with every request, e.g. `"Accept-Language" = "de, en"`
+    pub headers: BTreeMap<String, String>,
+    /// proxy of all requests, e.g. "http://proxy.corp:3128" (default: $HTTP_PROXY, $HTTPS_PROXY)
+    pub proxy: Option<String>,
+    /// additional trusted root certificate (PEM file), e.g. of a corporate CA
+    pub ca_cert: Option<PathBuf>,
+    /// accept invalid TLS certificates, e.g. self-signed ones of intranet sites
+    pub insecure: bool,
+    /// time to establish a connection in milliseconds (default: 10000)
+    pub connect_timeout_ms: Option<u64>,
+    /// total time of a request in milliseconds (default: 20000)
+    pub timeout_ms: Option<u64>,
+    /// retries of requests failing by timeout, connection or server error (default: 2)
+    pub retries: Option<u32>,
+    /// wait before the first retry in milliseconds, doubled for every further one (default: 500)
+    pub retry_delay_ms: Option<u64>,
+}
+
+/// `bkmr snapshot`, only built with feature `screenshot`
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ScreenshotSettings {
+    /// headless browser command (default: the first found of chromium, chromium-browser, google-chrome)
+    pub browser: Option<String>,
+    /// "width,height" of the browser window (default: "1280,800")
+    pub window_size: Option<String>,
+}
+
+impl Settings {
+    /// a missing default config file yields the defaults, an explicitly given file must exist
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Settings> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => {
+                let path = PathBuf::from(shellexpand::tilde(DEFAULT_CONFIG_PATH).as_ref());
+                if !path.exists() {
+                    return Ok(Settings::default());
+                }
+                path
+            }
+        };
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("Cannot read config {:?}", path))?;
+        Settings::parse(&content).with_context(|| format!("Invalid config {:?}", path))
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Settings> {
+        Ok(toml::from_str(content)?)
+    }
+}
+
 // Create a global configuration singleton
 lazy_static! {
     pub static ref CONFIG: Config = Config::new();
@@ -83,6 +375,173 @@ mod test {
             .try_init();
     }
 
+    #[rstest]
+    fn test_settings() {
+        let settings = Settings::parse(
+            r#"
+            editor = "code --wait"
+
+            [log.filters]
+            "bkmr::dal" = "debug"
+            skim = "warn"
+
+            [aliases]
+            work = "search -t work --fzf"
+
+            [display]
+            time_format = "%Y-%m-%d"
+
+            [handlers]
+            "ssh::" = "kitty +kitten ssh {}"
+
+            [tag_handlers]
+            video = "mpv {}"
+
+            [open]
+            confirm_above = 10
+            delay_ms = 500
+            pane = "tmux-window"
+
+            [fetch]
+            concurrency = 16
+            canonical = "replace"
+            index_content = true
+            detect_language = true
+            [fetch.hosts."*.intranet.corp"]
+            skip = true
+            [fetch.hosts."medium.com"]
+            user_agent = "Mozilla/5.0"
+
+            [screenshot]
+            browser = "/usr/bin/chromium"
+
+            [fzf]
+            columns = ["id", "domain", "title"]
+            color = "light"
+            external = "fzf --exact"
+            [fzf.keys]
+            yank = "ctrl-y"
+
+            [tui.keys]
+            top = "g"
+
+            [sync]
+            conflict = "field-merge"
+            [sync.git]
+            dir = "~/bkmr-sync"
+            remote = "github"
+            [sync.ssh]
+            ssh = "ssh -p 2222"
+            bkmr = "~/.cargo/bin/bkmr"
+            [sync.cloud]
+            url = "s3://bkmr/bookmarks.age"
+            region = "eu-central-1"
+            identity = "~/.config/bkmr/sync.key"
+            recipients = ["age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p"]
+
+            [theme]
+            preset = "solarized"
+            [theme.colors]
+            title = "255,135,0"
+
+            [[serve.users]]
+            name = "alice"
+            token = "4f1c9a"
+            namespace = "alice"
+            [[serve.users]]
+            name = "admin"
+            token = "93be07"
+
+            [[webhooks]]
+            url = "https://n8n.example.com/webhook/bkmr"
+            events = ["add", "delete"]
+            tags = ["team"]
+            payload = '{{"text": "{event}: {title} {url}"}}'
+            headers = { Authorization = "Bearer xyz" }
+
+            [http]
+            user_agent = "Mozilla/5.0"
+            proxy = "http://proxy.corp:3128"
+            insecure = true
+            timeout_ms = 5000
+            retries = 0
+
+            [http.headers]
+            "Accept-Language" = "de, en"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.display.time_format.as_deref(), Some("%Y-%m-%d"));
+        assert_eq!(settings.editor.as_deref(), Some("code --wait"));
+        assert_eq!(settings.aliases["work"], "search -t work --fzf");
+        assert_eq!(settings.handlers["ssh::"], "kitty +kitten ssh {}");
+        assert_eq!(settings.tag_handlers["video"], "mpv {}");
+        assert_eq!(settings.open.confirm_above, Some(10));
+        assert_eq!(settings.open.delay_ms, Some(500));
+        assert_eq!(settings.open.pane, Some(Pane::TmuxWindow));
+        assert_eq!(settings.fetch.concurrency, Some(16));
+        assert_eq!(settings.fetch.canonical, Some(Canonical::Replace));
+        assert_eq!(settings.fetch.index_content, Some(true));
+        assert_eq!(settings.fetch.detect_language, Some(true));
+        assert!(settings.fetch.hosts["*.intranet.corp"].skip);
+        assert_eq!(
+            settings.fetch.hosts["medium.com"].user_agent.as_deref(),
+            Some("Mozilla/5.0")
+        );
+        assert_eq!(settings.http.user_agent.as_deref(), Some("Mozilla/5.0"));
+        assert_eq!(settings.http.headers["Accept-Language"], "de, en");
+        assert_eq!(
+            settings.http.proxy.as_deref(),
+            Some("http://proxy.corp:3128")
+        );
+        assert!(settings.http.insecure);
+        assert_eq!(settings.fzf.keys["yank"], "ctrl-y");
+        assert_eq!(settings.fzf.columns, vec!["id", "domain", "title"]);
+        assert_eq!(settings.fzf.color.as_deref(), Some("light"));
+        assert_eq!(settings.fzf.external.as_deref(), Some("fzf --exact"));
+        assert_eq!(settings.tui.keys["top"], "g");
+        assert_eq!(settings.sync.conflict, Some(Conflict::FieldMerge));
+        assert_eq!(settings.sync.git.dir.as_deref(), Some("~/bkmr-sync"));
+        assert_eq!(settings.sync.git.remote.as_deref(), Some("github"));
+        assert_eq!(settings.sync.ssh.ssh.as_deref(), Some("ssh -p 2222"));
+        assert_eq!(settings.sync.ssh.bkmr.as_deref(), Some("~/.cargo/bin/bkmr"));
+        assert_eq!(
+            settings.sync.cloud.url.as_deref(),
+            Some("s3://bkmr/bookmarks.age")
+        );
+        assert_eq!(settings.sync.cloud.region.as_deref(), Some("eu-central-1"));
+        assert_eq!(settings.sync.cloud.recipients.len(), 1);
+        assert_eq!(settings.theme.preset.as_deref(), Some("solarized"));
+        assert_eq!(settings.theme.colors["title"], "255,135,0");
+        assert_eq!(settings.serve.users.len(), 2);
+        assert_eq!(settings.serve.users[0].namespace.as_deref(), Some("alice"));
+        assert_eq!(settings.serve.users[1].namespace, None);
+        assert_eq!(settings.webhooks.len(), 1);
+        assert_eq!(settings.webhooks[0].events, vec![Event::Add, Event::Delete]);
+        assert_eq!(settings.webhooks[0].headers["Authorization"], "Bearer xyz");
+        assert_eq!(
+            settings.screenshot.browser.as_deref(),
+            Some("/usr/bin/chromium")
+        );
+        assert_eq!(settings.http.timeout_ms, Some(5000));
+        assert_eq!(settings.http.retries, Some(0));
+        assert_eq!(settings.log.filters["bkmr::dal"], "debug");
+        assert_eq!(settings.log.filters["skim"], "warn");
+        assert_eq!(Settings::parse("").unwrap(), Settings::default());
+        assert!(Settings::parse("[log]\nfilters = 1").is_err());
+        assert!(Settings::parse("[open]\npane = \"screen\"").is_err());
+        assert!(Settings::parse("[sync]\nconflict = \"oldest-wins\"").is_err());
+        assert!(Settings::parse("[[webhooks]]\nevents = [\"save\"]").is_err());
+    }
+
+    #[rstest]
+    #[case(Some("/etc/bkmr/config.toml"), "/etc/bkmr")]
+    #[case(Some("config.toml"), "")]
+    fn test_config_dir(#[case] config: Option<&str>, #[case] expected: &str) {
+        assert_eq!(config_dir(config.map(Path::new)), PathBuf::from(expected));
+        assert!(config_dir(None).ends_with(".config/bkmr"));
+    }
+
     #[rstest]
     fn test_config() {
         println!("Using database at {}", CONFIG.db_url);
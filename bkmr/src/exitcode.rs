@@ -0,0 +1,77 @@
+//! Exit codes of the `bkmr` binary, scripts can branch on them:
+//!
+//! | code | meaning                                              |
+//! |------|------------------------------------------------------|
+//! | 0    | success                                              |
+//! | 1    | other failure                                        |
+//! | 2    | usage error: invalid arguments, ids, format, config  |
+//! | 3    | not found: bookmark, field or attachment             |
+//! | 4    | database error                                       |
+//! | 5    | network error                                        |
+//! | 6    | I/O error: files, stdin, editor                      |
+//! | 7    | aborted by the user                                  |
+//! | 8    | bookmark already exists                              |
+use diesel::result::Error as DieselError;
+
+use crate::edit::EditAborted;
+
+pub const OK: i32 = 0;
+pub const FAILURE: i32 = 1;
+/// same code clap uses for invalid command lines
+pub const USAGE: i32 = 2;
+pub const NOT_FOUND: i32 = 3;
+pub const DB: i32 = 4;
+pub const NETWORK: i32 = 5;
+pub const IO: i32 = 6;
+pub const ABORTED: i32 = 7;
+pub const EXISTS: i32 = 8;
+
+/// exit code for a database error
+pub fn from_diesel(e: &DieselError) -> i32 {
+    match e {
+        DieselError::NotFound => NOT_FOUND,
+        _ => DB,
+    }
+}
+
+/// exit code for the first error in the chain with a known cause
+pub fn from_error(e: &anyhow::Error) -> i32 {
+    e.chain()
+        .find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<DieselError>() {
+                Some(from_diesel(e))
+            } else if cause.is::<EditAborted>() {
+                Some(ABORTED)
+            } else if cause.is::<reqwest::Error>() {
+                Some(NETWORK)
+            } else if cause.is::<std::io::Error>() {
+                Some(IO)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(FAILURE)
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::{anyhow, Context};
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn test_from_error() {
+        let e = Err::<(), _>(DieselError::NotFound)
+            .context("Bookmark 99 not found")
+            .unwrap_err();
+        assert_eq!(from_error(&e), NOT_FOUND);
+        let e = anyhow::Error::new(DieselError::RollbackTransaction);
+        assert_eq!(from_error(&e), DB);
+        let e = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert_eq!(from_error(&e), IO);
+        assert_eq!(from_error(&anyhow!("other")), FAILURE);
+        let e = anyhow::Error::new(EditAborted(String::from("no changes")));
+        assert_eq!(from_error(&e), ABORTED);
+    }
+}
@@ -0,0 +1,916 @@
+//! Page metadata of many URLs at once: requests run concurrently on an async client,
+//! so enriching or checking hundreds of bookmarks does not wait for one request after the other.
+use std::collections::BTreeMap;
+use std::fs;
+use std::future::Future;
+use std::iter;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::Context;
+use futures::stream::{self, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, LOCATION, USER_AGENT};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, ClientBuilder, Method, Proxy, RequestBuilder, StatusCode, Url};
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{And, Attr, Name};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::environment::{HostSettings, HttpSettings};
+
+/// requests running at the same time, unless configured otherwise
+pub const DEFAULT_CONCURRENCY: usize = 8;
+/// a slow server must not hold up the whole batch, unless configured otherwise
+const TIMEOUT: Duration = Duration::from_secs(20);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// retries of failed requests, unless configured otherwise
+const RETRIES: u32 = 2;
+/// wait before the first retry, doubled for every further one, unless configured otherwise
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+/// longer redirect chains are considered broken
+const MAX_REDIRECTS: usize = 10;
+/// creating a snapshot on archive.org easily takes a minute
+const ARCHIVE_TIMEOUT: Duration = Duration::from_secs(120);
+const ARCHIVE_SAVE: &str = "https://web.archive.org/save/";
+const ARCHIVE_WEB: &str = "https://web.archive.org/web/";
+
+/// extra field of bookmarks keeping the canonical URL
+pub const CANONICAL_URL: &str = "canonical_url";
+/// extra field of bookmarks keeping the preview image of the page
+pub const IMAGE_URL: &str = "image_url";
+/// extra field of bookmarks keeping the RSS or Atom feed of the page
+pub const FEED_URL: &str = "feed_url";
+/// link types of feeds
+const FEED_TYPES: &[&str] = &["application/rss+xml", "application/atom+xml"];
+
+static CONCURRENCY: OnceLock<usize> = OnceLock::new();
+static CANONICAL: OnceLock<Canonical> = OnceLock::new();
+static INDEX_CONTENT: OnceLock<bool> = OnceLock::new();
+static DETECT_LANGUAGE: OnceLock<bool> = OnceLock::new();
+static HTTP: OnceLock<HttpSettings> = OnceLock::new();
+static HOSTS: OnceLock<Vec<(String, HostPolicy)>> = OnceLock::new();
+
+/// sets the number of concurrent requests, only the first call has an effect
+pub fn set_concurrency(concurrency: usize) {
+    let _ = CONCURRENCY.set(concurrency);
+}
+
+/// sets user agent, headers, proxy and TLS options of all requests, only the first call has an effect.
+/// Fails if the settings are invalid, e.g. a malformed header or proxy URL.
+pub fn set_http(http: HttpSettings) -> anyhow::Result<()> {
+    configure(Client::builder(), &http)?
+        .build()
+        .context("Invalid HTTP settings")?;
+    let _ = HTTP.set(http);
+    Ok(())
+}
+
+fn http() -> &'static HttpSettings {
+    HTTP.get_or_init(HttpSettings::default)
+}
+
+/// total time of a request
+fn timeout() -> Duration {
+    http()
+        .timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(TIMEOUT)
+}
+
+/// web requests to the hosts matching a pattern
+#[derive(Debug, Clone, PartialEq)]
+struct HostPolicy {
+    skip: bool,
+    /// including the user agent
+    headers: HeaderMap,
+}
+
+/// sets the policies per host pattern, only the first call has an effect.
+/// Fails if a pattern is no host name or a header is malformed.
+pub fn set_hosts(hosts: BTreeMap<String, HostSettings>) -> anyhow::Result<()> {
+    let _ = HOSTS.set(host_policies(hosts)?);
+    Ok(())
+}
+
+fn host_policies(
+    hosts: BTreeMap<String, HostSettings>,
+) -> anyhow::Result<Vec<(String, HostPolicy)>> {
+    let mut policies = Vec::new();
+    for (pattern, host) in hosts {
+        let domain = pattern.strip_prefix("*.").unwrap_or(&pattern);
+        if domain.is_empty() || domain.contains(['/', ':', '*']) {
+            anyhow::bail!("Invalid host pattern {:?}", pattern);
+        }
+        let mut headers = header_map(&host.headers)
+            .with_context(|| format!("Invalid headers of host {}", pattern))?;
+        if let Some(user_agent) = &host.user_agent {
+            headers.insert(
+                USER_AGENT,
+                HeaderValue::from_str(user_agent)
+                    .with_context(|| format!("Invalid user agent of host {}", pattern))?,
+            );
+        }
+        let policy = HostPolicy {
+            skip: host.skip,
+            headers,
+        };
+        policies.push((pattern.to_lowercase(), policy));
+    }
+    Ok(policies)
+}
+
+fn host_policy(url: &str) -> Option<&'static HostPolicy> {
+    matching_policy(HOSTS.get()?, url)
+}
+
+/// policy of the host of `url`: an exact pattern, else the longest matching "*.domain" pattern
+fn matching_policy<'a>(policies: &'a [(String, HostPolicy)], url: &str) -> Option<&'a HostPolicy> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?.to_lowercase();
+    policies
+        .iter()
+        .filter(|(pattern, _)| match pattern.strip_prefix("*.") {
+            Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+            None => host == *pattern,
+        })
+        .max_by_key(|(pattern, _)| (!pattern.starts_with("*."), pattern.len()))
+        .map(|(_, policy)| policy)
+}
+
+/// whether page data of `url` is fetched on add and refresh
+pub fn fetches(url: &str) -> bool {
+    !host_policy(url).is_some_and(|policy| policy.skip)
+}
+
+/// request of `url` with the headers of its host policy
+fn request(client: &Client, method: Method, url: &str) -> RequestBuilder {
+    let request = client.request(method, url);
+    match host_policy(url) {
+        Some(policy) => request.headers(policy.headers.clone()),
+        None => request,
+    }
+}
+
+pub(crate) fn header_map(headers: &BTreeMap<String, String>) -> anyhow::Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        map.insert(
+            HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name {:?}", name))?,
+            HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value of header {}", name))?,
+        );
+    }
+    Ok(map)
+}
+
+fn configure(builder: ClientBuilder, http: &HttpSettings) -> anyhow::Result<ClientBuilder> {
+    let user_agent = http
+        .user_agent
+        .clone()
+        .unwrap_or_else(|| format!("bkmr/{}", env!("CARGO_PKG_VERSION")));
+    let headers = header_map(&http.headers)?;
+    let connect_timeout = http
+        .connect_timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(CONNECT_TIMEOUT);
+    let mut builder = builder
+        .user_agent(user_agent)
+        .connect_timeout(connect_timeout)
+        .default_headers(headers)
+        .danger_accept_invalid_certs(http.insecure);
+    if let Some(proxy) = &http.proxy {
+        builder =
+            builder.proxy(Proxy::all(proxy).with_context(|| format!("Invalid proxy {}", proxy))?);
+    }
+    if let Some(path) = &http.ca_cert {
+        let path = shellexpand::tilde(&path.to_string_lossy()).into_owned();
+        let pem = fs::read(&path).with_context(|| format!("Cannot read {}", path))?;
+        builder = builder.add_root_certificate(
+            Certificate::from_pem(&pem).with_context(|| format!("Invalid certificate {}", path))?,
+        );
+    }
+    Ok(builder)
+}
+
+/// what to do with a canonical URL differing from the bookmarked one
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Canonical {
+    /// keep the bookmarked URL, the canonical one goes to `extra.canonical_url`
+    #[default]
+    Keep,
+    /// bookmark the canonical URL instead
+    Replace,
+    Ignore,
+}
+
+impl Canonical {
+    /// the URL to bookmark and the canonical URL to keep in `extra.canonical_url`
+    pub fn apply(self, url: &str, canonical_url: Option<String>) -> (String, Option<String>) {
+        match (self, canonical_url) {
+            (Canonical::Replace, Some(canonical_url)) => (canonical_url, None),
+            (Canonical::Keep, canonical_url) => (url.to_string(), canonical_url),
+            _ => (url.to_string(), None),
+        }
+    }
+}
+
+/// sets the handling of canonical URLs, only the first call has an effect
+pub fn set_canonical(canonical: Canonical) {
+    let _ = CANONICAL.set(canonical);
+}
+
+pub fn canonical() -> Canonical {
+    CANONICAL.get().copied().unwrap_or_default()
+}
+
+/// sets whether the readable text of pages is stored for search, only the first call has an effect
+pub fn set_index_content(index_content: bool) {
+    let _ = INDEX_CONTENT.set(index_content);
+}
+
+pub fn index_content() -> bool {
+    INDEX_CONTENT.get().copied().unwrap_or_default()
+}
+
+/// sets whether the language of fetched pages is detected, only the first call has an effect
+pub fn set_detect_language(detect_language: bool) {
+    let _ = DETECT_LANGUAGE.set(detect_language);
+}
+
+pub fn detect_language() -> bool {
+    DETECT_LANGUAGE.get().copied().unwrap_or_default()
+}
+
+/// what is fetched of a page, fields are empty if missing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Details {
+    pub title: String,
+    pub description: String,
+    pub keywords: String,
+    /// `<link rel="canonical">`, else the URL reached by redirects, `None` if it is the requested URL
+    pub canonical_url: Option<String>,
+    /// readable text of the page, only if contents are indexed
+    pub content: Option<String>,
+    /// preview image of the OpenGraph or Twitter card metadata
+    pub image_url: Option<String>,
+    /// ISO 639-1 code of the page language, only if languages are detected
+    pub lang: Option<String>,
+    /// first RSS or Atom feed announced by `<link rel="alternate">`
+    pub feed_url: Option<String>,
+}
+
+impl Details {
+    /// URLs found on the page which are kept in the extra fields of its bookmarks
+    pub fn extra_urls(&self) -> Vec<(&'static str, String)> {
+        [(IMAGE_URL, &self.image_url), (FEED_URL, &self.feed_url)]
+            .into_iter()
+            .filter_map(|(key, url)| url.clone().map(|url| (key, url)))
+            .collect()
+    }
+}
+
+/// details of the page at `url`, which was requested as `requested`.
+/// OpenGraph and Twitter card metadata take precedence over `<title>` and the description,
+/// they are usually written for previews and read better.
+pub fn parse_details(html: &str, requested: &str, url: &Url) -> Details {
+    let document = Document::from(html);
+    // OpenGraph uses `property`, Twitter cards `name`, but both are found in the wild
+    let meta = |name: &str| {
+        document
+            .find(And(Name("meta"), |n: &Node| {
+                n.attr("property") == Some(name) || n.attr("name") == Some(name)
+            }))
+            .filter_map(|n| n.attr("content"))
+            .map(str::trim)
+            .find(|content| !content.is_empty())
+            .map(str::to_owned)
+    };
+    let title = meta("og:title")
+        .or_else(|| meta("twitter:title"))
+        .or_else(|| {
+            document
+                .find(Name("title"))
+                .next()
+                .map(|n| n.text().trim().to_owned())
+        })
+        .unwrap_or_default();
+    let description = meta("og:description")
+        .or_else(|| meta("twitter:description"))
+        .or_else(|| meta("description"))
+        .unwrap_or_default();
+    let keywords = meta("keywords").unwrap_or_default();
+    let image_url = meta("og:image")
+        .or_else(|| meta("og:image:url"))
+        .or_else(|| meta("twitter:image"))
+        .or_else(|| meta("twitter:image:src"))
+        .and_then(|image| url.join(&image).ok())
+        .map(|image| image.to_string());
+    let feed_url = document
+        .find(And(Name("link"), |n: &Node| {
+            n.attr("rel").is_some_and(|rel| {
+                rel.split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("alternate"))
+            }) && n
+                .attr("type")
+                .is_some_and(|t| FEED_TYPES.contains(&t.trim().to_lowercase().as_str()))
+        }))
+        .filter_map(|n| n.attr("href"))
+        .find_map(|href| url.join(href.trim()).ok())
+        .map(|feed| feed.to_string());
+    let canonical_url = document
+        .find(And(Name("link"), Attr("rel", "canonical")))
+        .next()
+        .and_then(|n| n.attr("href"))
+        .and_then(|href| url.join(href).ok())
+        .unwrap_or_else(|| url.clone());
+    let canonical_url = match Url::parse(requested) {
+        Ok(requested) if requested == canonical_url => None,
+        _ => Some(canonical_url.to_string()),
+    };
+    debug!(
+        "{:?}, {:?}, {:?}, {:?}, {:?}, {:?}",
+        title, description, keywords, canonical_url, image_url, feed_url
+    );
+    Details {
+        title,
+        description,
+        keywords,
+        canonical_url,
+        content: None,
+        image_url,
+        lang: None,
+        feed_url,
+    }
+}
+
+/// elements whose text is read
+const TEXT_BLOCKS: &[&str] = &[
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "pre",
+    "blockquote",
+    "td",
+    "dt",
+    "dd",
+    "figcaption",
+];
+/// elements around the main text of a page
+const BOILERPLATE: &[&str] = &[
+    "nav", "header", "footer", "aside", "form", "script", "style", "noscript",
+];
+
+/// main text of a page like a reader view shows it: the text blocks of `<article>`,
+/// else of `<main>` or `<body>`, without navigation, scripts and the like
+pub fn readable_text(html: &str) -> String {
+    let document = Document::from(html);
+    let Some(root) = ["article", "main", "body"]
+        .iter()
+        .find_map(|name| document.find(Name(*name)).next())
+    else {
+        return String::new();
+    };
+    let is_block = |node: &Node| node.name().is_some_and(|name| TEXT_BLOCKS.contains(&name));
+    let is_boilerplate = |node: &Node| node.name().is_some_and(|name| BOILERPLATE.contains(&name));
+    root.find(is_block)
+        .filter(|node| {
+            // the text of nested blocks is part of the outer one
+            iter::successors(node.parent(), Node::parent)
+                .take_while(|ancestor| ancestor.index() != root.index())
+                .all(|ancestor| !is_block(&ancestor) && !is_boilerplate(&ancestor))
+        })
+        .map(|node| node.text().split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// ISO 639-3 codes of whatlang and their ISO 639-1 equivalents
+const ISO_639_1: &[(&str, &str)] = &[
+    ("afr", "af"),
+    ("aka", "ak"),
+    ("amh", "am"),
+    ("ara", "ar"),
+    ("aze", "az"),
+    ("bel", "be"),
+    ("ben", "bn"),
+    ("bul", "bg"),
+    ("cat", "ca"),
+    ("ces", "cs"),
+    ("cmn", "zh"),
+    ("dan", "da"),
+    ("deu", "de"),
+    ("ell", "el"),
+    ("eng", "en"),
+    ("epo", "eo"),
+    ("est", "et"),
+    ("fin", "fi"),
+    ("fra", "fr"),
+    ("guj", "gu"),
+    ("heb", "he"),
+    ("hin", "hi"),
+    ("hrv", "hr"),
+    ("hun", "hu"),
+    ("hye", "hy"),
+    ("ind", "id"),
+    ("ita", "it"),
+    ("jav", "jv"),
+    ("jpn", "ja"),
+    ("kan", "kn"),
+    ("kat", "ka"),
+    ("khm", "km"),
+    ("kor", "ko"),
+    ("lat", "la"),
+    ("lav", "lv"),
+    ("lit", "lt"),
+    ("mal", "ml"),
+    ("mar", "mr"),
+    ("mkd", "mk"),
+    ("mya", "my"),
+    ("nep", "ne"),
+    ("nld", "nl"),
+    ("nob", "nb"),
+    ("ori", "or"),
+    ("pan", "pa"),
+    ("pes", "fa"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("ron", "ro"),
+    ("rus", "ru"),
+    ("sin", "si"),
+    ("slk", "sk"),
+    ("slv", "sl"),
+    ("sna", "sn"),
+    ("spa", "es"),
+    ("srp", "sr"),
+    ("swe", "sv"),
+    ("tam", "ta"),
+    ("tel", "te"),
+    ("tgl", "tl"),
+    ("tha", "th"),
+    ("tuk", "tk"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("urd", "ur"),
+    ("uzb", "uz"),
+    ("vie", "vi"),
+    ("yid", "yi"),
+    ("zul", "zu"),
+];
+
+/// ISO 639-1 code of the language of `text`, `None` if it cannot be told reliably
+pub fn language(text: &str) -> Option<String> {
+    let info = whatlang::detect(text).filter(|info| info.is_reliable())?;
+    let code = info.lang().code();
+    let code = ISO_639_1
+        .iter()
+        .find(|(iso3, _)| *iso3 == code)
+        .map_or(code, |(_, iso1)| iso1);
+    Some(code.to_string())
+}
+
+/// fetches the details of all `urls` concurrently following redirects, results in the order of `urls`.
+/// URLs of skipped hosts yield empty details.
+pub fn load_urls_details(urls: &[&str]) -> Vec<anyhow::Result<Details>> {
+    let builder = Client::builder().timeout(timeout());
+    for_each_url(urls, builder, |client, url| async move {
+        if !fetches(&url) {
+            debug!("Skipping {}", url);
+            return Ok(Details::default());
+        }
+        let response = send(|| request(&client, Method::GET, &url)).await?;
+        let final_url = response.url().clone();
+        let body = response.text().await?;
+        let mut details = parse_details(&body, &url, &final_url);
+        if index_content() || detect_language() {
+            let text = readable_text(&body);
+            if detect_language() {
+                // pages without text blocks still have a title
+                details.lang = match text.trim() {
+                    "" => language(&format!("{}\n{}", details.title, details.description)),
+                    text => language(text),
+                };
+            }
+            details.content = index_content().then_some(text);
+        }
+        Ok(details)
+    })
+}
+
+/// details of a single page
+pub fn load_url_details(url: &str) -> anyhow::Result<Details> {
+    load_urls_details(&[url]).remove(0)
+}
+
+/// downloads the raw page, e.g. to store it as HTML snapshot
+pub fn load_url_content(url: &str) -> anyhow::Result<Vec<u8>> {
+    load_urls_content(&[url]).remove(0)
+}
+
+/// downloads all `urls` concurrently, results in the order of `urls`
+pub fn load_urls_content(urls: &[&str]) -> Vec<anyhow::Result<Vec<u8>>> {
+    let builder = Client::builder().timeout(timeout());
+    for_each_url(urls, builder, |client, url| async move {
+        let body = send(|| request(&client, Method::GET, &url))
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        debug!("Loaded {} bytes", body.len());
+        Ok(body.to_vec())
+    })
+}
+
+/// result of checking whether a link still works
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkStatus {
+    /// reachable, final HTTP status
+    Ok(u16),
+    /// reachable by permanent redirects: final HTTP status and the new URL
+    Moved(u16, String),
+    /// HTTP error status, `None` if there was no response at all, and the reason
+    Dead(Option<u16>, String),
+}
+
+/// checks all `urls` concurrently, results in the order of `urls`
+pub fn check_links(urls: &[&str]) -> Vec<LinkStatus> {
+    let builder = Client::builder()
+        .timeout(timeout())
+        .redirect(Policy::none());
+    for_each_url(urls, builder, |client, url| async move {
+        check_link(&client, url).await
+    })
+}
+
+/// follows redirects itself: only a chain of permanent redirects means the link moved
+async fn check_link(client: &Client, url: String) -> LinkStatus {
+    let mut current = url;
+    let mut moved_to = None;
+    let mut permanent = true;
+    for _ in 0..=MAX_REDIRECTS {
+        let response = match head_or_get(client, &current).await {
+            Ok(response) => response,
+            Err(e) => return LinkStatus::Dead(None, e.to_string()),
+        };
+        let status = response.status();
+        if status.is_redirection() {
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| response.url().join(location).ok());
+            let Some(location) = location else {
+                return LinkStatus::Dead(Some(status.as_u16()), "redirect without location".into());
+            };
+            permanent &= matches!(
+                status,
+                StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+            );
+            current = location.to_string();
+            if permanent {
+                moved_to = Some(current.clone());
+            }
+            continue;
+        }
+        return match (status.is_success(), moved_to) {
+            (true, Some(moved_to)) => LinkStatus::Moved(status.as_u16(), moved_to),
+            (true, None) => LinkStatus::Ok(status.as_u16()),
+            (false, _) => LinkStatus::Dead(Some(status.as_u16()), status.to_string()),
+        };
+    }
+    LinkStatus::Dead(None, "too many redirects".into())
+}
+
+/// HEAD is cheaper, but not every server supports it
+async fn head_or_get(client: &Client, url: &str) -> reqwest::Result<reqwest::Response> {
+    let response = send(|| request(client, Method::HEAD, url)).await?;
+    match response.status() {
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+            send(|| request(client, Method::GET, url)).await
+        }
+        _ => Ok(response),
+    }
+}
+
+/// sends the request built by `request`, retries timeouts, connection failures and
+/// server errors with exponential backoff
+async fn send<F>(request: F) -> reqwest::Result<reqwest::Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let retries = http().retries.unwrap_or(RETRIES);
+    let mut delay = http()
+        .retry_delay_ms
+        .map(Duration::from_millis)
+        .unwrap_or(RETRY_DELAY);
+    let mut attempt = 0;
+    loop {
+        let result = request().send().await;
+        let transient = match &result {
+            Ok(response) => {
+                response.status().is_server_error()
+                    || response.status() == StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => e.is_timeout() || e.is_connect(),
+        };
+        if !transient || attempt >= retries {
+            return result;
+        }
+        attempt += 1;
+        debug!("Retry {} of {} in {:?}", attempt, retries, delay);
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// requests snapshots of all `urls` on archive.org (Wayback Machine), returns their archive URLs
+pub fn archive_urls(urls: &[&str]) -> Vec<anyhow::Result<String>> {
+    let builder = Client::builder().timeout(ARCHIVE_TIMEOUT);
+    for_each_url(urls, builder, |client, url| async move {
+        let response = client
+            .get(format!("{}{}", ARCHIVE_SAVE, url))
+            .send()
+            .await?
+            .error_for_status()?;
+        snapshot_url(&response)
+            .ok_or_else(|| anyhow::anyhow!("No snapshot URL in response of archive.org"))
+    })
+}
+
+/// the save request redirects to the snapshot, or names it in `Content-Location`
+fn snapshot_url(response: &reqwest::Response) -> Option<String> {
+    if response.url().as_str().starts_with(ARCHIVE_WEB) {
+        return Some(response.url().to_string());
+    }
+    let location = response.headers().get("content-location")?.to_str().ok()?;
+    let url = response.url().join(location).ok()?.to_string();
+    url.starts_with(ARCHIVE_WEB).then_some(url)
+}
+
+/// response of [`send_request`]
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// a single request with the configured HTTP settings and retries, e.g. to sync storage.
+/// `headers` are sent in addition to the configured ones, error statuses are returned as well.
+pub fn send_request(
+    method: Method,
+    url: &str,
+    headers: HeaderMap,
+    body: Option<Vec<u8>>,
+) -> anyhow::Result<Response> {
+    let builder = Client::builder().timeout(timeout());
+    let mut results = for_each_url(&[url], builder, |client, url| {
+        let (method, headers, body) = (method.clone(), headers.clone(), body.clone());
+        async move {
+            let response = send(|| {
+                let request = client
+                    .request(method.clone(), &url)
+                    .headers(headers.clone());
+                match &body {
+                    Some(body) => request.body(body.clone()),
+                    None => request,
+                }
+            })
+            .await?;
+            Ok(Response {
+                status: response.status(),
+                headers: response.headers().clone(),
+                body: response.bytes().await?.to_vec(),
+            })
+        }
+    });
+    results.remove(0)
+}
+
+/// runs `f` for all `urls` with at most the configured number of requests at a time
+fn for_each_url<T, F, Fut>(urls: &[&str], builder: reqwest::ClientBuilder, f: F) -> Vec<T>
+where
+    F: Fn(Client, String) -> Fut,
+    Fut: Future<Output = T>,
+{
+    if urls.is_empty() {
+        return Vec::new();
+    }
+    let concurrency = *CONCURRENCY.get().unwrap_or(&DEFAULT_CONCURRENCY);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Cannot start runtime for fetching URLs");
+    // the settings are validated when they are set
+    let client = configure(builder, http())
+        .and_then(|builder| Ok(builder.build()?))
+        .expect("Cannot create HTTP client");
+    runtime.block_on(
+        stream::iter(urls)
+            .map(|url| f(client.clone(), url.to_string()))
+            .buffered(concurrency.max(1))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn test_parse_details() {
+        let html = r#"<html><head><title> sysid blog </title>
+            <meta name="description" content="Python, Rust">
+            </head><body></body></html>"#;
+        let url = Url::parse("https://sysid.github.io/").unwrap();
+        assert_eq!(
+            parse_details(html, "https://sysid.github.io", &url),
+            Details {
+                title: "sysid blog".to_string(),
+                description: "Python, Rust".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[rstest]
+    #[case("", "https://sysid.github.io/", None)]
+    #[case(
+        "",
+        "https://sysid.github.io/blog/",
+        Some("https://sysid.github.io/blog/")
+    )]
+    #[case(
+        r#"<link rel="canonical" href="/post">"#,
+        "https://sysid.github.io/",
+        Some("https://sysid.github.io/post")
+    )]
+    #[case(
+        r#"<link rel="canonical" href="https://sysid.github.io/">"#,
+        "https://sysid.github.io/?utm_source=x",
+        None
+    )]
+    fn test_parse_details_canonical_url(
+        #[case] head: &str,
+        #[case] final_url: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let html = format!("<html><head>{}</head></html>", head);
+        let url = Url::parse(final_url).unwrap();
+        let details = parse_details(&html, "https://sysid.github.io", &url);
+        assert_eq!(details.canonical_url.as_deref(), expected);
+    }
+
+    #[rstest]
+    fn test_parse_details_card_metadata() {
+        let html = r#"<html><head><title>sysid</title>
+            <meta name="description" content="Python, Rust">
+            <meta property="og:title" content=" sysid blog ">
+            <meta name="twitter:description" content="Posts about Python and Rust">
+            <meta name="twitter:image" content="/img/card.png">
+            </head><body></body></html>"#;
+        let url = Url::parse("https://sysid.github.io/").unwrap();
+        assert_eq!(
+            parse_details(html, "https://sysid.github.io", &url),
+            Details {
+                title: "sysid blog".to_string(),
+                description: "Posts about Python and Rust".to_string(),
+                image_url: Some("https://sysid.github.io/img/card.png".to_string()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[rstest]
+    #[case(
+        r#"<link rel="alternate" type="application/rss+xml" href="/feed.xml">"#,
+        Some("https://sysid.github.io/feed.xml")
+    )]
+    #[case(
+        r#"<link rel="alternate" type="application/atom+xml" href="https://feeds.sysid.de/atom">"#,
+        Some("https://feeds.sysid.de/atom")
+    )]
+    #[case(r#"<link rel="alternate" hreflang="de" href="/de/">"#, None)]
+    #[case(r#"<link rel="stylesheet" type="text/css" href="/style.css">"#, None)]
+    fn test_parse_details_feed_url(#[case] head: &str, #[case] expected: Option<&str>) {
+        let html = format!("<html><head>{}</head></html>", head);
+        let url = Url::parse("https://sysid.github.io/").unwrap();
+        let details = parse_details(&html, "https://sysid.github.io/", &url);
+        assert_eq!(details.feed_url.as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case(
+        "The quick brown fox jumps over the lazy dog and runs to the river of the valley. \
+         It is the end of the story.",
+        Some("en")
+    )]
+    #[case(
+        "Der schnelle braune Fuchs springt über den faulen Hund und läuft zum Fluss. \
+         Das ist das Ende der Geschichte, und die Nacht ist dunkel.",
+        Some("de")
+    )]
+    #[case("", None)]
+    fn test_language(#[case] text: &str, #[case] expected: Option<&str>) {
+        assert_eq!(language(text).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case("http://localhost:8080/a", Some("localhost"))]
+    #[case("https://www.intranet.corp/", Some("*.intranet.corp"))]
+    #[case("https://intranet.corp/", Some("*.intranet.corp"))]
+    #[case("https://docs.Wiki.intranet.corp/", Some("*.wiki.intranet.corp"))]
+    #[case("https://app.intranet.corp/", Some("app.intranet.corp"))]
+    #[case("https://notintranet.corp/", None)]
+    #[case("shell::ls", None)]
+    fn test_matching_policy(#[case] url: &str, #[case] expected: Option<&str>) {
+        let hosts = [
+            "localhost",
+            "*.intranet.corp",
+            "*.wiki.intranet.corp",
+            "app.intranet.corp",
+        ];
+        let policies = host_policies(
+            hosts
+                .iter()
+                .map(|host| {
+                    let settings = HostSettings {
+                        user_agent: Some(host.to_string()),
+                        ..Default::default()
+                    };
+                    (host.to_string(), settings)
+                })
+                .collect(),
+        )
+        .unwrap();
+        let user_agent = matching_policy(&policies, url).map(|policy| &policy.headers[USER_AGENT]);
+        assert_eq!(user_agent.map(|ua| ua.to_str().unwrap()), expected);
+    }
+
+    #[rstest]
+    #[case("http://x.org")]
+    #[case("*.")]
+    #[case("a.*.org")]
+    fn test_host_policies_invalid(#[case] host: &str) {
+        let hosts = BTreeMap::from([(host.to_string(), HostSettings::default())]);
+        assert!(host_policies(hosts).is_err());
+    }
+
+    #[rstest]
+    #[case(r#"headers = { "Bad Name" = "x" }"#)]
+    #[case(r#"headers = { "X-Ok" = "line\nbreak" }"#)]
+    #[case(r#"proxy = "::""#)]
+    #[case(r#"ca_cert = "/no/such/cert.pem""#)]
+    fn test_configure_invalid(#[case] http: &str) {
+        let http: HttpSettings = toml::from_str(http).unwrap();
+        assert!(configure(Client::builder(), &http).is_err());
+    }
+
+    #[rstest]
+    fn test_readable_text() {
+        let html = r#"<html><head><title>t</title><script>var x;</script></head><body>
+            <nav><ul><li>Home</li><li>Blog</li></ul></nav>
+            <article><h1>Rust  in
+            2023</h1><p>Ownership <b>matters</b>.</p><ul><li><p>nested</p></li></ul></article>
+            <footer><p>(c) sysid</p></footer></body></html>"#;
+        assert_eq!(
+            readable_text(html),
+            "Rust in 2023\nOwnership matters.\nnested"
+        );
+        let html = "<body><header><p>menu</p></header><div><p>text</p></div></body>";
+        assert_eq!(readable_text(html), "text");
+    }
+
+    #[rstest]
+    #[case(Canonical::Keep, ("https://a.org", Some("https://b.org")))]
+    #[case(Canonical::Replace, ("https://b.org", None))]
+    #[case(Canonical::Ignore, ("https://a.org", None))]
+    fn test_canonical_apply(#[case] canonical: Canonical, #[case] expected: (&str, Option<&str>)) {
+        let (url, kept) = canonical.apply("https://a.org", Some("https://b.org".to_string()));
+        assert_eq!((url.as_str(), kept.as_deref()), expected);
+    }
+
+    #[rstest]
+    fn test_load_urls_details_unreachable() {
+        let details = load_urls_details(&["http://localhost:1/a", "not a url"]);
+        assert_eq!(details.len(), 2);
+        assert!(details.iter().all(|d| d.is_err()));
+    }
+
+    #[rstest]
+    fn test_check_links_unreachable() {
+        let statuses = check_links(&["http://localhost:1/a"]);
+        assert!(matches!(statuses[..], [LinkStatus::Dead(None, _)]));
+    }
+}
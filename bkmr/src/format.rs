@@ -0,0 +1,418 @@
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{NaiveDateTime, Utc};
+use tracing::debug;
+
+use crate::fetch::FEED_URL;
+use crate::models::Bookmark;
+
+const FIELDS: [&str; 8] = [
+    "id", "url", "title", "tags", "desc", "flags", "ts", "visits",
+];
+const DEFAULT_TS_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const RELATIVE: &str = "relative";
+/// columns in database order, values are escaped
+const TSV: &str = "{id}\t{url}\t{title}\t{tags}\t{desc}\t{flags}\t{ts}";
+
+/// Output template like `{id:>5}\t{url}\t{tags}\t{ts:%Y-%m-%d}`
+///
+/// Placeholders: `{field}` or `{field:spec}` with field one of `id, url, title, tags, desc, flags, ts`
+/// or `extra.<key>` for user defined fields.
+/// spec: `<N`, `>N`, `^N` (pad to width N), `.N` (truncate to N chars) or for `ts` a chrono format
+/// or `relative` (e.g. "3 days ago").
+/// `{{`/`}}` are literal braces, `\t` and `\n` are tab and newline.
+///
+/// Named formats: `tsv` (tab separated, tabs/newlines/backslashes in values escaped as `\t`, `\n`, `\\`)
+#[derive(Debug, PartialEq, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+    escape: Escape,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Escape {
+    None,
+    Tsv,
+    /// values as TOML values: strings quoted, numbers bare
+    Toml,
+    /// values as content of JSON strings, the template quotes them
+    Json,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Segment {
+    Literal(String),
+    Field { name: String, spec: Option<String> },
+}
+
+impl Template {
+    /// named format or template string
+    pub fn from_format(format: &str) -> anyhow::Result<Template> {
+        match format {
+            "tsv" => Ok(Template {
+                escape: Escape::Tsv,
+                ..Template::parse(TSV)?
+            }),
+            _ => Template::parse(format),
+        }
+    }
+
+    /// template of a TOML document, e.g. `title = {title}`: values are rendered as TOML values
+    pub fn toml(template: &str) -> anyhow::Result<Template> {
+        Ok(Template {
+            escape: Escape::Toml,
+            ..Template::parse(template)?
+        })
+    }
+
+    /// template of a JSON document, e.g. `{{"text": "{title} {url}"}}`: values are escaped for
+    /// JSON strings
+    pub fn json(template: &str) -> anyhow::Result<Template> {
+        Ok(Template {
+            escape: Escape::Json,
+            ..Template::parse(template)?
+        })
+    }
+
+    pub fn parse(template: &str) -> anyhow::Result<Template> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '\\' if matches!(chars.peek(), Some('t' | 'n' | '\\')) => {
+                    match chars.next() {
+                        Some('t') => literal.push('\t'),
+                        Some('n') => literal.push('\n'),
+                        _ => literal.push('\\'),
+                    };
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => {
+                                return Err(anyhow!("Unclosed '{{' in template {:?}", template))
+                            }
+                        }
+                    }
+                    let (name, spec) = match placeholder.split_once(':') {
+                        Some((name, spec)) => (name.trim(), Some(spec.to_string())),
+                        None => (placeholder.trim(), None),
+                    };
+                    if !FIELDS.contains(&name) && !name.starts_with("extra.") {
+                        return Err(anyhow!(
+                            "Unknown field {{{}}}, valid fields: {}, extra.<key>",
+                            name,
+                            FIELDS.join(", ")
+                        ));
+                    }
+                    if let Some(spec) = spec.as_deref().filter(|spec| spec.contains('%')) {
+                        check_time_format(spec)?;
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field {
+                        name: name.to_string(),
+                        spec,
+                    });
+                }
+                '}' => return Err(anyhow!("Unmatched '}}' in template {:?}", template)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        debug!("{:?}", segments);
+        Ok(Template {
+            segments,
+            escape: Escape::None,
+        })
+    }
+
+    pub fn render(&self, bm: &Bookmark) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(s) => s.to_owned(),
+                Segment::Field { name, spec } => {
+                    let value = render_field(bm, name, spec.as_deref());
+                    match self.escape {
+                        Escape::None => value,
+                        Escape::Tsv => escape(&value),
+                        Escape::Toml if ["id", "flags", "visits"].contains(&name.as_str()) => value,
+                        Escape::Toml => toml::Value::String(value).to_string(),
+                        Escape::Json => {
+                            let quoted = serde_json::Value::String(value).to_string();
+                            quoted[1..quoted.len() - 1].to_string()
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+fn render_field(bm: &Bookmark, name: &str, spec: Option<&str>) -> String {
+    let value = match name {
+        "id" => bm.id.to_string(),
+        "url" => bm.URL.to_owned(),
+        "title" => bm.metadata.to_owned(),
+        "tags" => bm.get_tags().join(","),
+        "desc" => bm.desc.to_owned(),
+        "flags" => bm.flags.to_string(),
+        "visits" => bm.visits.to_string(),
+        "ts" => match spec {
+            Some(RELATIVE) => relative_time(&bm.last_update_ts, &Utc::now().naive_utc()),
+            Some(spec) if spec.contains('%') => bm.last_update_ts.format(spec).to_string(),
+            _ => bm.last_update_ts.format(DEFAULT_TS_FORMAT).to_string(),
+        },
+        _ => name
+            .strip_prefix("extra.")
+            .and_then(|key| bm.get_extra_value(key))
+            .unwrap_or_default(),
+    };
+    match spec {
+        Some(spec) if !spec.contains('%') && spec != RELATIVE => apply_spec(&value, spec),
+        _ => value,
+    }
+}
+
+/// fails on chrono formats with unknown or incomplete specifiers, e.g. `%Q`
+fn check_time_format(format: &str) -> anyhow::Result<()> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(anyhow!("Invalid time format {:?}", format));
+    }
+    Ok(())
+}
+
+static TIME_FORMAT: OnceLock<String> = OnceLock::new();
+
+/// sets the process wide format of timestamps in listings (config `display.time_format`),
+/// only the first call has an effect. Fails on invalid chrono formats
+pub fn set_time_format(format: &str) -> anyhow::Result<()> {
+    if format != RELATIVE {
+        check_time_format(format)?;
+    }
+    let _ = TIME_FORMAT.set(format.to_string());
+    Ok(())
+}
+
+/// `ts` (UTC) in the configured format, relative to now by default
+pub fn format_ts(ts: &NaiveDateTime) -> String {
+    match TIME_FORMAT.get().map(String::as_str) {
+        Some(RELATIVE) | None => relative_time(ts, &Utc::now().naive_utc()),
+        Some(format) => ts.format(format).to_string(),
+    }
+}
+
+/// e.g. "just now", "1 hour ago", "3 days ago"
+pub fn relative_time(ts: &NaiveDateTime, now: &NaiveDateTime) -> String {
+    let seconds = (*now - *ts).num_seconds();
+    let (n, unit) = match seconds {
+        s if s < 60 => return String::from("just now"),
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 86400 => (s / 3600, "hour"),
+        s if s < 30 * 86400 => (s / 86400, "day"),
+        s if s < 365 * 86400 => (s / (30 * 86400), "month"),
+        s => (s / (365 * 86400), "year"),
+    };
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// pads or truncates `value` according to `spec`, invalid specs are ignored
+fn apply_spec(value: &str, spec: &str) -> String {
+    let (kind, width) = match spec.chars().next() {
+        Some(c @ ('<' | '>' | '^' | '.')) => (c, &spec[1..]),
+        _ => ('<', spec),
+    };
+    let Ok(width) = width.parse::<usize>() else {
+        return value.to_string();
+    };
+    match kind {
+        '.' => value.chars().take(width).collect(),
+        '>' => format!("{:>width$}", value),
+        '^' => format!("{:^width$}", value),
+        _ => format!("{:<width$}", value),
+    }
+}
+
+/// OPML document of the feeds in `extra.feed_url` of `bms` for feed readers,
+/// bookmarks without feed are skipped
+pub fn opml(bms: &[Bookmark]) -> String {
+    let mut opml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <opml version=\"2.0\">\n  <head>\n    <title>bkmr feeds</title>\n  </head>\n  <body>\n",
+    );
+    for bm in bms {
+        let Some(feed_url) = bm.get_extra_value(FEED_URL) else {
+            continue;
+        };
+        let title = match bm.metadata.trim() {
+            "" => &bm.URL,
+            title => title,
+        };
+        opml.push_str(&format!(
+            "    <outline type=\"rss\" text=\"{0}\" title=\"{0}\" xmlUrl=\"{1}\" htmlUrl=\"{2}\"/>\n",
+            xml_escape(title),
+            xml_escape(&feed_url),
+            xml_escape(&bm.URL)
+        ));
+    }
+    opml.push_str("  </body>\n</opml>\n");
+    opml
+}
+
+/// escapes text for XML and HTML content and attribute values
+pub(crate) fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    fn bm() -> Bookmark {
+        let mut bm = Bookmark {
+            id: 7,
+            URL: String::from("https://www.sysid.de"),
+            metadata: String::from("sysid blog"),
+            tags: String::from(",aaa,xxx,"),
+            desc: String::from("a description"),
+            last_update_ts: NaiveDate::from_ymd_opt(2023, 4, 30)
+                .unwrap()
+                .and_hms_opt(9, 10, 11)
+                .unwrap(),
+            ..Default::default()
+        };
+        bm.set_extra_value("author", "sysid");
+        bm
+    }
+
+    #[rstest]
+    #[case("{id}\\t{url}\\t{tags}", "7\thttps://www.sysid.de\taaa,xxx")]
+    #[case("{id:>3}|{title:<12}|", "  7|sysid blog  |")]
+    #[case("{title:.5}", "sysid")]
+    #[case("{ts}", "2023-04-30 09:10:11")]
+    #[case("{ts:%Y-%m-%d}", "2023-04-30")]
+    #[case("{extra.author} {extra.missing}.", "sysid .")]
+    #[case("{{{id}}}", "{7}")]
+    #[case("{visits}", "0")]
+    fn test_render(bm: Bookmark, #[case] template: &str, #[case] expected: &str) {
+        let template = Template::parse(template).unwrap();
+        assert_eq!(template.render(&bm), expected);
+    }
+
+    #[rstest]
+    fn test_tsv(mut bm: Bookmark) {
+        bm.desc = String::from("line1\nline2\twith tab\\");
+        let template = Template::from_format("tsv").unwrap();
+        assert_eq!(
+            template.render(&bm),
+            "7\thttps://www.sysid.de\tsysid blog\taaa,xxx\tline1\\nline2\\twith tab\\\\\t0\t2023-04-30 09:10:11"
+        );
+    }
+
+    #[rstest]
+    #[case(0, "just now")]
+    #[case(59, "just now")]
+    #[case(60, "1 minute ago")]
+    #[case(7200, "2 hours ago")]
+    #[case(3 * 86400 + 5, "3 days ago")]
+    #[case(45 * 86400, "1 month ago")]
+    #[case(800 * 86400, "2 years ago")]
+    fn test_relative_time(#[case] seconds: i64, #[case] expected: &str) {
+        let ts = NaiveDate::from_ymd_opt(2023, 4, 30)
+            .unwrap()
+            .and_hms_opt(9, 10, 11)
+            .unwrap();
+        let now = ts + chrono::Duration::seconds(seconds);
+        assert_eq!(relative_time(&ts, &now), expected);
+    }
+
+    #[rstest]
+    fn test_toml(mut bm: Bookmark) {
+        bm.desc = String::from("line1\n\"line2\"");
+        let template = Template::toml("id = {id}\n# {title}\ndescription = {desc}\n").unwrap();
+        let rendered = template.render(&bm);
+        println!("{}", rendered);
+        assert!(
+            rendered.starts_with("id = 7\n# \"sysid blog\"\n"),
+            "{}",
+            rendered
+        );
+        let value: toml::Table = toml::from_str(&rendered).unwrap();
+        assert_eq!(value["description"].as_str(), Some("line1\n\"line2\""));
+    }
+
+    #[rstest]
+    fn test_json(mut bm: Bookmark) {
+        bm.desc = String::from("line1\n\"line2\"");
+        let template = Template::json(r#"{{"text": "{title}: {desc}", "id": {id}}}"#).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&template.render(&bm)).unwrap();
+        assert_eq!(value["text"], "sysid blog: line1\n\"line2\"");
+        assert_eq!(value["id"], 7);
+    }
+
+    #[rstest]
+    fn test_opml(mut bm: Bookmark) {
+        let without_feed = bm.clone();
+        bm.metadata = String::from("sysid <blog> & more");
+        bm.set_extra_value(FEED_URL, "https://www.sysid.de/feed.xml?a=1&b=2");
+        let opml = opml(&[bm, without_feed]);
+        assert_eq!(
+            opml,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <opml version=\"2.0\">\n  <head>\n    <title>bkmr feeds</title>\n  </head>\n  <body>\n    \
+             <outline type=\"rss\" text=\"sysid &lt;blog&gt; &amp; more\" title=\"sysid &lt;blog&gt; &amp; more\" \
+             xmlUrl=\"https://www.sysid.de/feed.xml?a=1&amp;b=2\" htmlUrl=\"https://www.sysid.de\"/>\n  \
+             </body>\n</opml>\n"
+        );
+    }
+
+    #[rstest]
+    #[case("{unknown}")]
+    #[case("{id}}")]
+    #[case("{id")]
+    #[case("{id} {ts:%Q}")]
+    #[case("{ts:%Y-%}")]
+    fn test_parse_invalid(#[case] template: &str) {
+        assert!(Template::parse(template).is_err());
+    }
+
+    #[rstest]
+    fn test_set_time_format_invalid() {
+        assert!(set_time_format("%Y-%Q").is_err());
+    }
+}
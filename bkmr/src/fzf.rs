@@ -1,128 +1,559 @@
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::iter;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, OnceLock};
+use anyhow::{anyhow, bail, Context};
 use arboard::Clipboard;
+use inquire::{Confirm, Select};
 use itertools::Itertools; // Import the itertools trait
 
 
 use crossterm::{execute, terminal::{Clear, ClearType}};
-use log::debug;
+use tracing::debug;
 use skim::{
     AnsiString, DisplayContext, ItemPreview, PreviewContext, Skim, SkimItem, SkimItemReceiver,
     SkimItemSender,
 };
 use skim::prelude::*;
-use stdext::function_name;
 use tuikit::prelude::*;
 
 use crate::environment::{CONFIG, FzfEnvOpts};
+use crate::format::format_ts;
 use crate::models::Bookmark;
-use crate::process::{edit_bms, open_bms};
-use crate::tag::Tags;
+use crate::process::{delete_bms, domain, edit_bms, open_bms};
 
 impl SkimItem for Bookmark {
     fn text(&self) -> Cow<str> {
-        let FzfEnvOpts {
-            show_tags,
-            ..
-        } = &CONFIG.fzf_opts;
-
-        let _text = match show_tags {
-            false => format!("[{}] {}, {}", self.id, self.metadata, self.URL),
-            true => {
-                format!(
-                    "[{}] {}, {}, {}",
-                    self.id,
-                    Tags::change_tag_string_delimiter(&(self.tags), " | "),
-                    self.metadata,
-                    self.URL
+        Cow::Owned(line(self, columns()).0)
+    }
+
+    fn display<'a>(&'a self, _context: DisplayContext<'a>) -> AnsiString<'a> {
+        let (text, fragments) = line(self, columns());
+        AnsiString::new_string(text, fragments)
+    }
+
+    fn preview(&self, context: PreviewContext) -> ItemPreview {
+        ItemPreview::AnsiText(preview_text(self, context.width))
+    }
+}
+
+/// fields of the picker lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Column {
+    Id,
+    Title,
+    Url,
+    /// host of the URL, much shorter on narrow terminals
+    Domain,
+    Tags,
+}
+
+impl Column {
+    /// column of its name in `[fzf] columns`
+    fn from_name(name: &str) -> Option<Column> {
+        match name {
+            "id" => Some(Column::Id),
+            "title" => Some(Column::Title),
+            "url" => Some(Column::Url),
+            "domain" => Some(Column::Domain),
+            "tags" => Some(Column::Tags),
+            _ => None,
+        }
+    }
+
+    fn color(&self) -> Option<Color> {
+        match self {
+            Column::Id => None,
+            Column::Title => Some(Color::GREEN),
+            Column::Url | Column::Domain => Some(Color::YELLOW),
+            Column::Tags => Some(Color::LIGHT_MAGENTA),
+        }
+    }
+}
+
+static COLUMNS: OnceLock<Vec<Column>> = OnceLock::new();
+static COLOR: OnceLock<String> = OnceLock::new();
+
+/// sets the fields of the picker lines in their order, e.g. `["id", "domain", "title"]`,
+/// only the first call has an effect. Empty keeps the default. Fails on unknown fields.
+pub fn set_columns(columns: &[String]) -> anyhow::Result<()> {
+    if columns.is_empty() {
+        return Ok(());
+    }
+    let columns = columns
+        .iter()
+        .map(|name| {
+            Column::from_name(name.trim()).ok_or_else(|| {
+                anyhow!(
+                    "Unknown picker column {:?}, expected one of id, title, url, domain, tags",
+                    name
                 )
-            }
+            })
+        })
+        .collect::<anyhow::Result<Vec<Column>>>()?;
+    let _ = COLUMNS.set(columns);
+    Ok(())
+}
+
+/// sets skim's color scheme in its `--color` syntax, e.g. "light" or "dark,matched:108",
+/// only the first call has an effect
+pub fn set_color(color: &str) {
+    let _ = COLOR.set(color.to_string());
+}
+
+/// configured columns, by default id, title and URL with the tags after the id if $BKMR_FZF_OPTS shows them
+fn columns() -> &'static [Column] {
+    COLUMNS.get_or_init(|| match CONFIG.fzf_opts.show_tags {
+        false => vec![Column::Id, Column::Title, Column::Url],
+        true => vec![Column::Id, Column::Tags, Column::Title, Column::Url],
+    })
+}
+
+/// colored char ranges of a line
+type Fragments = Vec<(Attr, (u32, u32))>;
+
+/// picker line of `bm` and the colored ranges of its fields: "[id] field, field, ..."
+fn line(bm: &Bookmark, columns: &[Column]) -> (String, Fragments) {
+    let mut text = String::new();
+    let mut fragments = Vec::new();
+    for (i, column) in columns.iter().enumerate() {
+        if i > 0 {
+            text.push_str(if columns[i - 1] == Column::Id {
+                " "
+            } else {
+                ", "
+            });
+        }
+        let field = match column {
+            Column::Id => format!("[{}]", bm.id),
+            Column::Title => bm.metadata.clone(),
+            Column::Url => bm.URL.clone(),
+            Column::Domain => domain(&bm.URL),
+            Column::Tags => bm.get_tags().join(" | "),
         };
-        Cow::Owned(_text)
-        // Cow::Borrowed(_text.as_str())
-    }
-
-    fn display<'a>(&'a self, context: DisplayContext<'a>) -> AnsiString<'a> {
-        let FzfEnvOpts {
-            show_tags,
-            ..
-        } = &CONFIG.fzf_opts;
-
-        let start_idx_tags = self.id.to_string().len() + 2;
-        let end_idx_tags = match show_tags {
-            false => 0,
-            true => {
-                let tags = Tags::change_tag_string_delimiter(&(self.tags), " | ");
-                start_idx_tags + tags.len() + 1
+        // ranges are in chars
+        let start = text.chars().count() as u32;
+        text.push_str(&field);
+        if let Some(fg) = column.color() {
+            let attr = Attr {
+                fg,
+                ..Attr::default()
+            };
+            fragments.push((attr, (start, text.chars().count() as u32)));
+        }
+    }
+    (text, fragments)
+}
+
+/// matcher of the bookmark picker: words `#tag` or `t:tag` of the query keep the bookmarks
+/// with a tag starting with `tag`, the rest of the query is matched by skim's default engine
+struct TagQueryEngineFactory {
+    inner: AndOrEngineFactory,
+}
+
+impl TagQueryEngineFactory {
+    fn new() -> Self {
+        TagQueryEngineFactory {
+            inner: AndOrEngineFactory::new(ExactOrFuzzyEngineFactory::builder().build()),
+        }
+    }
+}
+
+impl MatchEngineFactory for TagQueryEngineFactory {
+    fn create_engine_with_case(&self, query: &str, case: CaseMatching) -> Box<dyn MatchEngine> {
+        let (tags, rest) = split_tag_query(query);
+        Box::new(TagQueryEngine {
+            tags,
+            all: rest.is_empty(),
+            inner: self.inner.create_engine_with_case(&rest, case),
+        })
+    }
+}
+
+struct TagQueryEngine {
+    tags: Vec<String>,
+    /// no query besides the tags
+    all: bool,
+    inner: Box<dyn MatchEngine>,
+}
+
+impl MatchEngine for TagQueryEngine {
+    fn match_item(&self, item: Arc<dyn SkimItem>) -> Option<MatchResult> {
+        if self.tags.is_empty() {
+            return self.inner.match_item(item);
+        }
+        let bm = (*item).as_any().downcast_ref::<Bookmark>()?;
+        if !has_tags(bm, &self.tags) {
+            return None;
+        }
+        if self.all {
+            // equal ranks keep the order of the bookmarks
+            return Some(MatchResult {
+                rank: [0; 4],
+                matched_range: MatchRange::ByteRange(0, 0),
+            });
+        }
+        self.inner.match_item(item)
+    }
+}
+
+impl fmt::Display for TagQueryEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(Tags: {:?} {})", self.tags, self.inner)
+    }
+}
+
+/// the lowercase tags of the words `#tag` and `t:tag` of `query` and the rest of it
+pub(crate) fn split_tag_query(query: &str) -> (Vec<String>, String) {
+    let mut tags = Vec::new();
+    let mut rest = Vec::new();
+    for word in query.split_whitespace() {
+        match word.strip_prefix('#').or_else(|| word.strip_prefix("t:")) {
+            Some(tag) if !tag.is_empty() => tags.push(tag.to_lowercase()),
+            // a lone "#" is being typed
+            Some(_) => {}
+            None => rest.push(word),
+        }
+    }
+    (tags, rest.join(" "))
+}
+
+/// whether every one of `tags` starts a tag of `bm`
+pub(crate) fn has_tags(bm: &Bookmark, tags: &[String]) -> bool {
+    let bm_tags: Vec<String> = bm.get_tags().iter().map(|t| t.to_lowercase()).collect();
+    tags.iter()
+        .all(|tag| bm_tags.iter().any(|t| t.starts_with(tag.as_str())))
+}
+
+/// the full bookmark for the preview pane, the description wrapped to `width`
+fn preview_text(bm: &Bookmark, width: usize) -> String {
+    let mut lines = vec![
+        format!("\x1b[32m{}\x1b[m [{}]", bm.metadata, bm.id),
+        format!("\x1b[33m{}\x1b[m", bm.URL),
+        String::new(),
+    ];
+    if !bm.desc.trim().is_empty() {
+        lines.extend(wrap(bm.desc.trim(), width));
+        lines.push(String::new());
+    }
+    lines.push(format!("Tags:    \x1b[95m{}\x1b[m", bm.get_tags().join(" ")));
+    lines.push(format!("Updated: {}", format_ts(&bm.last_update_ts)));
+    if let Some(last_visited) = &bm.last_visited {
+        lines.push(format!("Visited: {} ({} times)", format_ts(last_visited), bm.visits));
+    }
+    lines.join("\n")
+}
+
+/// lines of at most `width` chars broken at blanks, longer words are not broken
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
             }
-        };
-        let attr_tags = Attr {
-            fg: Color::LIGHT_MAGENTA,
-            ..Attr::default()
-        };
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
 
-        let start_idx_metadata = match show_tags {
-            false => self.id.to_string().len() + 2,
-            true => end_idx_tags + 1
-        };
-        let end_idx_metadata = start_idx_metadata + self.metadata.len() + 1;
-        let attr_metadata = Attr {
-            fg: Color::GREEN,
-            // bg: Color::Rgb(5, 10, 15),
-            ..Attr::default()
-        };
+/// actions on the bookmarks selected in the picker
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FzfAction {
+    Open,
+    Edit,
+    Delete,
+    Yank,
+    PrintIds,
+    /// asks for one of the other actions
+    Menu,
+    /// replaces the bookmarks by the result of the query typed in the picker
+    Reload,
+}
 
-        let start_idx_url = end_idx_metadata + 1;
-        let end_idx_url = start_idx_url + self.URL.len() + 1;
-        let attr_url = Attr {
-            fg: Color::YELLOW,
-            ..Attr::default()
+impl FzfAction {
+    /// action of its name in `[fzf.keys]`
+    fn from_name(name: &str) -> Option<FzfAction> {
+        match name {
+            "open" => Some(FzfAction::Open),
+            "edit" => Some(FzfAction::Edit),
+            "delete" => Some(FzfAction::Delete),
+            "yank" => Some(FzfAction::Yank),
+            "ids" => Some(FzfAction::PrintIds),
+            "menu" => Some(FzfAction::Menu),
+            "reload" => Some(FzfAction::Reload),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FzfAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            FzfAction::Open => "open all",
+            FzfAction::Edit => "edit",
+            FzfAction::Delete => "delete",
+            FzfAction::Yank => "copy URLs",
+            FzfAction::PrintIds => "print ids",
+            FzfAction::Menu => "more actions",
+            FzfAction::Reload => "search database",
         };
+        f.write_str(label)
+    }
+}
+
+/// keys of the actions unless configured otherwise, Enter always opens
+const DEFAULT_KEYS: [(&str, FzfAction); 5] = [
+    ("ctrl-e", FzfAction::Edit),
+    ("ctrl-o", FzfAction::Yank),
+    ("ctrl-d", FzfAction::Delete),
+    ("ctrl-a", FzfAction::Menu),
+    ("ctrl-r", FzfAction::Reload),
+];
 
-        match show_tags {
-            false => AnsiString::new_str(
-                context.text,
-                vec![
-                    (
-                        attr_metadata,
-                        (start_idx_metadata as u32, end_idx_metadata as u32),
-                    ),
-                    (attr_url, (start_idx_url as u32, end_idx_url as u32)),
-                ],
-            ),
-            true => AnsiString::new_str(
-                context.text,
-                vec![
-                    (attr_tags, (start_idx_tags as u32, end_idx_tags as u32)),
-                    (
-                        attr_metadata,
-                        (start_idx_metadata as u32, end_idx_metadata as u32),
-                    ),
-                    (attr_url, (start_idx_url as u32, end_idx_url as u32)),
-                ],
+static KEYS: OnceLock<Vec<(String, FzfAction)>> = OnceLock::new();
+
+/// sets the keys of picker actions on top of the defaults, e.g. `open = "ctrl-o"`,
+/// only the first call has an effect. Fails on unknown actions or keys.
+pub fn set_keys(keys: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let _ = KEYS.set(keybindings(keys)?);
+    Ok(())
+}
+
+fn keybindings(keys: &BTreeMap<String, String>) -> anyhow::Result<Vec<(String, FzfAction)>> {
+    let mut bindings: Vec<(String, FzfAction)> = DEFAULT_KEYS
+        .iter()
+        .map(|(key, action)| (key.to_string(), *action))
+        .collect();
+    for (name, key) in keys {
+        let action = FzfAction::from_name(name).ok_or_else(|| {
+            anyhow!(
+                "Unknown picker action {:?}, expected one of open, edit, delete, yank, ids, menu, reload",
+                name
             )
+        })?;
+        let key = key.trim().to_lowercase();
+        if parse_key(&key).is_none() {
+            bail!(
+                "Invalid key {:?} of picker action {}, e.g. ctrl-d, alt-d or f2",
+                key,
+                name
+            );
+        }
+        // the configured key replaces the default key of the action and the default action of the key
+        bindings.retain(|(k, a)| *a != action && *k != key);
+        bindings.push((key, action));
+    }
+    Ok(bindings)
+}
+
+/// key in skim's `--bind` syntax: ctrl-<char>, alt-<char> or f<1-12>
+fn parse_key(key: &str) -> Option<Key> {
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphanumeric() => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = key.strip_prefix("ctrl-") {
+        // ctrl-i and ctrl-m arrive as Tab and Enter
+        return single(c).filter(|c| !matches!(c, 'i' | 'm')).map(Key::Ctrl);
+    }
+    if let Some(c) = key.strip_prefix("alt-") {
+        return single(c).map(Key::Alt);
+    }
+    match key.strip_prefix('f')?.parse::<u8>() {
+        Ok(n @ 1..=12) => Some(Key::F(n)),
+        _ => None,
+    }
+}
+
+/// picks bookmarks of `bms` and runs an action on them, the picker starts with `query`.
+/// The reload key replaces `bms` by `reload` of the current query, e.g. a full-text search.
+pub fn fzf_process(
+    bms: &[Bookmark],
+    query: Option<&str>,
+    reload: impl Fn(&str) -> anyhow::Result<Vec<Bookmark>>,
+) {
+    let keys = KEYS.get_or_init(|| keybindings(&BTreeMap::new()).expect("default keys"));
+    if let Some(picked) = run_picker(bms, query, reload, keys, "Enter: open", false) {
+        act(picked, keys);
+    }
+    execute!(std::io::stdout(), Clear(ClearType::FromCursorDown)).unwrap();
+}
+
+/// picks a tag of `bms`, then bookmarks with this tag and runs an action on them.
+/// Aborting the bookmark picker, e.g. by Backspace on an empty query, goes back to the tags.
+pub fn fzf_tags(bms: &[Bookmark]) {
+    // the tag is the filter, no reload by full-text search
+    let keys: Vec<(String, FzfAction)> = KEYS
+        .get_or_init(|| keybindings(&BTreeMap::new()).expect("default keys"))
+        .iter()
+        .filter(|(_, action)| *action != FzfAction::Reload)
+        .cloned()
+        .collect();
+    let tags: Vec<String> = tag_counts(bms)
+        .iter()
+        .map(|(tag, n)| format!("{} ({})", tag, n))
+        .collect();
+    while let Some(line) = pick_line(&tags, "tag> ", "Enter: show bookmarks, ESC: quit") {
+        let Some((tag, _)) = line.rsplit_once(" (") else {
+            break;
+        };
+        let tagged: Vec<Bookmark> = bms
+            .iter()
+            .filter(|bm| bm.get_tags().iter().any(|t| t == tag))
+            .cloned()
+            .collect();
+        let no_reload = |_: &str| Err(anyhow!("no reload"));
+        if let Some(picked) = run_picker(&tagged, None, no_reload, &keys, "Enter: open", true) {
+            act(picked, &keys);
+            break;
         }
     }
+    execute!(std::io::stdout(), Clear(ClearType::FromCursorDown)).unwrap();
+}
 
-    fn preview(&self, _context: PreviewContext) -> ItemPreview {
-        let text = format!("[{}] {}, {}", &self.id, &self.metadata, &self.URL);
-        ItemPreview::AnsiText(format!("\x1b[31mhello:\x1b[m\n{}", text))
+/// tags of `bms` with their number of bookmarks, the most frequent first
+pub(crate) fn tag_counts(bms: &[Bookmark]) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for tag in bms.iter().flat_map(|bm| bm.get_tags()) {
+        *counts.entry(tag).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .sorted_by(|(t1, n1), (t2, n2)| n2.cmp(n1).then(t1.cmp(t2)))
+        .collect()
+}
+
+/// runs the action of the key which ended the picker
+fn act(picked: Picked, keys: &[(String, FzfAction)]) {
+    let action = match picked.key {
+        Key::Enter => Some(FzfAction::Open),
+        _ => keys
+            .iter()
+            .find(|(key, _)| parse_key(key).as_ref() == Some(&picked.key))
+            .map(|(_, action)| *action),
+    };
+    debug!("{:?}: {:?}", picked.key, action);
+    if let Some(action) = action {
+        print_selected(&picked.bms);
+        run_action(action, picked.bms).unwrap_or_else(|e| {
+            eprintln!("{:?}", e);
+        });
+    }
+}
+
+/// picks bookmarks of `bms` like `fzf_process` and returns them instead of running an action,
+/// e.g. to print them for scripts. `None` if the picker is aborted.
+pub fn fzf_select(
+    bms: &[Bookmark],
+    query: Option<&str>,
+    reload: impl Fn(&str) -> anyhow::Result<Vec<Bookmark>>,
+) -> Option<Vec<Bookmark>> {
+    let keys: Vec<(String, FzfAction)> = KEYS
+        .get_or_init(|| keybindings(&BTreeMap::new()).expect("default keys"))
+        .iter()
+        .filter(|(_, action)| *action == FzfAction::Reload)
+        .cloned()
+        .collect();
+    let picked = run_picker(bms, query, reload, &keys, "Enter: select", false)?;
+    match picked.key {
+        Key::Enter => Some(picked.bms),
+        _ => None,
+    }
+}
+
+/// result of the picker: the key which ended it, the typed query and the selected bookmarks
+struct Picked {
+    key: Key,
+    query: String,
+    bms: Vec<Bookmark>,
+}
+
+/// runs the picker until a key other than the reload key ends it, `keys` accept the selection.
+/// `None` if it is aborted, with `back` also by Backspace on an empty query.
+fn run_picker(
+    bms: &[Bookmark],
+    query: Option<&str>,
+    reload: impl Fn(&str) -> anyhow::Result<Vec<Bookmark>>,
+    keys: &[(String, FzfAction)],
+    enter: &str,
+    back: bool,
+) -> Option<Picked> {
+    let header = iter::once(format!("TAB: select, {}", enter))
+        .chain(back.then(|| "BSpace: back".to_string()))
+        .chain(
+            keys.iter()
+                .map(|(key, action)| format!("{}: {}", key, action)),
+        )
+        .join(", ");
+
+    let mut bms = bms.to_vec();
+    let mut query = query.unwrap_or_default().to_string();
+    loop {
+        let picked = match EXTERNAL.get() {
+            Some(command) => run_external(command, &bms, &query, keys, &header, back)
+                .unwrap_or_else(|e| {
+                    eprintln!("{:?}", e);
+                    None
+                })?,
+            None => run_skim(&bms, &query, keys, &header, back)?,
+        };
+        if !is_reload(keys, &picked.key) {
+            return Some(picked);
+        }
+        query = picked.query;
+        match reload(&query) {
+            Ok(reloaded) => bms = reloaded,
+            // e.g. a query which is no valid FTS syntax, the picker stays as it is
+            Err(e) => debug!("Cannot reload {:?}: {:?}", query, e),
+        }
     }
 }
 
-pub fn fzf_process(bms: &Vec<Bookmark>) {
+/// the embedded skim
+fn run_skim(
+    bms: &[Bookmark],
+    query: &str,
+    keys: &[(String, FzfAction)],
+    header: &str,
+    back: bool,
+) -> Option<Picked> {
     let FzfEnvOpts {
         reverse,
         height,
+        preview_window,
         ..
     } = &CONFIG.fzf_opts;
-
+    let bind: Vec<String> = keys
+        .iter()
+        .map(|(key, _)| format!("{}:accept", key))
+        .chain(back.then(|| "bspace:if-query-empty(abort)+backward-delete-char".to_string()))
+        .collect();
     let options = SkimOptionsBuilder::default()
         .reverse(reverse.to_owned())
-        .height(Some(&height))
+        .height(Some(height))
         .multi(true)
+        .query(Some(query))
+        // an empty preview command shows `SkimItem::preview` of the current bookmark
+        .preview(Some(""))
+        .preview_window(Some(preview_window))
+        .color(COLOR.get().map(String::as_str))
+        .engine_factory(Some(Rc::new(TagQueryEngineFactory::new())))
         // For full list of accepted keywords see `parse_event` in `src/event.rs`.
-        .bind(vec!["ctrl-o:accept", "ctrl-e:accept"])
+        .bind(bind.iter().map(String::as_str).collect())
+        .header(Some(header))
         .build()
         .unwrap();
 
@@ -133,91 +564,305 @@ pub fn fzf_process(bms: &Vec<Bookmark>) {
     }
     drop(tx_item); // so that skim could know when to stop waiting for more items.
 
-    let mut stdout = std::io::stdout();
-    Skim::run_with(&options, Some(rx_item)).map(|out| match out.final_key {
-        Key::Ctrl('e') => {
-            let filtered = filter_bms(out);
-            // id selection not necessary since all bms are filtered, just open all bms
-            let ids = (1..=filtered.len()).map(|i| i as i32).collect();
-            debug!(
-                "({}:{}) {:?}, {:?}",
-                function_name!(),
-                line!(),
-                ids,
-                filtered
-            );
-            edit_bms(ids, filtered).unwrap_or_else(|e| {
-                debug!("{}: {}", function_name!(), e);
-            });
-            // clear screen
-            // let mut stdout = std::io::stdout();
-            execute!(stdout, Clear(ClearType::FromCursorDown)).unwrap();
-        }
-        Key::Ctrl('o') => {
-            let filtered = filter_bms(out);
-            // id selection not necessary since all bms are filtered, just open all bms
-            let ids: Vec<i32> = (1..=filtered.len()).map(|i| i as i32).collect();
-            debug!(
-                "({}:{}) {:?}, {:?}",
-                function_name!(),
-                line!(),
-                ids,
-                filtered
-            );
-            // open_bms(ids, filtered).unwrap_or_else(|e| {
-            //     debug!("{}: {}", function_name!(), e);
-            // });
-            // Change this part to copy the bookmark URLs to the clipboard using the arboard crate
-            let mut clipboard = Clipboard::new().unwrap();
-            let urls = filtered
-                .iter()
-                .map(|bm| &bm.URL)
-                .join("\n");
-            clipboard.set_text(urls).unwrap_or_else(|e| {
-                debug!("{}: {}", function_name!(), e);
-            });
-            println!("Copied URLs to clipboard");
-            // let mut stdout = std::io::stdout();
-            execute!(stdout, Clear(ClearType::FromCursorDown)).unwrap();
-        }
-        Key::Enter => {
-            let filtered = filter_bms(out);
-            // id selection not necessary since all bms are filtered, just open all bms
-            let ids: Vec<i32> = (1..=filtered.len()).map(|i| i as i32).collect();
-            debug!(
-                "({}:{}) {:?}, {:?}",
-                function_name!(),
-                line!(),
-                ids,
-                filtered
-            );
-            open_bms(ids, filtered).unwrap_or_else(|e| {
-                debug!("{}: {}", function_name!(), e);
-            });
-            // let mut stdout = std::io::stdout();
-            execute!(stdout, Clear(ClearType::FromCursorDown)).unwrap();
-        }
-        Key::ESC => {
-            debug!("({}:{}) Esc", function_name!(), line!());
-            // let mut stdout = std::io::stdout();
-            execute!(stdout, Clear(ClearType::FromCursorDown)).unwrap();
-        }
-        _ => (),
-    });
-}
-
-fn filter_bms(out: SkimOutput) -> Vec<Bookmark> {
-    debug!(
-        "({}:{}) query: {:?} cmd: {:?}",
-        function_name!(),
-        line!(),
-        out.query,
-        out.cmd
-    );
-
-    out.selected_items.iter().for_each(|i| {
-        println!("{}{}", i.output(), "\n");
-    });
+    let out = Skim::run_with(&options, Some(rx_item))?;
+    if out.is_abort {
+        return None;
+    }
+    debug!("query: {:?} cmd: {:?}", out.query, out.cmd);
+    Some(Picked {
+        bms: selected_bms(&out),
+        key: out.final_key,
+        query: out.query,
+    })
+}
+
+static EXTERNAL: OnceLock<Vec<String>> = OnceLock::new();
+
+/// sets the command line of an installed fzf used instead of the embedded skim, e.g. "fzf --exact",
+/// only the first call has an effect. Its layout and keys come from $FZF_DEFAULT_OPTS.
+pub fn set_external(command: &str) -> anyhow::Result<()> {
+    match shlex::split(command) {
+        Some(command) if !command.is_empty() => {
+            let _ = EXTERNAL.set(command);
+            Ok(())
+        }
+        _ => bail!("Invalid fzf command {:?}", command),
+    }
+}
+
+/// runs the external fzf with `bms` as lines "<id>\t<colored picker line>", only the picker line is shown.
+/// `None` if it is aborted.
+fn run_external(
+    command: &[String],
+    bms: &[Bookmark],
+    query: &str,
+    keys: &[(String, FzfAction)],
+    header: &str,
+    back: bool,
+) -> anyhow::Result<Option<Picked>> {
+    let mut fzf = Command::new(&command[0]);
+    fzf.args(&command[1..])
+        .args([
+            "--multi",
+            "--ansi",
+            "--print-query",
+            "--delimiter",
+            "\t",
+            "--with-nth",
+            "2..",
+        ])
+        .arg(format!("--query={}", query))
+        .arg(format!("--header={}", header));
+    if back {
+        fzf.arg("--bind=backward-eof:abort");
+    }
+    if !keys.is_empty() {
+        fzf.arg(format!(
+            "--expect={}",
+            keys.iter().map(|(key, _)| key).join(",")
+        ));
+    }
+    if let Ok(exe) = std::env::current_exe() {
+        // `bkmr show` writes to stderr
+        let exe = shlex::try_quote(&exe.to_string_lossy())?.into_owned();
+        fzf.arg(format!("--preview={} show {{1}} 2>&1", exe));
+    }
+    let mut child = fzf
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Cannot run {}", command[0]))?;
+    {
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        for bm in bms {
+            let (text, fragments) = line(bm, columns());
+            // fzf reads one bookmark per line, tabs separate the id
+            let text = text.replace(['\t', '\n', '\r'], " ");
+            // fzf may exit before reading everything
+            if writeln!(stdin, "{}\t{}", bm.id, ansi(&text, &fragments)).is_err() {
+                break;
+            }
+        }
+    }
+    let output = child.wait_with_output()?;
+    debug!("{} exited with {}", command[0], output.status);
+    match output.status.code() {
+        // selected or no match
+        Some(0) | Some(1) => {}
+        // aborted
+        Some(130) => return Ok(None),
+        _ => bail!("{} failed ({})", command[0], output.status),
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let query = lines.next().unwrap_or_default().to_string();
+    let key = match lines.next().unwrap_or_default() {
+        "" => Key::Enter,
+        key => {
+            parse_key(key).ok_or_else(|| anyhow!("Unexpected key {:?} of {}", key, command[0]))?
+        }
+    };
+    let bms = lines
+        .filter_map(|line| line.split('\t').next()?.parse::<i32>().ok())
+        .filter_map(|id| bms.iter().find(|bm| bm.id == id).cloned())
+        .collect();
+    Ok(Some(Picked { key, query, bms }))
+}
+
+/// `text` with ANSI escapes for the colors of `fragments`
+fn ansi(text: &str, fragments: &Fragments) -> String {
+    let mut colored = String::new();
+    for (i, c) in text.chars().enumerate() {
+        let i = i as u32;
+        for (attr, (start, end)) in fragments {
+            if let Color::AnsiValue(n) = attr.fg {
+                if i == *start {
+                    colored.push_str(&format!("\x1b[38;5;{}m", n));
+                }
+                if i == *end {
+                    colored.push_str("\x1b[m");
+                }
+            }
+        }
+        colored.push(c);
+    }
+    if fragments
+        .iter()
+        .any(|(_, (_, end))| *end as usize == text.chars().count())
+    {
+        colored.push_str("\x1b[m");
+    }
+    colored
+}
+
+fn is_reload(keys: &[(String, FzfAction)], final_key: &Key) -> bool {
+    keys.iter().any(|(key, action)| {
+        *action == FzfAction::Reload && parse_key(key).as_ref() == Some(final_key)
+    })
+}
+
+/// runs `action` on `bms`
+fn run_action(action: FzfAction, mut bms: Vec<Bookmark>) -> anyhow::Result<()> {
+    // id selection not necessary since all bms are filtered, positions of all bms
+    let ids: Vec<i32> = (1..=bms.len()).map(|i| i as i32).collect();
+    match action {
+        FzfAction::Open => open_bms(ids, bms),
+        FzfAction::Edit => edit_bms(ids, bms),
+        FzfAction::Delete => {
+            let question = format!("Really delete these {} bookmarks?", bms.len());
+            if Confirm::new(&question).with_default(false).prompt()? {
+                // ascending ids, deletion compacts the ids from the highest one on
+                bms.sort_by_key(|bm| bm.id);
+                delete_bms(ids, bms, false)?;
+            }
+            Ok(())
+        }
+        FzfAction::Yank => {
+            Clipboard::new()?.set_text(bms.iter().map(|bm| &bm.URL).join("\n"))?;
+            eprintln!("Copied {} URLs to clipboard", bms.len());
+            Ok(())
+        }
+        FzfAction::PrintIds => {
+            println!("{}", bms.iter().map(|bm| bm.id).join(","));
+            Ok(())
+        }
+        FzfAction::Menu => {
+            let actions = vec![
+                FzfAction::Open,
+                FzfAction::Edit,
+                FzfAction::Delete,
+                FzfAction::Yank,
+                FzfAction::PrintIds,
+            ];
+            match Select::new(&format!("{} bookmarks:", bms.len()), actions).prompt() {
+                Ok(action) => run_action(action, bms),
+                Err(_) => Ok(()),
+            }
+        }
+        // handled by the picker itself
+        FzfAction::Reload => Ok(()),
+    }
+}
+
+/// one of `lines` picked by the embedded skim or the external fzf, None if aborted
+fn pick_line(lines: &[String], prompt: &str, header: &str) -> Option<String> {
+    if let Some(command) = EXTERNAL.get() {
+        return pick_line_external(command, lines, prompt, header).unwrap_or_else(|e| {
+            eprintln!("{:?}", e);
+            None
+        });
+    }
+    let FzfEnvOpts {
+        reverse,
+        height,
+        ..
+    } = &CONFIG.fzf_opts;
+
+    let options = SkimOptionsBuilder::default()
+        .reverse(reverse.to_owned())
+        .height(Some(height))
+        .prompt(Some(prompt))
+        .header(Some(header))
+        .color(COLOR.get().map(String::as_str))
+        .build()
+        .unwrap();
+
+    let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) = unbounded();
+    for line in lines {
+        tx_item.send(Arc::new(line.clone())).unwrap();
+    }
+    drop(tx_item);
+
+    let out = Skim::run_with(&options, Some(rx_item))?;
+    if out.is_abort {
+        return None;
+    }
+    out.selected_items
+        .first()
+        .map(|item| item.output().to_string())
+}
+
+fn pick_line_external(
+    command: &[String],
+    lines: &[String],
+    prompt: &str,
+    header: &str,
+) -> anyhow::Result<Option<String>> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .arg("--no-multi")
+        .arg(format!("--prompt={}", prompt))
+        .arg(format!("--header={}", header))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Cannot run {}", command[0]))?;
+    {
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        for line in lines {
+            if writeln!(stdin, "{}", line).is_err() {
+                break;
+            }
+        }
+    }
+    let output = child.wait_with_output()?;
+    match output.status.code() {
+        Some(0) => {}
+        Some(1) | Some(130) => return Ok(None),
+        _ => bail!("{} failed ({})", command[0], output.status),
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(str::to_string))
+}
+
+/// multi-select of `tags` with `selected` preselected, None if aborted
+pub fn pick_tags(tags: &[String], selected: &[String]) -> Option<Vec<String>> {
+    let FzfEnvOpts {
+        reverse,
+        height,
+        ..
+    } = &CONFIG.fzf_opts;
+
+    let options = SkimOptionsBuilder::default()
+        .reverse(reverse.to_owned())
+        .height(Some(height))
+        .multi(true)
+        .prompt(Some("tags> "))
+        .color(COLOR.get().map(String::as_str))
+        .header(Some("TAB: toggle tag, Enter: apply, ESC: abort"))
+        .pre_select_items(Some(selected.join("\n")))
+        .build()
+        .unwrap();
+
+    let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) = unbounded();
+    for tag in tags {
+        tx_item.send(Arc::new(tag.clone())).unwrap();
+    }
+    drop(tx_item);
+
+    let out = Skim::run_with(&options, Some(rx_item))?;
+    execute!(std::io::stdout(), Clear(ClearType::FromCursorDown)).unwrap();
+    if out.is_abort {
+        return None;
+    }
+    Some(
+        out.selected_items
+            .iter()
+            .map(|item| item.output().to_string())
+            .collect(),
+    )
+}
+
+/// the picker lines of the selected bookmarks
+fn print_selected(bms: &[Bookmark]) {
+    for bm in bms {
+        println!("{}\n", line(bm, columns()).0);
+    }
+}
+
+fn selected_bms(out: &SkimOutput) -> Vec<Bookmark> {
     let selected_bms = out
         .selected_items
         .iter()
@@ -229,11 +874,161 @@ fn filter_bms(out: SkimOutput) -> Vec<Bookmark> {
                 .to_owned()
         })
         .collect::<Vec<Bookmark>>();
-    debug!(
-        "({}:{}) selected_bms: {:?}",
-        function_name!(),
-        line!(),
-        selected_bms
-    );
+    debug!("selected_bms: {:?}", selected_bms);
     selected_bms
 }
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("", 10, vec![])]
+    #[case("a bb ccc dddd", 6, vec!["a bb", "ccc", "dddd"])]
+    #[case("a verylongword b", 4, vec!["a", "verylongword", "b"])]
+    #[case("line1\nline 2", 80, vec!["line1", "line 2"])]
+    fn test_wrap(#[case] text: &str, #[case] width: usize, #[case] expected: Vec<&str>) {
+        assert_eq!(wrap(text, width), expected);
+    }
+
+    #[rstest]
+    #[case(vec![Column::Id, Column::Title, Column::Url], "[7] Rust, https://www.rust-lang.org/learn")]
+    #[case(vec![Column::Id, Column::Tags, Column::Title], "[7] lang | rust, Rust")]
+    #[case(vec![Column::Domain, Column::Id, Column::Title], "rust-lang.org, [7] Rust")]
+    fn test_line(#[case] columns: Vec<Column>, #[case] expected: &str) {
+        let bm = Bookmark {
+            id: 7,
+            URL: "https://www.rust-lang.org/learn".to_string(),
+            metadata: "Rust".to_string(),
+            tags: ",lang,rust,".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(line(&bm, &columns).0, expected);
+    }
+
+    #[rstest]
+    fn test_line_fragments() {
+        let bm = Bookmark {
+            id: 7,
+            URL: "https://www.unicode.org/".to_string(),
+            metadata: "Ünïcode".to_string(),
+            ..Default::default()
+        };
+        let (text, fragments) = line(&bm, &[Column::Id, Column::Title, Column::Domain]);
+        assert_eq!(text, "[7] Ünïcode, unicode.org");
+        let ranges: Vec<(u32, u32)> = fragments.iter().map(|(_, range)| *range).collect();
+        assert_eq!(ranges, vec![(4, 11), (13, 24)]);
+    }
+
+    #[rstest]
+    fn test_ansi() {
+        let green = Attr {
+            fg: Color::GREEN,
+            ..Attr::default()
+        };
+        let yellow = Attr {
+            fg: Color::YELLOW,
+            ..Attr::default()
+        };
+        assert_eq!(
+            ansi("[1] Ünï, a.org", &vec![(green, (4, 7)), (yellow, (9, 14))]),
+            "[1] \x1b[38;5;2mÜnï\x1b[m, \x1b[38;5;3ma.org\x1b[m"
+        );
+        assert_eq!(ansi("[1]", &vec![]), "[1]");
+    }
+
+    #[rstest]
+    fn test_tag_counts() {
+        let bms: Vec<Bookmark> = [",rust,lang,", ",py,lang,", ",rust,", ""]
+            .iter()
+            .map(|tags| Bookmark {
+                tags: tags.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        assert_eq!(
+            tag_counts(&bms),
+            vec![
+                ("lang".to_string(), 2),
+                ("rust".to_string(), 2),
+                ("py".to_string(), 1),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("", vec![], "")]
+    #[case("rust book", vec![], "rust book")]
+    #[case("#Rust book t:lang", vec!["rust", "lang"], "book")]
+    #[case("book # t:", vec![], "book")]
+    fn test_split_tag_query(#[case] query: &str, #[case] tags: Vec<&str>, #[case] rest: &str) {
+        assert_eq!(
+            split_tag_query(query),
+            (
+                tags.into_iter().map(String::from).collect(),
+                rest.to_string()
+            )
+        );
+    }
+
+    #[rstest]
+    #[case(vec![], true)]
+    #[case(vec!["rust"], true)]
+    #[case(vec!["ru", "la"], true)]
+    #[case(vec!["rust", "py"], false)]
+    #[case(vec!["ust"], false)]
+    fn test_has_tags(#[case] tags: Vec<&str>, #[case] expected: bool) {
+        let bm = Bookmark {
+            tags: ",Lang,rust,".to_string(),
+            ..Default::default()
+        };
+        let tags: Vec<String> = tags.into_iter().map(String::from).collect();
+        assert_eq!(has_tags(&bm, &tags), expected);
+    }
+
+    #[rstest]
+    fn test_set_columns_invalid() {
+        assert!(set_columns(&["id".to_string(), "author".to_string()]).is_err());
+    }
+
+    #[rstest]
+    #[case("ctrl-d", Some(Key::Ctrl('d')))]
+    #[case("alt-y", Some(Key::Alt('y')))]
+    #[case("f2", Some(Key::F(2)))]
+    #[case("ctrl-m", None)]
+    #[case("f13", None)]
+    #[case("ctrl-dd", None)]
+    #[case("enter", None)]
+    fn test_parse_key(#[case] key: &str, #[case] expected: Option<Key>) {
+        assert_eq!(parse_key(key), expected);
+    }
+
+    #[rstest]
+    fn test_keybindings() {
+        let keys = BTreeMap::from([
+            ("open".to_string(), "ctrl-o".to_string()),
+            ("yank".to_string(), "Ctrl-Y".to_string()),
+        ]);
+        assert_eq!(
+            keybindings(&keys).unwrap(),
+            vec![
+                ("ctrl-e".to_string(), FzfAction::Edit),
+                ("ctrl-d".to_string(), FzfAction::Delete),
+                ("ctrl-a".to_string(), FzfAction::Menu),
+                ("ctrl-r".to_string(), FzfAction::Reload),
+                ("ctrl-o".to_string(), FzfAction::Open),
+                ("ctrl-y".to_string(), FzfAction::Yank),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("archive", "ctrl-x")]
+    #[case("open", "ctrl-")]
+    fn test_keybindings_invalid(#[case] action: &str, #[case] key: &str) {
+        let keys = BTreeMap::from([(action.to_string(), key.to_string())]);
+        assert!(keybindings(&keys).is_err());
+    }
+}
@@ -0,0 +1,491 @@
+//! `bkmr grpc` (cargo feature "grpc"): search and CRUD of bookmarks over gRPC, the service
+//! `bkmr.v1.Bookmarks` of `proto/bkmr.proto`.
+//!
+//! HTTP/2 without TLS, uncompressed messages, unary calls only. The protobuf encoding of the few
+//! messages is done here instead of by generated code.
+use std::convert::Infallible;
+use std::net::TcpListener;
+
+use anyhow::Context;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use tracing::debug;
+
+use crate::bms::Bookmarks;
+use crate::dal::Dal;
+use crate::models::{Bookmark, NewBookmark};
+use crate::serve;
+use crate::tag::Tags;
+
+/// the published service definition
+pub const PROTO: &str = include_str!("../proto/bkmr.proto");
+const SERVICE: &str = "/bkmr.v1.Bookmarks/";
+/// default maximum message size of gRPC
+const MAX_MESSAGE: usize = 4 << 20;
+/// bytes escaped in grpc-message
+const MESSAGE_ESCAPE: &AsciiSet = &CONTROLS.add(b'%');
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+/// status codes of gRPC
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const ALREADY_EXISTS: u32 = 6;
+const PERMISSION_DENIED: u32 = 7;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+const UNAUTHENTICATED: u32 = 16;
+
+#[derive(Debug, PartialEq)]
+struct Status {
+    code: u32,
+    message: String,
+}
+
+impl Status {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<DieselError> for Status {
+    fn from(e: DieselError) -> Self {
+        Status::new(INTERNAL, e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for Status {
+    fn from(e: anyhow::Error) -> Self {
+        Status::new(INTERNAL, format!("{:#}", e))
+    }
+}
+
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint(field << 3 | wire_type);
+    }
+
+    /// default values are not encoded
+    fn int32(&mut self, field: u64, value: i32) {
+        if value != 0 {
+            self.key(field, VARINT);
+            // negative numbers are sign extended to 64 bits
+            self.varint(value as i64 as u64);
+        }
+    }
+
+    fn uint32(&mut self, field: u64, value: u32) {
+        if value != 0 {
+            self.key(field, VARINT);
+            self.varint(value as u64);
+        }
+    }
+
+    fn string(&mut self, field: u64, value: &str) {
+        if !value.is_empty() {
+            self.bytes(field, value.as_bytes());
+        }
+    }
+
+    fn bytes(&mut self, field: u64, value: &[u8]) {
+        self.key(field, LENGTH_DELIMITED);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+}
+
+/// a field of a decoded message
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// fixed32 and fixed64, not used by the messages
+    Fixed,
+}
+
+impl Value<'_> {
+    fn int32(&self) -> i32 {
+        match self {
+            Value::Varint(v) => *v as i32,
+            _ => 0,
+        }
+    }
+
+    fn string(&self) -> Result<String, Status> {
+        match self {
+            Value::Bytes(bytes) => String::from_utf8(bytes.to_vec())
+                .map_err(|_| Status::new(INVALID_ARGUMENT, "String is not UTF-8")),
+            _ => Err(Status::new(INVALID_ARGUMENT, "String expected")),
+        }
+    }
+}
+
+/// the fields of the message `buf` with their numbers
+fn fields(buf: &[u8]) -> Result<Vec<(u64, Value<'_>)>, Status> {
+    let invalid = || Status::new(INVALID_ARGUMENT, "Invalid protobuf message");
+    let mut pos = 0;
+    let varint = |pos: &mut usize| -> Result<u64, Status> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *buf.get(*pos).ok_or_else(invalid)?;
+            *pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(invalid())
+    };
+    let mut fields = Vec::new();
+    while pos < buf.len() {
+        let key = varint(&mut pos)?;
+        let value = match key & 0x7 {
+            VARINT => Value::Varint(varint(&mut pos)?),
+            LENGTH_DELIMITED => {
+                let length = varint(&mut pos)? as usize;
+                let bytes = buf
+                    .get(pos..pos.saturating_add(length))
+                    .ok_or_else(invalid)?;
+                pos += length;
+                Value::Bytes(bytes)
+            }
+            FIXED64 => {
+                pos += 8;
+                Value::Fixed
+            }
+            FIXED32 => {
+                pos += 4;
+                Value::Fixed
+            }
+            _ => return Err(invalid()),
+        };
+        fields.push((key >> 3, value));
+    }
+    if pos > buf.len() {
+        return Err(invalid());
+    }
+    Ok(fields)
+}
+
+fn encode_bookmark(bm: &Bookmark) -> Vec<u8> {
+    let mut e = Encoder::default();
+    e.int32(1, bm.id);
+    e.string(2, &bm.URL);
+    e.string(3, &bm.metadata);
+    for tag in bm.get_tags() {
+        e.string(4, &tag);
+    }
+    e.string(5, &bm.desc);
+    e.string(
+        6,
+        &bm.last_update_ts.format("%Y-%m-%dT%H:%M:%S").to_string(),
+    );
+    e.int32(7, bm.visits);
+    e.string(8, &bm.extra);
+    e.buf
+}
+
+/// answers the call of `method` with the protobuf message `request`
+fn call(method: &str, request: &[u8]) -> Result<Vec<u8>, Status> {
+    let fields = fields(request)?;
+    let id = || {
+        fields
+            .iter()
+            .find(|(field, _)| *field == 1)
+            .map_or(0, |(_, value)| value.int32())
+    };
+    let strings = |number: u64| -> Result<Vec<String>, Status> {
+        fields
+            .iter()
+            .filter(|(field, _)| *field == number)
+            .map(|(_, value)| value.string())
+            .collect()
+    };
+    let string = |number: u64| -> Result<Option<String>, Status> { Ok(strings(number)?.pop()) };
+    let mut dal = Dal::default();
+    let get = |dal: &mut Dal, id: i32| {
+        dal.get_bookmark_by_id(id)
+            .map_err(|_| Status::new(NOT_FOUND, format!("No bookmark {}", id)))
+    };
+    match method {
+        "Search" => {
+            let query = string(1)?.unwrap_or_default();
+            let tags = strings(2)?;
+            let limit = fields
+                .iter()
+                .find(|(field, _)| *field == 3)
+                .map_or(0, |(_, value)| value.int32() as u32);
+            let mut bms = Bookmarks::try_new(query)
+                .map_err(|e| Status::new(INVALID_ARGUMENT, format!("Invalid query: {}", e)))?
+                .bms;
+            if !tags.is_empty() {
+                bms = Bookmarks::match_all(Tags::clean_tags(tags), bms, false);
+            }
+            if limit > 0 {
+                bms.truncate(limit as usize);
+            }
+            let mut e = Encoder::default();
+            for bm in &bms {
+                e.bytes(1, &encode_bookmark(bm));
+            }
+            Ok(e.buf)
+        }
+        "Get" => Ok(encode_bookmark(&get(&mut dal, id())?)),
+        "Add" => {
+            let url = string(1)?.filter(|url| !url.trim().is_empty());
+            let url = url.ok_or_else(|| Status::new(INVALID_ARGUMENT, "No url"))?;
+            let (mut title, mut desc) = (string(2)?, string(4)?);
+            let fetch = fields
+                .iter()
+                .any(|(field, value)| *field == 5 && value.int32() != 0);
+            if fetch && (title.is_none() || desc.is_none()) {
+                let details = crate::fetch::load_url_details(&url)?;
+                title = title.or(Some(details.title));
+                desc = desc.or(Some(details.description));
+            }
+            let bm = NewBookmark {
+                URL: url.clone(),
+                metadata: title.unwrap_or_default(),
+                tags: Tags::create_normalized_tag_string(Some(strings(3)?.join(","))),
+                desc: desc.unwrap_or_default(),
+                flags: 0,
+            };
+            match dal.insert_bookmark(bm) {
+                Ok(bms) => Ok(encode_bookmark(&bms[0])),
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(
+                    Status::new(ALREADY_EXISTS, format!("Bookmark already exists: {}", url)),
+                ),
+                Err(e) => Err(e.into()),
+            }
+        }
+        "Update" => {
+            let mut bm = get(&mut dal, id())?;
+            if let Some(url) = string(2)? {
+                bm.URL = url;
+            }
+            if let Some(title) = string(3)? {
+                bm.metadata = title;
+            }
+            if let Some(desc) = string(4)? {
+                bm.desc = desc;
+            }
+            let remove = Tags::clean_tags(strings(6)?);
+            let mut tags = bm.get_tags();
+            tags.extend(strings(5)?);
+            tags.retain(|tag| !remove.contains(tag));
+            bm.tags = Tags::create_normalized_tag_string(Some(tags.join(",")));
+            dal.update_bookmark(bm.clone())?;
+            Ok(encode_bookmark(&get(&mut dal, bm.id)?))
+        }
+        "Delete" => {
+            let bm = get(&mut dal, id())?;
+            dal.delete_bookmark2(bm.id)?;
+            Ok(encode_bookmark(&bm))
+        }
+        "Tags" => {
+            let prefix = string(1)?.unwrap_or_default();
+            let mut e = Encoder::default();
+            for tag in dal.get_all_tags()? {
+                if tag.tag.starts_with(&prefix) {
+                    let mut t = Encoder::default();
+                    t.string(1, &tag.tag);
+                    t.uint32(2, tag.n as u32);
+                    e.bytes(1, &t.buf);
+                }
+            }
+            Ok(e.buf)
+        }
+        _ => Err(Status::new(
+            UNIMPLEMENTED,
+            format!("Unknown method {}", method),
+        )),
+    }
+}
+
+/// the message of a length-prefixed gRPC frame
+fn unframe(body: &[u8]) -> Result<&[u8], Status> {
+    let invalid = || Status::new(INVALID_ARGUMENT, "Invalid gRPC frame");
+    let (header, message) = (body.get(..5).ok_or_else(invalid)?, &body[5..]);
+    if header[0] != 0 {
+        return Err(Status::new(UNIMPLEMENTED, "Compressed messages"));
+    }
+    let length = u32::from_be_bytes(header[1..5].try_into().expect("4 bytes")) as usize;
+    if length != message.len() {
+        return Err(invalid());
+    }
+    Ok(message)
+}
+
+fn frame(message: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0];
+    frame.extend((message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// answers a call of `path` with the framed request `body`
+fn respond(path: &str, headers: &HeaderMap, body: &[u8]) -> Result<Vec<u8>, Status> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match serve::access(token) {
+        Some(true) => {}
+        Some(false) => {
+            return Err(Status::new(
+                PERMISSION_DENIED,
+                "No gRPC for users with namespace",
+            ))
+        }
+        None => return Err(Status::new(UNAUTHENTICATED, "Token missing or unknown")),
+    }
+    let Some(method) = path.strip_prefix(SERVICE) else {
+        return Err(Status::new(
+            UNIMPLEMENTED,
+            format!("Unknown service {}", path),
+        ));
+    };
+    debug!("{}", method);
+    call(method, unframe(body)?).map(|message| frame(&message))
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let (parts, mut body) = request.into_parts();
+    let mut bytes = Vec::new();
+    let mut result = Ok(());
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) if bytes.len() + chunk.len() <= MAX_MESSAGE + 5 => bytes.extend(chunk),
+            Ok(_) => result = Err(Status::new(INVALID_ARGUMENT, "Message too large")),
+            Err(e) => result = Err(Status::new(INVALID_ARGUMENT, e.to_string())),
+        }
+        if result.is_err() {
+            break;
+        }
+    }
+    let path = parts.uri.path().to_string();
+    let result = match result {
+        Ok(()) => tokio::task::spawn_blocking(move || respond(&path, &parts.headers, &bytes))
+            .await
+            .unwrap_or_else(|e| Err(Status::new(INTERNAL, e.to_string()))),
+        Err(status) => Err(status),
+    };
+    Ok(response(result))
+}
+
+/// the message, if any, and the status in the trailers
+fn response(result: Result<Vec<u8>, Status>) -> Response<Body> {
+    let (data, status) = match result {
+        Ok(data) => (Some(data), Status::new(0, "")),
+        Err(status) => (None, status),
+    };
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(status.code));
+    if !status.message.is_empty() {
+        let message = utf8_percent_encode(&status.message, MESSAGE_ESCAPE).to_string();
+        if let Ok(message) = HeaderValue::from_str(&message) {
+            trailers.insert("grpc-message", message);
+        }
+    }
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if let Some(data) = data {
+            if sender.send_data(data.into()).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_trailers(trailers).await;
+    });
+    let mut response = Response::new(body);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    response
+}
+
+/// answers calls on `listener` until the process ends
+pub fn serve(listener: TcpListener) -> anyhow::Result<()> {
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Cannot start runtime")?;
+    runtime.block_on(async {
+        let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+        Server::from_tcp(listener)?
+            .http2_only(true)
+            .serve(make_service)
+            .await?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn test_encode_decode() {
+        let mut e = Encoder::default();
+        e.int32(1, -1);
+        e.string(2, "rust");
+        e.string(2, "");
+        e.uint32(3, 300);
+        e.bytes(4, &[]);
+        let fields = fields(&e.buf).unwrap();
+        assert_eq!(fields.len(), 4);
+        assert_eq!((fields[0].0, fields[0].1.int32()), (1, -1));
+        assert_eq!(fields[1].1.string().unwrap(), "rust");
+        assert_eq!((fields[2].0, fields[2].1.int32()), (3, 300));
+        assert_eq!(fields[3].1.string().unwrap(), "");
+        // the message ends within a field
+        assert!(super::fields(&e.buf[..e.buf.len() - 3]).is_err());
+    }
+
+    #[rstest]
+    fn test_frame() {
+        let framed = frame(b"\x08\x01");
+        assert_eq!(framed, b"\x00\x00\x00\x00\x02\x08\x01");
+        assert_eq!(unframe(&framed).unwrap(), b"\x08\x01");
+        assert_eq!(unframe(&framed[..6]).unwrap_err().code, INVALID_ARGUMENT);
+        assert_eq!(
+            unframe(b"\x01\x00\x00\x00\x00").unwrap_err().code,
+            UNIMPLEMENTED
+        );
+    }
+
+    #[rstest]
+    fn test_respond_errors() {
+        let headers = HeaderMap::new();
+        let unknown = respond("/bkmr.v1.Bookmarks/Export", &headers, &frame(&[]));
+        assert_eq!(unknown.unwrap_err().code, UNIMPLEMENTED);
+        let other = respond("/grpc.health.v1.Health/Check", &headers, &frame(&[]));
+        assert_eq!(other.unwrap_err().code, UNIMPLEMENTED);
+        let add = respond("/bkmr.v1.Bookmarks/Add", &headers, &frame(&[]));
+        assert_eq!(add.unwrap_err(), Status::new(INVALID_ARGUMENT, "No url"));
+    }
+}
@@ -0,0 +1,515 @@
+//! Launching bookmarks: URI prefixes are mapped to command templates from the config,
+//! e.g. `"ssh::" = "kitty +kitten ssh {}"`. `shell::` runs the rest of the URI as shell command,
+//! `copy::` puts it on the clipboard, `snip::` and `markdown::` print it to stdout,
+//! anything else is opened by the OS, or by the command configured for a tag of the bookmark,
+//! e.g. `video = "mpv {}"`.
+//! Shell commands and URLs can have placeholders like `{pod}`, filled in before opening them,
+//! e.g. `https://github.com/search?q={query}`: values in URLs are percent-encoded.
+//! Shell commands can run in a new tmux window/pane or wezterm tab instead of the current terminal.
+//! If a bookmark cannot be opened, its fallbacks are tried in order.
+use std::collections::BTreeMap;
+use std::env;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::anyhow;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::helper::abspath;
+use crate::models::Bookmark;
+
+pub const SHELL_PREFIX: &str = "shell::";
+pub const COPY_PREFIX: &str = "copy::";
+pub const SNIP_PREFIX: &str = "snip::";
+pub const MARKDOWN_PREFIX: &str = "markdown::";
+
+static HANDLERS: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+static TAG_HANDLERS: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+/// sets the handlers of the config file, only the first call has an effect
+pub fn set_handlers(handlers: BTreeMap<String, String>) {
+    let _ = HANDLERS.set(handlers);
+}
+
+/// sets the handlers per tag of the config file, only the first call has an effect
+pub fn set_tag_handlers(tag_handlers: BTreeMap<String, String>) {
+    let _ = TAG_HANDLERS.set(tag_handlers);
+}
+
+/// how a bookmark is launched
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// command line run by `sh -c`
+    Shell(String),
+    /// text copied to the clipboard
+    Copy(String),
+    /// text printed to stdout
+    Print(String),
+    /// markdown printed to stdout, rendered on a terminal
+    Markdown(String),
+    /// URI or path opened by the OS
+    Os(String),
+}
+
+/// action for `uri` of a bookmark with `tags` according to the configured handlers
+pub fn action(uri: &str, tags: &[String]) -> anyhow::Result<Action> {
+    action_with_handler(uri, tags).map(|(action, _)| action)
+}
+
+/// like `action`, with a description of the handler that chose the action
+pub fn action_with_handler(uri: &str, tags: &[String]) -> anyhow::Result<(Action, String)> {
+    let none = BTreeMap::new();
+    choose(
+        uri,
+        tags,
+        HANDLERS.get().unwrap_or(&none),
+        TAG_HANDLERS.get().unwrap_or(&none),
+    )
+}
+
+/// action for `uri` and the handler choosing it: a prefix handler, builtin prefix, tag handler or the OS
+pub fn choose(
+    uri: &str,
+    tags: &[String],
+    handlers: &BTreeMap<String, String>,
+    tag_handlers: &BTreeMap<String, String>,
+) -> anyhow::Result<(Action, String)> {
+    let action = resolve(uri, handlers)?;
+    let handler = match (&action, prefix_handler(uri, handlers)) {
+        (_, Some((prefix, _))) => format!("[handlers] {:?}", prefix),
+        (Action::Shell(_), None) => format!("builtin {:?}", SHELL_PREFIX),
+        (Action::Copy(_), None) => format!("builtin {:?}", COPY_PREFIX),
+        (Action::Print(_), None) => format!("builtin {:?}", SNIP_PREFIX),
+        (Action::Markdown(_), None) => format!("builtin {:?}", MARKDOWN_PREFIX),
+        (Action::Os(_), None) => match tag_handler(tags, tag_handlers) {
+            Some((tag, _)) => format!("[tag_handlers] {:?}", tag),
+            None => "OS".to_string(),
+        },
+    };
+    Ok((resolve_tags(action, tags, tag_handlers)?, handler))
+}
+
+fn quote(s: &str) -> anyhow::Result<String> {
+    shlex::try_quote(s)
+        .map(|s| s.into_owned())
+        .map_err(|e| anyhow!("Cannot quote {:?}: {}", s, e))
+}
+
+/// the longest matching prefix of `handlers` wins, before the builtin prefixes.
+/// In the command template `{}` is replaced by the rest of the URI, `{uri}` by the full URI, both shell quoted.
+pub fn resolve(uri: &str, handlers: &BTreeMap<String, String>) -> anyhow::Result<Action> {
+    if let Some((prefix, template)) = prefix_handler(uri, handlers) {
+        let rest = quote(&uri[prefix.len()..])?;
+        let cmd = template
+            .split("{uri}")
+            .map(|part| part.replace("{}", &rest))
+            .collect::<Vec<_>>()
+            .join(&quote(uri)?);
+        return Ok(Action::Shell(cmd));
+    }
+    if let Some(cmd) = uri.strip_prefix(SHELL_PREFIX) {
+        Ok(Action::Shell(cmd.to_string()))
+    } else if let Some(text) = uri.strip_prefix(COPY_PREFIX) {
+        Ok(Action::Copy(text.to_string()))
+    } else if let Some(text) = uri.strip_prefix(SNIP_PREFIX) {
+        Ok(Action::Print(text.to_string()))
+    } else if let Some(text) = uri.strip_prefix(MARKDOWN_PREFIX) {
+        Ok(Action::Markdown(text.to_string()))
+    } else {
+        Ok(Action::Os(uri.to_string()))
+    }
+}
+
+/// prefix and command template of the handler for `uri`
+fn prefix_handler<'a>(
+    uri: &str,
+    handlers: &'a BTreeMap<String, String>,
+) -> Option<(&'a String, &'a String)> {
+    handlers
+        .iter()
+        .filter(|(prefix, _)| uri.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+}
+
+/// tag and command template of the handler for the first tag in `tags` having one
+fn tag_handler<'a>(
+    tags: &'a [String],
+    tag_handlers: &'a BTreeMap<String, String>,
+) -> Option<(&'a String, &'a String)> {
+    tags.iter()
+        .find_map(|tag| tag_handlers.get(tag).map(|template| (tag, template)))
+}
+
+/// a web page or file is opened by the handler of the first tag in `tags` having one.
+/// In the command template `{}` is replaced by the URI, shell quoted, `~` of local paths expanded.
+pub fn resolve_tags(
+    action: Action,
+    tags: &[String],
+    tag_handlers: &BTreeMap<String, String>,
+) -> anyhow::Result<Action> {
+    let Action::Os(uri) = action else {
+        return Ok(action);
+    };
+    match tag_handler(tags, tag_handlers) {
+        Some((_, template)) => {
+            let target = abspath(&uri).unwrap_or(uri);
+            Ok(Action::Shell(template.replace("{}", &quote(&target)?)))
+        }
+        None => Ok(Action::Os(uri)),
+    }
+}
+
+/// extra field with the working directory of shell commands
+pub const CWD_KEY: &str = "cwd";
+/// prefix of extra fields with environment variables of shell commands, e.g. `env.KUBECONFIG`
+pub const ENV_PREFIX: &str = "env.";
+
+/// working directory and environment variables a shell command runs with
+#[derive(Debug, Default, PartialEq)]
+pub struct ShellContext {
+    pub cwd: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+}
+
+impl From<&Bookmark> for ShellContext {
+    /// from the extra fields `cwd` (`~` is expanded) and `env.<NAME>`
+    fn from(bm: &Bookmark) -> Self {
+        ShellContext {
+            cwd: bm
+                .get_extra_value(CWD_KEY)
+                .map(|cwd| PathBuf::from(shellexpand::tilde(&cwd).as_ref())),
+            env: bm
+                .get_extra()
+                .keys()
+                .filter_map(|key| {
+                    let name = key.strip_prefix(ENV_PREFIX)?;
+                    Some((name.to_string(), bm.get_extra_value(key)?))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// prefix of extra fields with targets tried in order if the URI cannot be opened, e.g. `fallback.1`
+pub const FALLBACK_PREFIX: &str = "fallback.";
+/// fallback target opening the copy of the bookmarked page on archive.org
+pub const ARCHIVE_FALLBACK: &str = "archive";
+/// extra field with the snapshot stored by `bkmr archive`
+pub const ARCHIVE_URL: &str = "archive_url";
+
+/// the stored snapshot of `bm` on archive.org, else its latest snapshot
+pub fn archive_url(bm: &Bookmark) -> String {
+    bm.get_extra_value(ARCHIVE_URL)
+        .unwrap_or_else(|| format!("https://web.archive.org/web/{}", bm.URL))
+}
+
+/// `bm` opening its snapshot on archive.org without fallbacks, bookmarks other than web pages are unchanged
+pub fn archived(bm: &Bookmark) -> Bookmark {
+    let mut archived = bm.clone();
+    if bm.URL.starts_with("http") {
+        archived.URL = archive_url(bm);
+        for key in bm.get_extra().keys() {
+            if key.starts_with(FALLBACK_PREFIX) {
+                archived.remove_extra_value(key);
+            }
+        }
+    }
+    archived
+}
+
+/// the URI of `bm` followed by its fallbacks, ordered by their number
+pub fn targets(bm: &Bookmark) -> Vec<String> {
+    let mut fallbacks: Vec<(u32, String)> = bm
+        .get_extra()
+        .keys()
+        .filter_map(|key| {
+            let n = key.strip_prefix(FALLBACK_PREFIX)?.parse().ok()?;
+            Some((n, bm.get_extra_value(key)?))
+        })
+        .collect();
+    fallbacks.sort();
+    let fallbacks = fallbacks
+        .into_iter()
+        .map(|(_, target)| match target.as_str() {
+            ARCHIVE_FALLBACK => archive_url(bm),
+            _ => target,
+        });
+    iter::once(bm.URL.clone()).chain(fallbacks).collect()
+}
+
+/// where shell commands run instead of the current terminal
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pane {
+    TmuxWindow,
+    TmuxPane,
+    WeztermTab,
+    /// tmux window or wezterm tab, whichever bkmr runs in, else the current terminal
+    Auto,
+}
+
+static PANE: OnceLock<Pane> = OnceLock::new();
+
+/// sets where shell commands run, only the first call has an effect
+pub fn set_pane(pane: Pane) {
+    let _ = PANE.set(pane);
+}
+
+/// where shell commands run, None: in the current terminal
+pub fn pane() -> Option<Pane> {
+    match PANE.get()? {
+        Pane::Auto if env::var_os("TMUX").is_some() => Some(Pane::TmuxWindow),
+        Pane::Auto if env::var_os("WEZTERM_PANE").is_some() => Some(Pane::WeztermTab),
+        Pane::Auto => None,
+        pane => Some(*pane),
+    }
+}
+
+/// command line running `cmd` by `sh -c` in a new `pane` (not `Auto`) with `cwd` and `env`
+pub fn pane_command(
+    pane: Pane,
+    cmd: &str,
+    cwd: Option<&Path>,
+    env: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut argv: Vec<String> = match pane {
+        Pane::TmuxWindow | Pane::Auto => vec!["tmux".into(), "new-window".into()],
+        Pane::TmuxPane => vec!["tmux".into(), "split-window".into()],
+        Pane::WeztermTab => vec!["wezterm".into(), "cli".into(), "spawn".into()],
+    };
+    if let Some(cwd) = cwd {
+        let flag = if pane == Pane::WeztermTab {
+            "--cwd"
+        } else {
+            "-c"
+        };
+        argv.extend([flag.into(), cwd.to_string_lossy().into_owned()]);
+    }
+    if pane == Pane::WeztermTab {
+        argv.push("--".into());
+    }
+    // the new pane does not inherit the environment of bkmr
+    if !env.is_empty() {
+        argv.push("env".into());
+        argv.extend(
+            env.iter()
+                .map(|(name, value)| format!("{}={}", name, value)),
+        );
+    }
+    argv.extend(["sh".into(), "-c".into(), cmd.to_string()]);
+    argv
+}
+
+/// `{name}`, not `${name}` which is a shell variable
+fn placeholder_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\$?\{([A-Za-z_][A-Za-z0-9_-]*)\}").unwrap())
+}
+
+/// names of the placeholders in `cmd`, in order of appearance, without duplicates
+pub fn placeholders(cmd: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for caps in placeholder_regex().captures_iter(cmd) {
+        let name = &caps[1];
+        if !caps[0].starts_with('$') && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// replaces the placeholders in `cmd` by `values` as given, placeholders without value are kept
+pub fn interpolate(cmd: &str, values: &BTreeMap<String, String>) -> String {
+    placeholder_regex()
+        .replace_all(cmd, |caps: &Captures| match values.get(&caps[1]) {
+            Some(value) if !caps[0].starts_with('$') => value.clone(),
+            _ => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// unreserved characters of RFC 3986, everything else is percent-encoded in URL placeholder values
+const URL_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// `value` encoded to be put into a URL, e.g. as query parameter
+pub fn url_encode(value: &str) -> String {
+    utf8_percent_encode(value, URL_VALUE).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("https://youtu.be/x", &["music", "video"], Action::Shell("mpv https://youtu.be/x".to_string()))]
+    #[case("/no/such/file.md", &["doc"], Action::Shell("$EDITOR /no/such/file.md".to_string()))]
+    #[case("https://www.sysid.de", &["blog"], Action::Os("https://www.sysid.de".to_string()))]
+    #[case("shell::ls", &["video"], Action::Shell("ls".to_string()))]
+    fn test_resolve_tags(#[case] uri: &str, #[case] tags: &[&str], #[case] expected: Action) {
+        let tag_handlers = BTreeMap::from([
+            ("video".to_string(), "mpv {}".to_string()),
+            ("doc".to_string(), "$EDITOR {}".to_string()),
+        ]);
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        let action = resolve(uri, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            resolve_tags(action, &tags, &tag_handlers).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("shell::ls -l", Action::Shell("ls -l".to_string()))]
+    #[case("copy::secret token", Action::Copy("secret token".to_string()))]
+    #[case("snip::a\nb", Action::Print("a\nb".to_string()))]
+    #[case("markdown::# Title", Action::Markdown("# Title".to_string()))]
+    #[case("https://www.sysid.de", Action::Os("https://www.sysid.de".to_string()))]
+    #[case("ssh::host", Action::Shell("kitty +kitten ssh host".to_string()))]
+    #[case("ssh::my host", Action::Shell("kitty +kitten ssh 'my host'".to_string()))]
+    #[case("ssh::root@host", Action::Shell("ssh root@host".to_string()))]
+    #[case("mailto:a@b.org", Action::Shell("mail mailto:a@b.org # a@b.org".to_string()))]
+    #[case("ssh::{}", Action::Shell("kitty +kitten ssh '{}'".to_string()))]
+    #[case("shell::vim", Action::Shell("sh -c shell::vim".to_string()))]
+    fn test_resolve(#[case] uri: &str, #[case] expected: Action) {
+        let handlers = BTreeMap::from([
+            ("ssh::".to_string(), "kitty +kitten ssh {}".to_string()),
+            ("ssh::root@".to_string(), "ssh root@{}".to_string()),
+            ("mailto:".to_string(), "mail {uri} # {}".to_string()),
+            ("shell::vim".to_string(), "sh -c {uri}".to_string()),
+        ]);
+        assert_eq!(resolve(uri, &handlers).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("ssh::host", "[handlers] \"ssh::\"")]
+    #[case("shell::ls", "builtin \"shell::\"")]
+    #[case("snip::ls", "builtin \"snip::\"")]
+    #[case("https://youtu.be/x", "[tag_handlers] \"video\"")]
+    #[case("https://www.sysid.de", "OS")]
+    fn test_choose(#[case] uri: &str, #[case] expected: &str) {
+        let handlers = BTreeMap::from([("ssh::".to_string(), "ssh {}".to_string())]);
+        let tag_handlers = BTreeMap::from([("video".to_string(), "mpv {}".to_string())]);
+        let tags = if uri.contains("youtu") {
+            vec!["video".to_string()]
+        } else {
+            vec![]
+        };
+        let (_, handler) = choose(uri, &tags, &handlers, &tag_handlers).unwrap();
+        assert_eq!(handler, expected);
+    }
+
+    #[rstest]
+    fn test_resolve_builtin() {
+        assert_eq!(
+            resolve("shell::ls", &BTreeMap::new()).unwrap(),
+            Action::Shell("ls".to_string())
+        );
+    }
+
+    #[rstest]
+    #[case("kubectl logs {pod} -n {ns} # {pod}", vec!["pod", "ns"])]
+    #[case("awk '{print $1}' | xargs -I{} echo {} ${HOME}", vec![])]
+    fn test_placeholders(#[case] cmd: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(placeholders(cmd), expected);
+    }
+
+    #[rstest]
+    fn test_interpolate() {
+        let values = BTreeMap::from([
+            ("pod".to_string(), "web-1".to_string()),
+            ("HOME".to_string(), "x".to_string()),
+        ]);
+        assert_eq!(
+            interpolate("kubectl logs {pod} -n {ns} ${HOME}", &values),
+            "kubectl logs web-1 -n {ns} ${HOME}"
+        );
+    }
+
+    #[rstest]
+    fn test_targets() {
+        let mut bm = Bookmark {
+            URL: "https://www.sysid.de".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(targets(&bm), vec!["https://www.sysid.de"]);
+        bm.set_extra_value("fallback.10", "archive");
+        bm.set_extra_value("fallback.2", "~/sysid.html");
+        bm.set_extra_value("fallback.x", "ignored");
+        assert_eq!(
+            targets(&bm),
+            vec![
+                "https://www.sysid.de",
+                "~/sysid.html",
+                "https://web.archive.org/web/https://www.sysid.de"
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_archived() {
+        let mut bm = Bookmark {
+            URL: "https://www.sysid.de".to_string(),
+            ..Default::default()
+        };
+        bm.set_extra_value("fallback.1", "~/sysid.html");
+        assert_eq!(
+            targets(&archived(&bm)),
+            vec!["https://web.archive.org/web/https://www.sysid.de"]
+        );
+        let snapshot = "https://web.archive.org/web/20230501000000/https://www.sysid.de";
+        bm.set_extra_value(ARCHIVE_URL, snapshot);
+        assert_eq!(targets(&archived(&bm)), vec![snapshot]);
+        bm.URL = "shell::ls".to_string();
+        assert_eq!(archived(&bm), bm);
+    }
+
+    #[rstest]
+    #[case(Pane::TmuxWindow, "tmux new-window -c /tmp env A=1 sh -c ls")]
+    #[case(Pane::TmuxPane, "tmux split-window -c /tmp env A=1 sh -c ls")]
+    #[case(Pane::WeztermTab, "wezterm cli spawn --cwd /tmp -- env A=1 sh -c ls")]
+    fn test_pane_command(#[case] pane: Pane, #[case] expected: &str) {
+        let env = BTreeMap::from([("A".to_string(), "1".to_string())]);
+        assert_eq!(
+            pane_command(pane, "ls", Some(Path::new("/tmp")), &env).join(" "),
+            expected
+        );
+        assert_eq!(
+            pane_command(pane, "ls -l", None, &BTreeMap::new())
+                .last()
+                .unwrap(),
+            "ls -l"
+        );
+    }
+
+    #[rstest]
+    #[case("rust", "rust")]
+    #[case("rust lang & co/x?", "rust%20lang%20%26%20co%2Fx%3F")]
+    #[case("a-b.c_d~ü", "a-b.c_d~%C3%BC")]
+    fn test_url_encode(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(url_encode(value), expected);
+    }
+
+    #[rstest]
+    fn test_shell_context() {
+        let mut bm = Bookmark::default();
+        assert_eq!(ShellContext::from(&bm), ShellContext::default());
+        bm.set_extra_value("cwd", "/tmp");
+        bm.set_extra_value("env.KUBECONFIG", "/etc/kube");
+        bm.set_extra_value("author", "sysid");
+        let context = ShellContext::from(&bm);
+        assert_eq!(context.cwd, Some(PathBuf::from("/tmp")));
+        assert_eq!(
+            context.env,
+            BTreeMap::from([("KUBECONFIG".to_string(), "/etc/kube".to_string())])
+        );
+    }
+}
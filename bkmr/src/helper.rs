@@ -1,9 +1,16 @@
+use atty::Stream;
 use camino::Utf8Path;
+use clap::ValueEnum;
 use diesel::sqlite::Sqlite;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-use log::debug;
+use std::env;
 use std::error::Error;
-use stdext::function_name;
+use std::io;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use termcolor::ColorChoice;
+use tracing::debug;
 
 pub fn init_logger() {
     let _ = env_logger::builder()
@@ -21,33 +28,41 @@ pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 pub fn init_db(
     connection: &mut impl MigrationHarness<Sqlite>,
 ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    debug!("({}:{}) {:?}", function_name!(), line!(), "--> initdb <--");
+    debug!("{:?}", "--> initdb <--");
     connection.revert_all_migrations(MIGRATIONS)?;
     connection
         .pending_migrations(MIGRATIONS)?
         .iter()
         .for_each(|m| {
-            debug!(
-                "({}:{}) Pending Migration: {}",
-                function_name!(),
-                line!(),
-                m.name()
-            );
+            debug!("Pending Migration: {}", m.name());
         });
     connection.run_pending_migrations(MIGRATIONS)?;
     Ok(())
 }
 
+/// most ids of a range like `1-3`, larger ones are typos rather than bookmarks
+pub const MAX_RANGE: i64 = 100_000;
+
+/// parses ids and id ranges like `5` or `1-3` into a sorted vector without duplicates,
+/// None for ranges of more than [`MAX_RANGE`] ids
 #[allow(clippy::ptr_arg)]
 pub fn ensure_int_vector(vec: &Vec<String>) -> Option<Vec<i32>> {
-    vec.iter()
-        .map(|s| s.parse::<i32>())
-        .collect::<Result<Vec<_>, _>>()
-        .map(|mut v| {
-            v.sort();
-            v
-        })
-        .ok()
+    let mut ids = Vec::new();
+    for s in vec {
+        match s.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (start.parse::<i32>().ok()?, end.parse::<i32>().ok()?);
+                if start > end || end as i64 - start as i64 >= MAX_RANGE {
+                    return None;
+                }
+                ids.extend(start..=end);
+            }
+            None => ids.push(s.parse::<i32>().ok()?),
+        }
+    }
+    ids.sort();
+    ids.dedup();
+    Some(ids)
 }
 
 /// resolves existing path and follows symlinks, returns None if path does not exist
@@ -56,10 +71,139 @@ pub fn abspath(p: &str) -> Option<String> {
         .ok()
         .and_then(|x| Utf8Path::new(x.as_ref()).canonicalize_utf8().ok())
         .and_then(|p| Some(p.into_string()));
-    debug!("({}:{}) {:?} -> {:?}", function_name!(), line!(), p, abs_p);
+    debug!("{:?} -> {:?}", p, abs_p);
     abs_p
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorMode {
+    /// colors only if the output is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR_MODE: OnceLock<ColorMode> = OnceLock::new();
+
+/// sets the process wide color mode (`--color`), only the first call has an effect
+pub fn set_color_mode(mode: ColorMode) {
+    let _ = COLOR_MODE.set(mode);
+}
+
+/// color choice for output to `stream` according to color mode, NO_COLOR and TTY detection
+pub fn color_choice(stream: Stream) -> ColorChoice {
+    let mode = COLOR_MODE.get().copied().unwrap_or_default();
+    resolve_color_choice(
+        mode,
+        env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()),
+        atty::is(stream),
+    )
+}
+
+fn resolve_color_choice(mode: ColorMode, no_color: bool, is_tty: bool) -> ColorChoice {
+    match mode {
+        ColorMode::Always => ColorChoice::Always,
+        ColorMode::Never => ColorChoice::Never,
+        ColorMode::Auto if no_color || !is_tty => ColorChoice::Never,
+        ColorMode::Auto => ColorChoice::Auto,
+    }
+}
+
+static EDITOR: OnceLock<String> = OnceLock::new();
+const DEFAULT_EDITOR: &str = "vim";
+
+/// sets the editor of the config file, only the first call has an effect
+pub fn set_editor(editor: &str) {
+    let _ = EDITOR.set(editor.to_string());
+}
+
+/// editor command line, first found of: $VISUAL, $EDITOR, config `editor`, vim.
+/// Arguments are split like a shell would, e.g. "code --wait"
+pub fn editor_command() -> anyhow::Result<Vec<String>> {
+    resolve_editor(
+        env::var("VISUAL").ok(),
+        env::var("EDITOR").ok(),
+        EDITOR.get().map(String::as_str),
+    )
+}
+
+fn resolve_editor(
+    visual: Option<String>,
+    editor: Option<String>,
+    configured: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    let editor = [visual.as_deref(), editor.as_deref(), configured]
+        .into_iter()
+        .flatten()
+        .find(|e| !e.trim().is_empty())
+        .unwrap_or(DEFAULT_EDITOR);
+    match shlex::split(editor) {
+        Some(cmd) if !cmd.is_empty() => Ok(cmd),
+        _ => Err(anyhow::anyhow!("Invalid editor command: {:?}", editor)),
+    }
+}
+
+static PAGER_ENABLED: OnceLock<bool> = OnceLock::new();
+/// used if $PAGER is not set: quit if one screen, keep colors, do not clear the screen
+const DEFAULT_PAGER: &str = "less -FRX";
+
+/// enables/disables paging of long output (`--no-pager`), only the first call has an effect
+pub fn set_pager(enabled: bool) {
+    let _ = PAGER_ENABLED.set(enabled);
+}
+
+/// writes `output` to `stream`, through $PAGER if both `stream` and stdout are terminals
+/// and `output` does not fit on the screen
+pub fn page(output: &[u8], stream: Stream) -> io::Result<()> {
+    let lines = output.iter().filter(|b| **b == b'\n').count();
+    let height = crossterm::terminal::size().map(|(_, h)| h as usize).ok();
+    let is_tty = atty::is(stream) && atty::is(Stream::Stdout);
+    let enabled = PAGER_ENABLED.get().copied().unwrap_or(true);
+    if needs_pager(lines, height, enabled, is_tty) {
+        if let Some(pager) = pager_command(env::var("PAGER").ok()) {
+            match spawn_pager(&pager, output) {
+                Ok(()) => return Ok(()),
+                Err(e) => debug!("Pager {:?} failed: {:?}", pager, e),
+            }
+        }
+    }
+    match stream {
+        Stream::Stdout => io::stdout().write_all(output),
+        _ => io::stderr().write_all(output),
+    }
+}
+
+fn needs_pager(lines: usize, height: Option<usize>, enabled: bool, is_tty: bool) -> bool {
+    enabled && is_tty && height.is_some_and(|h| lines >= h)
+}
+
+/// pager command line: $PAGER, "" or "cat" mean no pager
+fn pager_command(pager: Option<String>) -> Option<Vec<String>> {
+    let pager = pager.unwrap_or_else(|| DEFAULT_PAGER.to_string());
+    match shlex::split(&pager) {
+        Some(cmd) if !cmd.is_empty() && cmd[0] != "cat" => Some(cmd),
+        _ => None,
+    }
+}
+
+fn spawn_pager(pager: &[String], output: &[u8]) -> io::Result<()> {
+    let mut child = Command::new(&pager[0])
+        .args(&pager[1..])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // the user may quit the pager before reading everything
+        if let Err(e) = stdin.write_all(output) {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                return Err(e);
+            }
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     // use log::debug;
@@ -82,10 +226,32 @@ mod test {
     #[case(vec ! ["1".to_string(), "2".to_string(), "3".to_string()], Some(vec ! [1, 2, 3]))]
     #[case(vec ! ["3".to_string(), "1".to_string(), "2".to_string()], Some(vec ! [1, 2, 3]))]
     #[case(vec ! ["a".to_string(), "2".to_string(), "3".to_string()], None)]
+    #[case(vec ! ["1-3".to_string(), "8".to_string(), "12-13".to_string()], Some(vec ! [1, 2, 3, 8, 12, 13]))]
+    #[case(vec ! ["2-3".to_string(), "3".to_string()], Some(vec ! [2, 3]))]
+    #[case(vec ! ["3-1".to_string()], None)]
+    #[case(vec ! ["1-a".to_string()], None)]
+    #[case(vec ! ["1-100000".to_string()], Some((1..=100000).collect()))]
+    #[case(vec ! ["1-100001".to_string()], None)]
+    #[case(vec ! ["0-2147483647".to_string()], None)]
     fn test_ensure_int_vector(#[case] x: Vec<String>, #[case] expected: Option<Vec<i32>>) {
         assert_eq!(ensure_int_vector(&x), expected);
     }
 
+    #[rstest]
+    #[case(ColorMode::Always, true, false, ColorChoice::Always)]
+    #[case(ColorMode::Never, false, true, ColorChoice::Never)]
+    #[case(ColorMode::Auto, false, true, ColorChoice::Auto)]
+    #[case(ColorMode::Auto, true, true, ColorChoice::Never)]
+    #[case(ColorMode::Auto, false, false, ColorChoice::Never)]
+    fn test_resolve_color_choice(
+        #[case] mode: ColorMode,
+        #[case] no_color: bool,
+        #[case] is_tty: bool,
+        #[case] expected: ColorChoice,
+    ) {
+        assert_eq!(resolve_color_choice(mode, no_color, is_tty), expected);
+    }
+
     // Tests are fragile, because they depend on machine specific setup
     #[rstest]
     #[case("", None)]
@@ -99,4 +265,55 @@ mod test {
     fn test_abspath(#[case] x: &str, #[case] expected: Option<String>) {
         assert_eq!(abspath(x), expected);
     }
+
+    #[rstest]
+    #[case(100, Some(40), true, true, true)]
+    #[case(10, Some(40), true, true, false)]
+    #[case(100, Some(40), false, true, false)]
+    #[case(100, Some(40), true, false, false)]
+    #[case(100, None, true, true, false)]
+    fn test_needs_pager(
+        #[case] lines: usize,
+        #[case] height: Option<usize>,
+        #[case] enabled: bool,
+        #[case] is_tty: bool,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(needs_pager(lines, height, enabled, is_tty), expected);
+    }
+
+    #[rstest]
+    #[case(None, Some(vec!["less", "-FRX"]))]
+    #[case(Some("more"), Some(vec!["more"]))]
+    #[case(Some("cat"), None)]
+    #[case(Some(""), None)]
+    fn test_pager_command(#[case] pager: Option<&str>, #[case] expected: Option<Vec<&str>>) {
+        assert_eq!(
+            pager_command(pager.map(String::from)),
+            expected.map(|v| v.into_iter().map(String::from).collect())
+        );
+    }
+
+    #[rstest]
+    #[case(Some("code --wait"), Some("nano"), None, vec!["code", "--wait"])]
+    #[case(Some(""), Some("nano"), Some("hx"), vec!["nano"])]
+    #[case(None, None, Some("hx"), vec!["hx"])]
+    #[case(None, None, None, vec!["vim"])]
+    #[case(None, Some("'/opt/my editor/bin/ed' -w"), None, vec!["/opt/my editor/bin/ed", "-w"])]
+    fn test_resolve_editor(
+        #[case] visual: Option<&str>,
+        #[case] editor: Option<&str>,
+        #[case] configured: Option<&str>,
+        #[case] expected: Vec<&str>,
+    ) {
+        assert_eq!(
+            resolve_editor(
+                visual.map(String::from),
+                editor.map(String::from),
+                configured
+            )
+            .unwrap(),
+            expected
+        );
+    }
 }
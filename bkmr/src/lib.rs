@@ -4,101 +4,96 @@
 
 extern crate skim;
 
+use anyhow::Context;
 use itertools::Itertools;
-use log::{debug, error, warn};
-use reqwest::blocking::Client;
-use select::document::Document;
-use select::predicate::{Attr, Name};
 use std::collections::HashSet;
+use tracing::debug;
 
 use crate::dal::Dal;
-use crate::environment::CONFIG;
-use crate::models::Bookmark;
+use crate::fetch::{Canonical, Details, LinkStatus};
+use crate::models::{Bookmark, NewBookmark};
 use crate::tag::Tags;
-#[allow(unused_imports)]
-use stdext::function_name;
 
 pub mod bms;
+pub mod changes;
+pub mod cloud;
+pub mod daemon;
 pub mod dal;
+#[cfg(target_os = "linux")]
+pub mod dbus;
+pub mod edit;
 pub mod environment;
+pub mod exitcode;
+pub mod fetch;
+pub mod format;
 pub mod fzf;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handler;
 pub mod helper;
+pub mod mcp;
 pub mod models;
 pub mod process;
+pub mod protocol;
+pub mod report;
+pub mod rpc;
 pub mod schema;
+#[cfg(feature = "screenshot")]
+pub mod screenshot;
+pub mod serve;
+pub mod site;
+pub mod stats;
+pub mod sync;
 pub mod tag;
+pub mod theme;
+pub mod tui;
+pub mod webhook;
 
 /// creates list of normalized tags from "tag1,t2,t3" string
 /// be aware of shell parsing rules, so no blanks or quotes
 pub fn load_url_details(url: &str) -> Result<(String, String, String), anyhow::Error> {
-    let client = Client::new();
-    let body = client.get(url).send()?.text()?;
-
-    let document = Document::from(body.as_str());
-    // let document = Document::from(body.to_string());
-
-    let title = document
-        .find(Name("title"))
-        .next()
-        .and_then(|n| Some(n.text().trim().to_owned()))
-        .unwrap_or_default();
-
-    debug!("({}:{}) Title {:?}", function_name!(), line!(), title);
-
-    let description = document
-        .find(Attr("name", "description"))
-        .next()
-        .and_then(|n| n.attr("content"))
-        .unwrap_or_default();
-    debug!(
-        "({}:{}) Description {:?}",
-        function_name!(),
-        line!(),
-        description
-    );
-
-    let keywords = document
-        .find(Attr("name", "keywords"))
-        .next()
-        .and_then(|node| node.attr("content"))
-        .unwrap_or_default();
-
-    debug!("({}:{}) Keywords {:?}", function_name!(), line!(), keywords);
+    let details = fetch::load_url_details(url)?;
+    Ok((details.title, details.description, details.keywords))
+}
 
-    Ok((title, description.to_owned(), keywords.to_owned()))
+/// downloads the raw page, e.g. to store it as HTML snapshot
+pub fn load_url_content(url: &str) -> Result<Vec<u8>, anyhow::Error> {
+    fetch::load_url_content(url)
 }
 
-pub fn update_bookmarks(ids: Vec<i32>, tags: Vec<String>, tags_not: Vec<String>, force: bool) {
+/// updates the tags of all bookmarks in one transaction: either all or none are updated.
+/// `dry_run`: returns the updated bookmarks without changing the database
+pub fn update_bookmarks(
+    ids: Vec<i32>,
+    tags: Vec<String>,
+    tags_not: Vec<String>,
+    force: bool,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Bookmark>> {
     // let mut bms = Bookmarks::new("".to_string());
 
-    let mut dal = Dal::new(CONFIG.db_url.clone());
-    for id in ids {
-        update_bm(id, &tags, &tags_not, &mut dal, force)
-    }
+    let mut dal = Dal::default();
+    dal.transaction_or_dry_run(dry_run, |dal| {
+        ids.into_iter()
+            .map(|id| update_bm(id, &tags, &tags_not, dal, force))
+            .collect()
+    })
 }
 
-pub fn update_bm(id: i32, tags: &Vec<String>, tags_not: &Vec<String>, dal: &mut Dal, force: bool) {
+pub fn update_bm(
+    id: i32,
+    tags: &Vec<String>,
+    tags_not: &Vec<String>,
+    dal: &mut Dal,
+    force: bool,
+) -> anyhow::Result<Bookmark> {
     let tags: HashSet<String> = tags.iter().cloned().collect();
     let tags_not: HashSet<String> = tags_not.iter().cloned().collect();
-    debug!(
-        "({}:{}) tags {:?}, tags_not {:?}",
-        function_name!(),
-        line!(),
-        tags,
-        tags_not
-    );
-
-    let bm = dal.get_bookmark_by_id(id);
-    if let Err(e) = bm {
-        warn!(
-            "({}:{}) Cannot load {:?}, continue.",
-            function_name!(),
-            line!(),
-            e
-        );
-        return;
-    }
-    let bm = bm.unwrap();
+    debug!("tags {:?}, tags_not {:?}", tags, tags_not);
+
+    let bm = dal
+        .get_bookmark_by_id(id)
+        .with_context(|| format!("Cannot load bookmark {}", id))?;
 
     let new_tags = if force {
         tags
@@ -114,26 +109,328 @@ pub fn update_bm(id: i32, tags: &Vec<String>, tags_not: &Vec<String>, dal: &mut
     };
 
     let bm_tags: Vec<String> = new_tags.iter().sorted().cloned().collect();
-    debug!("({}:{}) {:?}", function_name!(), line!(), bm_tags);
-
-    let bm = dal.update_bookmark(Bookmark {
-        tags: format!(",{},", bm_tags.join(",")),
-        ..bm
-    });
-    if let Err(e) = bm {
-        error!(
-            "({}:{}) Error update {:?}, continue.",
-            function_name!(),
-            line!(),
-            e
-        );
-    }
+    debug!("{:?}", bm_tags);
+
+    let mut updated = dal
+        .update_bookmark(Bookmark {
+            tags: format!(",{},", bm_tags.join(",")),
+            ..bm
+        })
+        .with_context(|| format!("Cannot update bookmark {}", id))?;
+    updated
+        .pop()
+        .with_context(|| format!("Bookmark {} not updated", id))
 }
 
 // pub fn add_bm(bm: Bookmark) {
 //
 // }
 
+/// one line of a batch file: `url` or `url|tags|title`
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct BatchEntry {
+    pub url: String,
+    pub tags: Option<String>,
+    pub title: Option<String>,
+}
+
+/// parses batch input, empty lines and lines starting with `#` are skipped
+pub fn parse_batch(input: &str) -> Vec<BatchEntry> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.splitn(3, '|').map(str::trim);
+            let url = fields.next().unwrap_or_default().to_string();
+            let mut field = || {
+                fields
+                    .next()
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+            };
+            let tags = field();
+            let title = field();
+            BatchEntry { url, tags, title }
+        })
+        .collect()
+}
+
+/// inserts all entries in one transaction, URLs which already exist are skipped.
+/// `dry_run`: returns the bookmarks which would be added without changing the database
+pub fn add_bookmarks_batch(
+    entries: Vec<BatchEntry>,
+    no_web: bool,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Bookmark>> {
+    let details: Vec<Details> = if no_web {
+        vec![Default::default(); entries.len()]
+    } else {
+        let urls: Vec<&str> = entries.iter().map(|e| e.url.as_str()).collect();
+        fetch::load_urls_details(&urls)
+            .into_iter()
+            .zip(&urls)
+            .map(|(details, url)| {
+                details.unwrap_or_else(|e| {
+                    debug!("{}: {:?}", url, e);
+                    eprintln!("Cannot enrich URL data from web: {}", url);
+                    Default::default()
+                })
+            })
+            .collect()
+    };
+
+    let mut dal = Dal::default();
+    dal.transaction_or_dry_run(dry_run, |dal| {
+        let mut added = Vec::new();
+        for (entry, details) in entries.into_iter().zip(details) {
+            let mut fetched_urls = details.extra_urls();
+            let (url, canonical_url) = fetch::canonical().apply(&entry.url, details.canonical_url);
+            fetched_urls.extend(canonical_url.map(|url| (fetch::CANONICAL_URL, url)));
+            if dal.bm_exists(&url)? {
+                eprintln!("Bookmark already exists: {}", url);
+                continue;
+            }
+            let bms = dal
+                .insert_bookmark(NewBookmark {
+                    URL: url.clone(),
+                    metadata: entry.title.unwrap_or(details.title),
+                    tags: Tags::create_normalized_tag_string(with_lang_tag(
+                        entry.tags,
+                        details.lang.as_deref(),
+                    )),
+                    desc: details.description,
+                    flags: 0,
+                })
+                .and_then(|bms| keep_fetched_urls(dal, bms, fetched_urls))
+                .with_context(|| format!("Adding {}", url))?;
+            if let Some(content) = &details.content {
+                for bm in &bms {
+                    dal.set_content(bm.id, content)
+                        .with_context(|| format!("Storing content of {}", url))?;
+                }
+            }
+            added.extend(bms);
+        }
+        Ok(added)
+    })
+}
+
+/// prefix of the tag with the detected language of a page
+pub const LANG_TAG: &str = "lang:";
+
+/// comma separated `tags` and the tag of the detected `lang`
+pub fn with_lang_tag(tags: Option<String>, lang: Option<&str>) -> Option<String> {
+    match lang {
+        Some(lang) => Some(format!("{},{}{}", tags.unwrap_or_default(), LANG_TAG, lang)),
+        None => tags,
+    }
+}
+
+/// stores the fetched URLs, e.g. canonical URL, preview image and feed, of newly added `bms`
+/// in their extra fields
+pub fn keep_fetched_urls(
+    dal: &mut Dal,
+    bms: Vec<Bookmark>,
+    urls: Vec<(&str, String)>,
+) -> Result<Vec<Bookmark>, diesel::result::Error> {
+    if urls.is_empty() {
+        return Ok(bms);
+    }
+    let mut updated = Vec::new();
+    for mut bm in bms {
+        for (key, url) in &urls {
+            bm.set_extra_value(key, url);
+        }
+        updated.extend(dal.update_bookmark(bm)?);
+    }
+    Ok(updated)
+}
+
+/// re-fetches title, description, canonical URL, preview image, feed and, if enabled, language and content of the web pages among `bms`,
+/// other bookmarks are skipped.
+/// Returns the changed bookmarks, fields are only replaced by different, non-empty values.
+/// A canonical URL which is bookmarked already is kept in the extra fields instead of replacing the URL.
+/// `dry_run`: returns the changed bookmarks without changing the database
+pub fn refresh_bookmarks(bms: Vec<Bookmark>, dry_run: bool) -> anyhow::Result<Vec<Bookmark>> {
+    let bms: Vec<Bookmark> = bms
+        .into_iter()
+        .filter(|bm| bm.URL.starts_with("http"))
+        .collect();
+    let urls: Vec<&str> = bms.iter().map(|bm| bm.URL.as_str()).collect();
+    let mut contents = Vec::new();
+    let changed: Vec<Bookmark> = fetch::load_urls_details(&urls)
+        .into_iter()
+        .zip(&bms)
+        .filter_map(|(details, bm)| match details {
+            Ok(mut details) => {
+                contents.extend(details.content.take().map(|content| (bm.id, content)));
+                refreshed(bm, details, fetch::canonical())
+            }
+            Err(e) => {
+                debug!("{}: {:?}", bm.URL, e);
+                eprintln!("Cannot enrich URL data from web: {}", bm.URL);
+                None
+            }
+        })
+        .collect();
+
+    let mut dal = Dal::default();
+    dal.transaction_or_dry_run(dry_run, |dal| {
+        for (id, content) in &contents {
+            dal.set_content(*id, content)
+                .with_context(|| format!("Error storing content of bookmark {}", id))?;
+        }
+        let mut updated = Vec::new();
+        for mut bm in changed {
+            let id = bm.id;
+            let old = bms
+                .iter()
+                .find(|old| old.id == id)
+                .expect("refreshed bookmark");
+            if bm.URL != old.URL && dal.bm_exists(&bm.URL)? {
+                eprintln!("Bookmark already exists: {}, keeping {}", bm.URL, old.URL);
+                let canonical_url = std::mem::replace(&mut bm.URL, old.URL.clone());
+                bm.set_extra_value(fetch::CANONICAL_URL, &canonical_url);
+            }
+            updated.extend(
+                dal.update_bookmark(bm)
+                    .with_context(|| format!("Error updating bookmark {}", id))?,
+            );
+        }
+        Ok(updated)
+    })
+}
+
+/// `bm` with the fetched `details`, canonical URL handled by `canonical`, `None` if nothing changes
+pub fn refreshed(bm: &Bookmark, details: Details, canonical: Canonical) -> Option<Bookmark> {
+    let mut new = bm.clone();
+    for (key, url) in details.extra_urls() {
+        new.set_extra_value(key, &url);
+    }
+    if !details.title.trim().is_empty() {
+        new.metadata = details.title;
+    }
+    if !details.description.trim().is_empty() {
+        new.desc = details.description;
+    }
+    let (url, canonical_url) = canonical.apply(&bm.URL, details.canonical_url);
+    new.URL = url;
+    if let Some(canonical_url) = canonical_url {
+        new.set_extra_value(fetch::CANONICAL_URL, &canonical_url);
+    }
+    if let Some(lang) = details.lang {
+        let mut tags: Vec<String> = new
+            .get_tags()
+            .into_iter()
+            .filter(|t| !t.starts_with(LANG_TAG))
+            .collect();
+        tags.push(format!("{}{}", LANG_TAG, lang));
+        new.set_tags(tags);
+    }
+    let changed = new.URL != bm.URL
+        || new.metadata != bm.metadata
+        || new.desc != bm.desc
+        || new.get_tags() != bm.get_tags()
+        || new.get_extra() != bm.get_extra();
+    changed.then_some(new)
+}
+
+/// requests snapshots of the web pages among `bms` on archive.org, other bookmarks are skipped.
+/// The archive URLs are stored in `extra.archive_url` of the bookmarks, failures leave them unchanged.
+pub fn archive_bookmarks(
+    bms: Vec<Bookmark>,
+) -> anyhow::Result<Vec<(Bookmark, anyhow::Result<String>)>> {
+    let bms: Vec<Bookmark> = bms
+        .into_iter()
+        .filter(|bm| bm.URL.starts_with("http"))
+        .collect();
+    let urls: Vec<&str> = bms.iter().map(|bm| bm.URL.as_str()).collect();
+    let results = fetch::archive_urls(&urls);
+
+    let mut dal = Dal::default();
+    dal.transaction(|dal| {
+        let mut archived = Vec::new();
+        for (mut bm, result) in bms.into_iter().zip(results) {
+            if let Ok(archive_url) = &result {
+                bm.set_extra_value(handler::ARCHIVE_URL, archive_url);
+                bm = dal
+                    .update_bookmark(bm.clone())
+                    .with_context(|| format!("Error updating bookmark {}", bm.id))?
+                    .remove(0);
+            }
+            archived.push((bm, result));
+        }
+        Ok(archived)
+    })
+}
+
+/// tag of bookmarks whose link fails
+pub const DEAD_TAG: &str = "dead";
+/// tag of bookmarks whose link redirects permanently
+pub const MOVED_TAG: &str = "moved";
+/// tag of bookmarks deleted to the trash in `bkmr tui`, untagging restores them
+pub const TRASH_TAG: &str = "trash";
+
+/// checks the links of the web pages among `bms` concurrently, other bookmarks are skipped.
+/// The result is recorded in the bookmarks, see [`checked`], only changed ones are written.
+/// `dry_run`: checks without changing the database
+pub fn check_bookmarks(
+    bms: Vec<Bookmark>,
+    dry_run: bool,
+) -> anyhow::Result<Vec<(Bookmark, LinkStatus)>> {
+    let bms: Vec<Bookmark> = bms
+        .into_iter()
+        .filter(|bm| bm.URL.starts_with("http"))
+        .collect();
+    let urls: Vec<&str> = bms.iter().map(|bm| bm.URL.as_str()).collect();
+    let results: Vec<(Bookmark, LinkStatus)> =
+        bms.iter().cloned().zip(fetch::check_links(&urls)).collect();
+
+    let mut dal = Dal::default();
+    dal.transaction_or_dry_run(dry_run, |dal| {
+        for (bm, status) in &results {
+            let new = checked(bm, status);
+            if new.tags != bm.tags || new.get_extra() != bm.get_extra() {
+                dal.update_bookmark(new)
+                    .with_context(|| format!("Error updating bookmark {}", bm.id))?;
+            }
+        }
+        Ok(results)
+    })
+}
+
+/// `bm` with the check result: `extra.http_status`, `extra.moved_to` and the tag
+/// [`DEAD_TAG`] or [`MOVED_TAG`], which is removed again once the link works
+pub fn checked(bm: &Bookmark, status: &LinkStatus) -> Bookmark {
+    let mut new = bm.clone();
+    let (code, moved_to, tag) = match status {
+        LinkStatus::Ok(code) => (Some(*code), None, None),
+        LinkStatus::Moved(code, url) => (Some(*code), Some(url), Some(MOVED_TAG)),
+        LinkStatus::Dead(code, _) => (*code, None, Some(DEAD_TAG)),
+    };
+    match code {
+        Some(code) => new.set_extra_value("http_status", &code.to_string()),
+        None => {
+            new.remove_extra_value("http_status");
+        }
+    }
+    match moved_to {
+        Some(url) => new.set_extra_value("moved_to", url),
+        None => {
+            new.remove_extra_value("moved_to");
+        }
+    }
+    let mut tags: Vec<String> = new
+        .get_tags()
+        .into_iter()
+        .filter(|t| t != DEAD_TAG && t != MOVED_TAG)
+        .collect();
+    tags.extend(tag.map(String::from));
+    new.set_tags(tags);
+    new
+}
+
 #[cfg(test)]
 #[ctor::ctor]
 fn init() {
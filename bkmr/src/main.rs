@@ -1,28 +1,68 @@
 use camino::Utf8Path;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
 use std::fs::create_dir_all;
+use std::io;
 use std::io::Write;
+use std::net::TcpListener;
 use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use anyhow::Context;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
 use diesel::result::DatabaseErrorKind;
 use diesel::result::Error::DatabaseError;
+use diesel::result::Error as DieselError;
 use inquire::Confirm;
 use itertools::Itertools;
 
-use log::{debug, error, info};
+use tracing::{debug, error, info};
 use stdext::function_name;
+use atty::Stream;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use tracing_subscriber::EnvFilter;
 
 use bkmr::bms::Bookmarks;
+use bkmr::changes;
+use bkmr::daemon;
+use bkmr::cloud::{Age, Storage};
 use bkmr::dal::Dal;
-use bkmr::environment::CONFIG;
-use bkmr::fzf::fzf_process;
-use bkmr::helper::{ensure_int_vector, init_db};
-use bkmr::load_url_details;
-use bkmr::models::NewBookmark;
-use bkmr::process::{bms_to_json, delete_bms, edit_bms, open_bm, process, show_bms};
+use bkmr::edit::{
+    parse_template as parse_edit_template, set_template as set_edit_template, EditAborted,
+    TEMPLATE_FILE,
+};
+use bkmr::environment::{config_dir, CloudSyncSettings, Settings, CONFIG};
+use bkmr::exitcode;
+use bkmr::fetch::{canonical, load_url_details, Details, LinkStatus, CANONICAL_URL, FEED_URL};
+use bkmr::format::{opml, set_time_format, Template};
+use bkmr::fzf::{fzf_process, fzf_select, fzf_tags, pick_tags};
+use bkmr::handler;
+use bkmr::handler::{set_handlers, set_pane, set_tag_handlers, targets, Pane};
+use bkmr::helper::{
+    color_choice, ensure_int_vector, init_db, set_color_mode, set_editor, set_pager, ColorMode,
+    MAX_RANGE,
+};
+use bkmr::{keep_fetched_urls, load_url_content, parse_batch, with_lang_tag};
+use bkmr::models::{Bookmark, NewAttachment, NewBookmark};
+use bkmr::process::{
+    bms_to_json, confirm_open, delete_bms, edit_bms, new_bms, open_bms_throttled, print_json,
+    print_open_bm, process, set_open_confirm_above, set_throttle, show_bms, show_bms_compact,
+    show_bms_formatted, show_changes, Throttle,
+};
+use bkmr::mcp;
+use bkmr::protocol;
+use bkmr::protocol::ProtocolAction;
+use bkmr::report;
+use bkmr::rpc;
+use bkmr::serve;
+use bkmr::site;
+use bkmr::stats;
+use bkmr::sync;
 use bkmr::tag::Tags;
+use bkmr::tui;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -39,6 +79,22 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 
+    /// Output as JSON to stdout, no colors and prompts: open and delete require --yes
+    #[arg(long = "json", global = true)]
+    json: bool,
+
+    /// Colorize output, auto: only on a terminal and if NO_COLOR is not set
+    #[arg(long = "color", value_enum, default_value_t = ColorMode::Auto, global = true)]
+    color: ColorMode,
+
+    /// Log format on stderr, json: one JSON object per line
+    #[arg(long = "log-format", value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    /// Do not pipe long output into $PAGER (default: less -FRX)
+    #[arg(long = "no-pager", global = true)]
+    no_pager: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -50,35 +106,8 @@ enum Commands {
         /// FTS query (full text search)
         fts_query: Option<String>,
 
-        #[arg(
-        short = 'e',
-        long = "exact",
-        help = "match exact, comma separated list"
-        )]
-        tags_exact: Option<String>,
-
-        #[arg(short = 't', long = "tags", help = "match all, comma separated list")]
-        tags_all: Option<String>,
-
-        #[arg(
-        short = 'T',
-        long = "Tags",
-        help = "not match all, comma separated list"
-        )]
-        tags_all_not: Option<String>,
-
-        #[arg(short = 'n', long = "ntags", help = "match any, comma separated list")]
-        tags_any: Option<String>,
-
-        #[arg(
-        short = 'N',
-        long = "Ntags",
-        help = "not match any, comma separated list"
-        )]
-        tags_any_not: Option<String>,
-
-        #[arg(long = "prefix", help = "tags to prefix the tags option")]
-        tags_prefix: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
 
         #[arg(short = 'o', long = "descending", help = "order by age, descending")]
         order_desc: bool,
@@ -86,29 +115,90 @@ enum Commands {
         #[arg(short = 'O', long = "ascending", help = "order by age, ascending")]
         order_asc: bool,
 
+        /// order of the bookmarks, frecency: often and recently opened first
+        /// [default: frecency with --fzf and --fzf-tags, title otherwise]
+        #[arg(long = "sort", value_enum, conflicts_with_all = ["order_desc", "order_asc"])]
+        sort: Option<SortOrder>,
+
         #[arg(long = "np", help = "no prompt")]
         non_interactive: bool,
 
         #[arg(
         long = "fzf",
-        help = "use fuzzy finder: [CTRL-O: open, CTRL-E: edit, ENTER: open]"
+        help = "use fuzzy finder: [ENTER: open, CTRL-E: edit, CTRL-O: copy URLs, CTRL-A: more actions]"
         )]
         is_fuzzy: bool,
 
+        /// fuzzy finder drilling down from tags: pick a tag, then its bookmarks;
+        /// ESC or Backspace on an empty query goes back to the tags
+        #[arg(long = "fzf-tags", conflicts_with_all = ["is_fuzzy", "format", "quiet", "compact"])]
+        is_fuzzy_tags: bool,
+
+        /// with --fzf: print the selected bookmarks instead of opening them, as URLs unless
+        /// --format, --quiet --ids or --json is given; exits with 7 if nothing is selected
+        #[arg(long = "print", requires = "is_fuzzy")]
+        print: bool,
+
+        #[arg(
+        long = "format",
+        help = "'tsv' or output template, e.g. '{id}\\t{url}\\t{tags}', fields: id,url,title,tags,desc,flags,ts,extra.<key>"
+        )]
+        format: Option<String>,
+
+        #[arg(
+        short = 'q',
+        long = "quiet",
+        conflicts_with = "format",
+        help = "print only URLs, one per line, no prompt"
+        )]
+        quiet: bool,
+
+        #[arg(long = "ids", requires = "quiet", help = "with --quiet: print only ids")]
+        only_ids: bool,
+
         #[arg(
-        long = "json",
-        help = "non-interactive mode, output as json"
+        long = "compact",
+        conflicts_with_all = ["format", "quiet"],
+        help = "one line per bookmark: id, title, domain, tags"
         )]
-        is_json: bool,
+        compact: bool,
     },
     /// Open/launch bookmarks
     Open {
-        /// list of ids, separated by comma, no blanks
-        ids: String,
+        /// list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin.
+        /// No ids: open the bookmarks matching --query and tag filters
+        #[arg(
+        required_unless_present_any = [
+        "query", "tags_exact", "tags_all", "tags_all_not", "tags_any", "tags_any_not", "tags_prefix"
+        ],
+        conflicts_with_all = [
+        "query", "tags_exact", "tags_all", "tags_all_not", "tags_any", "tags_any_not", "tags_prefix"
+        ]
+        )]
+        ids: Option<String>,
+        #[arg(long = "query", help = "open bookmarks matching FTS query instead of ids")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+        #[arg(short = 'y', long = "yes", help = "open many bookmarks without confirmation")]
+        yes: bool,
+        /// run shell commands in a new tmux window or wezterm tab (config: open.pane)
+        #[arg(long = "in-pane")]
+        in_pane: bool,
+        /// show what would be run: handler, resolved path or command, nothing is opened
+        #[arg(long = "print", conflicts_with = "yes")]
+        print: bool,
+        /// open web pages as snapshot on archive.org, stored by `bkmr archive` or the latest one
+        #[arg(long = "archived")]
+        archived: bool,
+        /// values of placeholders in shell commands and URLs, e.g. `-- pod=web-1` for `{pod}`; missing ones are prompted for
+        #[arg(last = true, value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        args: Vec<(String, String)>,
     },
     /// Add a bookmark
     Add {
-        url: String,
+        #[arg(required_unless_present = "batch")]
+        url: Option<String>,
         /// list of tags, separated by comma, no blanks in between
         tags: Option<String>,
         #[arg(long = "title", help = "title")]
@@ -119,40 +209,268 @@ enum Commands {
         no_web: bool,
         #[arg(short = 'e', long = "edit", help = "edit the bookmark while adding")]
         edit: bool,
+        #[arg(
+        long = "batch",
+        value_name = "FILE",
+        conflicts_with_all = ["url", "tags", "title", "desc", "edit"],
+        help = "add URLs from file ('-': stdin), one per line: url or url|tags|title"
+        )]
+        batch: Option<String>,
+        #[arg(long = "dry-run", requires = "batch", help = "with --batch: show what would be added")]
+        dry_run: bool,
     },
+    /// Write new bookmarks in the editor, no URL fetch: for snippets, shell commands, notes
+    New,
     /// Delete bookmarks
     Delete {
-        /// list of ids, separated by comma, no blanks
+        /// list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin
         ids: String,
+        #[arg(long = "dry-run", help = "show what would be deleted")]
+        dry_run: bool,
+        #[arg(short = 'y', long = "yes", help = "delete without confirmation")]
+        yes: bool,
     },
     /// Update bookmarks
     Update {
-        /// list of ids, separated by comma, no blanks
+        /// list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin
         ids: String,
-        #[arg(short = 't', long = "tags", help = "add tags to taglist")]
+        #[arg(short = 't', long = "tags", value_name = "TAGS", help = "add tags to taglist")]
         tags: Option<String>,
-        #[arg(short = 'n', long = "ntags", help = "remove tags from taglist")]
+        #[arg(short = 'n', long = "ntags", value_name = "TAGS", help = "remove tags from taglist")]
         tags_not: Option<String>,
         #[arg(short = 'f', long = "force", help = "overwrite taglist with tags")]
         force: bool,
+        #[arg(long = "dry-run", help = "show the resulting tags without updating")]
+        dry_run: bool,
+        #[arg(
+        long = "pick-tags",
+        conflicts_with_all = ["tags", "tags_not", "force"],
+        help = "pick tags to add/remove from the existing tags interactively"
+        )]
+        pick_tags: bool,
     },
     /// Edit bookmarks
     Edit {
-        /// Edit bookmarks, list of ids, separated by comma, no blanks
+        /// Edit bookmarks, list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin.
+        /// No ids: the most recently added/updated bookmark
+        ids: Option<String>,
+    },
+    /// Take screenshots of web bookmarks with a headless Chromium, stored as attachments
+    #[cfg(feature = "screenshot")]
+    Snapshot {
+        /// list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin
         ids: String,
     },
-    /// Show Bookmarks (list of ids, separated by comma, no blanks)
-    Show { ids: String },
+    /// Re-fetch title and description of web bookmarks, e.g. added with --no-web or with stale titles
+    Refresh {
+        /// list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin.
+        /// No ids: the bookmarks matching --query and tag filters
+        #[arg(
+        required_unless_present_any = [
+        "query", "tags_exact", "tags_all", "tags_all_not", "tags_any", "tags_any_not", "tags_prefix",
+        "empty_title"
+        ],
+        conflicts_with_all = [
+        "query", "tags_exact", "tags_all", "tags_all_not", "tags_any", "tags_any_not", "tags_prefix"
+        ]
+        )]
+        ids: Option<String>,
+        #[arg(long = "query", help = "refresh bookmarks matching FTS query instead of ids")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+        #[arg(long = "empty-title", help = "only bookmarks without title")]
+        empty_title: bool,
+        #[arg(long = "dry-run", help = "show the changes without updating")]
+        dry_run: bool,
+    },
+    /// Check links of web bookmarks, failures get the tag 'dead' or 'moved' (permanent redirect)
+    Check {
+        /// list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin.
+        /// No ids: all bookmarks matching --query and tag filters
+        #[arg(conflicts_with_all = [
+        "query", "tags_exact", "tags_all", "tags_all_not", "tags_any", "tags_any_not", "tags_prefix"
+        ])]
+        ids: Option<String>,
+        #[arg(long = "query", help = "check bookmarks matching FTS query instead of ids")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+        #[arg(long = "dry-run", help = "report without tagging")]
+        dry_run: bool,
+    },
+    /// Request snapshots of web bookmarks on archive.org, stored as extra.archive_url
+    Archive {
+        /// list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin.
+        /// No ids: the bookmarks matching --query and tag filters
+        #[arg(
+        required_unless_present_any = [
+        "query", "tags_exact", "tags_all", "tags_all_not", "tags_any", "tags_any_not", "tags_prefix"
+        ],
+        conflicts_with_all = [
+        "query", "tags_exact", "tags_all", "tags_all_not", "tags_any", "tags_any_not", "tags_prefix"
+        ]
+        )]
+        ids: Option<String>,
+        #[arg(long = "query", help = "archive bookmarks matching FTS query instead of ids")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+    },
+    /// Show Bookmarks (list of ids, separated by comma, ranges allowed: 1-5,8, '-': read from stdin)
+    Show {
+        /// No ids: the most recently added/updated bookmark
+        #[arg(conflicts_with_all = [
+        "query", "tags_exact", "tags_all", "tags_all_not", "tags_any", "tags_any_not", "tags_prefix"
+        ])]
+        ids: Option<String>,
+        #[arg(long = "query", help = "show bookmarks matching FTS query instead of ids")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+        #[arg(long = "format", help = "'tsv' or output template, e.g. '{id}\\t{url}\\t{tags}'")]
+        format: Option<String>,
+        #[arg(
+        long = "compact",
+        conflicts_with = "format",
+        help = "one line per bookmark: id, title, domain, tags"
+        )]
+        compact: bool,
+    },
+    /// Terminal UI: searchable list, details and keys to open, edit, delete and tag bookmarks
+    Tui {
+        /// FTS query (full text search) selecting the bookmarks
+        fts_query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+    },
     /// Tag for which related tags should be shown. No input: all tags are printed
     Tags {
         /// Tag for which related tags should be shown. No input: all tags are shown
         tag: Option<String>,
     },
+    /// Overview: bookmarks per kind (web, shell, file, snippet), top tags, additions per month, database size
+    #[command(args_conflicts_with_subcommands = true)]
+    Stats {
+        #[command(subcommand)]
+        command: Option<StatsCommands>,
+        /// number of most used tags shown
+        #[arg(long, default_value = "10")]
+        top: usize,
+    },
+    /// Maintenance reports
+    Report {
+        #[command(subcommand)]
+        command: ReportCommands,
+    },
     /// Initialize bookmark database
     CreateDb {
         /// pathname to database file
         path: String,
     },
+    /// User defined fields per bookmark (stored as JSON)
+    Meta {
+        #[command(subcommand)]
+        command: MetaCommands,
+    },
+    /// Files attached to bookmarks (snapshots, PDFs, screenshots)
+    Attach {
+        #[command(subcommand)]
+        command: AttachCommands,
+    },
+    /// RSS and Atom feeds found on bookmarked pages, stored as extra.feed_url
+    Feeds {
+        #[command(subcommand)]
+        command: FeedsCommands,
+    },
+    /// Database maintenance: portable SQL dump and load
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// Export bookmarks, site: static HTML pages with search, e.g. for GitHub Pages
+    Export {
+        #[arg(long = "format", value_enum)]
+        format: ExportFormat,
+        /// target directory, created if missing
+        dir: String,
+        #[arg(long = "title", default_value = "Bookmarks", help = "title of the pages")]
+        title: String,
+        #[arg(long = "no-favicons", help = "do not download the icons of the sites")]
+        no_favicons: bool,
+        #[arg(long = "query", help = "bookmarks matching FTS query")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+    },
+    /// Sync bookmarks across machines, merging local and remote changes
+    Sync {
+        #[command(subcommand)]
+        command: SyncCommands,
+    },
+    /// Change feed as JSON: adds, updates and deletes after a cursor, for mirroring the bookmarks
+    Changes {
+        /// `next` of the previous feed [default: from the start]
+        #[arg(long, default_value = "")]
+        since: String,
+        /// maximum number of changes, the next feed continues after them
+        #[arg(long)]
+        limit: Option<i64>,
+    },
+    /// bkmr:// URLs for other applications and web pages: bkmr://open/<id>, bkmr://add?url=<url>
+    Protocol {
+        #[command(subcommand)]
+        command: ProtocolCommands,
+    },
+    /// JSON-RPC on stdin/stdout for editor plugins, one request per line: search, add, open, tags
+    Rpc,
+    /// Model Context Protocol server on stdin/stdout for LLM assistants: search, add, open bookmarks
+    Mcp,
+    /// Web UI to search, add and open bookmarks, e.g. from a phone: http://<host>:<port>/
+    Serve {
+        /// address to listen on, 0.0.0.0 for all machines of the network
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+        /// port to listen on [default: $BKMR_PORT or 9999]
+        #[arg(long)]
+        port: Option<u16>,
+        /// also serve search, add and open on the DBus session bus as de.sysid.Bkmr (Linux)
+        #[arg(long)]
+        dbus: bool,
+    },
+    /// Long running process: web UI and REST of serve, DBus service (Linux), change notifications
+    Daemon {
+        /// address to listen on, 0.0.0.0 for all machines of the network
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+        /// port to listen on [default: $BKMR_PORT or 9999]
+        #[arg(long)]
+        port: Option<u16>,
+        /// no DBus service, e.g. without session bus
+        #[arg(long)]
+        no_dbus: bool,
+        /// milliseconds between checks of the database for changes
+        #[arg(long, default_value = "1000")]
+        interval_ms: u64,
+    },
+    /// gRPC server of search and CRUD, the service bkmr.v1.Bookmarks of proto/bkmr.proto
+    #[cfg(feature = "grpc")]
+    Grpc {
+        /// address to listen on, 0.0.0.0 for all machines of the network
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+        /// port to listen on
+        #[arg(long, default_value = "50051")]
+        port: u16,
+        /// print the service definition instead
+        #[arg(long)]
+        proto: bool,
+    },
+    /// Print shell completion script, e.g. `bkmr completions zsh > ~/.zfunc/_bkmr`
+    Completions {
+        /// target shell
+        shell: Shell,
+    },
     #[command(hide = true)]
     Xxx {
         /// list of ids, separated by comma, no blanks
@@ -162,14 +480,338 @@ enum Commands {
     },
 }
 
+/// tag filters shared by commands which select bookmarks
+#[derive(Args, Debug, Default, Clone)]
+struct TagFilter {
+    #[arg(
+    short = 'e',
+    long = "exact",
+    value_name = "TAGS",
+    help = "match exact, comma separated list"
+    )]
+    tags_exact: Option<String>,
+
+    #[arg(short = 't', long = "tags", value_name = "TAGS", help = "match all, comma separated list")]
+    tags_all: Option<String>,
+
+    #[arg(
+    short = 'T',
+    long = "Tags",
+    value_name = "TAGS",
+    help = "not match all, comma separated list"
+    )]
+    tags_all_not: Option<String>,
+
+    #[arg(short = 'n', long = "ntags", value_name = "TAGS", help = "match any, comma separated list")]
+    tags_any: Option<String>,
+
+    #[arg(
+    short = 'N',
+    long = "Ntags",
+    value_name = "TAGS",
+    help = "not match any, comma separated list"
+    )]
+    tags_any_not: Option<String>,
+
+    #[arg(long = "prefix", value_name = "TAGS", help = "tags to prefix the tags option")]
+    tags_prefix: Option<String>,
+}
+
+impl TagFilter {
+    fn is_empty(&self) -> bool {
+        self.tags_exact.is_none()
+            && self.tags_all.is_none()
+            && self.tags_all_not.is_none()
+            && self.tags_any.is_none()
+            && self.tags_any_not.is_none()
+            && self.tags_prefix.is_none()
+    }
+}
+
+#[derive(Subcommand)]
+enum MetaCommands {
+    /// Set field <key> of bookmark <id> to <value>
+    Set { id: i32, key: String, value: String },
+    /// Print field <key> of bookmark <id>, all fields as JSON if no key is given
+    Get { id: i32, key: Option<String> },
+    /// Remove field <key> from bookmark <id>
+    Unset { id: i32, key: String },
+}
+
+#[derive(Subcommand)]
+enum AttachCommands {
+    /// Attach a file to bookmark <id>
+    Add {
+        id: i32,
+        /// file to attach
+        file: Option<String>,
+        #[arg(long = "snapshot", help = "attach an HTML snapshot of the bookmarked URL")]
+        snapshot: bool,
+    },
+    /// List attachments of bookmark <id>
+    List { id: i32 },
+    /// Open an attachment of bookmark <id>, default: the latest one
+    Open {
+        id: i32,
+        /// attachment id as shown by `attach list`
+        attachment: Option<i32>,
+    },
+    /// Remove an attachment (attachment id as shown by `attach list`)
+    Remove { attachment: i32 },
+}
+
+#[derive(Subcommand)]
+enum FeedsCommands {
+    /// List bookmarks with a feed: id, feed URL, title
+    List {
+        #[arg(long = "query", help = "bookmarks matching FTS query")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+    },
+    /// Write the feeds as OPML to stdout, e.g. to import them into a feed reader
+    Export {
+        #[arg(long = "query", help = "bookmarks matching FTS query")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// Write a plain-text SQL dump (schema and data) to stdout
+    Dump,
+    /// Load a SQL dump into the database
+    Load {
+        /// dump file created by `bkmr db dump`
+        file: String,
+        #[arg(short = 'f', long = "force", help = "replace existing database content")]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SyncCommands {
+    /// Sync via a git repository holding an export of the bookmarks (bookmarks.ndjson)
+    Git {
+        /// git repository, default: `[sync.git] dir` of the config
+        dir: Option<String>,
+        #[arg(long = "remote", help = "remote to pull from and push to, default: origin")]
+        remote: Option<String>,
+        #[arg(long = "dry-run", help = "show the changes to the local bookmarks")]
+        dry_run: bool,
+    },
+    /// Sync with the bkmr database of another machine over ssh, both get the merged bookmarks
+    Ssh {
+        /// e.g. user@host or a host of ~/.ssh/config
+        destination: String,
+        #[arg(long = "bkmr", help = "bkmr command on the peer, default: bkmr")]
+        bkmr: Option<String>,
+        #[arg(long = "dry-run", help = "show the changes to both databases")]
+        dry_run: bool,
+    },
+    /// Sync through an age encrypted snapshot on S3-compatible or WebDAV storage: `[sync.cloud]`
+    Cloud {
+        #[arg(long = "dry-run", help = "show the changes to the bookmarks and the snapshot")]
+        dry_run: bool,
+    },
+    /// Write the export of the bookmarks (one JSON line per bookmark) to stdout
+    Export,
+    /// Replace the bookmarks by an export read from stdin, used by `sync ssh` on the peer
+    #[command(hide = true)]
+    Import,
+}
+
+#[derive(Subcommand)]
+enum ProtocolCommands {
+    /// Register bkmr as handler of bkmr:// URLs with the OS (xdg, macOS)
+    Install,
+    /// Run the action of a bkmr:// URL, called by the OS
+    Handle {
+        /// e.g. bkmr://open/12 or bkmr://add?url=https%3A%2F%2Fwww.sysid.de&tags=blog&title=sysid
+        url: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum ExportFormat {
+    /// static HTML site of the web bookmarks: index, tag pages, favicons
+    Site,
+}
+
+#[derive(Subcommand)]
+enum ReportCommands {
+    /// Dead and moved links of the last check, duplicates, untagged bookmarks and empty titles,
+    /// with the commands fixing them
+    Health {
+        #[arg(long = "query", help = "bookmarks matching FTS query")]
+        query: Option<String>,
+        #[command(flatten)]
+        filter: TagFilter,
+    },
+}
+
+#[derive(Subcommand)]
+enum StatsCommands {
+    /// Most opened bookmarks of a period, counted by the log of opens
+    Top {
+        /// only bookmarks with this tag
+        #[arg(short = 't', long = "tag")]
+        tag: Option<String>,
+        /// period before now, e.g. 12h, 30d or 2w [default: all opens]
+        #[arg(long, value_parser = stats::parse_period)]
+        since: Option<chrono::Duration>,
+        /// maximum number of bookmarks
+        #[arg(long, default_value = "10")]
+        limit: i64,
+    },
+    /// Web bookmarks per host: number, average age and dead links, most bookmarks first
+    Domains {
+        /// maximum number of hosts
+        #[arg(long, default_value = "20")]
+        limit: usize,
+    },
+    /// Additions and opens per day as a grid of weeks, like the contribution graph of GitHub
+    Heatmap {
+        /// number of weeks up to today
+        #[arg(long, default_value = "53")]
+        weeks: u32,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum SortOrder {
+    Title,
+    /// visits weighted by the age of the last one, ties by title
+    Frecency,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
 fn main() {
-    // let stdout = StandardStream::stdout(ColorChoice::Always);
+    let args: Vec<String> = env::args().collect();
+    // config is needed before parsing the command line: it may define aliases
+    let config = config_arg(&args);
+    let settings = Settings::load(config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    let args = expand_alias(args, &settings.aliases).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    let cli = Cli::parse_from(args);
+    set_color_mode(cli.color);
+    set_pager(!cli.no_pager);
+    if let Some(time_format) = &settings.display.time_format {
+        set_time_format(time_format).unwrap_or_else(|e| {
+            eprintln!("display.time_format: {}", e);
+            process::exit(exitcode::USAGE);
+        });
+    }
+    if let Some(editor) = &settings.editor {
+        set_editor(editor);
+    }
+    set_handlers(settings.handlers.clone());
+    set_tag_handlers(settings.tag_handlers.clone());
+    if let Some(concurrency) = settings.fetch.concurrency {
+        bkmr::fetch::set_concurrency(concurrency);
+    }
+    if let Some(canonical) = settings.fetch.canonical {
+        bkmr::fetch::set_canonical(canonical);
+    }
+    if let Some(index_content) = settings.fetch.index_content {
+        bkmr::fetch::set_index_content(index_content);
+    }
+    if let Some(detect_language) = settings.fetch.detect_language {
+        bkmr::fetch::set_detect_language(detect_language);
+    }
+    bkmr::fetch::set_http(settings.http.clone()).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    bkmr::fetch::set_hosts(settings.fetch.hosts.clone()).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    bkmr::webhook::set_webhooks(settings.webhooks.clone()).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    serve::set_hosts(settings.serve.hosts.clone());
+    serve::set_users(settings.serve.users.clone()).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    bkmr::fzf::set_keys(&settings.fzf.keys).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    bkmr::fzf::set_columns(&settings.fzf.columns).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    if let Some(color) = &settings.fzf.color {
+        bkmr::fzf::set_color(color);
+    }
+    if let Some(external) = &settings.fzf.external {
+        bkmr::fzf::set_external(external).unwrap_or_else(|e| {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::USAGE);
+        });
+    }
+    tui::set_keys(&settings.tui.keys).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    if let Some(conflict) = settings.sync.conflict {
+        sync::set_conflict(conflict);
+    }
+    bkmr::theme::set_theme(&settings.theme).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    #[cfg(feature = "screenshot")]
+    {
+        if let Some(browser) = &settings.screenshot.browser {
+            bkmr::screenshot::set_browser(browser);
+        }
+        if let Some(window_size) = &settings.screenshot.window_size {
+            bkmr::screenshot::set_window_size(window_size);
+        }
+    }
+    if let Some(confirm_above) = settings.open.confirm_above {
+        set_open_confirm_above(confirm_above);
+    }
+    let in_pane = matches!(cli.command, Some(Commands::Open { in_pane: true, .. }));
+    match (in_pane, settings.open.pane) {
+        (true, pane) => set_pane(pane.unwrap_or(Pane::Auto)),
+        (false, Some(pane)) => set_pane(pane),
+        (false, None) => {}
+    }
+    let throttle = Throttle::default();
+    set_throttle(Throttle {
+        concurrency: settings.open.concurrency.unwrap_or(throttle.concurrency),
+        delay: settings
+            .open
+            .delay_ms
+            .map(Duration::from_millis)
+            .unwrap_or(throttle.delay),
+    });
+    load_edit_template(config_dir(config.as_deref()).join(TEMPLATE_FILE)).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
     // use stderr as human output in order to make stdout output passable to downstream processes
-    let stderr = StandardStream::stderr(ColorChoice::Always);
-
-    let cli = Cli::parse();
+    let stderr = StandardStream::stderr(color_choice(Stream::Stderr));
 
-    set_logger(&cli);
+    set_logger(&cli, &settings);
 
     let Some(command) = cli.command else {
         eprintln!("No command given. Usage: bkmr <command> [options]"); // TODO: use clap native
@@ -179,35 +821,66 @@ fn main() {
     match command {
         Commands::Search {
             fts_query,
-            tags_exact,
-            tags_all,
-            tags_all_not,
-            tags_any,
-            tags_any_not,
-            tags_prefix,
+            filter,
             order_desc,
             order_asc,
+            sort,
             non_interactive,
             is_fuzzy,
-            is_json,
+            is_fuzzy_tags,
+            print,
+            format,
+            quiet,
+            only_ids,
+            compact,
         } => {
-            if let Some(_value) = search_bookmarks(
-                tags_prefix,
-                tags_all,
+            let template = match (quiet, only_ids) {
+                (true, true) => Some(parse_template("{id}")),
+                (true, false) => Some(parse_template("{url}")),
+                _ => format.map(|f| parse_template(&f)),
+            };
+            let frecent = |default| sort.map_or(default, |sort| sort == SortOrder::Frecency);
+            if is_fuzzy_tags {
+                let mut bms = filter_bookmarks(fts_query, filter).bms;
+                sort_bookmarks(&mut bms, order_desc, order_asc, frecent(true));
+                fzf_tags(&bms);
+            } else if let Some(_value) = search_bookmarks(
                 fts_query,
-                tags_any,
-                tags_all_not,
-                tags_any_not,
-                tags_exact,
-                order_desc,
-                order_asc,
-                is_fuzzy,
-                is_json,
-                non_interactive,
+                filter,
+                SearchOpts {
+                    order_desc,
+                    order_asc,
+                    frecent: frecent(is_fuzzy),
+                    is_fuzzy,
+                    print,
+                    is_json: cli.json,
+                    template,
+                    compact,
+                    non_interactive,
+                },
                 stderr,
             ) {}
         }
-        Commands::Open { ids } => open_bookmarks(ids),
+        Commands::Open {
+            ids,
+            query,
+            filter,
+            yes,
+            print,
+            archived,
+            args,
+            ..
+        } => {
+            let ids = match ids {
+                Some(ids) => get_ids(ids).unwrap(),
+                None => matching_ids(query, filter),
+            };
+            if print {
+                print_open_bookmarks(ids, args.into_iter().collect(), archived);
+                return;
+            }
+            open_bookmarks(ids, args.into_iter().collect(), yes, cli.json, archived)
+        }
         Commands::Add {
             url,
             tags,
@@ -215,18 +888,140 @@ fn main() {
             desc,
             no_web,
             edit,
-        } => add_bookmark(url, tags, title, desc, no_web, edit),
-        Commands::Delete { ids } => delete_bookmarks(ids),
+            batch,
+            dry_run,
+        } => match (batch, url) {
+            (Some(batch), _) => add_bookmarks_batch(batch, no_web, dry_run, cli.json),
+            (None, Some(url)) => add_bookmark(url, tags, title, desc, no_web, edit, cli.json),
+            (None, None) => unreachable!("clap requires url or --batch"),
+        },
+        Commands::New => new_bookmarks(cli.json),
+        Commands::Delete { ids, dry_run, yes } => delete_bookmarks(ids, dry_run, yes, cli.json),
         Commands::Update {
             ids,
             tags,
             tags_not,
             force,
-        } => update_bookmarks(force, tags, tags_not, ids),
-        Commands::Edit { ids } => edit_bookmarks(ids),
-        Commands::Show { ids } => show_bookmarks(ids),
-        Commands::Tags { tag } => show_tags(tag),
-        Commands::CreateDb { path } => create_db(path),
+            dry_run,
+            pick_tags,
+        } => update_bookmarks(force, tags, tags_not, ids, dry_run, pick_tags, cli.json),
+        Commands::Edit { ids } => edit_bookmarks(ids, cli.json),
+        #[cfg(feature = "screenshot")]
+        Commands::Snapshot { ids } => snapshot_bookmarks(ids),
+        Commands::Archive { ids, query, filter } => archive_bookmarks(ids, query, filter, cli.json),
+        Commands::Check {
+            ids,
+            query,
+            filter,
+            dry_run,
+        } => check_bookmarks(ids, query, filter, dry_run, cli.json),
+        Commands::Refresh {
+            ids,
+            query,
+            filter,
+            empty_title,
+            dry_run,
+        } => refresh_bookmarks(ids, query, filter, empty_title, dry_run, cli.json),
+        Commands::Show {
+            ids,
+            query,
+            filter,
+            format,
+            compact,
+        } => {
+            let template = format.map(|f| parse_template(&f));
+            if query.is_some() || !filter.is_empty() {
+                show_matching_bookmarks(query, filter, template, compact, cli.json)
+            } else {
+                show_bookmarks(ids, template, compact, cli.json)
+            }
+        }
+        Commands::Tui { fts_query, filter } => run_tui(fts_query, filter),
+        Commands::Tags { tag } => show_tags(tag, cli.json),
+        Commands::Stats { command, top } => match command {
+            Some(StatsCommands::Top { tag, since, limit }) => {
+                show_most_opened(tag, since, limit, cli.json)
+            }
+            Some(StatsCommands::Heatmap { weeks }) => show_heatmap(weeks, cli.json),
+            Some(StatsCommands::Domains { limit }) => show_domains(limit, cli.json),
+            None => show_stats(top, cli.json),
+        },
+        Commands::Report { command } => match command {
+            ReportCommands::Health { query, filter } => show_health(query, filter, cli.json),
+        },
+        Commands::CreateDb { path } => create_db(path, cli.json),
+        Commands::Meta { command } => match command {
+            MetaCommands::Set { id, key, value } => set_meta(id, key, Some(value), cli.json),
+            MetaCommands::Get { id, key } => get_meta(id, key, cli.json),
+            MetaCommands::Unset { id, key } => set_meta(id, key, None, cli.json),
+        },
+        Commands::Attach { command } => match command {
+            AttachCommands::Add { id, file, snapshot } => {
+                add_attachment(id, file, snapshot, cli.json)
+            }
+            AttachCommands::List { id } => list_attachments(id, cli.json),
+            AttachCommands::Open { id, attachment } => open_attachment(id, attachment, cli.json),
+            AttachCommands::Remove { attachment } => remove_attachment(attachment, cli.json),
+        },
+        Commands::Feeds { command } => match command {
+            FeedsCommands::List { query, filter } => list_feeds(query, filter, cli.json),
+            FeedsCommands::Export { query, filter } => {
+                print!("{}", opml(&feed_bookmarks(query, filter)))
+            }
+        },
+        Commands::Db { command } => match command {
+            DbCommands::Dump => dump_db(),
+            DbCommands::Load { file, force } => load_db(file, force, cli.json),
+        },
+        Commands::Export {
+            format: ExportFormat::Site,
+            dir,
+            title,
+            no_favicons,
+            query,
+            filter,
+        } => export_site(&dir, &title, !no_favicons, query, filter),
+        Commands::Changes { since, limit } => print_change_feed(&since, limit),
+        Commands::Sync { command } => match command {
+            SyncCommands::Git {
+                dir,
+                remote,
+                dry_run,
+            } => sync_git(
+                dir.or(settings.sync.git.dir),
+                remote.or(settings.sync.git.remote),
+                dry_run,
+            ),
+            SyncCommands::Ssh {
+                destination,
+                bkmr,
+                dry_run,
+            } => sync_ssh(
+                destination,
+                settings.sync.ssh.ssh.as_deref().unwrap_or("ssh"),
+                bkmr.or(settings.sync.ssh.bkmr).as_deref().unwrap_or("bkmr"),
+                dry_run,
+            ),
+            SyncCommands::Cloud { dry_run } => sync_cloud(&settings.sync.cloud, dry_run),
+            SyncCommands::Export => sync_export(),
+            SyncCommands::Import => sync_import(),
+        },
+        Commands::Protocol { command } => match command {
+            ProtocolCommands::Install => install_protocol(),
+            ProtocolCommands::Handle { url } => handle_protocol_url(url),
+        },
+        Commands::Rpc => run_rpc(),
+        Commands::Mcp => run_mcp(),
+        Commands::Serve { bind, port, dbus } => run_serve(bind, port, dbus),
+        Commands::Daemon {
+            bind,
+            port,
+            no_dbus,
+            interval_ms,
+        } => run_daemon(bind, port, !no_dbus, Duration::from_millis(interval_ms)),
+        #[cfg(feature = "grpc")]
+        Commands::Grpc { bind, port, proto } => run_grpc(bind, port, proto),
+        Commands::Completions { shell } => print_completions(shell),
         Commands::Xxx { ids, tags } => {
             eprintln!(
                 "({}:{}) ids: {:?}, tags: {:?}",
@@ -240,21 +1035,46 @@ fn main() {
     // Continued program logic goes here...
 }
 
-fn search_bookmarks(
-    tags_prefix: Option<String>,
-    tags_all: Option<String>,
+/// bookmarks matching the FTS query and tag filters
+/// exits with a usage error if `fts_query` is no valid FTS syntax
+fn filter_bookmarks(fts_query: Option<String>, filter: TagFilter) -> Bookmarks {
+    try_filter_bookmarks(fts_query, filter).unwrap_or_else(|e| match e {
+        DieselError::DatabaseError(_, ref info) if is_query_error(info.message()) => {
+            eprintln!("Invalid query: {}", e);
+            process::exit(exitcode::USAGE);
+        }
+        e => {
+            eprintln!("Error getting bookmarks: {:?}", e);
+            process::exit(exitcode::from_diesel(&e));
+        }
+    })
+}
+
+/// errors of SQLite for invalid FTS queries, e.g. `"` or `unknown:term`
+fn is_query_error(message: &str) -> bool {
+    [
+        "fts5:",
+        "unterminated string",
+        "no such column",
+        "unknown special query",
+    ]
+    .iter()
+    .any(|prefix| message.starts_with(prefix))
+}
+
+/// fails if `fts_query` is no valid FTS syntax
+fn try_filter_bookmarks(
     fts_query: Option<String>,
-    tags_any: Option<String>,
-    tags_all_not: Option<String>,
-    tags_any_not: Option<String>,
-    tags_exact: Option<String>,
-    order_desc: bool,
-    order_asc: bool,
-    is_fuzzy: bool,
-    is_json: bool,
-    non_interactive: bool,
-    mut stderr: StandardStream,
-) -> Option<()> {
+    filter: TagFilter,
+) -> Result<Bookmarks, DieselError> {
+    let TagFilter {
+        tags_exact,
+        tags_all,
+        tags_all_not,
+        tags_any,
+        tags_any_not,
+        tags_prefix,
+    } = filter;
     let _tags_all = if let Some(tags_prefix) = tags_prefix {
         if let Some(tags_all) = tags_all {
             format!("{},{}", tags_all, tags_prefix)
@@ -264,9 +1084,9 @@ fn search_bookmarks(
     } else {
         tags_all.clone().unwrap_or_default()
     };
-    debug!("({}:{}) tags: {:?}", function_name!(), line!(), _tags_all);
+    debug!("tags: {:?}", _tags_all);
     let fts_query = fts_query.unwrap_or_default();
-    let mut bms = Bookmarks::new(fts_query);
+    let mut bms = Bookmarks::try_new(fts_query)?;
     bms.filter(
         Some(_tags_all),
         tags_any,
@@ -274,107 +1094,347 @@ fn search_bookmarks(
         tags_any_not,
         tags_exact,
     );
-    if order_desc {
-        debug!(
-            "({}:{}) order_desc {:?}",
-            function_name!(),
-            line!(),
-            order_desc
-        );
-        bms.bms.sort_by_key(|bm| bm.last_update_ts);
-        bms.bms.reverse();
-    } else if order_asc {
-        debug!(
-            "({}:{}) order_asc {:?}",
-            function_name!(),
-            line!(),
-            order_asc
-        );
-        bms.bms.sort_by_key(|bm| bm.last_update_ts);
-    } else {
-        debug!("({}:{}) order_by_metadata", function_name!(), line!());
-        bms.bms.sort_by_key(|bm| bm.metadata.to_lowercase())
-    }
-    if is_fuzzy {
-        fzf_process(&bms.bms);
-        return Some(());
-    }
-    debug!("({}:{})\n{:#?}\n", function_name!(), line!(), bms.bms);
-    if is_json {
-        bms_to_json(&bms.bms);
-        return None;
-    }
-    show_bms(&bms.bms);
-    eprintln!("Found {} bookmarks", bms.bms.len());
+    Ok(bms)
+}
 
-    if non_interactive {
-        debug!("Non Interactive. Exiting..");
-        let ids: Vec<String> = bms.bms.iter().map(|bm| bm.id).sorted().map(|id| id.to_string()).collect();
-        println!("{}", ids.join(","));
-    } else {
-        stderr
-            .set_color(ColorSpec::new().set_fg(Some(Color::Green)))
-            .unwrap();
-        writeln!(&mut stderr, "Selection: ").unwrap();
-        stderr.reset().unwrap();
-        process(&bms.bms);
+fn run_rpc() {
+    rpc::serve(io::stdin().lock(), io::stdout().lock()).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::IO);
+    });
+}
+
+fn export_site(dir: &str, title: &str, favicons: bool, query: Option<String>, filter: TagFilter) {
+    let bms = filter_bookmarks(query, filter).bms;
+    let dir = PathBuf::from(shellexpand::tilde(dir).as_ref());
+    match site::export_site(&bms, &dir, title, favicons) {
+        Ok(n) => eprintln!("Exported {} web bookmarks to {}", n, dir.display()),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::IO);
+        }
     }
-    None
 }
 
-fn open_bookmarks(ids: String) {
-    let mut dal = Dal::new(CONFIG.db_url.clone());
-    let ids = get_ids(ids);
-    for id in ids.unwrap() {
-        let bm = dal.get_bookmark_by_id(id);
-        match bm {
-            Ok(bm) => {
-                debug!("({}:{}) Opening {:?}", function_name!(), line!(), bm);
-                open_bm(&bm).unwrap();
-                // open::that(bm.URL).unwrap();
-            }
-            Err(_) => {
-                error!(
-                    "({}:{}) Bookmark with id {} not found",
-                    function_name!(),
-                    line!(),
-                    id
-                );
-            }
+fn print_change_feed(since: &str, limit: Option<i64>) {
+    match changes::feed(&mut Dal::default(), since, limit) {
+        Ok(feed) => print_json(&feed),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::USAGE);
         }
     }
 }
 
-fn add_bookmark(
-    url: String,
-    tags: Option<String>,
-    title: Option<String>,
-    desc: Option<String>,
-    no_web: bool,
-    edit: bool,
-) {
-    let mut dal = Dal::new(CONFIG.db_url.clone());
-    debug!(
-        "({}:{}) Add {:?}, {:?}, {:?}, {:?}, {:?}, {:?}",
-        function_name!(),
-        line!(),
-        url,
-        tags,
-        title,
-        desc,
-        no_web,
-        edit,
-    );
+fn run_mcp() {
+    mcp::serve(io::stdin().lock(), io::stdout().lock()).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::IO);
+    });
+}
 
-    let unknown_tags =
-        Bookmarks::new("".to_string()).check_tags(Tags::normalize_tag_string(tags.clone()));
-    if !unknown_tags.is_empty() {
-        debug!(
-            "({}:{}) unknown_tags: {:?}",
-            function_name!(),
-            line!(),
-            unknown_tags
-        );
+fn run_serve(bind: String, port: Option<u16>, dbus: bool) {
+    if dbus {
+        serve_dbus();
+    }
+    let listener = listen(&bind, port);
+    serve::serve(listener).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::IO);
+    });
+}
+
+fn run_daemon(bind: String, port: Option<u16>, dbus: bool, interval: Duration) {
+    let listener = listen(&bind, port);
+    daemon::run(listener, dbus, interval).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::IO);
+    });
+}
+
+#[cfg(feature = "grpc")]
+fn run_grpc(bind: String, port: u16, proto: bool) {
+    if proto {
+        print!("{}", bkmr::grpc::PROTO);
+        return;
+    }
+    let listener = TcpListener::bind((bind.as_str(), port)).unwrap_or_else(|e| {
+        eprintln!("Cannot listen on {}:{}: {}", bind, port, e);
+        process::exit(exitcode::USAGE);
+    });
+    eprintln!("Serving bookmarks over gRPC on {}:{}", bind, port);
+    bkmr::grpc::serve(listener).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::IO);
+    });
+}
+
+fn listen(bind: &str, port: Option<u16>) -> TcpListener {
+    let address = (bind, port.unwrap_or(CONFIG.port));
+    let listener = TcpListener::bind(address).unwrap_or_else(|e| {
+        eprintln!("Cannot listen on {}:{}: {}", address.0, address.1, e);
+        process::exit(exitcode::USAGE);
+    });
+    eprintln!("Serving bookmarks on http://{}:{}/", address.0, address.1);
+    listener
+}
+
+/// registers on the session bus before serving, so that a taken name fails early
+#[cfg(target_os = "linux")]
+fn serve_dbus() {
+    bkmr::dbus::spawn().unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    eprintln!(
+        "Serving bookmarks on the session bus as {}",
+        bkmr::dbus::NAME
+    );
+}
+
+#[cfg(not(target_os = "linux"))]
+fn serve_dbus() {
+    eprintln!("DBus is only supported on Linux");
+    process::exit(exitcode::USAGE);
+}
+
+fn run_tui(fts_query: Option<String>, filter: TagFilter) {
+    let mut bms = filter_bookmarks(fts_query.clone(), filter.clone()).bms;
+    sort_bookmarks(&mut bms, false, false, true);
+    let load = |query: &str| {
+        // the query typed in the TUI narrows the one given
+        let query = [fts_query.as_deref().unwrap_or_default(), query]
+            .iter()
+            .filter(|q| !q.trim().is_empty())
+            .map(|q| format!("({})", q))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let query = Some(query).filter(|q| !q.is_empty());
+        let mut bms = try_filter_bookmarks(query, filter.clone())?.bms;
+        sort_bookmarks(&mut bms, false, false, true);
+        Ok(bms)
+    };
+    tui::run(bms, load).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::IO);
+    });
+}
+
+/// by last update if `order_desc` or `order_asc`, else by frecency if `frecent` and by title
+fn sort_bookmarks(bms: &mut [Bookmark], order_desc: bool, order_asc: bool, frecent: bool) {
+    if order_desc {
+        debug!("order_desc {:?}", order_desc);
+        bms.sort_by_key(|bm| bm.last_update_ts);
+        bms.reverse();
+    } else if order_asc {
+        debug!("order_asc {:?}", order_asc);
+        bms.sort_by_key(|bm| bm.last_update_ts);
+    } else {
+        debug!("order_by_metadata");
+        bms.sort_by_key(|bm| bm.metadata.to_lowercase());
+        if frecent {
+            // stable: equally frecent bookmarks stay ordered by title
+            let now = chrono::Utc::now().naive_utc();
+            bms.sort_by_key(|bm| std::cmp::Reverse(bm.frecency(now)));
+        }
+    }
+}
+
+/// how `bkmr search` sorts and shows the bookmarks found
+struct SearchOpts {
+    order_desc: bool,
+    order_asc: bool,
+    /// most frecent first, see `sort_bookmarks`
+    frecent: bool,
+    is_fuzzy: bool,
+    /// fzf prints the selection instead of opening it
+    print: bool,
+    is_json: bool,
+    template: Option<Template>,
+    compact: bool,
+    non_interactive: bool,
+}
+
+fn search_bookmarks(
+    fts_query: Option<String>,
+    filter: TagFilter,
+    opts: SearchOpts,
+    mut stderr: StandardStream,
+) -> Option<()> {
+    let SearchOpts {
+        order_desc,
+        order_asc,
+        frecent,
+        is_fuzzy,
+        print,
+        is_json,
+        template,
+        compact,
+        non_interactive,
+    } = opts;
+    let mut bms = filter_bookmarks(fts_query.clone(), filter.clone());
+    sort_bookmarks(&mut bms.bms, order_desc, order_asc, frecent);
+    let reload = |query: &str| {
+        let query = Some(query.to_string()).filter(|q| !q.trim().is_empty());
+        let mut bms = try_filter_bookmarks(query, filter.clone())?.bms;
+        sort_bookmarks(&mut bms, order_desc, order_asc, frecent);
+        Ok(bms)
+    };
+    if is_fuzzy && print {
+        let Some(selected) = fzf_select(&bms.bms, fts_query.as_deref(), reload) else {
+            process::exit(exitcode::ABORTED);
+        };
+        if is_json {
+            bms_to_json(&selected);
+        } else {
+            let template = template.unwrap_or_else(|| parse_template("{url}"));
+            show_bms_formatted(&selected, &template);
+        }
+        return None;
+    }
+    if is_fuzzy {
+        fzf_process(&bms.bms, fts_query.as_deref(), reload);
+        return Some(());
+    }
+    debug!("\n{:#?}\n", bms.bms);
+    if is_json {
+        bms_to_json(&bms.bms);
+        return None;
+    }
+    if let Some(template) = template {
+        show_bms_formatted(&bms.bms, &template);
+        return None;
+    }
+    if compact {
+        show_bms_compact(&bms.bms);
+    } else {
+        show_bms(&bms.bms);
+    }
+    eprintln!("Found {} bookmarks", bms.bms.len());
+
+    if non_interactive {
+        debug!("Non Interactive. Exiting..");
+        let ids: Vec<String> = bms.bms.iter().map(|bm| bm.id).sorted().map(|id| id.to_string()).collect();
+        println!("{}", ids.join(","));
+    } else {
+        stderr
+            .set_color(ColorSpec::new().set_fg(Some(Color::Green)))
+            .unwrap();
+        writeln!(&mut stderr, "Selection: ").unwrap();
+        stderr.reset().unwrap();
+        process(&bms.bms);
+    }
+    None
+}
+
+/// ids of the bookmarks matching `query` and `filter`
+fn matching_ids(query: Option<String>, filter: TagFilter) -> Vec<i32> {
+    let bms = filter_bookmarks(query, filter).bms;
+    if bms.is_empty() {
+        eprintln!("No bookmarks found");
+        process::exit(exitcode::NOT_FOUND);
+    }
+    bms.iter().map(|bm| bm.id).collect()
+}
+
+/// dry run of `open`: placeholders without value are shown as they are
+fn print_open_bookmarks(ids: Vec<i32>, args: BTreeMap<String, String>, archived: bool) {
+    let mut dal = Dal::default();
+    let mut exit_code = exitcode::OK;
+    for id in ids {
+        match dal.get_bookmark_by_id(id) {
+            Ok(bm) if archived => print_open_bm(&handler::archived(&bm), &args),
+            Ok(bm) => print_open_bm(&bm, &args),
+            Err(e) => {
+                error!("Bookmark with id {} not found", id);
+                exit_code = exitcode::from_diesel(&e);
+            }
+        }
+    }
+    if exit_code != exitcode::OK {
+        process::exit(exit_code);
+    }
+}
+
+/// more bookmarks than configured are only opened after confirmation, unless `yes`.
+/// `archived`: web pages are opened as snapshot on archive.org
+fn open_bookmarks(
+    ids: Vec<i32>,
+    args: BTreeMap<String, String>,
+    yes: bool,
+    json: bool,
+    archived: bool,
+) {
+    let mut dal = Dal::default();
+    let mut bms = Vec::new();
+    let mut exit_code = exitcode::OK;
+    for id in ids {
+        match dal.get_bookmark_by_id(id) {
+            Ok(bm) => bms.push(bm),
+            Err(e) => {
+                error!("Bookmark with id {} not found", id);
+                exit_code = exitcode::from_diesel(&e);
+            }
+        }
+    }
+    if !yes {
+        refuse_json_prompt(json);
+        match confirm_open(&bms) {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("Aborted");
+                process::exit(exitcode::ABORTED);
+            }
+            Err(e) => {
+                eprintln!("{}, use --yes in scripts: {}", e, e.root_cause());
+                process::exit(exitcode::IO);
+            }
+        }
+    }
+    let targets: Vec<Bookmark> = if archived {
+        bms.iter().map(handler::archived).collect()
+    } else {
+        bms.clone()
+    };
+    debug!("Opening {:?}", targets);
+    let failures = open_bms_throttled(&targets, &args);
+    for (bm, e) in &failures {
+        eprintln!("Cannot open {}: {:?}", bm.URL, e);
+        exit_code = exitcode::from_error(e);
+    }
+    if json {
+        let opened: Vec<Bookmark> = bms
+            .iter()
+            .filter(|bm| !failures.iter().any(|(failed, _)| failed.id == bm.id))
+            .cloned()
+            .collect();
+        bms_to_json(&opened);
+    }
+    if exit_code != exitcode::OK {
+        process::exit(exit_code);
+    }
+}
+
+fn add_bookmark(
+    url: String,
+    tags: Option<String>,
+    title: Option<String>,
+    desc: Option<String>,
+    no_web: bool,
+    edit: bool,
+    json: bool,
+) {
+    let mut dal = Dal::default();
+    debug!(
+        "Add {:?}, {:?}, {:?}, {:?}, {:?}, {:?}",
+        url, tags, title, desc, no_web, edit,
+    );
+
+    let unknown_tags =
+        Bookmarks::new("".to_string()).check_tags(Tags::normalize_tag_string(tags.clone()));
+    // JSON output is meant for scripts: no prompt, new tags are accepted
+    if !unknown_tags.is_empty() && !json {
+        debug!("unknown_tags: {:?}", unknown_tags);
         eprintln!("Unknown tags: {:?}", unknown_tags);
         let ans = Confirm::new(format!("Unknown tags: {:?}, create?", unknown_tags).as_str())
             .with_default(false)
@@ -385,16 +1445,16 @@ fn add_bookmark(
             Ok(true) => {}
             Ok(false) => {
                 eprintln!("Aborted");
-                return;
+                process::exit(exitcode::ABORTED);
             }
             Err(_) => {
                 eprintln!("Error, try again later");
-                return;
+                process::exit(exitcode::IO);
             }
         }
     }
 
-    let (_title, _description, _keywords) = if !no_web {
+    let details = if !no_web {
         let result = load_url_details(&url);
         result.unwrap_or_else(|e| {
             debug!("Cannot enrich URL details from web: {:?}", e);
@@ -402,109 +1462,465 @@ fn add_bookmark(
             Default::default()
         })
     } else {
-        Default::default()
+        Details::default()
     };
-    let title = title.unwrap_or(_title);
-    let description = desc.unwrap_or(_description);
-    debug!(
-        "({}:{}) title: {:?}, description: {:?}",
-        function_name!(),
-        line!(),
-        title,
-        description
-    );
-    match dal.insert_bookmark(NewBookmark {
-        URL: url.to_string(),
-        metadata: title,
-        tags: Tags::create_normalized_tag_string(tags),
-        desc: description,
-        flags: 0,
-    }) {
+    let mut fetched_urls = details.extra_urls();
+    let (url, canonical_url) = canonical().apply(&url, details.canonical_url);
+    fetched_urls.extend(canonical_url.map(|url| (CANONICAL_URL, url)));
+    let title = title.unwrap_or(details.title);
+    let description = desc.unwrap_or(details.description);
+    debug!("title: {:?}, description: {:?}", title, description);
+    match dal
+        .insert_bookmark(NewBookmark {
+            URL: url.to_string(),
+            metadata: title,
+            tags: Tags::create_normalized_tag_string(with_lang_tag(tags, details.lang.as_deref())),
+            desc: description,
+            flags: 0,
+        })
+        .and_then(|bms| keep_fetched_urls(&mut dal, bms, fetched_urls))
+        .and_then(|bms| {
+            if let Some(content) = &details.content {
+                dal.set_content(bms[0].id, content)?;
+            }
+            Ok(bms)
+        })
+    {
         Ok(bms) => {
             if edit {
                 edit_bms(vec![1], bms.clone()).unwrap_or_else(|e| {
-                    error!(
-                        "({}:{}) Error editing bookmark: {:?}",
-                        function_name!(),
-                        line!(),
-                        e
-                    );
+                    error!("Error editing bookmark: {:?}", e);
                 });
             }
+            if json {
+                // re-read to include changes made in the editor
+                let bms = dal
+                    .get_bookmark_by_id(bms[0].id)
+                    .map(|bm| vec![bm])
+                    .unwrap_or(bms);
+                bms_to_json(&bms);
+                return;
+            }
             println!("Added bookmark: {:?}", bms[0].id);
             show_bms(&bms)
         }
         Err(e) => {
             if let DatabaseError(DatabaseErrorKind::UniqueViolation, _) = e {
                 eprintln!("Bookmark already exists: {}", url);
+                process::exit(exitcode::EXISTS);
             } else {
-                error!(
-                    "({}:{}) Error adding bookmark: {:?}",
-                    function_name!(),
-                    line!(),
-                    e
-                );
+                error!("Error adding bookmark: {:?}", e);
+                process::exit(exitcode::DB);
             }
         }
     }
 }
 
-fn delete_bookmarks(ids: String) {
-    let ids = get_ids(ids);
+fn add_bookmarks_batch(batch: String, no_web: bool, dry_run: bool, json: bool) {
+    let input = if batch == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(&batch)
+    };
+    let entries = parse_batch(&input.unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {}", batch, e);
+        process::exit(exitcode::IO);
+    }));
+    debug!("{:?}", entries);
+    match bkmr::add_bookmarks_batch(entries, no_web, dry_run) {
+        Ok(bms) if json => bms_to_json(&bms),
+        Ok(bms) if dry_run => {
+            println!("Would add {} bookmarks", bms.len());
+            show_bms(&bms);
+        }
+        Ok(bms) => {
+            println!("Added {} bookmarks", bms.len());
+            show_bms(&bms);
+        }
+        Err(e) => {
+            eprintln!("Error adding bookmarks, nothing added: {:?}", e);
+            process::exit(exitcode::from_error(&e));
+        }
+    }
+}
+
+/// `yes`: no confirmation prompt, JSON output is meant for scripts and never prompts
+fn delete_bookmarks(ids: String, dry_run: bool, yes: bool, json: bool) {
+    let ids = get_ids(ids).unwrap();
     let bms = Bookmarks::new("".to_string());
-    delete_bms(ids.unwrap(), bms.bms).unwrap_or_else(|e| {
+    if !yes && !dry_run {
+        refuse_json_prompt(json);
+        confirm_delete(&ids, &bms.bms);
+    }
+    let deleted = delete_bms(ids, bms.bms, dry_run).unwrap_or_else(|e| {
         eprintln!(
             "Error ({}:{}) Deleting Bookmarks: {:?}",
             function_name!(),
             line!(),
             e
         );
-        process::exit(1);
+        process::exit(exitcode::from_error(&e));
+    });
+    if json {
+        bms_to_json(&deleted);
+    }
+}
+
+/// --json does not prompt, a confirmation must be given by --yes
+fn refuse_json_prompt(json: bool) {
+    if json {
+        eprintln!("--json does not ask for confirmation, use --yes to confirm.");
+        process::exit(exitcode::USAGE);
+    }
+}
+
+/// lists the bookmarks to be deleted and exits unless the user confirms
+fn confirm_delete(ids: &[i32], bms: &[Bookmark]) {
+    // ids are positions in the list, invalid ones are reported by the deletion itself
+    let selected: Vec<(i32, &Bookmark)> = ids
+        .iter()
+        .filter_map(|id| Some((*id, bms.get(usize::try_from(id - 1).ok()?)?)))
+        .collect();
+    for (id, bm) in &selected {
+        eprintln!("{:>5}: {} ({})", id, bm.metadata, bm.URL);
+    }
+    let ans = Confirm::new(format!("Really delete these {} bookmarks?", selected.len()).as_str())
+        .with_default(false)
+        .prompt();
+    match ans {
+        Ok(true) => {}
+        Ok(false) => {
+            eprintln!("Aborted");
+            process::exit(exitcode::ABORTED);
+        }
+        Err(e) => {
+            eprintln!("Cannot confirm deletion, use --yes in scripts: {}", e);
+            process::exit(exitcode::IO);
+        }
+    }
+}
+
+/// bookmarks by `ids`, or matching `query` and `filter`, and the exit code for ids not found
+fn selected_bookmarks(
+    ids: Option<String>,
+    query: Option<String>,
+    filter: TagFilter,
+) -> (Vec<Bookmark>, i32) {
+    let Some(ids) = ids else {
+        return (filter_bookmarks(query, filter).bms, exitcode::OK);
+    };
+    let mut dal = Dal::default();
+    let mut bms = Vec::new();
+    let mut exit_code = exitcode::OK;
+    for id in get_ids(ids).unwrap() {
+        match dal.get_bookmark_by_id(id) {
+            Ok(bm) => bms.push(bm),
+            Err(e) => {
+                error!("Bookmark with id {} not found", id);
+                exit_code = exitcode::from_diesel(&e);
+            }
+        }
+    }
+    (bms, exit_code)
+}
+
+/// reports the snapshot of each bookmark, failed requests set the exit code
+fn archive_bookmarks(ids: Option<String>, query: Option<String>, filter: TagFilter, json: bool) {
+    let (bms, mut exit_code) = selected_bookmarks(ids, query, filter);
+    let results = bkmr::archive_bookmarks(bms).unwrap_or_else(|e| {
+        eprintln!("Error archiving bookmarks, no bookmark updated: {:?}", e);
+        process::exit(exitcode::from_error(&e));
+    });
+    let mut archived = Vec::new();
+    for (bm, result) in results {
+        match result {
+            Ok(archive_url) => {
+                if !json {
+                    eprintln!("Archived {}: {}", bm.id, archive_url);
+                }
+                archived.push(bm);
+            }
+            Err(e) => {
+                eprintln!("Cannot archive {}: {:?}", bm.URL, e);
+                exit_code = exitcode::from_error(&e);
+            }
+        }
+    }
+    if json {
+        bms_to_json(&archived);
+    }
+    if exit_code != exitcode::OK {
+        process::exit(exit_code);
+    }
+}
+
+/// reports broken and moved links and a summary, working links are only counted
+fn check_bookmarks(
+    ids: Option<String>,
+    query: Option<String>,
+    filter: TagFilter,
+    dry_run: bool,
+    json: bool,
+) {
+    let (bms, exit_code) = selected_bookmarks(ids, query, filter);
+    let results = bkmr::check_bookmarks(bms, dry_run).unwrap_or_else(|e| {
+        eprintln!("Error checking bookmarks, no bookmark updated: {:?}", e);
+        process::exit(exitcode::from_error(&e));
+    });
+    if json {
+        let report: Vec<_> = results
+            .iter()
+            .map(|(bm, status)| {
+                let (result, code, detail) = match status {
+                    LinkStatus::Ok(code) => ("ok", Some(*code), None),
+                    LinkStatus::Moved(code, url) => ("moved", Some(*code), Some(url)),
+                    LinkStatus::Dead(code, reason) => ("dead", *code, Some(reason)),
+                };
+                serde_json::json!({
+                    "id": bm.id,
+                    "url": bm.URL,
+                    "result": result,
+                    "http_status": code,
+                    "detail": detail,
+                })
+            })
+            .collect();
+        print_json(&report);
+    } else {
+        let (mut n_moved, mut n_dead) = (0, 0);
+        for (bm, status) in &results {
+            match status {
+                LinkStatus::Ok(_) => {}
+                LinkStatus::Moved(_, url) => {
+                    n_moved += 1;
+                    eprintln!("moved {:>5}: {} -> {}", bm.id, bm.URL, url);
+                }
+                LinkStatus::Dead(_, reason) => {
+                    n_dead += 1;
+                    eprintln!("dead  {:>5}: {} ({})", bm.id, bm.URL, reason);
+                }
+            }
+        }
+        eprintln!(
+            "Checked {} links: {} ok, {} moved, {} dead",
+            results.len(),
+            results.len() - n_moved - n_dead,
+            n_moved,
+            n_dead
+        );
+    }
+    if exit_code != exitcode::OK {
+        process::exit(exit_code);
+    }
+}
+
+/// shows the fetched changes as diff, unchanged bookmarks and other than web pages are left alone
+fn refresh_bookmarks(
+    ids: Option<String>,
+    query: Option<String>,
+    filter: TagFilter,
+    empty_title: bool,
+    dry_run: bool,
+    json: bool,
+) {
+    let (mut bms, exit_code) = selected_bookmarks(ids, query, filter);
+    if empty_title {
+        bms.retain(|bm| bm.metadata.trim().is_empty());
+    }
+    if bms.is_empty() {
+        eprintln!("No bookmarks found");
+        process::exit(exitcode::NOT_FOUND);
+    }
+    let updated = bkmr::refresh_bookmarks(bms.clone(), dry_run).unwrap_or_else(|e| {
+        eprintln!("Error refreshing bookmarks, no bookmark updated: {:?}", e);
+        process::exit(exitcode::from_error(&e));
     });
+    if json {
+        bms_to_json(&updated);
+    } else {
+        show_changes(&bms, &updated).unwrap_or_else(|e| {
+            error!("Cannot show changes: {:?}", e);
+            0
+        });
+        let verb = if dry_run {
+            "Would refresh"
+        } else {
+            "Refreshed"
+        };
+        eprintln!("{} {} of {} bookmarks", verb, updated.len(), bms.len());
+    }
+    if exit_code != exitcode::OK {
+        process::exit(exit_code);
+    }
 }
 
-fn update_bookmarks(force: bool, tags: Option<String>, tags_not: Option<String>, ids: String) {
+fn update_bookmarks(
+    force: bool,
+    tags: Option<String>,
+    tags_not: Option<String>,
+    ids: String,
+    dry_run: bool,
+    pick_tags: bool,
+    json: bool,
+) {
     if force && (tags.is_none() || tags_not.is_some()) {
         eprintln!(
             "({}:{}) Force update requires tags but no ntags.",
             function_name!(),
             line!()
         );
-        process::exit(1);
+        process::exit(exitcode::USAGE);
+    }
+    let ids = get_ids(ids).unwrap();
+    let (tags, tags_not) = if pick_tags {
+        pick_bookmark_tags(&ids)
+    } else {
+        (
+            Tags::normalize_tag_string(tags),
+            Tags::normalize_tag_string(tags_not),
+        )
+    };
+    if !json {
+        println!("Update {:?}, {:?}, {:?}, {:?}", ids, tags, tags_not, force);
+    }
+    let updated = bkmr::update_bookmarks(ids, tags, tags_not, force, dry_run).unwrap_or_else(|e| {
+        eprintln!("Error updating bookmarks, no bookmark updated: {:?}", e);
+        process::exit(exitcode::from_error(&e));
+    });
+    if json {
+        bms_to_json(&updated);
+    } else if dry_run {
+        // the database still holds the original tags
+        let mut dal = Dal::default();
+        for bm in &updated {
+            let old_tags = dal
+                .get_bookmark_by_id(bm.id)
+                .map(|bm| bm.tags)
+                .unwrap_or_default();
+            println!("Would update {}: {} -> {}", bm.id, old_tags, bm.tags);
+        }
+    }
+}
+
+/// tags to add and to remove, picked from all existing tags: tags shared by the bookmarks are preselected
+fn pick_bookmark_tags(ids: &[i32]) -> (Vec<String>, Vec<String>) {
+    let mut dal = Dal::default();
+    let bm_tags = ids
+        .iter()
+        .map(|id| {
+            dal.get_bookmark_by_id(*id)
+                .map(|bm| Tags::normalize_tag_string(Some(bm.tags)))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("Cannot load bookmarks {:?}: {}", ids, e);
+            process::exit(exitcode::from_diesel(&e));
+        });
+    let common = Tags::common_tags(&bm_tags);
+    let Some(picked) = pick_tags(&dal.get_all_tags_as_vec(), &common) else {
+        eprintln!("Aborted, no bookmark updated");
+        process::exit(exitcode::ABORTED);
+    };
+    Tags::picked_changes(&common, &picked)
+}
+
+fn new_bookmarks(json: bool) {
+    match new_bms() {
+        Ok(bms) if json => bms_to_json(&bms),
+        Ok(bms) => show_bms(&bms),
+        Err(e) => {
+            if let Some(aborted) = e.downcast_ref::<EditAborted>() {
+                eprintln!("{}", aborted);
+            } else {
+                eprintln!("Error adding bookmarks: {:?}", e);
+            }
+            process::exit(exitcode::from_error(&e));
+        }
     }
-    let ids = get_ids(ids);
-    let tags = Tags::normalize_tag_string(tags);
-    let tags_not = Tags::normalize_tag_string(tags_not);
-    println!("Update {:?}, {:?}, {:?}, {:?}", ids, tags, tags_not, force);
-    bkmr::update_bookmarks(ids.unwrap(), tags, tags_not, force);
 }
 
-fn edit_bookmarks(ids: String) {
-    let ids = get_ids(ids);
+fn edit_bookmarks(ids: Option<String>, json: bool) {
+    let ids = get_ids_or_last(ids);
     let bms = Bookmarks::new("".to_string());
-    edit_bms(ids.unwrap(), bms.bms).unwrap_or_else(|e| {
+    // ids are positions in the list
+    let edited: Vec<i32> = ids
+        .iter()
+        .filter_map(|id| Some(bms.bms.get(usize::try_from(id - 1).ok()?)?.id))
+        .collect();
+    edit_bms(ids, bms.bms).unwrap_or_else(|e| {
+        if let Some(aborted) = e.downcast_ref::<EditAborted>() {
+            eprintln!("{}", aborted);
+            process::exit(exitcode::ABORTED);
+        }
         eprintln!(
             "Error ({}:{}) Editing Bookmarks: {:?}",
             function_name!(),
             line!(),
             e
         );
-        process::exit(1);
+        process::exit(exitcode::from_error(&e));
     });
+    if json {
+        let mut dal = Dal::default();
+        let bms: Vec<Bookmark> = edited
+            .into_iter()
+            .filter_map(|id| dal.get_bookmark_by_id(id).ok())
+            .collect();
+        bms_to_json(&bms);
+    }
+}
+
+/// attaches a screenshot to each web bookmark, failures set the exit code
+#[cfg(feature = "screenshot")]
+fn snapshot_bookmarks(ids: String) {
+    let mut dal = Dal::default();
+    let mut exit_code = exitcode::OK;
+    for id in get_ids(ids).unwrap() {
+        let Ok(bm) = dal.get_bookmark_by_id(id) else {
+            eprintln!("Bookmark with id {} not found", id);
+            exit_code = exitcode::NOT_FOUND;
+            continue;
+        };
+        if !bm.URL.starts_with("http") {
+            eprintln!("Skipping {}: not a web page", bm.URL);
+            continue;
+        }
+        let result = bkmr::screenshot::capture(&bm.URL).and_then(|content| {
+            let attachment = NewAttachment {
+                bookmark_id: bm.id,
+                name: bkmr::screenshot::SCREENSHOT_NAME.to_string(),
+                content,
+            };
+            Ok(dal.insert_attachment(attachment)?)
+        });
+        match result {
+            Ok(attachment_id) => eprintln!("Attached screenshot [{}] to {}", attachment_id, bm.URL),
+            Err(e) => {
+                eprintln!("Cannot take screenshot of {}: {:?}", bm.URL, e);
+                exit_code = exitcode::from_error(&e);
+            }
+        }
+    }
+    if exit_code != exitcode::OK {
+        process::exit(exit_code);
+    }
 }
 
-fn create_db(path: String) {
+fn create_db(path: String, json: bool) {
     let path = Utf8Path::new(&path);
     if !path.exists() {
-        println!("Creating database at {:?}", path);
+        if !json {
+            println!("Creating database at {:?}", path);
+        }
         let parent = path.parent();
         if let Some(parent) = parent {
             create_dir_all(parent).unwrap();
-            debug!("({}:{}) Created {:?}", function_name!(), line!(), parent);
+            debug!("Created {:?}", parent);
         }
 
         let mut dal = Dal::new(path.to_string());
         match init_db(&mut dal.conn) {
+            Ok(_) if json => print_json(&serde_json::json!({ "created": path.as_str() })),
             Ok(_) => {
                 println!("Database created at {:?}", path);
             }
@@ -515,7 +1931,7 @@ fn create_db(path: String) {
                     line!(),
                     e
                 );
-                process::exit(1);
+                process::exit(exitcode::DB);
             }
         }
         let _ = dal.clean_table();
@@ -526,17 +1942,431 @@ fn create_db(path: String) {
             line!(),
             path
         );
-        process::exit(1);
+        process::exit(exitcode::EXISTS);
+    }
+}
+
+/// sets (or removes if `value` is None) a user defined field, --json: prints the remaining fields
+fn set_meta(id: i32, key: String, value: Option<String>, json: bool) {
+    let mut dal = Dal::default();
+    let Ok(mut bm) = dal.get_bookmark_by_id(id) else {
+        eprintln!("Bookmark with id {} not found", id);
+        process::exit(exitcode::NOT_FOUND);
+    };
+    match value {
+        Some(value) => bm.set_extra_value(&key, &value),
+        None => {
+            if !bm.remove_extra_value(&key) {
+                eprintln!("Bookmark {} has no field {:?}", id, key);
+                process::exit(exitcode::NOT_FOUND);
+            }
+        }
+    }
+    let extra = bm.get_extra();
+    if let Err(e) = dal.update_bookmark(bm) {
+        eprintln!(
+            "Error ({}:{}) Updating bookmark: {:?}",
+            function_name!(),
+            line!(),
+            e
+        );
+        process::exit(exitcode::DB);
+    }
+    if json {
+        print_json(&extra);
+    }
+}
+
+fn get_meta(id: i32, key: Option<String>, json: bool) {
+    let mut dal = Dal::default();
+    let Ok(bm) = dal.get_bookmark_by_id(id) else {
+        eprintln!("Bookmark with id {} not found", id);
+        process::exit(exitcode::NOT_FOUND);
+    };
+    match key {
+        Some(key) => match bm.get_extra_value(&key) {
+            Some(value) if json => print_json(&serde_json::json!({ key: value })),
+            Some(value) => println!("{}", value),
+            None => {
+                eprintln!("Bookmark {} has no field {:?}", id, key);
+                process::exit(exitcode::NOT_FOUND);
+            }
+        },
+        None => print_json(&bm.get_extra()),
+    }
+}
+
+fn add_attachment(id: i32, file: Option<String>, snapshot: bool, json: bool) {
+    let mut dal = Dal::default();
+    let Ok(bm) = dal.get_bookmark_by_id(id) else {
+        eprintln!("Bookmark with id {} not found", id);
+        process::exit(exitcode::NOT_FOUND);
+    };
+    let (name, content) = match (file, snapshot) {
+        (Some(file), false) => {
+            let name = Utf8Path::new(&file)
+                .file_name()
+                .unwrap_or("attachment")
+                .to_string();
+            let content = fs::read(&file).unwrap_or_else(|e| {
+                eprintln!(
+                    "({}:{}) Cannot read {}: {}",
+                    function_name!(),
+                    line!(),
+                    file,
+                    e
+                );
+                process::exit(exitcode::IO);
+            });
+            (name, content)
+        }
+        (None, true) => {
+            let content = load_url_content(&bm.URL).unwrap_or_else(|e| {
+                eprintln!("Cannot load snapshot of {}: {}", bm.URL, e);
+                process::exit(exitcode::from_error(&e));
+            });
+            (String::from("snapshot.html"), content)
+        }
+        _ => {
+            eprintln!("Either a file or --snapshot is required.");
+            process::exit(exitcode::USAGE);
+        }
+    };
+    match dal.insert_attachment(NewAttachment {
+        bookmark_id: bm.id,
+        name: name.clone(),
+        content,
+    }) {
+        Ok(attachment_id) if json => {
+            let attachments = dal.get_attachments(bm.id).unwrap_or_default();
+            print_json(&attachments.iter().find(|a| a.id == attachment_id));
+        }
+        Ok(attachment_id) => eprintln!("Attached {} [{}] to {}", name, attachment_id, bm.URL),
+        Err(e) => {
+            eprintln!(
+                "Error ({}:{}) Adding attachment: {:?}",
+                function_name!(),
+                line!(),
+                e
+            );
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+fn list_attachments(id: i32, json: bool) {
+    let mut dal = Dal::default();
+    match dal.get_attachments(id) {
+        Ok(attachments) if json => print_json(&attachments),
+        Ok(attachments) => {
+            for a in attachments {
+                println!("[{}] {} ({} bytes, {})", a.id, a.name, a.size, a.created_ts);
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "Error ({}:{}) Listing attachments: {:?}",
+                function_name!(),
+                line!(),
+                e
+            );
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+/// bookmarks matching `query` and `filter` which have a feed
+fn feed_bookmarks(query: Option<String>, filter: TagFilter) -> Vec<Bookmark> {
+    filter_bookmarks(query, filter)
+        .bms
+        .into_iter()
+        .filter(|bm| bm.get_extra_value(FEED_URL).is_some())
+        .collect()
+}
+
+fn list_feeds(query: Option<String>, filter: TagFilter, json: bool) {
+    let bms = feed_bookmarks(query, filter);
+    if json {
+        bms_to_json(&bms);
+        return;
+    }
+    for bm in bms {
+        let feed_url = bm.get_extra_value(FEED_URL).unwrap_or_default();
+        println!("{}\t{}\t{}", bm.id, feed_url, bm.metadata);
+    }
+}
+
+/// writes the attachment to a temporary file and opens it with the OS default application,
+/// --json: prints the file
+fn open_attachment(id: i32, attachment: Option<i32>, json: bool) {
+    let mut dal = Dal::default();
+    let attachments = dal.get_attachments(id).unwrap_or_default();
+    let attachment_id = match attachment {
+        Some(attachment_id) => match attachments.iter().find(|a| a.id == attachment_id) {
+            Some(a) => a.id,
+            None => {
+                eprintln!("Bookmark {} has no attachment {}", id, attachment_id);
+                process::exit(exitcode::NOT_FOUND);
+            }
+        },
+        None => match attachments.last() {
+            Some(a) => a.id,
+            None => {
+                eprintln!("Bookmark {} has no attachments", id);
+                process::exit(exitcode::NOT_FOUND);
+            }
+        },
+    };
+    let result = dal
+        .get_attachment(attachment_id)
+        .map_err(anyhow::Error::from)
+        .and_then(|a| {
+            let path = tempfile::Builder::new()
+                .prefix("bkmr-")
+                .suffix(&format!("-{}", a.name))
+                .tempfile()?
+                .into_temp_path()
+                .keep()?;
+            fs::write(&path, a.content)?;
+            debug!("Opening {:?}", path);
+            open::that(&path)?;
+            Ok(path)
+        });
+    match result {
+        Ok(path) if json => print_json(&serde_json::json!({ "id": attachment_id, "path": path })),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Cannot open attachment {}: {}", attachment_id, e);
+            process::exit(exitcode::from_error(&e));
+        }
+    }
+}
+
+fn remove_attachment(attachment: i32, json: bool) {
+    let mut dal = Dal::default();
+    match dal.delete_attachment(attachment) {
+        Ok(0) => {
+            eprintln!("Attachment {} not found", attachment);
+            process::exit(exitcode::NOT_FOUND);
+        }
+        Ok(_) if json => print_json(&serde_json::json!({ "removed": attachment })),
+        Ok(_) => eprintln!("Removed attachment {}", attachment),
+        Err(e) => {
+            eprintln!(
+                "Error ({}:{}) Removing attachment: {:?}",
+                function_name!(),
+                line!(),
+                e
+            );
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+fn install_protocol() {
+    let installed = env::current_exe()
+        .context("Cannot locate the bkmr executable")
+        .and_then(|exe| protocol::install(&exe));
+    match installed {
+        Ok(path) => eprintln!("Registered bkmr:// URLs: {}", path.display()),
+        Err(e) => {
+            eprintln!("Cannot register bkmr:// URLs: {:?}", e);
+            process::exit(exitcode::IO);
+        }
+    }
+}
+
+/// web pages can send these URLs: only web pages and files are opened or added, no commands
+fn handle_protocol_url(url: String) {
+    let action = protocol::parse(&url).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::USAGE);
+    });
+    debug!("{:?}", action);
+    let refuse = |uri: &str| {
+        eprintln!(
+            "Refusing to open {} from {}: not a web page or file",
+            uri, url
+        );
+        process::exit(exitcode::USAGE);
+    };
+    match action {
+        ProtocolAction::Open(id) => {
+            let bm = Dal::default().get_bookmark_by_id(id).unwrap_or_else(|e| {
+                eprintln!("Bookmark with id {} not found: {}", id, e);
+                process::exit(exitcode::from_diesel(&e));
+            });
+            if let Some(target) = targets(&bm).iter().find(|t| !protocol::is_safe(t)) {
+                refuse(target);
+            }
+            open_bookmarks(vec![id], BTreeMap::new(), true, false, false)
+        }
+        ProtocolAction::Add { url, tags, title } => {
+            if !protocol::is_safe(&url) {
+                refuse(&url);
+            }
+            // no terminal to ask for new tags, they are accepted like in scripts
+            add_bookmark(url, tags, title, None, false, false, true)
+        }
+    }
+}
+
+fn dump_db() {
+    let mut dal = Dal::default();
+    match dal.dump() {
+        Ok(dump) => print!("{}", dump),
+        Err(e) => {
+            eprintln!(
+                "Error ({}:{}) Dumping database: {:?}",
+                function_name!(),
+                line!(),
+                e
+            );
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+fn load_db(file: String, force: bool, json: bool) {
+    let dump = fs::read_to_string(&file).unwrap_or_else(|e| {
+        eprintln!(
+            "({}:{}) Cannot read {}: {}",
+            function_name!(),
+            line!(),
+            file,
+            e
+        );
+        process::exit(exitcode::IO);
+    });
+    let mut dal = Dal::default();
+    if !force && !dal.is_empty_db().unwrap_or(false) {
+        eprintln!("Database is not empty, use --force to replace its content.");
+        process::exit(exitcode::EXISTS);
+    }
+    match dal.load(&dump, force) {
+        Ok(_) if json => print_json(&serde_json::json!({ "loaded": file })),
+        Ok(_) => eprintln!("Loaded {}", file),
+        Err(e) => {
+            eprintln!(
+                "Error ({}:{}) Loading dump: {:?}",
+                function_name!(),
+                line!(),
+                e
+            );
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+fn sync_git(dir: Option<String>, remote: Option<String>, dry_run: bool) {
+    let Some(dir) = dir else {
+        eprintln!("No repository given, set `[sync.git] dir` in the config.");
+        process::exit(exitcode::USAGE);
+    };
+    let dir = PathBuf::from(shellexpand::tilde(&dir).as_ref());
+    let mut dal = Dal::default();
+    match sync::sync_git(&mut dal, &dir, remote.as_deref(), dry_run) {
+        Ok(changes) => print_changes(&dir.display().to_string(), &changes, dry_run),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::IO);
+        }
     }
 }
 
-fn show_tags(tag: Option<String>) {
-    let mut dal = Dal::new(CONFIG.db_url.clone());
+fn sync_ssh(destination: String, ssh: &str, bkmr: &str, dry_run: bool) {
+    let mut dal = Dal::default();
+    let state = sync::state_path(&destination);
+    match sync::sync_ssh(&mut dal, &destination, ssh, bkmr, &state, dry_run) {
+        Ok((changes, peer_changes)) => {
+            print_changes("local", &changes, dry_run);
+            print_changes(&destination, &peer_changes, dry_run);
+        }
+        Err(e) => {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::IO);
+        }
+    }
+}
+
+fn sync_cloud(settings: &CloudSyncSettings, dry_run: bool) {
+    let (storage, age) = Storage::from_settings(settings)
+        .and_then(|storage| Ok((storage, Age::from_settings(settings)?)))
+        .unwrap_or_else(|e| {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::USAGE);
+        });
+    let mut dal = Dal::default();
+    let target = storage.to_string();
+    let state = sync::state_path(&target);
+    match sync::sync_cloud(&mut dal, &storage, &age, &state, dry_run) {
+        Ok((changes, snapshot_changes)) => {
+            print_changes("local", &changes, dry_run);
+            print_changes(&target, &snapshot_changes, dry_run);
+        }
+        Err(e) => {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::IO);
+        }
+    }
+}
+
+/// changes of a sync per bookmark and their counts, to stderr
+fn print_changes(target: &str, changes: &sync::Changes, dry_run: bool) {
+    let prefix = if dry_run { "Would sync" } else { "Synced" };
+    for record in &changes.added {
+        eprintln!("{} {}: added {}", prefix, target, record.url);
+    }
+    for record in &changes.updated {
+        eprintln!("{} {}: updated {}", prefix, target, record.url);
+    }
+    for url in &changes.deleted {
+        eprintln!("{} {}: deleted {}", prefix, target, url);
+    }
+    eprintln!(
+        "{} {}: {} added, {} updated, {} deleted",
+        prefix,
+        target,
+        changes.added.len(),
+        changes.updated.len(),
+        changes.deleted.len()
+    );
+}
+
+fn sync_export() {
+    let mut dal = Dal::default();
+    match dal.get_bookmarks("") {
+        Ok(bms) => print!("{}", sync::to_ndjson(&sync::records(&bms))),
+        Err(e) => {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+fn sync_import() {
+    let records = io::read_to_string(io::stdin())
+        .context("Cannot read stdin")
+        .and_then(|text| sync::parse_ndjson(&text))
+        .unwrap_or_else(|e| {
+            eprintln!("{:?}", e);
+            process::exit(exitcode::IO);
+        });
+    let mut dal = Dal::default();
+    sync::import(&mut dal, &records).unwrap_or_else(|e| {
+        eprintln!("{:?}", e);
+        process::exit(exitcode::DB);
+    });
+}
+
+fn show_tags(tag: Option<String>, json: bool) {
+    let mut dal = Dal::default();
     let tags = match tag {
         Some(tag) => dal.get_related_tags(&tag),
         None => dal.get_all_tags(),
     };
     match tags {
+        Ok(tags) if json => print_json(&tags),
         Ok(tags) => {
             for tag in tags {
                 println!("{}: {}", tag.n, tag.tag);
@@ -549,87 +2379,404 @@ fn show_tags(tag: Option<String>) {
                 line!(),
                 e
             );
-            process::exit(1);
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+fn show_stats(top: usize, json: bool) {
+    match stats::stats(&mut Dal::default(), top) {
+        Ok(stats) if json => print_json(&stats),
+        Ok(stats) => println!("{}", stats),
+        Err(e) => {
+            eprintln!("Error getting statistics: {:?}", e);
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+fn show_health(query: Option<String>, filter: TagFilter, json: bool) {
+    let health = report::health(filter_bookmarks(query, filter).bms);
+    if json {
+        print_json(&health);
+    } else {
+        print!("{}", health);
+    }
+}
+
+fn show_domains(limit: usize, json: bool) {
+    let bms = Dal::default().get_bookmarks("").unwrap_or_else(|e| {
+        eprintln!("Error getting bookmarks: {:?}", e);
+        process::exit(exitcode::DB);
+    });
+    let mut domains = stats::domains(&bms, chrono::Utc::now().naive_utc());
+    domains.truncate(limit);
+    if json {
+        print_json(&domains);
+    } else {
+        print!("{}", stats::format_domains(&domains));
+    }
+}
+
+fn show_heatmap(weeks: u32, json: bool) {
+    let today = chrono::Local::now().date_naive();
+    match stats::heatmap(&mut Dal::default(), today, weeks) {
+        Ok(heatmap) if json => print_json(&heatmap),
+        Ok(heatmap) => println!("{}", heatmap),
+        Err(e) => {
+            eprintln!("Error getting the activity: {:?}", e);
+            process::exit(exitcode::DB);
+        }
+    }
+}
+
+fn show_most_opened(
+    tag: Option<String>,
+    since: Option<chrono::Duration>,
+    limit: i64,
+    json: bool,
+) {
+    match stats::top(&mut Dal::default(), tag.as_deref(), since, limit) {
+        Ok(opened) if json => print_json(&opened),
+        Ok(opened) => {
+            for o in opened {
+                println!(
+                    "{:>4}  {:>4}: {}  {}",
+                    o.opens, o.bookmark.id, o.bookmark.metadata, o.bookmark.URL
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("Error getting the most opened bookmarks: {:?}", e);
+            process::exit(exitcode::DB);
         }
     }
 }
 
-fn show_bookmarks(ids: String) {
-    let mut dal = Dal::new(CONFIG.db_url.clone());
-    let ids = get_ids(ids);
+fn show_bookmarks(ids: Option<String>, template: Option<Template>, compact: bool, json: bool) {
+    let mut dal = Dal::default();
+    let ids = get_ids_or_last(ids);
     let mut bms = vec![];
-    for id in ids.unwrap() {
+    let mut exit_code = exitcode::OK;
+    for id in ids {
         let bm = dal.get_bookmark_by_id(id);
         match bm {
             Ok(bm) => {
-                debug!("({}:{}) {:?}", function_name!(), line!(), bm);
+                debug!("{:?}", bm);
                 bms.push(bm);
             }
-            Err(_) => {
+            Err(e) => {
                 eprintln!("Bookmark with id {} not found", id);
+                exit_code = exitcode::from_diesel(&e);
             }
         }
     }
-    show_bms(&bms);
+    if json {
+        bms_to_json(&bms);
+    } else if let Some(template) = template {
+        show_bms_formatted(&bms, &template);
+    } else if compact {
+        show_bms_compact(&bms);
+    } else {
+        show_bms(&bms);
+    }
+    if exit_code != exitcode::OK {
+        process::exit(exit_code);
+    }
+}
+
+/// like `search`, but without interaction
+fn show_matching_bookmarks(
+    query: Option<String>,
+    filter: TagFilter,
+    template: Option<Template>,
+    compact: bool,
+    json: bool,
+) {
+    let mut bms = filter_bookmarks(query, filter).bms;
+    bms.sort_by_key(|bm| bm.id);
+    if json {
+        bms_to_json(&bms);
+    } else if let Some(template) = template {
+        show_bms_formatted(&bms, &template);
+    } else {
+        if compact {
+            show_bms_compact(&bms);
+        } else {
+            show_bms(&bms);
+        }
+        eprintln!("Found {} bookmarks", bms.len());
+    }
+}
+
+fn parse_template(format: &str) -> Template {
+    Template::from_format(format).unwrap_or_else(|e| {
+        eprintln!("Invalid format: {}", e);
+        process::exit(exitcode::USAGE);
+    })
+}
+
+/// no ids: id of the most recently added/updated bookmark
+fn get_ids_or_last(ids: Option<String>) -> Vec<i32> {
+    match ids {
+        Some(ids) => get_ids(ids).unwrap_or_default(),
+        None => match Dal::default().get_last_bookmark() {
+            Ok(bm) => vec![bm.id],
+            Err(_) => {
+                eprintln!("No bookmarks found");
+                process::exit(exitcode::NOT_FOUND);
+            }
+        },
+    }
 }
 
+/// `-` reads the ids from stdin, one per line or separated by comma
 fn get_ids(ids: String) -> Option<Vec<i32>> {
-    let ids = ensure_int_vector(&ids.split(',').map(|s| s.to_owned()).collect());
+    let ids = if ids == "-" {
+        io::read_to_string(io::stdin()).unwrap_or_else(|e| {
+            eprintln!("Cannot read ids from stdin: {}", e);
+            process::exit(exitcode::IO);
+        })
+    } else {
+        ids
+    };
+    let ids = ensure_int_vector(
+        &ids.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_owned())
+            .collect(),
+    );
     if ids.is_none() {
         eprintln!(
-            "({}:{}) Invalid input, only numbers allowed {:?}",
+            "({}:{}) Invalid input, only numbers and ranges of up to {} ids allowed",
             function_name!(),
             line!(),
-            ids
+            MAX_RANGE
         );
-        process::exit(1);
+        process::exit(exitcode::USAGE);
     }
     ids
 }
 
-fn set_logger(cli: &Cli) {
+/// Tag completion: tags are looked up via `bkmr tags` at completion time (bash, zsh, fish)
+const BASH_TAG_COMPLETION: &str = r#"
+_bkmr_tags() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "${prev}" in
+        -t|--tags|-T|--Tags|-n|--ntags|-N|--Ntags|-e|--exact|--prefix)
+            local head=""
+            [[ "${cur}" == *,* ]] && head="${cur%,*},"
+            COMPREPLY=($(compgen -P "${head}" -W "$(bkmr tags 2>/dev/null | cut -d' ' -f2)" -- "${cur##*,}"))
+            ;;
+        *)
+            _bkmr "$@"
+            ;;
+    esac
+}
+complete -F _bkmr_tags -o bashdefault -o default bkmr
+"#;
+
+const ZSH_TAG_COMPLETION: &str = r#"
+_bkmr_tags() {
+    local -a tags
+    tags=(${${(f)"$(bkmr tags 2>/dev/null)"}#*: })
+    _sequence compadd -a tags
+}
+"#;
+
+const FISH_TAG_COMPLETION: &str = r#"
+function __bkmr_tags
+    set -l head (string match -r '.*,' -- (commandline -ct))
+    bkmr tags 2>/dev/null | string replace -r '^\d+: ' "$head"
+end
+complete -c bkmr -n "__fish_seen_subcommand_from search" -s t -l tags -s T -l Tags -s n -l ntags -s N -l Ntags -s e -l exact -l prefix -x -a "(__bkmr_tags)"
+complete -c bkmr -n "__fish_seen_subcommand_from update" -s t -l tags -s n -l ntags -x -a "(__bkmr_tags)"
+"#;
+
+fn print_completions(shell: Shell) {
+    let mut buf = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "bkmr", &mut buf);
+    let script = String::from_utf8_lossy(&buf);
+    let script = match shell {
+        Shell::Bash => format!("{}{}", script, BASH_TAG_COMPLETION),
+        Shell::Zsh => script
+            .replacen(
+                "#compdef bkmr\n",
+                &format!("#compdef bkmr\n{}", ZSH_TAG_COMPLETION),
+                1,
+            )
+            .replace(":TAGS:_default", ":TAGS:_bkmr_tags")
+            .replace(":TAGS: ", ":TAGS:_bkmr_tags"),
+        Shell::Fish => format!("{}{}", script, FISH_TAG_COMPLETION),
+        _ => script.into_owned(),
+    };
+    print!("{}", script);
+}
+
+/// optional template for the editor buffer of `edit` and `new`
+fn load_edit_template(path: PathBuf) -> anyhow::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    fs::read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|template| parse_edit_template(&template))
+        .map(set_edit_template)
+        .with_context(|| format!("Invalid edit template {:?}", path))
+}
+
+/// `name=value` argument
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected NAME=VALUE, got {:?}", arg)),
+    }
+}
+
+/// value of `-c/--config` as given on the command line
+fn config_arg(args: &[String]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == "-c" || arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// position of the subcommand: first argument which is neither an option nor an option's value
+fn subcommand_position(cmd: &clap::Command, args: &[String]) -> Option<usize> {
+    let takes_value = |arg: &str| {
+        cmd.get_arguments().any(|a| {
+            a.get_action().takes_values()
+                && (a.get_long().map(|l| format!("--{}", l)).as_deref() == Some(arg)
+                    || a.get_short().map(|s| format!("-{}", s)).as_deref() == Some(arg))
+        })
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--" {
+            return None;
+        }
+        if !arg.starts_with('-') {
+            return Some(i);
+        }
+        if takes_value(arg) {
+            i += 1;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// replaces a user defined alias by its expansion, builtin subcommands cannot be overridden
+fn expand_alias(
+    mut args: Vec<String>,
+    aliases: &BTreeMap<String, String>,
+) -> anyhow::Result<Vec<String>> {
+    let cmd = Cli::command();
+    let Some(pos) = subcommand_position(&cmd, &args) else {
+        return Ok(args);
+    };
+    if cmd.find_subcommand(&args[pos]).is_some() {
+        return Ok(args);
+    }
+    let Some(alias) = aliases.get(&args[pos]) else {
+        return Ok(args);
+    };
+    let expansion = shlex::split(alias)
+        .with_context(|| format!("Invalid alias {} = {:?}", args[pos], alias))?;
+    debug!("alias {} -> {:?}", args[pos], expansion);
+    args.splice(pos..=pos, expansion);
+    Ok(args)
+}
+
+const NOISY_MODULES: [&str; 6] = ["skim", "tuikit", "html5ever", "reqwest", "mio", "want"];
+
+fn set_logger(cli: &Cli, settings: &Settings) {
     // Note, only flags can have multiple occurrences
+    let level = match cli.debug {
+        0 => "warn",
+        1 => "info",
+        2 => "trace",
+        _ => {
+            eprintln!("Don't be crazy");
+            return;
+        }
+    };
+    let mut directives = vec![level.to_string()];
+    if cli.debug > 0 {
+        directives.extend(NOISY_MODULES.iter().map(|m| format!("{}=info", m)));
+    }
+    directives.extend(
+        settings
+            .log
+            .filters
+            .iter()
+            .map(|(module, level)| format!("{}={}", module, level)),
+    );
+    let filter = EnvFilter::try_new(directives.join(",")).unwrap_or_else(|e| {
+        eprintln!("Invalid log filter in config: {}", e);
+        EnvFilter::new(level)
+    });
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(color_choice(Stream::Stderr) != ColorChoice::Never)
+        .with_file(cli.debug > 1)
+        .with_line_number(cli.debug > 1);
+    let _ = match cli.log_format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
     match cli.debug {
-        0 => {
-            let _ = env_logger::builder()
-                .filter_level(log::LevelFilter::Warn)
-                .try_init();
-        }
-        1 => {
-            let _ = env_logger::builder()
-                .filter_level(log::LevelFilter::Info)
-                .filter_module("skim", log::LevelFilter::Info)
-                .filter_module("tuikit", log::LevelFilter::Info)
-                .filter_module("html5ever", log::LevelFilter::Info)
-                .filter_module("reqwest", log::LevelFilter::Info)
-                .filter_module("mio", log::LevelFilter::Info)
-                .filter_module("want", log::LevelFilter::Info)
-                .try_init();
-            info!("Debug mode: info");
-        }
-        2 => {
-            let _ = env_logger::builder()
-                .filter_level(log::LevelFilter::max())
-                .filter_module("skim", log::LevelFilter::Info)
-                .filter_module("tuikit", log::LevelFilter::Info)
-                .filter_module("html5ever", log::LevelFilter::Info)
-                .filter_module("reqwest", log::LevelFilter::Info)
-                .filter_module("mio", log::LevelFilter::Info)
-                .filter_module("want", log::LevelFilter::Info)
-                .try_init();
-            debug!("Debug mode: debug");
-        }
-        _ => eprintln!("Don't be crazy"),
+        1 => info!("Debug mode: info"),
+        2 => debug!("Debug mode: debug"),
+        _ => {}
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Cli;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use crate::{config_arg, expand_alias, Cli};
 
     #[test]
     fn verify_cli() {
         use clap::CommandFactory;
         Cli::command().debug_assert()
     }
+
+    #[test]
+    fn test_expand_alias() {
+        let aliases = BTreeMap::from([
+            ("work".to_string(), "search -t work --fzf".to_string()),
+            ("show".to_string(), "search".to_string()),
+        ]);
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            expand_alias(args("bkmr -d --color never work -o"), &aliases).unwrap(),
+            args("bkmr -d --color never search -t work --fzf -o")
+        );
+        // builtin subcommands win
+        assert_eq!(
+            expand_alias(args("bkmr show 1"), &aliases).unwrap(),
+            args("bkmr show 1")
+        );
+        assert_eq!(
+            expand_alias(args("bkmr unknown"), &aliases).unwrap(),
+            args("bkmr unknown")
+        );
+        assert_eq!(
+            config_arg(&args("bkmr -d --config /tmp/x.toml work")),
+            Some(PathBuf::from("/tmp/x.toml"))
+        );
+    }
 }
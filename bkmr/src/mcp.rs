@@ -0,0 +1,177 @@
+//! `bkmr mcp`: Model Context Protocol server on stdin/stdout, so that LLM assistants can search,
+//! add and open bookmarks. The tools are the methods of `bkmr rpc`:
+//! - `search_bookmarks {query?, tags?, limit?}`
+//! - `add_bookmark {url, title?, tags?, desc?, fetch?}`
+//! - `open_bookmark {id}`: snippets are returned instead of printed, shell commands are not run
+use std::io::{BufRead, Write};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::rpc::{respond_with, serve_with, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND};
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+#[derive(Deserialize)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+/// answers the requests of `input` on `output` until `input` ends
+pub fn serve(input: impl BufRead, output: impl Write) -> anyhow::Result<()> {
+    serve_with(input, output, |line| respond_with(line, call))
+}
+
+fn call(method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "initialize" => Ok(json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {"tools": {}},
+            "serverInfo": {"name": "bkmr", "version": env!("CARGO_PKG_VERSION")},
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({"tools": tools()})),
+        "tools/call" => {
+            let tool: ToolCall = serde_json::from_value(params)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            call_tool(&tool.name, tool.arguments)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {:?}", method),
+        )),
+    }
+}
+
+/// failures of the tool are results for the assistant, not protocol errors
+fn call_tool(name: &str, arguments: Value) -> Result<Value, RpcError> {
+    let method = match name {
+        "search_bookmarks" => "search",
+        "add_bookmark" => "add",
+        "open_bookmark" => "open",
+        _ => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("Unknown tool {:?}", name),
+            ))
+        }
+    };
+    let (text, is_error) = match crate::rpc::call(method, arguments) {
+        Ok(result) => (
+            serde_json::to_string_pretty(&result).expect("results are serializable"),
+            false,
+        ),
+        Err(error) => (error.message, true),
+    };
+    Ok(json!({
+        "content": [{"type": "text", "text": text}],
+        "isError": is_error,
+    }))
+}
+
+fn tools() -> Value {
+    let tags = |description: &str| {
+        json!({"type": "array", "items": {"type": "string"}, "description": description})
+    };
+    json!([
+        {
+            "name": "search_bookmarks",
+            "description": "Search the user's bookmarks: web pages, snippets, notes and shell commands",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "SQLite FTS5 full-text query over URL, title, description and tags, \
+                            e.g. 'rust AND async' or 'python*'; all bookmarks if missing",
+                    },
+                    "tags": tags("bookmarks having all of these tags"),
+                    "limit": {"type": "integer", "description": "maximum number of bookmarks"},
+                },
+            },
+        },
+        {
+            "name": "add_bookmark",
+            "description": "Add a bookmark for the user",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "url": {"type": "string", "description": "URL, or snip:: followed by a snippet"},
+                    "title": {"type": "string"},
+                    "tags": tags("tags of the bookmark"),
+                    "desc": {"type": "string", "description": "description"},
+                    "fetch": {
+                        "type": "boolean",
+                        "description": "load title and description of the web page if not given",
+                    },
+                },
+                "required": ["url"],
+            },
+        },
+        {
+            "name": "open_bookmark",
+            "description": "Open a bookmark on the user's machine, e.g. in the browser; \
+                returns the text of snippets instead",
+            "inputSchema": {
+                "type": "object",
+                "properties": {"id": {"type": "integer", "description": "id of the bookmark"}},
+                "required": ["id"],
+            },
+        },
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    fn request(method: &str, params: Value) -> String {
+        json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}).to_string()
+    }
+
+    #[rstest]
+    fn test_initialize() {
+        let response = respond_with(&request("initialize", json!({})), call).unwrap();
+        assert_eq!(response["result"]["protocolVersion"], PROTOCOL_VERSION);
+        assert_eq!(response["result"]["serverInfo"]["name"], "bkmr");
+        let initialized = r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#;
+        assert_eq!(respond_with(initialized, call), None);
+    }
+
+    #[rstest]
+    fn test_tools_list() {
+        let response = respond_with(&request("tools/list", json!({})), call).unwrap();
+        let names: Vec<&str> = response["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["search_bookmarks", "add_bookmark", "open_bookmark"]
+        );
+    }
+
+    #[rstest]
+    fn test_tools_call_errors() {
+        let unknown = request("tools/call", json!({"name": "delete_bookmark"}));
+        let response = respond_with(&unknown, call).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        // invalid arguments are reported to the assistant
+        let invalid = request(
+            "tools/call",
+            json!({"name": "open_bookmark", "arguments": {"id": "x"}}),
+        );
+        let response = respond_with(&invalid, call).unwrap();
+        assert_eq!(response["result"]["isError"], true);
+        assert!(response["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("invalid type"));
+    }
+}
@@ -2,17 +2,19 @@
 
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
+use diesel::sql_types::BigInt;
 use diesel::sql_types::Integer;
 use diesel::sql_types::Text;
-use log::debug;
-use serde::{Serialize};
-use stdext::function_name;
+use diesel::sql_types::Timestamp;
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+use tracing::debug;
 
 use crate::tag::Tags;
 
-use super::schema::bookmarks;
+use super::schema::{attachments, bookmarks};
 
-#[derive(QueryableByName, Debug, PartialOrd, PartialEq)]
+#[derive(QueryableByName, Debug, PartialOrd, PartialEq, Serialize)]
 pub struct TagsFrequency {
     #[diesel(sql_type = Integer)]
     pub n: i32,
@@ -20,6 +22,44 @@ pub struct TagsFrequency {
     pub tag: String,
 }
 
+/// number per month `%Y-%m`, e.g. of additions
+#[derive(QueryableByName, Debug, PartialEq, Serialize)]
+pub struct MonthCount {
+    #[diesel(sql_type = Text)]
+    pub month: String,
+    #[diesel(sql_type = Integer)]
+    pub n: i32,
+}
+
+/// additions and opens of a local day `%Y-%m-%d`
+#[derive(QueryableByName, Debug, Clone, PartialEq)]
+pub struct DayActivity {
+    #[diesel(sql_type = Text)]
+    pub day: String,
+    #[diesel(sql_type = Integer)]
+    pub added: i32,
+    #[diesel(sql_type = Integer)]
+    pub opened: i32,
+}
+
+/// schema object as listed in `sqlite_master`
+#[derive(QueryableByName, Debug, PartialEq)]
+pub struct SchemaObject {
+    #[diesel(sql_type = Text)]
+    pub kind: String,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(sql_type = Text)]
+    pub sql: String,
+}
+
+/// single text column result, e.g. column names or generated SQL statements
+#[derive(QueryableByName, Debug, PartialEq)]
+pub struct TextRow {
+    #[diesel(sql_type = Text)]
+    pub text: String,
+}
+
 #[derive(Queryable, QueryableByName, Debug, PartialOrd, PartialEq, Clone, Default, Serialize)]
 #[diesel(table_name = bookmarks)]
 pub struct Bookmark {
@@ -32,6 +72,21 @@ pub struct Bookmark {
     #[serde(with = "serde_with::chrono::NaiveDateTime")]
     pub last_update_ts: NaiveDateTime,
     // pub last_update_ts: DateTime<Utc>,
+    /// user defined fields as JSON object, e.g. `{"author": "xxx"}`
+    #[serde(serialize_with = "serialize_extra")]
+    pub extra: String,
+    /// number of opens
+    pub visits: i32,
+    pub last_visited: Option<NaiveDateTime>,
+}
+
+/// emits the extra column as JSON object instead of an escaped string
+fn serialize_extra<S: Serializer>(extra: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    Value::Object(parse_extra(extra)).serialize(serializer)
+}
+
+fn parse_extra(extra: &str) -> Map<String, Value> {
+    serde_json::from_str(extra).unwrap_or_default()
 }
 
 impl Bookmark {
@@ -40,7 +95,43 @@ impl Bookmark {
     }
     pub fn set_tags(&mut self, tags: Vec<String>) {
         self.tags = format!(",{},", Tags::clean_tags(tags).join(","));
-        debug!("({}:{}) {:?}", function_name!(), line!(), self);
+        debug!("{:?}", self);
+    }
+    /// user defined fields, empty if none are set (or the column is not valid JSON)
+    pub fn get_extra(&self) -> Map<String, Value> {
+        parse_extra(&self.extra)
+    }
+    pub fn get_extra_value(&self, key: &str) -> Option<String> {
+        self.get_extra().get(key).map(|v| match v {
+            Value::String(s) => s.to_owned(),
+            _ => v.to_string(),
+        })
+    }
+    pub fn set_extra_value(&mut self, key: &str, value: &str) {
+        let mut extra = self.get_extra();
+        extra.insert(key.to_string(), Value::String(value.to_string()));
+        self.extra = Value::Object(extra).to_string();
+    }
+    /// frecency at `now`: the visits weighted by the age of the last one, 0 if never visited
+    pub fn frecency(&self, now: NaiveDateTime) -> i64 {
+        let Some(last_visited) = self.last_visited else {
+            return 0;
+        };
+        let weight = match (now - last_visited).num_days() {
+            ..=4 => 100,
+            5..=14 => 70,
+            15..=31 => 50,
+            32..=90 => 30,
+            _ => 10,
+        };
+        self.visits as i64 * weight
+    }
+    /// returns false if the key did not exist
+    pub fn remove_extra_value(&mut self, key: &str) -> bool {
+        let mut extra = self.get_extra();
+        let removed = extra.remove(key).is_some();
+        self.extra = Value::Object(extra).to_string();
+        removed
     }
 }
 
@@ -54,6 +145,62 @@ pub struct NewBookmark {
     pub flags: i32,
 }
 
+#[derive(Queryable, Debug, Clone, PartialEq)]
+#[diesel(table_name = attachments)]
+pub struct Attachment {
+    pub id: i32,
+    pub bookmark_id: i32,
+    pub name: String,
+    pub content: Vec<u8>,
+    pub created_ts: NaiveDateTime,
+}
+
+/// attachment without its content, for listings
+#[derive(QueryableByName, Debug, Clone, PartialEq, Serialize)]
+pub struct AttachmentInfo {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Integer)]
+    pub bookmark_id: i32,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(sql_type = Integer)]
+    pub size: i32,
+    #[diesel(sql_type = Timestamp)]
+    #[serde(with = "serde_with::chrono::NaiveDateTime")]
+    pub created_ts: NaiveDateTime,
+}
+
+/// a bookmark with its number of opens in a period
+#[derive(QueryableByName, Debug, Clone, PartialEq, Serialize)]
+pub struct Opened {
+    #[diesel(sql_type = BigInt)]
+    pub opens: i64,
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub bookmark: Bookmark,
+}
+
+/// an entry of the change log, written by triggers on every add, update and delete
+#[derive(Queryable, Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    /// position in the log, the cursor of the change feed
+    pub seq: i32,
+    /// add, update or delete
+    pub op: String,
+    pub URL: String,
+    #[serde(with = "serde_with::chrono::NaiveDateTime")]
+    pub ts: NaiveDateTime,
+}
+
+#[derive(Insertable, Clone, Debug, PartialEq)]
+#[diesel(table_name = attachments)]
+pub struct NewAttachment {
+    pub bookmark_id: i32,
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
 #[cfg(test)]
 mod test {
     use chrono::NaiveDate;
@@ -91,4 +238,40 @@ mod test {
         bm.set_tags(vec!["zzz".to_string()]);
         assert_eq!(bm.get_tags(), vec!("zzz".to_string()));
     }
+
+    #[rstest]
+    #[case(0, None, 0)]
+    #[case(3, Some(1), 300)]
+    #[case(3, Some(10), 210)]
+    #[case(20, Some(400), 200)]
+    fn test_frecency(
+        mut bm: Bookmark,
+        #[case] visits: i32,
+        #[case] days_ago: Option<i64>,
+        #[case] expected: i64,
+    ) {
+        let now = bm.last_update_ts + chrono::Duration::days(1000);
+        bm.visits = visits;
+        bm.last_visited = days_ago.map(|d| now - chrono::Duration::days(d));
+        assert_eq!(bm.frecency(now), expected);
+    }
+
+    #[rstest]
+    fn test_extra(mut bm: Bookmark) {
+        assert!(bm.get_extra().is_empty());
+        bm.set_extra_value("author", "sysid");
+        bm.set_extra_value("rating", "5");
+        assert_eq!(bm.get_extra_value("author"), Some("sysid".to_string()));
+        assert_eq!(bm.get_extra_value("rating"), Some("5".to_string()));
+        assert!(bm.remove_extra_value("author"));
+        assert!(!bm.remove_extra_value("author"));
+        assert_eq!(bm.extra, r#"{"rating":"5"}"#);
+    }
+
+    #[rstest]
+    fn test_extra_serialized_as_object(mut bm: Bookmark) {
+        bm.set_extra_value("rating", "5");
+        let json = serde_json::to_value(&bm).unwrap();
+        assert_eq!(json["extra"]["rating"], "5");
+    }
 }
@@ -1,54 +1,62 @@
-use std::{fs, io};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::{fs, io, thread};
 
 use anyhow::Context;
-use std::fs::File;
+use arboard::Clipboard;
+use diesel::result::Error as DieselError;
 use std::io::Write;
 use std::process::{Command, Stdio};
 use atty::Stream;
+use inquire::{Confirm, Text};
 
-use indoc::formatdoc;
-use log::{debug, error};
+use tracing::{debug, error};
 use regex::Regex;
 use stdext::function_name;
-use serde_json;
+use serde::Serialize;
 
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use termcolor::{BufferWriter, Color, ColorSpec, StandardStream, WriteColor};
 
 use crate::dal::Dal;
-use crate::environment::CONFIG;
+use crate::edit;
+use crate::edit::EditAborted;
+use crate::format::{format_ts, Template};
+use crate::handler;
+use crate::handler::{Action, ShellContext};
 use crate::helper;
 use crate::helper::abspath;
 use crate::models::Bookmark;
+use crate::theme::theme;
 
 pub fn show_bms(bms: &Vec<Bookmark>) {
-    // let mut stdout = StandardStream::stdout(ColorChoice::Always);
-        // Check if the output is a TTY
-    let color_choice = if atty::is(Stream::Stdout) {
-        ColorChoice::Auto
-    } else {
-        ColorChoice::Never
-    };
-    let mut stderr = StandardStream::stderr(color_choice);
+    // rendered completely first, so that long lists can be paged
+    let mut stderr = BufferWriter::stderr(helper::color_choice(Stream::Stderr)).buffer();
+    let theme = theme();
     let first_col_width = bms.len().to_string().len();
 
     for (i, bm) in bms.iter().enumerate() {
         stderr
-            .set_color(ColorSpec::new().set_fg(Some(Color::Green)))
+            .set_color(ColorSpec::new().set_fg(Some(theme.title)))
             .unwrap();
         write!(&mut stderr, "{:first_col_width$}. {}", i + 1, bm.metadata).unwrap();
         stderr
-            .set_color(ColorSpec::new().set_fg(Some(Color::White)))
+            .set_color(ColorSpec::new().set_fg(Some(theme.id)))
+            .unwrap();
+        write!(&mut stderr, " [{}]", bm.id).unwrap();
+        stderr
+            .set_color(ColorSpec::new().set_fg(Some(theme.date)))
             .unwrap();
-        write!(&mut stderr, " [{}]\n", bm.id).unwrap();
+        writeln!(&mut stderr, " {}", format_ts(&bm.last_update_ts)).unwrap();
 
         stderr
-            .set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))
+            .set_color(ColorSpec::new().set_fg(Some(theme.url)))
             .unwrap();
         writeln!(&mut stderr, "{:first_col_width$}  {}", "", bm.URL).unwrap();
 
         if !bm.desc.is_empty() {
             stderr
-                .set_color(ColorSpec::new().set_fg(Some(Color::White)))
+                .set_color(ColorSpec::new().set_fg(Some(theme.desc)))
                 .unwrap();
             writeln!(&mut stderr, "{:first_col_width$}  {}", "", bm.desc).unwrap();
         }
@@ -56,18 +64,114 @@ pub fn show_bms(bms: &Vec<Bookmark>) {
         let tags = bm.tags.replace(',', " ");
         if tags.find(|c: char| !c.is_whitespace()).is_some() {
             stderr
-                .set_color(ColorSpec::new().set_fg(Some(Color::Blue)))
+                .set_color(ColorSpec::new().set_fg(Some(theme.tags)))
                 .unwrap();
             writeln!(&mut stderr, "{:first_col_width$}  {}", "", tags.trim()).unwrap();
         }
 
         stderr.reset().unwrap();
-        eprintln!();
+        writeln!(&mut stderr).unwrap();
+    }
+    helper::page(stderr.as_slice(), Stream::Stderr).expect("Failed to write bookmarks.");
+}
+
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+const MAX_DOMAIN_WIDTH: usize = 24;
+
+/// one line per bookmark: position, id, title, domain and tags, truncated to the terminal width
+pub fn show_bms_compact(bms: &[Bookmark]) {
+    let mut stderr = BufferWriter::stderr(helper::color_choice(Stream::Stderr)).buffer();
+    let theme = theme();
+    let width = crossterm::terminal::size()
+        .map(|(w, _)| w as usize)
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH);
+    let first_col_width = bms.len().to_string().len();
+    let id_width = bms
+        .iter()
+        .map(|bm| bm.id.to_string().len())
+        .max()
+        .unwrap_or(1);
+
+    for (i, bm) in bms.iter().enumerate() {
+        let rest = width.saturating_sub(first_col_width + id_width + 5);
+        let (title, domain, tags) = compact_fields(bm, rest);
+        write!(&mut stderr, "{:first_col_width$}. ", i + 1).unwrap();
+        stderr
+            .set_color(ColorSpec::new().set_fg(Some(theme.id)))
+            .unwrap();
+        write!(&mut stderr, "[{:>id_width$}] ", bm.id).unwrap();
+        stderr
+            .set_color(ColorSpec::new().set_fg(Some(theme.title)))
+            .unwrap();
+        write!(&mut stderr, "{}", title).unwrap();
+        stderr
+            .set_color(ColorSpec::new().set_fg(Some(theme.url)))
+            .unwrap();
+        write!(&mut stderr, "{}", domain).unwrap();
+        stderr
+            .set_color(ColorSpec::new().set_fg(Some(theme.tags)))
+            .unwrap();
+        write!(&mut stderr, "{}", tags).unwrap();
+        stderr.reset().unwrap();
+        writeln!(&mut stderr).unwrap();
+    }
+    helper::page(stderr.as_slice(), Stream::Stderr).expect("Failed to write bookmarks.");
+}
+
+/// host of `url` without "www.", `url` itself if it has none
+pub(crate) fn domain(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.host_str()
+                .map(|h| h.trim_start_matches("www.").to_string())
+        })
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// title, domain and tags fitting into `width` columns: tags are kept, the title gets the rest
+fn compact_fields(bm: &Bookmark, width: usize) -> (String, String, String) {
+    let domain = truncate(&domain(&bm.URL), MAX_DOMAIN_WIDTH);
+    let tags = truncate(&bm.get_tags().join(","), width / 3);
+    let title_width = width.saturating_sub(domain.chars().count() + tags.chars().count() + 2);
+    let title = format!("{:<title_width$}", truncate(&bm.metadata, title_width));
+    let domain = if tags.is_empty() {
+        domain
+    } else {
+        format!("{} ", domain)
+    };
+    (format!("{} ", title), domain, tags)
+}
+
+/// cuts `s` to `width` chars, marking the cut with `…`
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        s.to_string()
+    } else if width == 0 {
+        String::new()
+    } else {
+        let mut t: String = s.chars().take(width - 1).collect();
+        t.push('…');
+        t
     }
 }
 
+/// prints one line per bookmark to stdout, rendered by `template`
+pub fn show_bms_formatted(bms: &[Bookmark], template: &Template) {
+    let output: String = bms
+        .iter()
+        .map(|bm| format!("{}\n", template.render(bm)))
+        .collect();
+    helper::page(output.as_bytes(), Stream::Stdout).expect("Failed to write bookmarks.");
+}
+
 pub fn bms_to_json(bms: &Vec<Bookmark>) {
-    let json = serde_json::to_string_pretty(bms).expect("Failed to serialize bookmarks to JSON.");
+    print_json(bms);
+}
+
+/// pretty prints any serializable value as JSON to stdout
+pub fn print_json<T: Serialize + ?Sized>(value: &T) {
+    let json = serde_json::to_string_pretty(value).expect("Failed to serialize to JSON.");
     io::stdout().write_all(json.as_bytes()).expect("Failed to write JSON to stdout.");
     println!();
 }
@@ -79,12 +183,12 @@ fn parse(input: &str) -> Vec<String> {
         .filter(|s| !s.is_empty())
         .map(|s| s.to_string())
         .collect();
-    debug!("({}:{}) {:?}", function_name!(), line!(), tokens);
+    debug!("{:?}", tokens);
     tokens
 }
 
 pub fn process(bms: &Vec<Bookmark>) {
-    // debug!("({}:{}) {:?}", function_name!(), line!(), bms);
+    // debug!("{:?}", bms);
     let help_text = r#"
         <n1> <n2>:      opens selection in browser
         p <n1> <n2>:    print id-list of selection
@@ -112,43 +216,31 @@ pub fn process(bms: &Vec<Bookmark>) {
             "p" => {
                 if let Some(ids) = helper::ensure_int_vector(&tokens.split_off(1)) {
                     print_ids(ids, bms.clone()).unwrap_or_else(|e| {
-                        error!("({}:{}) {}", function_name!(), line!(), e);
+                        error!("{}", e);
                     });
                     break;
                 } else {
-                    error!(
-                        "({}:{}) Invalid input, only numbers allowed",
-                        function_name!(),
-                        line!()
-                    );
+                    error!("Invalid input, only numbers allowed");
                 }
             }
             "d" => {
                 if let Some(ids) = helper::ensure_int_vector(&tokens.split_off(1)) {
-                    delete_bms(ids, bms.clone()).unwrap_or_else(|e| {
-                        error!("({}:{}) {}", function_name!(), line!(), e);
-                    });
+                    if let Err(e) = delete_bms(ids, bms.clone(), false) {
+                        error!("{}", e);
+                    }
                     break;
                 } else {
-                    error!(
-                        "({}:{}) Invalid input, only numbers allowed",
-                        function_name!(),
-                        line!(),
-                    );
+                    error!("Invalid input, only numbers allowed");
                 }
             }
             "e" => {
                 if let Some(ids) = helper::ensure_int_vector(&tokens.split_off(1)) {
                     edit_bms(ids, bms.clone()).unwrap_or_else(|e| {
-                        error!("({}:{}) {}", function_name!(), line!(), e);
+                        error!("{}", e);
                     });
                     break;
                 } else {
-                    error!(
-                        "({}:{}) Invalid input, only numbers allowed",
-                        function_name!(),
-                        line!(),
-                    );
+                    error!("Invalid input, only numbers allowed");
                 }
             }
             "h" => println!("{}", help_text),
@@ -157,14 +249,10 @@ pub fn process(bms: &Vec<Bookmark>) {
             s if regex.is_match(s) => {
                 if let Some(ids) = helper::ensure_int_vector(&tokens) {
                     open_bms(ids, bms.clone()).unwrap_or_else(|e| {
-                        error!("({}:{}) {}", function_name!(), line!(), e);
+                        error!("{}", e);
                     });
                 } else {
-                    error!(
-                        "({}:{}) Invalid input, only numbers allowed",
-                        function_name!(),
-                        line!(),
-                    );
+                    error!("Invalid input, only numbers allowed");
                 }
             }
             _ => {
@@ -175,125 +263,639 @@ pub fn process(bms: &Vec<Bookmark>) {
     }
 }
 
+/// edits all selected bookmarks in one editor buffer
 pub fn edit_bms(ids: Vec<i32>, bms: Vec<Bookmark>) -> anyhow::Result<()> {
-    debug!("({}:{}) {:?}", function_name!(), line!(), ids);
-    do_sth_with_bms(ids, bms, do_edit)
-        .with_context(|| format!("({}:{}) Error opening bookmarks", function_name!(), line!()))?;
-    Ok(())
+    debug!("{:?}", ids);
+    let mut selected = Vec::new();
+    do_sth_with_bms(ids, bms, |bm| {
+        selected.push(bm.clone());
+        Ok(())
+    })?;
+    edit_in_editor(&selected)
 }
 
 pub fn open_bm(bm: &Bookmark) -> anyhow::Result<()> {
-    _open_bm(&bm.URL)?;
+    open_bm_with_args(bm, &BTreeMap::new())
+}
+
+/// `args`: values of the placeholders in shell commands, e.g. `pod` for `{pod}`, missing ones are prompted for
+pub fn open_bm_with_args(bm: &Bookmark, args: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    open_targets(bm, args)?;
+    record_visit(bm);
     Ok(())
 }
 
-fn _open_bm(uri: &str) -> anyhow::Result<()> {
-    if uri.starts_with("shell::") {
-        let cmd = uri.replace("shell::", "");
-        debug!("({}:{}) Shell Command {:?}", function_name!(), line!(), cmd);
-        let mut child = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .with_context(|| format!("({}:{}) Error opening {}", function_name!(), line!(), uri))?;
-
-        let status = child.wait().expect("Failed to wait on Vim");
-        debug!(
-            "({}:{}) Exit status from command: {:?}",
-            function_name!(),
-            line!(),
-            status
-        );
-        Ok(())
-    } else {
-        debug!(
-            "({}:{}) General OS open {:?}",
-            function_name!(),
-            line!(),
-            uri
-        );
-        // todo error propagation upstream not working
-        match abspath(uri) {
-            Some(p) => {
-                open::that(p)?;
+/// opens `bm` for clients without a terminal, e.g. of `bkmr rpc`: nothing is prompted for,
+/// placeholders are kept, targets running a command or printing to stdout are refused
+pub fn open_bm_unattended(bm: &Bookmark) -> anyhow::Result<()> {
+    let context = ShellContext::from(bm);
+    let tags = bm.get_tags();
+    try_targets(bm, |target| {
+        let action = handler::action(target, &tags)?;
+        match prepare(action, &BTreeMap::new(), &context, false)? {
+            launch @ (Launch::Copy(_) | Launch::Os(_)) => launch.run(),
+            _ => Err(anyhow::anyhow!(
+                "{} runs a command or prints, not opened without a terminal",
+                target
+            )),
+        }
+    })?;
+    record_visit(bm);
+    Ok(())
+}
+
+/// opens the URI of `bm`, if that fails its fallbacks in order until one can be opened
+fn open_targets(bm: &Bookmark, args: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let context = ShellContext::from(bm);
+    let tags = bm.get_tags();
+    try_targets(bm, |target| _open_bm(target, &tags, args, &context))
+}
+
+/// `open` on the URI of `bm`, if that fails on its fallbacks in order until one succeeds
+fn try_targets(bm: &Bookmark, open: impl Fn(&str) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    let targets = handler::targets(bm);
+    let mut result = Ok(());
+    for (i, target) in targets.iter().enumerate() {
+        result = open(target);
+        match &result {
+            Ok(()) if i > 0 => eprintln!("Opened fallback {}", target),
+            Ok(()) => {}
+            Err(e) if i + 1 < targets.len() => {
+                eprintln!("Cannot open {}: {:#}, trying fallback", target, e);
+                continue;
+            }
+            Err(_) => {}
+        }
+        break;
+    }
+    result
+}
+
+/// the bookmark has been opened, a failed bookkeeping must not turn it into an error
+fn record_visit(bm: &Bookmark) {
+    if let Err(e) = Dal::default().record_visit(bm.id) {
+        error!("Cannot record visit of bookmark {}: {}", bm.id, e);
+    }
+}
+
+/// how web bookmarks are opened: in parallel, at most `concurrency` at a time, launches `delay` apart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throttle {
+    pub concurrency: usize,
+    pub delay: Duration,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle {
+            concurrency: 4,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
+
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+/// sets how web bookmarks are opened, only the first call has an effect
+pub fn set_throttle(throttle: Throttle) {
+    let _ = THROTTLE.set(throttle);
+}
+
+/// opens all `bms` and returns the failures, one per bookmark.
+/// Bookmarks launched by the OS (web pages, files) are throttled and opened last,
+/// the others and those with placeholders may prompt or print and are opened one after another first
+pub fn open_bms_throttled<'a>(
+    bms: &'a [Bookmark],
+    args: &BTreeMap<String, String>,
+) -> Vec<(&'a Bookmark, anyhow::Error)> {
+    let throttle = THROTTLE.get().copied().unwrap_or_default();
+    let (web, other): (Vec<&Bookmark>, Vec<&Bookmark>) =
+        bms.iter()
+            .partition(|bm| match handler::action(&bm.URL, &bm.get_tags()) {
+                Ok(Action::Os(uri)) => handler::placeholders(&uri).is_empty(),
+                _ => false,
+            });
+    let mut failures = Vec::new();
+    for bm in other {
+        if let Err(e) = open_bm_with_args(bm, args) {
+            failures.push((bm, e));
+        }
+    }
+    for (i, chunk) in web.chunks(throttle.concurrency.max(1)).enumerate() {
+        let results: Vec<anyhow::Result<()>> = thread::scope(|s| {
+            let launches: Vec<_> = chunk
+                .iter()
+                .enumerate()
+                .map(|(j, bm)| {
+                    if i + j > 0 {
+                        thread::sleep(throttle.delay);
+                    }
+                    debug!("Launching {:?}", bm.URL);
+                    s.spawn(move || open_targets(bm, args))
+                })
+                .collect();
+            launches
+                .into_iter()
+                .map(|launch| {
+                    launch
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("Launcher panicked")))
+                })
+                .collect()
+        });
+        for (bm, result) in chunk.iter().zip(results) {
+            match result {
+                Ok(()) => record_visit(bm),
+                Err(e) => failures.push((*bm, e)),
             }
-            None => {
-                open::that(uri)?;
+        }
+    }
+    failures
+}
+
+/// `tags` of the bookmark may select the opener of web pages and files,
+/// shell commands run in `context`: working directory and environment of the bookmark
+fn _open_bm(
+    uri: &str,
+    tags: &[String],
+    args: &BTreeMap<String, String>,
+    context: &ShellContext,
+) -> anyhow::Result<()> {
+    let launch = prepare(handler::action(uri, tags)?, args, context, true)?;
+    debug!("Launch {:?}", launch);
+    launch.run()
+}
+
+/// what opening a bookmark does, placeholders filled in
+#[derive(Debug, PartialEq)]
+enum Launch<'a> {
+    /// command line run by `sh -c` in the current terminal
+    Shell(String, &'a ShellContext),
+    /// command line starting a new tmux window/pane or wezterm tab
+    Pane(Vec<String>),
+    Copy(String),
+    Print(String),
+    Markdown(String),
+    /// URI or absolute path opened by the OS
+    Os(String),
+}
+
+/// `prompt`: missing placeholder values are prompted for, else they are kept
+fn prepare<'a>(
+    action: Action,
+    args: &BTreeMap<String, String>,
+    context: &'a ShellContext,
+    prompt: bool,
+) -> anyhow::Result<Launch<'a>> {
+    match action {
+        Action::Shell(cmd) => {
+            let cmd = fill_placeholders(&cmd, args, |value| value.to_string(), prompt)?;
+            match handler::pane() {
+                Some(pane) => {
+                    let cwd = context.cwd.clone().or_else(|| std::env::current_dir().ok());
+                    Ok(Launch::Pane(handler::pane_command(
+                        pane,
+                        &cmd,
+                        cwd.as_deref(),
+                        &context.env,
+                    )))
+                }
+                None => Ok(Launch::Shell(cmd, context)),
             }
         }
-        Ok(())
+        Action::Copy(text) => Ok(Launch::Copy(text)),
+        Action::Print(text) => Ok(Launch::Print(text)),
+        Action::Markdown(text) => Ok(Launch::Markdown(text)),
+        Action::Os(uri) => {
+            let uri = fill_placeholders(&uri, args, handler::url_encode, prompt)?;
+            match abspath(&uri) {
+                Some(path) => Ok(Launch::Os(path)),
+                // a missing file must fail for the fallbacks to be tried
+                None if uri.starts_with(['/', '~', '.']) => {
+                    Err(anyhow::anyhow!("No such file: {}", uri))
+                }
+                None => Ok(Launch::Os(uri)),
+            }
+        }
+    }
+}
+
+impl Launch<'_> {
+    fn run(self) -> anyhow::Result<()> {
+        match self {
+            Launch::Shell(cmd, context) => {
+                let mut command = Command::new("sh");
+                if let Some(cwd) = &context.cwd {
+                    command.current_dir(cwd);
+                }
+                let mut child = command
+                    .arg("-c")
+                    .arg(&cmd)
+                    .envs(&context.env)
+                    .stdin(Stdio::inherit())
+                    .stdout(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .spawn()
+                    .with_context(|| {
+                        format!("({}:{}) Error running {}", function_name!(), line!(), cmd)
+                    })?;
+
+                let status = child.wait().expect("Failed to wait on Vim");
+                debug!("Exit status from command: {:?}", status);
+                Ok(())
+            }
+            // starts the command without waiting for it
+            Launch::Pane(argv) => {
+                let status = Command::new(&argv[0])
+                    .args(&argv[1..])
+                    .status()
+                    .with_context(|| format!("Cannot run {}", argv[0]))?;
+                if !status.success() {
+                    return Err(anyhow::anyhow!("{} failed: {}", argv[0], status));
+                }
+                Ok(())
+            }
+            Launch::Copy(text) => {
+                let mut clipboard = Clipboard::new().context("Cannot access the clipboard")?;
+                clipboard
+                    .set_text(text)
+                    .context("Cannot copy to the clipboard")?;
+                eprintln!("Copied to clipboard");
+                Ok(())
+            }
+            Launch::Print(text) => {
+                println!("{}", text);
+                Ok(())
+            }
+            Launch::Markdown(text) => {
+                print_markdown(&text);
+                Ok(())
+            }
+            Launch::Os(target) => {
+                // todo error propagation upstream not working
+                open::that(target)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// what `run` would do, one line per detail
+    fn describe(&self) -> Vec<String> {
+        let join = |argv: &[&str]| shlex::try_join(argv.iter().copied()).unwrap_or(argv.join(" "));
+        match self {
+            Launch::Shell(cmd, context) => {
+                let mut lines = vec![format!("run: {}", join(&["sh", "-c", cmd]))];
+                if let Some(cwd) = &context.cwd {
+                    lines.push(format!("cwd: {}", cwd.display()));
+                }
+                lines.extend(context.env.iter().map(|(k, v)| format!("env: {}={}", k, v)));
+                lines
+            }
+            Launch::Pane(argv) => {
+                let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+                vec![format!("run: {}", join(&argv))]
+            }
+            Launch::Copy(text) => vec![format!("copy to clipboard: {}", text)],
+            Launch::Print(text) => vec![format!("print: {}", text)],
+            Launch::Markdown(text) => vec![format!("print markdown: {}", text)],
+            Launch::Os(target) => vec![format!("open by OS: {}", target)],
+        }
+    }
+}
+
+/// shows what opening `bm` would run, for the URI and each fallback, nothing is opened
+pub fn print_open_bm(bm: &Bookmark, args: &BTreeMap<String, String>) {
+    let context = ShellContext::from(bm);
+    eprintln!("{}: {}", bm.id, bm.metadata);
+    for (i, target) in handler::targets(bm).iter().enumerate() {
+        eprintln!("   {}: {}", if i == 0 { "uri" } else { "fallback" }, target);
+        let launch =
+            handler::action_with_handler(target, &bm.get_tags()).and_then(|(action, handler)| {
+                eprintln!("   handler: {}", handler);
+                prepare(action, args, &context, false)
+            });
+        match launch {
+            Ok(launch) => launch
+                .describe()
+                .iter()
+                .for_each(|line| eprintln!("   {}", line)),
+            Err(e) => eprintln!("   error: {:#}", e),
+        }
+    }
+}
+
+/// rendered if built with feature `markdown` and colors are enabled for stdout, else printed as is
+fn print_markdown(text: &str) {
+    #[cfg(feature = "markdown")]
+    if helper::color_choice(Stream::Stdout) != termcolor::ColorChoice::Never {
+        termimad::print_text(text);
+        return;
+    }
+    println!("{}", text);
+}
+
+/// `cmd` with the placeholders replaced by `args`, values not given are prompted for on a terminal
+/// if `prompt`, else kept. `encode`: applied to the values, e.g. for URLs
+fn fill_placeholders(
+    cmd: &str,
+    args: &BTreeMap<String, String>,
+    encode: fn(&str) -> String,
+    prompt: bool,
+) -> anyhow::Result<String> {
+    let mut values = args.clone();
+    for name in handler::placeholders(cmd) {
+        if values.contains_key(&name) || !prompt {
+            continue;
+        }
+        if !atty::is(Stream::Stdin) {
+            return Err(anyhow::anyhow!(
+                "Missing value for {{{}}}, pass it as: bkmr open <id> -- {}=<value>",
+                name,
+                name
+            ));
+        }
+        let value = Text::new(&format!("{}:", name))
+            .prompt()
+            .with_context(|| format!("No value for {{{}}}", name))?;
+        values.insert(name, value);
     }
+    let values = values
+        .into_iter()
+        .map(|(name, value)| (name, encode(&value)))
+        .collect();
+    Ok(handler::interpolate(cmd, &values))
+}
+
+/// opening more bookmarks at once needs a confirmation, unless configured otherwise
+pub const DEFAULT_OPEN_CONFIRM_ABOVE: usize = 5;
+/// number of titles shown when asking for confirmation
+const OPEN_PREVIEW: usize = 5;
+
+static OPEN_CONFIRM_ABOVE: OnceLock<usize> = OnceLock::new();
+
+/// sets the number of bookmarks opened without confirmation, only the first call has an effect
+pub fn set_open_confirm_above(confirm_above: usize) {
+    let _ = OPEN_CONFIRM_ABOVE.set(confirm_above);
+}
+
+/// asks before opening more bookmarks than configured, shows their number and the first titles.
+/// Err: no confirmation possible, e.g. without terminal
+pub fn confirm_open(bms: &[Bookmark]) -> anyhow::Result<bool> {
+    let confirm_above = *OPEN_CONFIRM_ABOVE
+        .get()
+        .unwrap_or(&DEFAULT_OPEN_CONFIRM_ABOVE);
+    if bms.len() <= confirm_above {
+        return Ok(true);
+    }
+    for bm in bms.iter().take(OPEN_PREVIEW) {
+        eprintln!("{:>5}: {} ({})", bm.id, bm.metadata, bm.URL);
+    }
+    if bms.len() > OPEN_PREVIEW {
+        eprintln!("       ... and {} more", bms.len() - OPEN_PREVIEW);
+    }
+    Confirm::new(format!("Really open these {} bookmarks?", bms.len()).as_str())
+        .with_default(false)
+        .prompt()
+        .context("Cannot confirm opening")
 }
 
 pub fn open_bms(ids: Vec<i32>, bms: Vec<Bookmark>) -> anyhow::Result<()> {
-    debug!("({}:{}) {:?}", function_name!(), line!(), ids);
+    debug!("{:?}", ids);
 
-    do_sth_with_bms(ids, bms, open_bm)
-        .with_context(|| format!("({}:{}) Error opening bookmarks", function_name!(), line!()))?;
+    let mut selected = Vec::new();
+    do_sth_with_bms(ids, bms, |bm| {
+        selected.push(bm.clone());
+        Ok(())
+    })?;
+    if !confirm_open(&selected)? {
+        eprintln!("Aborted");
+        return Ok(());
+    }
+    let failures = open_bms_throttled(&selected, &BTreeMap::new());
+    for (bm, e) in &failures {
+        eprintln!("Cannot open {}: {:?}", bm.URL, e);
+    }
+    if !failures.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} of {} bookmarks could not be opened",
+            failures.len(),
+            selected.len()
+        ));
+    }
     Ok(())
 }
 
-pub fn delete_bms(mut ids: Vec<i32>, bms: Vec<Bookmark>) -> anyhow::Result<()> {
+/// returns the deleted bookmarks, `dry_run`: nothing is deleted
+pub fn delete_bms(
+    mut ids: Vec<i32>,
+    bms: Vec<Bookmark>,
+    dry_run: bool,
+) -> anyhow::Result<Vec<Bookmark>> {
     // reverse sort necessary due to DB compaction (deletion of last entry first)
     ids.reverse();
-    debug!("({}:{}) {:?}", function_name!(), line!(), &ids);
-    fn delete_bm(bm: &Bookmark) -> anyhow::Result<()> {
-        let _ = Dal::new(CONFIG.db_url.clone()).delete_bookmark2(bm.id)?;
-        eprintln!("Deleted: {}", bm.URL);
-        Ok(())
+    debug!("{:?}", &ids);
+    let mut deleted = Vec::new();
+    Dal::default()
+        .transaction_or_dry_run(dry_run, |dal| {
+            do_sth_with_bms(ids, bms, |bm| {
+                let _ = dal.delete_bookmark2(bm.id)?;
+                deleted.push(bm.clone());
+                Ok(())
+            })
+        })
+        .with_context(|| {
+            format!(
+                "({}:{}) Error deleting bookmarks, no bookmark deleted",
+                function_name!(),
+                line!()
+            )
+        })?;
+    let action = if dry_run { "Would delete" } else { "Deleted" };
+    for bm in &deleted {
+        eprintln!("{}: {}", action, bm.URL);
     }
-    do_sth_with_bms(ids, bms, delete_bm).with_context(|| {
-        format!(
-            "({}:{}) Error deleting bookmarks",
-            function_name!(),
-            line!()
-        )
-    })?;
-    Ok(())
+    Ok(deleted)
 }
 
 fn do_sth_with_bms(
     ids: Vec<i32>,
     bms: Vec<Bookmark>,
-    do_sth: fn(bm: &Bookmark) -> anyhow::Result<()>,
+    mut do_sth: impl FnMut(&Bookmark) -> anyhow::Result<()>,
 ) -> anyhow::Result<()> {
-    debug!("({}:{}) {:?}", function_name!(), line!(), ids);
+    debug!("{:?}", ids);
     for id in ids {
-        if id as usize > bms.len() {
-            eprintln!("Id {} out of range", id);
-            continue;
+        if id < 1 || id as usize > bms.len() {
+            return Err(DieselError::NotFound).with_context(|| format!("Id {} out of range", id));
         }
         let bm = &bms[id as usize - 1];
-        debug!("({}:{}) {:?}: bm {:?}", function_name!(), line!(), id, bm);
+        debug!("{:?}: bm {:?}", id, bm);
         do_sth(bm).with_context(|| format!("({}:{}): bm {:?}", function_name!(), line!(), bm))?;
     }
     Ok(())
 }
 
 pub fn do_edit(bm: &Bookmark) -> anyhow::Result<()> {
-    // Create a file inside of `std::env::temp_dir()`.
-    // let mut file = tempfile()?;
-    let mut temp_file = File::create("temp.txt")?;
-
-    let template = formatdoc! {r###"
-        # Lines beginning with "#" will be stripped.
-        # Add URL in next line (single line).
-        {url}
-        # Add TITLE in next line (single line). Leave blank to web fetch, "-" for no title.
-        {title}
-        # Add comma-separated TAGS in next line (single line).
-        {tags}
-        # Add COMMENTS in next line(s). Leave blank to web fetch, "-" for no comments.
-        {comments}
-        "###,
-        url=bm.URL.clone(),
-        title=bm.metadata.clone(),
-        tags=bm.tags.clone(),
-        comments=bm.desc.clone(),
-    };
+    edit_in_editor(std::slice::from_ref(bm))
+}
+
+/// opens `bms` in the editor, all changes are applied or none
+fn edit_in_editor(bms: &[Bookmark]) -> anyhow::Result<()> {
+    let modified_content = run_editor(&edit::render(bms)?)?;
+    let mut edited = edit::parse(&modified_content)
+        .and_then(|records| edit::apply(records, bms))
+        .context("No changes applied")?;
+    edited
+        .iter_mut()
+        .filter(|bm| bm.metadata.trim().is_empty() && bm.URL.starts_with("http"))
+        .for_each(fill_from_web);
+    debug!("{:?}", edited);
+    confirm_changes(bms, &edited)?;
+
+    let updated = Dal::default()
+        .transaction(|dal| {
+            let mut updated = Vec::new();
+            for bm in edited {
+                let id = bm.id;
+                updated.extend(
+                    dal.update_bookmark(bm)
+                        .with_context(|| format!("Error updating bookmark {}", id))?,
+                );
+            }
+            Ok::<_, anyhow::Error>(updated)
+        })
+        .with_context(|| {
+            format!(
+                "({}:{}) Error editing bookmarks, no changes applied",
+                function_name!(),
+                line!()
+            )
+        })?;
+    show_bms(&updated);
+    Ok(())
+}
+
+/// shows the changed fields and asks to apply them, without terminal the changes are applied
+fn confirm_changes(bms: &[Bookmark], edited: &[Bookmark]) -> anyhow::Result<()> {
+    let n_changed = show_changes(bms, edited)?;
+    if n_changed == 0 {
+        return Err(EditAborted(String::from("no changes")).into());
+    }
+    if !atty::is(Stream::Stdin) {
+        return Ok(());
+    }
+    match Confirm::new(&format!("Apply changes to {} bookmarks?", n_changed))
+        .with_default(true)
+        .prompt()
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(EditAborted(String::from("changes rejected")).into()),
+        Err(e) => Err(EditAborted(format!("cannot confirm changes: {}", e)).into()),
+    }
+}
+
+/// prints the changed fields of `changed` compared to `bms` as diff, returns the number of changed bookmarks
+pub fn show_changes(bms: &[Bookmark], changed: &[Bookmark]) -> anyhow::Result<usize> {
+    let mut stderr = StandardStream::stderr(helper::color_choice(Stream::Stderr));
+    let mut n_changed = 0;
+    for new in changed {
+        let Some(old) = bms.iter().find(|bm| bm.id == new.id) else {
+            continue;
+        };
+        let changes = edit::diff(old, new);
+        if changes.is_empty() {
+            continue;
+        }
+        n_changed += 1;
+        writeln!(&mut stderr, "Bookmark {}:", new.id)?;
+        for change in changes {
+            for (sign, value, color) in [
+                ("-", change.old, Color::Red),
+                ("+", change.new, Color::Green),
+            ] {
+                stderr.set_color(ColorSpec::new().set_fg(Some(color)))?;
+                for line in value.split('\n') {
+                    writeln!(&mut stderr, "{} {:<12} {}", sign, change.field, line)?;
+                }
+            }
+            stderr.reset()?;
+        }
+    }
+    Ok(n_changed)
+}
+
+/// sets title and, if empty, description of `bm` from its web page
+fn fill_from_web(bm: &mut Bookmark) {
+    match crate::load_url_details(&bm.URL) {
+        Ok((title, description, _keywords)) => {
+            debug!("{}: {:?}, {:?}", bm.URL, title, description);
+            bm.metadata = title;
+            if bm.desc.trim().is_empty() {
+                bm.desc = description;
+            }
+        }
+        Err(e) => {
+            debug!("{}: {:?}", bm.URL, e);
+            eprintln!("Cannot enrich URL data from web: {}", bm.URL);
+        }
+    }
+}
+
+/// creates bookmarks written in the editor from scratch, no web fetch
+pub fn new_bms() -> anyhow::Result<Vec<Bookmark>> {
+    let modified_content = run_editor(&edit::render_new()?)?;
+    let records = edit::parse(&modified_content)
+        .and_then(edit::check_new)
+        .context("No bookmark added")?;
+    debug!("{:?}", records);
+
+    Dal::default()
+        .transaction(|dal| {
+            let mut added = Vec::new();
+            for record in records {
+                let bm = record.clone().into_new();
+                if dal.bm_exists(&bm.URL)? {
+                    return Err(anyhow::anyhow!("Bookmark already exists: {}", bm.URL));
+                }
+                let url = bm.URL.clone();
+                for inserted in dal
+                    .insert_bookmark(bm)
+                    .with_context(|| format!("Error adding {}", url))?
+                {
+                    // extra fields are not part of the insert
+                    let bm = record.clone().apply(&inserted);
+                    if bm == inserted {
+                        added.push(inserted);
+                    } else {
+                        added.extend(dal.update_bookmark(bm)?);
+                    }
+                }
+            }
+            Ok(added)
+        })
+        .with_context(|| {
+            format!(
+                "({}:{}) Error adding bookmarks, nothing added",
+                function_name!(),
+                line!()
+            )
+        })
+}
+
+/// lets the user edit `template` in a temp file, returns the saved content.
+/// Editor failure, unchanged or emptied content abort the edit.
+pub fn run_editor(template: &str) -> anyhow::Result<String> {
+    // unique file in the OS temp dir, removed when dropped, also on errors
+    let mut temp_file = tempfile::Builder::new()
+        .prefix("bkmr-")
+        .suffix(".toml")
+        .tempfile()
+        .with_context(|| {
+            format!(
+                "({}:{}) Error creating temp file",
+                function_name!(),
+                line!()
+            )
+        })?;
 
     temp_file.write_all(template.as_bytes()).with_context(|| {
         format!(
@@ -303,57 +905,39 @@ pub fn do_edit(bm: &Bookmark) -> anyhow::Result<()> {
         )
     })?;
 
-    // get default OS editor in varialbe to use in Command::new
-    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vim".to_string());
-    debug!(
-        "({}:{}) Using editor: {:?}",
-        function_name!(),
-        line!(),
-        editor
-    );
-    // Open the temporary file with Vim
-    Command::new(&editor)
-        .arg("temp.txt")
+    let editor = helper::editor_command()?;
+    debug!("Using editor: {:?}", editor);
+    let status = Command::new(&editor[0])
+        .args(&editor[1..])
+        .arg(temp_file.path())
         .status()
         .with_context(|| {
             format!(
-                "({}:{}) Error opening temp file with [{}], check your EDITOR variable.",
+                "({}:{}) Error opening temp file with {:?}, check your VISUAL/EDITOR variable.",
                 function_name!(),
                 line!(),
                 &editor
             )
         })?;
 
+    if !status.success() {
+        return Err(EditAborted(format!("editor exited with {}", status)).into());
+    }
+
     // Read the modified content of the file back into a string
-    let modified_content = fs::read_to_string("temp.txt")
+    let modified_content = fs::read_to_string(temp_file.path())
         .with_context(|| format!("({}:{}) Error reading temp file", function_name!(), line!()))?;
-    let lines: Vec<&str> = modified_content
-        .split('\n')
-        .filter(|l| !l.starts_with('#'))
-        .collect();
-    let new_bm = Bookmark {
-        id: bm.id,
-        URL: lines[0].to_string(),
-        metadata: lines[1].to_string(), // title
-        tags: lines[2].to_string(),
-        desc: lines[3].to_string(), // comments
-        flags: bm.flags,
-        last_update_ts: Default::default(), // will be overwritten by diesel
-    };
-    // println!("Modified content: {}", modified_content);
-    debug!("({}:{}) lines: {:?}", function_name!(), line!(), lines);
-
-    let updated = Dal::new(CONFIG.db_url.clone())
-        .update_bookmark(new_bm)
-        .with_context(|| format!("({}:{}) Error updating bookmark", function_name!(), line!()))?;
-    // Delete the temporary file
-    fs::remove_file("temp.txt")?;
-    show_bms(&updated);
-    Ok(())
+    if modified_content == template {
+        return Err(EditAborted(String::from("no changes")).into());
+    }
+    if edit::is_empty(&modified_content) {
+        return Err(EditAborted(String::from("empty buffer")).into());
+    }
+    Ok(modified_content)
 }
 
 fn print_ids(ids: Vec<i32>, bms: Vec<Bookmark>) -> anyhow::Result<()> {
-    debug!("({}:{}) ids: {:?}", function_name!(), line!(), ids);
+    debug!("ids: {:?}", ids);
     let selected_bms = if ids.is_empty() {
         bms // print all
     } else {
@@ -424,7 +1008,7 @@ mod test {
     #[ignore = "Manual Test with Makefile"]
     #[case(r#####"shell::vim +/"## SqlAlchemy" $HOME/dev/s/private/bkmr/bkmr/tests/resources/sample_docu.md"#####)]
     fn test_open_bm(#[case] bm: &str) {
-        _open_bm(bm).unwrap();
+        _open_bm(bm, &[], &BTreeMap::new(), &ShellContext::default()).unwrap();
     }
 
     #[rstest]
@@ -434,6 +1018,108 @@ mod test {
         open_bms(ids, bms).unwrap();
     }
 
+    #[rstest]
+    fn test_open_bm_unattended_refuses_shell_fallback() {
+        let marker = std::env::temp_dir().join("bkmr-unattended-fallback");
+        let _ = fs::remove_file(&marker);
+        let mut bm = Bookmark {
+            URL: "/no/such/file".to_string(),
+            ..Default::default()
+        };
+        bm.set_extra_value("fallback.1", &format!("shell::touch {}", marker.display()));
+        let e = open_bm_unattended(&bm).unwrap_err();
+        assert!(e.to_string().contains("not opened without a terminal"));
+        assert!(!marker.exists());
+    }
+
+    #[rstest]
+    fn test_open_bms_throttled() {
+        let bms: Vec<Bookmark> = ["snip::a", "snip::b"]
+            .iter()
+            .map(|url| Bookmark {
+                URL: url.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        assert!(open_bms_throttled(&bms, &BTreeMap::new()).is_empty());
+    }
+
+    #[rstest]
+    fn test_open_targets() {
+        let mut bm = Bookmark {
+            URL: "/no/such/file.pdf".to_string(),
+            ..Default::default()
+        };
+        assert!(open_targets(&bm, &BTreeMap::new()).is_err());
+        bm.set_extra_value("fallback.1", "~/no/such/file.pdf");
+        bm.set_extra_value("fallback.2", "snip::fallback");
+        open_targets(&bm, &BTreeMap::new()).unwrap();
+    }
+
+    #[rstest]
+    fn test_prepare() {
+        let context = ShellContext {
+            cwd: Some("/tmp".into()),
+            ..Default::default()
+        };
+        let launch = prepare(
+            Action::Shell("ls {dir}".to_string()),
+            &BTreeMap::new(),
+            &context,
+            false,
+        )
+        .unwrap();
+        assert_eq!(launch, Launch::Shell("ls {dir}".to_string(), &context));
+        assert_eq!(
+            launch.describe(),
+            vec!["run: sh -c 'ls {dir}'", "cwd: /tmp"]
+        );
+        let launch = prepare(
+            Action::Os("https://github.com/search?q={query}".to_string()),
+            &BTreeMap::from([("query".to_string(), "a b".to_string())]),
+            &context,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            launch.describe(),
+            vec!["open by OS: https://github.com/search?q=a%20b"]
+        );
+        let missing = prepare(
+            Action::Os("~/no/such/file".to_string()),
+            &BTreeMap::new(),
+            &context,
+            false,
+        );
+        assert!(missing.is_err());
+    }
+
+    #[rstest]
+    fn test_fill_placeholders() {
+        let args = BTreeMap::from([("pod".to_string(), "web-1".to_string())]);
+        assert_eq!(
+            fill_placeholders(
+                "kubectl logs {pod} -n {ns} | awk '{print $1}'",
+                &args,
+                |value| value.to_string(),
+                false
+            )
+            .unwrap(),
+            "kubectl logs web-1 -n {ns} | awk '{print $1}'"
+        );
+        let args = BTreeMap::from([("query".to_string(), "rust lang".to_string())]);
+        assert_eq!(
+            fill_placeholders(
+                "https://github.com/search?q={query}",
+                &args,
+                handler::url_encode,
+                true
+            )
+            .unwrap(),
+            "https://github.com/search?q=rust%20lang"
+        );
+    }
+
     #[rstest]
     // #[case(vec ! [String::from("1")])]
     #[case(vec ! [])]
@@ -459,4 +1145,27 @@ mod test {
         });
         assert!(result.is_err());
     }
+
+    #[rstest]
+    #[case("abc", 5, "abc")]
+    #[case("abcdef", 4, "abc…")]
+    #[case("abc", 0, "")]
+    fn test_truncate(#[case] s: &str, #[case] width: usize, #[case] expected: &str) {
+        assert_eq!(truncate(s, width), expected);
+    }
+
+    #[rstest]
+    fn test_compact_fields() {
+        let bm = Bookmark {
+            URL: String::from("https://www.sysid.de/posts/x"),
+            metadata: String::from("a rather long title of a blog post"),
+            tags: String::from(",aaa,xxx,"),
+            ..Default::default()
+        };
+        let (title, domain, tags) = compact_fields(&bm, 30);
+        assert_eq!(title, "a rather lon… ");
+        assert_eq!(domain, "sysid.de ");
+        assert_eq!(tags, "aaa,xxx");
+        assert_eq!((title + &domain + &tags).chars().count(), 30);
+    }
 }
@@ -0,0 +1,214 @@
+//! `bkmr://` URLs let other applications and web pages trigger bkmr actions:
+//! `bkmr://open/<id>` and `bkmr://add?url=<url>&tags=<tags>&title=<title>`.
+//! `bkmr protocol install` registers bkmr with the OS as handler of the scheme.
+//! Only web pages and files are opened or added this way, never shell commands.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, Context};
+use percent_encoding::percent_decode_str;
+
+use crate::handler;
+use crate::handler::Action;
+
+pub const SCHEME: &str = "bkmr://";
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolAction {
+    /// `bkmr://open/<id>`
+    Open(i32),
+    /// `bkmr://add?url=<url>&tags=<tags>&title=<title>`, tags and title are optional
+    Add {
+        url: String,
+        tags: Option<String>,
+        title: Option<String>,
+    },
+}
+
+pub fn parse(uri: &str) -> anyhow::Result<ProtocolAction> {
+    let rest = uri
+        .strip_prefix(SCHEME)
+        .ok_or_else(|| anyhow!("Not a {} URL: {}", SCHEME, uri))?;
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let mut params = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            Ok((decode(name)?, decode(value)?))
+        })
+        .collect::<anyhow::Result<BTreeMap<String, String>>>()
+        .with_context(|| format!("Invalid query in {}", uri))?;
+    match path.trim_end_matches('/') {
+        "add" => Ok(ProtocolAction::Add {
+            url: params
+                .remove("url")
+                .ok_or_else(|| anyhow!("Missing url in {}", uri))?,
+            tags: params.remove("tags"),
+            title: params.remove("title"),
+        }),
+        path => match path.strip_prefix("open/") {
+            Some(id) => id
+                .parse()
+                .map(ProtocolAction::Open)
+                .with_context(|| format!("Invalid id in {}", uri)),
+            None => Err(anyhow!(
+                "Unknown action in {}, use {}open/<id> or {}add?url=<url>",
+                uri,
+                SCHEME,
+                SCHEME
+            )),
+        },
+    }
+}
+
+/// query values may be percent-encoded and use `+` for blanks
+fn decode(s: &str) -> anyhow::Result<String> {
+    Ok(percent_decode_str(&s.replace('+', " "))
+        .decode_utf8()?
+        .into_owned())
+}
+
+/// whether `uri` may be opened or added by a `bkmr://` URL: only what the OS opens, no commands
+pub fn is_safe(uri: &str) -> bool {
+    matches!(handler::action(uri, &[]), Ok(Action::Os(_)))
+}
+
+/// registers `exe` as handler of `bkmr://` URLs for the current user, returns the installed file
+pub fn install(exe: &Path) -> anyhow::Result<PathBuf> {
+    if cfg!(target_os = "macos") {
+        install_macos(exe)
+    } else {
+        install_xdg(exe)
+    }
+}
+
+const DESKTOP_FILE: &str = "bkmr-protocol.desktop";
+
+fn desktop_entry(exe: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+        Type=Application\n\
+        Name=bkmr\n\
+        Comment=Open bkmr:// URLs\n\
+        Exec=\"{}\" protocol handle %u\n\
+        Terminal=false\n\
+        NoDisplay=true\n\
+        MimeType=x-scheme-handler/bkmr;\n",
+        exe.display()
+    )
+}
+
+/// desktop entry in `$XDG_DATA_HOME/applications`, set as default for the scheme by `xdg-mime`
+fn install_xdg(exe: &Path) -> anyhow::Result<PathBuf> {
+    let data_home = std::env::var("XDG_DATA_HOME")
+        .unwrap_or_else(|_| shellexpand::tilde("~/.local/share").into_owned());
+    let dir = Path::new(&data_home).join("applications");
+    fs::create_dir_all(&dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    let path = dir.join(DESKTOP_FILE);
+    fs::write(&path, desktop_entry(exe))
+        .with_context(|| format!("Cannot write {}", path.display()))?;
+    run(Command::new("xdg-mime").args(["default", DESKTOP_FILE, "x-scheme-handler/bkmr"]))?;
+    // only refreshes the cache of desktop entries, not available everywhere
+    let _ = Command::new("update-desktop-database").arg(&dir).status();
+    Ok(path)
+}
+
+const LSREGISTER: &str = "/System/Library/Frameworks/CoreServices.framework/Frameworks/\
+    LaunchServices.framework/Support/lsregister";
+
+/// AppleScript application in `~/Applications` receiving the URLs, registered with Launch Services
+fn install_macos(exe: &Path) -> anyhow::Result<PathBuf> {
+    let app = PathBuf::from(shellexpand::tilde("~/Applications/bkmr-protocol.app").as_ref());
+    let handle = format!(
+        "do shell script quoted form of \"{}\" & \" protocol handle \" & quoted form of theURL",
+        exe.display()
+    );
+    run(Command::new("osacompile").arg("-o").arg(&app).args([
+        "-e",
+        "on open location theURL",
+        "-e",
+        &handle,
+        "-e",
+        "end open location",
+    ]))?;
+    run(Command::new("plutil")
+        .args([
+            "-replace",
+            "CFBundleURLTypes",
+            "-json",
+            r#"[{"CFBundleURLName": "bkmr", "CFBundleURLSchemes": ["bkmr"]}]"#,
+        ])
+        .arg(app.join("Contents/Info.plist")))?;
+    run(Command::new(LSREGISTER).arg("-f").arg(&app))?;
+    Ok(app)
+}
+
+fn run(command: &mut Command) -> anyhow::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .with_context(|| format!("Cannot run {}", program))?;
+    if !status.success() {
+        return Err(anyhow!("{} failed: {}", program, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("bkmr://open/12", ProtocolAction::Open(12))]
+    #[case("bkmr://open/12/", ProtocolAction::Open(12))]
+    #[case(
+        "bkmr://add?url=https%3A%2F%2Fwww.sysid.de%2F%3Fa%3D1&tags=blog,rust&title=sysid+blog",
+        ProtocolAction::Add {
+            url: "https://www.sysid.de/?a=1".to_string(),
+            tags: Some("blog,rust".to_string()),
+            title: Some("sysid blog".to_string()),
+        }
+    )]
+    #[case(
+        "bkmr://add/?url=https://www.sysid.de",
+        ProtocolAction::Add {
+            url: "https://www.sysid.de".to_string(),
+            tags: None,
+            title: None,
+        }
+    )]
+    fn test_parse(#[case] uri: &str, #[case] expected: ProtocolAction) {
+        assert_eq!(parse(uri).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("https://www.sysid.de")]
+    #[case("bkmr://open/x")]
+    #[case("bkmr://add?tags=x")]
+    #[case("bkmr://delete/1")]
+    #[case("bkmr://add?url=%FF")]
+    fn test_parse_invalid(#[case] uri: &str) {
+        assert!(parse(uri).is_err());
+    }
+
+    #[rstest]
+    #[case("https://www.sysid.de", true)]
+    #[case("~/papers/paper.pdf", true)]
+    #[case("shell::rm -rf ~", false)]
+    #[case("copy::secret", false)]
+    fn test_is_safe(#[case] uri: &str, #[case] expected: bool) {
+        assert_eq!(is_safe(uri), expected);
+    }
+
+    #[rstest]
+    fn test_desktop_entry() {
+        let entry = desktop_entry(Path::new("/usr/local/bin/bkmr"));
+        assert!(entry.contains("Exec=\"/usr/local/bin/bkmr\" protocol handle %u\n"));
+        assert!(entry.contains("MimeType=x-scheme-handler/bkmr;\n"));
+    }
+}
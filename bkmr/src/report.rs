@@ -0,0 +1,213 @@
+//! Maintenance report of `bkmr report health`: dead and moved links of the last `bkmr check`,
+//! duplicate candidates, untagged bookmarks and empty titles, each with the command fixing them.
+//! Bookmarks in the trash are left out.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::models::Bookmark;
+use crate::{DEAD_TAG, MOVED_TAG, TRASH_TAG};
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Health {
+    /// tagged dead by `bkmr check`
+    pub dead: Vec<Bookmark>,
+    /// tagged moved by `bkmr check`, the new URL is `extra.moved_to`
+    pub moved: Vec<Bookmark>,
+    /// number of web bookmarks never checked
+    pub unchecked: usize,
+    /// groups of bookmarks of the same page, see [`page_key`]
+    pub duplicates: Vec<Vec<Bookmark>>,
+    /// without tags other than dead and moved
+    pub untagged: Vec<Bookmark>,
+    pub untitled: Vec<Bookmark>,
+}
+
+impl Health {
+    pub fn is_healthy(&self) -> bool {
+        self.dead.is_empty()
+            && self.moved.is_empty()
+            && self.unchecked == 0
+            && self.duplicates.is_empty()
+            && self.untagged.is_empty()
+            && self.untitled.is_empty()
+    }
+}
+
+/// the report of `bms`
+pub fn health(bms: Vec<Bookmark>) -> Health {
+    let mut health = Health::default();
+    let mut pages: BTreeMap<String, Vec<Bookmark>> = BTreeMap::new();
+    for bm in bms {
+        let tags = bm.get_tags();
+        if tags.iter().any(|t| t == TRASH_TAG) {
+            continue;
+        }
+        if tags.iter().any(|t| t == DEAD_TAG) {
+            health.dead.push(bm.clone());
+        }
+        if tags.iter().any(|t| t == MOVED_TAG) {
+            health.moved.push(bm.clone());
+        }
+        if bm.URL.starts_with("http") && bm.get_extra_value("http_status").is_none() {
+            health.unchecked += 1;
+        }
+        if tags.iter().all(|t| t == DEAD_TAG || t == MOVED_TAG) {
+            health.untagged.push(bm.clone());
+        }
+        if bm.metadata.trim().is_empty() {
+            health.untitled.push(bm.clone());
+        }
+        let key = bm
+            .get_extra_value("canonical_url")
+            .and_then(|url| page_key(&url))
+            .or_else(|| page_key(&bm.URL));
+        if let Some(key) = key {
+            pages.entry(key).or_default().push(bm);
+        }
+    }
+    health.duplicates = pages.into_values().filter(|bms| bms.len() > 1).collect();
+    health
+        .duplicates
+        .sort_by_key(|bms| bms.iter().map(|bm| bm.id).min());
+    health
+}
+
+/// web pages are the same regardless of scheme, `www.`, letter case of the host, fragment and
+/// trailing slash. None for other bookmarks
+fn page_key(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    let host = url.host_str()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let path = url.path().trim_end_matches('/');
+    Some(match url.query() {
+        Some(query) => format!("{}{}?{}", host, path, query),
+        None => format!("{}{}", host, path),
+    })
+}
+
+fn ids(bms: &[Bookmark]) -> String {
+    bms.iter()
+        .map(|bm| bm.id.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// a section of the text report: heading, the bookmarks and the fix
+fn section(f: &mut fmt::Formatter<'_>, heading: &str, bms: &[Bookmark], fix: &str) -> fmt::Result {
+    if bms.is_empty() {
+        return Ok(());
+    }
+    writeln!(f, "{} ({}):", heading, bms.len())?;
+    for bm in bms {
+        writeln!(f, "  [{}] {}  {}", bm.id, bm.metadata, bm.URL)?;
+    }
+    writeln!(f, "  -> {}", fix.replace("{ids}", &ids(bms)))
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_healthy() {
+            return writeln!(f, "No problems found");
+        }
+        section(
+            f,
+            "Dead links",
+            &self.dead,
+            "bkmr check {ids} to check again, bkmr open --archived <id>, bkmr delete {ids}",
+        )?;
+        section(
+            f,
+            "Moved links",
+            &self.moved,
+            "bkmr edit {ids}: the new URLs are in extra.moved_to",
+        )?;
+        if self.unchecked > 0 {
+            writeln!(f, "Web bookmarks never checked: {}", self.unchecked)?;
+            writeln!(f, "  -> bkmr check")?;
+        }
+        if !self.duplicates.is_empty() {
+            writeln!(f, "Duplicate candidates ({}):", self.duplicates.len())?;
+            for bms in &self.duplicates {
+                for bm in bms {
+                    writeln!(f, "  [{}] {}  {}", bm.id, bm.metadata, bm.URL)?;
+                }
+                writeln!(f, "  -> bkmr show {}, bkmr delete <id>", ids(bms))?;
+            }
+        }
+        section(
+            f,
+            "Untagged",
+            &self.untagged,
+            "bkmr update --pick-tags {ids}",
+        )?;
+        section(
+            f,
+            "Empty titles",
+            &self.untitled,
+            "bkmr refresh --empty-title",
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    fn bookmark(id: i32, url: &str, title: &str, tags: &str) -> Bookmark {
+        Bookmark {
+            id,
+            URL: url.to_string(),
+            metadata: title.to_string(),
+            tags: tags.to_string(),
+            extra: r#"{"http_status": "200"}"#.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case("https://www.Sysid.de/blog/#top", Some("sysid.de/blog"))]
+    #[case("http://sysid.de/blog", Some("sysid.de/blog"))]
+    #[case("https://sysid.de/?q=rust", Some("sysid.de?q=rust"))]
+    #[case("shell::ls", None)]
+    #[case("/home/user/notes.md", None)]
+    fn test_page_key(#[case] url: &str, #[case] expected: Option<&str>) {
+        assert_eq!(page_key(url).as_deref(), expected);
+    }
+
+    #[rstest]
+    fn test_health() {
+        let mut canonical = bookmark(5, "https://blog.org/p?utm=x", "Post", ",blog,");
+        canonical.set_extra_value("canonical_url", "https://blog.org/p");
+        let health = health(vec![
+            bookmark(1, "https://www.sysid.de/", "sysid", ",blog,"),
+            bookmark(2, "http://sysid.de", "", ",dead,"),
+            bookmark(3, "https://blog.org/p", "Post", ",blog,moved,"),
+            bookmark(4, "https://gone.org", "Gone", ",trash,dead,"),
+            canonical,
+            Bookmark {
+                extra: String::new(),
+                ..bookmark(6, "https://new.org", "New", ",blog,")
+            },
+        ]);
+        let ids = |bms: &[Bookmark]| bms.iter().map(|bm| bm.id).collect::<Vec<_>>();
+        assert_eq!(ids(&health.dead), vec![2]);
+        assert_eq!(ids(&health.moved), vec![3]);
+        assert_eq!(health.unchecked, 1);
+        let duplicates: Vec<Vec<i32>> = health.duplicates.iter().map(|bms| ids(bms)).collect();
+        assert_eq!(duplicates, vec![vec![1, 2], vec![3, 5]]);
+        assert_eq!(ids(&health.untagged), vec![2]);
+        assert_eq!(ids(&health.untitled), vec![2]);
+        let text = health.to_string();
+        assert!(text.contains("Dead links (1):\n  [2]   http://sysid.de\n"));
+        assert!(text.contains("  -> bkmr update --pick-tags 2\n"));
+        assert!(!health.is_healthy());
+    }
+}
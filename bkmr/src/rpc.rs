@@ -0,0 +1,338 @@
+//! `bkmr rpc`: JSON-RPC 2.0 on stdin/stdout, one request or response per line, so that editor
+//! plugins can keep one bkmr process running instead of starting it per keystroke.
+//!
+//! Methods:
+//! - `search {query?, tags?, limit?}`: bookmarks matching the FTS query and having all tags
+//! - `add {url, title?, tags?, desc?, fetch?}`: the new bookmark, `fetch` loads title and description
+//! - `open {id}`: opens the bookmark, snippets are returned as `{"text": ...}` instead of printed
+//! - `tags {prefix?}`: tags with their number of bookmarks
+use std::io::{BufRead, Write};
+
+use anyhow::Context;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::bms::Bookmarks;
+use crate::dal::Dal;
+use crate::handler;
+use crate::handler::Action;
+use crate::models::{Bookmark, NewBookmark};
+use crate::process::open_bm_unattended;
+use crate::tag::Tags;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+pub(crate) const METHOD_NOT_FOUND: i64 = -32601;
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+/// failures of the method itself, e.g. an unknown bookmark
+const SERVER_ERROR: i64 = -32000;
+
+/// methods of `bkmr rpc`
+pub const METHODS: &[&str] = &["search", "add", "open", "tags"];
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct RpcError {
+    pub(crate) code: i64,
+    pub(crate) message: String,
+}
+
+impl RpcError {
+    pub(crate) fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<DieselError> for RpcError {
+    fn from(e: DieselError) -> Self {
+        RpcError::new(SERVER_ERROR, e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        RpcError::new(SERVER_ERROR, format!("{:#}", e))
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SearchParams {
+    query: Option<String>,
+    tags: Vec<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct AddParams {
+    url: String,
+    title: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    desc: Option<String>,
+    #[serde(default)]
+    fetch: bool,
+}
+
+#[derive(Deserialize)]
+struct OpenParams {
+    id: i32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct TagsParams {
+    prefix: Option<String>,
+}
+
+/// answers the requests of `input` on `output` until `input` ends
+pub fn serve(input: impl BufRead, output: impl Write) -> anyhow::Result<()> {
+    serve_with(input, output, |line| respond(line, METHODS))
+}
+
+/// writes the responses of `respond` to the lines of `input`
+pub(crate) fn serve_with(
+    input: impl BufRead,
+    mut output: impl Write,
+    respond: impl Fn(&str) -> Option<Value>,
+) -> anyhow::Result<()> {
+    for line in input.lines() {
+        let line = line.context("Cannot read request")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = respond(&line) {
+            writeln!(output, "{}", response).context("Cannot write response")?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// response to one request calling one of `methods`, None for notifications (requests without id)
+pub fn respond(line: &str, methods: &[&str]) -> Option<Value> {
+    respond_with(line, |method, params| {
+        allowed(method, methods)?;
+        call(method, params)
+    })
+}
+
+/// fails for methods other than `methods`
+pub(crate) fn allowed(method: &str, methods: &[&str]) -> Result<(), RpcError> {
+    match methods.contains(&method) {
+        true => Ok(()),
+        false => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {:?}, one of {}", method, methods.join(", ")),
+        )),
+    }
+}
+
+/// response to one request answered by `call` with method and params
+pub(crate) fn respond_with(
+    line: &str,
+    call: impl Fn(&str, Value) -> Result<Value, RpcError>,
+) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(error_response(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+        }
+    };
+    let id = request.get("id").cloned();
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        let error = RpcError::new(INVALID_REQUEST, "Missing method");
+        return Some(error_response(id.unwrap_or(Value::Null), error));
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    debug!("{} {}", method, params);
+    let result = call(method, params);
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => error_response(id, error),
+    })
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": error.code, "message": error.message},
+    })
+}
+
+/// result of a method of `bkmr rpc`
+pub(crate) fn call(method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "search" => search(parse_params(params)?),
+        "add" => add(parse_params(params)?),
+        "open" => open(parse_params(params)?),
+        "tags" => tags(parse_params(params)?),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {:?}", method),
+        )),
+    }
+}
+
+/// missing params are the defaults of methods having them
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = match params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn search(params: SearchParams) -> Result<Value, RpcError> {
+    let query = params.query.unwrap_or_default();
+    let mut bms = Bookmarks::try_new(query)?.bms;
+    if !params.tags.is_empty() {
+        bms = Bookmarks::match_all(Tags::clean_tags(params.tags), bms, false);
+    }
+    if let Some(limit) = params.limit {
+        bms.truncate(limit);
+    }
+    Ok(serde_json::to_value(bms).expect("bookmarks are serializable"))
+}
+
+fn add(params: AddParams) -> Result<Value, RpcError> {
+    let (title, desc) = match params.fetch {
+        true => {
+            let details = crate::fetch::load_url_details(&params.url)?;
+            (
+                params.title.unwrap_or(details.title),
+                params.desc.unwrap_or(details.description),
+            )
+        }
+        false => (
+            params.title.unwrap_or_default(),
+            params.desc.unwrap_or_default(),
+        ),
+    };
+    let bm = NewBookmark {
+        URL: params.url.clone(),
+        metadata: title,
+        tags: Tags::create_normalized_tag_string(Some(params.tags.join(","))),
+        desc,
+        flags: 0,
+    };
+    match Dal::default().insert_bookmark(bm) {
+        Ok(bms) => Ok(serde_json::to_value(&bms[0]).expect("bookmarks are serializable")),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(RpcError::new(
+                SERVER_ERROR,
+                format!("Bookmark already exists: {}", params.url),
+            ))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// stdout carries the responses and stdin the requests: snippets are returned, bookmarks running
+/// commands, printing or prompting are refused
+fn open(params: OpenParams) -> Result<Value, RpcError> {
+    let mut dal = Dal::default();
+    let bm = dal
+        .get_bookmark_by_id(params.id)
+        .map_err(|e| RpcError::new(SERVER_ERROR, format!("Bookmark {}: {}", params.id, e)))?;
+    if let Action::Print(text) | Action::Markdown(text) = handler::action(&bm.URL, &bm.get_tags())?
+    {
+        dal.record_visit(bm.id)?;
+        return Ok(json!({"id": bm.id, "text": text}));
+    }
+    check_targets(&bm)?;
+    open_bm_unattended(&bm)?;
+    Ok(json!({"id": bm.id}))
+}
+
+/// the URI and all fallbacks of `bm` can be opened without running a command or printing
+fn check_targets(bm: &Bookmark) -> Result<(), RpcError> {
+    let tags = bm.get_tags();
+    for target in handler::targets(bm) {
+        if !matches!(
+            handler::action(&target, &tags)?,
+            Action::Copy(_) | Action::Os(_)
+        ) {
+            return Err(RpcError::new(
+                SERVER_ERROR,
+                format!(
+                    "Bookmark {} runs a command or prints ({}), not opened over RPC",
+                    bm.id, target
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn tags(params: TagsParams) -> Result<Value, RpcError> {
+    let prefix = params.prefix.unwrap_or_default();
+    let tags: Vec<Value> = Dal::default()
+        .get_all_tags()?
+        .into_iter()
+        .filter(|t| t.tag.starts_with(&prefix))
+        .map(|t| json!({"tag": t.tag, "count": t.n}))
+        .collect();
+    Ok(Value::Array(tags))
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("{", PARSE_ERROR)]
+    #[case(r#"{"jsonrpc": "2.0", "id": 1}"#, INVALID_REQUEST)]
+    #[case(r#"{"jsonrpc": "2.0", "id": 1, "method": "delete"}"#, METHOD_NOT_FOUND)]
+    #[case(
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "open", "params": {}}"#,
+        INVALID_PARAMS
+    )]
+    #[case(
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "add", "params": {"url": 1}}"#,
+        INVALID_PARAMS
+    )]
+    fn test_respond_errors(#[case] request: &str, #[case] code: i64) {
+        let response = respond(request, METHODS).unwrap();
+        assert_eq!(response["error"]["code"], code);
+        assert!(response.get("result").is_none());
+    }
+
+    #[rstest]
+    #[case("https://sysid.de", None, true)]
+    #[case("https://sysid.de", Some("copy::secret"), true)]
+    #[case("shell::ls", None, false)]
+    #[case("/no/such/file", Some("shell::echo INJECTED"), false)]
+    #[case("/no/such/file", Some("snip::text"), false)]
+    fn test_check_targets(#[case] url: &str, #[case] fallback: Option<&str>, #[case] ok: bool) {
+        let mut bm = Bookmark {
+            id: 2,
+            URL: url.to_string(),
+            ..Default::default()
+        };
+        if let Some(fallback) = fallback {
+            bm.set_extra_value("fallback.1", fallback);
+        }
+        assert_eq!(check_targets(&bm).is_ok(), ok);
+    }
+
+    #[rstest]
+    fn test_respond_notification() {
+        assert_eq!(
+            respond(r#"{"jsonrpc": "2.0", "method": "delete"}"#, METHODS),
+            None
+        );
+    }
+}
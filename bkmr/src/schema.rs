@@ -9,6 +9,9 @@ diesel::table! {
         desc -> Text,
         flags -> Integer,
         last_update_ts -> Timestamp,
+        extra -> Text,
+        visits -> Integer,
+        last_visited -> Nullable<Timestamp>,
     }
 }
 
@@ -23,3 +26,37 @@ diesel::table! {
         last_update_ts -> Timestamp,
     }
 }
+
+diesel::table! {
+    contents (bookmark_id) {
+        bookmark_id -> Integer,
+        content -> Text,
+    }
+}
+
+diesel::table! {
+    attachments (id) {
+        id -> Integer,
+        bookmark_id -> Integer,
+        name -> Text,
+        content -> Binary,
+        created_ts -> Timestamp,
+    }
+}
+
+diesel::table! {
+    changes (seq) {
+        seq -> Integer,
+        op -> Text,
+        URL -> Text,
+        ts -> Timestamp,
+    }
+}
+
+diesel::table! {
+    opens (id) {
+        id -> Integer,
+        URL -> Text,
+        ts -> Timestamp,
+    }
+}
@@ -0,0 +1,74 @@
+//! Screenshots of web pages taken by a headless Chromium, stored as attachments of their bookmarks.
+//! Built with feature `screenshot`; the browser itself is not bundled and must be installed.
+use std::process::Command;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Context};
+use tracing::debug;
+
+/// tried in order if no browser is configured
+const BROWSERS: &[&str] = &[
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+];
+const DEFAULT_WINDOW_SIZE: &str = "1280,800";
+/// attachment name of screenshots
+pub const SCREENSHOT_NAME: &str = "screenshot.png";
+
+static BROWSER: OnceLock<String> = OnceLock::new();
+static WINDOW_SIZE: OnceLock<String> = OnceLock::new();
+
+/// sets the browser command, only the first call has an effect
+pub fn set_browser(browser: &str) {
+    let _ = BROWSER.set(browser.to_string());
+}
+
+/// sets the window size as "width,height", only the first call has an effect
+pub fn set_window_size(window_size: &str) {
+    let _ = WINDOW_SIZE.set(window_size.to_string());
+}
+
+/// PNG screenshot of the page at `url`
+pub fn capture(url: &str) -> anyhow::Result<Vec<u8>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join(SCREENSHOT_NAME);
+    let window_size = WINDOW_SIZE
+        .get()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_WINDOW_SIZE);
+    let browsers = match BROWSER.get() {
+        Some(browser) => vec![browser.as_str()],
+        None => BROWSERS.to_vec(),
+    };
+    for browser in browsers {
+        debug!("{} {}", browser, url);
+        let output = Command::new(browser)
+            .arg("--headless")
+            .arg("--disable-gpu")
+            .arg("--hide-scrollbars")
+            .arg(format!("--window-size={}", window_size))
+            .arg(format!("--screenshot={}", path.display()))
+            .arg(url)
+            .output();
+        let output = match output {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Cannot run {}", browser)),
+        };
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed ({}): {}",
+                browser,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        return std::fs::read(&path)
+            .with_context(|| format!("{} wrote no screenshot of {}", browser, url));
+    }
+    Err(anyhow!(
+        "No browser found, install Chromium or configure [screenshot] browser"
+    ))
+}
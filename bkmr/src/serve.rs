@@ -0,0 +1,810 @@
+//! `bkmr serve`: a small web UI for browsers on the LAN, e.g. of a phone, and JSON-RPC over HTTP.
+//!
+//! Routes:
+//! - `GET /`: the embedded single-page UI
+//! - `POST /rpc`: one JSON-RPC request, methods search, add and tags of `bkmr rpc`. Only
+//!   `Content-Type: application/json` from the same origin, other web pages cannot post it
+//! - `GET /open/<id>`: web bookmarks redirect to their URL, snippets are returned as text
+//! - `GET /changes?since=<token>&limit=<n>&wait=<seconds>`: the change feed of `bkmr changes`,
+//!   `wait` holds the request until there are changes (long polling)
+//!
+//! Only requests for localhost, IP addresses and the names of `[serve] hosts` are answered: a web
+//! page whose domain resolves to this machine must not reach it (DNS rebinding). Only web pages
+//! can be added, no files, commands or snippets.
+//!
+//! Without `[[serve.users]]` there is no authentication: everybody reaching the port can read and
+//! add bookmarks. With users, requests other than the UI itself need a token, and users with a
+//! namespace see the shared bookmarks and their own ones only: bookmarks tagged with the namespace
+//! of another user are hidden.
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use reqwest::Url;
+use serde_json::{json, Value};
+use tracing::{debug, error};
+
+use crate::changes;
+use crate::dal::Dal;
+use crate::environment::UserSettings;
+use crate::handler;
+use crate::handler::Action;
+use crate::rpc;
+use crate::rpc::RpcError;
+use crate::tag::Tags;
+
+/// the web UI, without external resources
+const INDEX: &str = include_str!("serve.html");
+/// JSON-RPC methods over HTTP, `open` would open bookmarks on the serving machine
+const METHODS: &[&str] = &["search", "add", "tags"];
+/// requests are small, larger ones are refused
+const MAX_BODY: usize = 1 << 20;
+/// a stalled client must not hold its thread forever
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// longest `wait` of /changes
+const MAX_WAIT: Duration = Duration::from_secs(60);
+/// interval of checking for changes while waiting
+const POLL: Duration = Duration::from_millis(500);
+
+static USERS: OnceLock<Vec<User>> = OnceLock::new();
+static HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+
+#[derive(Debug, Default, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    /// of the `Authorization: Bearer` header
+    token: Option<String>,
+    content_type: Option<String>,
+    host: Option<String>,
+    /// set by browsers for requests of web pages
+    origin: Option<String>,
+    body: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct User {
+    name: String,
+    token: String,
+    namespace: Option<String>,
+}
+
+/// the bookmarks a request may see
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope<'a> {
+    /// no users configured, or a user without namespace
+    All,
+    /// the bookmarks without namespace tag and those of the namespace
+    Namespace(&'a str),
+}
+
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    location: Option<String>,
+    body: String,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: impl Into<String>) -> Self {
+        Response {
+            status,
+            content_type,
+            location: None,
+            body: body.into(),
+        }
+    }
+
+    fn text(status: u16, body: impl Into<String>) -> Self {
+        Response::new(status, "text/plain; charset=utf-8", body)
+    }
+
+    fn redirect(location: String) -> Self {
+        Response {
+            location: Some(location),
+            ..Response::text(303, "")
+        }
+    }
+
+    fn write(&self, mut output: impl Write) -> std::io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            204 => "No Content",
+            303 => "See Other",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            415 => "Unsupported Media Type",
+            500 => "Internal Server Error",
+            _ => "",
+        };
+        write!(output, "HTTP/1.1 {} {}\r\n", self.status, reason)?;
+        write!(output, "Content-Type: {}\r\n", self.content_type)?;
+        write!(output, "Content-Length: {}\r\n", self.body.len())?;
+        if let Some(location) = &self.location {
+            write!(output, "Location: {}\r\n", location)?;
+        }
+        write!(
+            output,
+            "Cache-Control: no-store\r\nConnection: close\r\n\r\n"
+        )?;
+        output.write_all(self.body.as_bytes())?;
+        output.flush()
+    }
+}
+
+/// sets the names of the server besides localhost and IP addresses, only the first call has an
+/// effect
+pub fn set_hosts(hosts: Vec<String>) {
+    let _ = HOSTS.set(
+        hosts
+            .iter()
+            .map(|host| host.trim().to_lowercase())
+            .collect(),
+    );
+}
+
+/// sets the users, only the first call has an effect.
+/// Fails on empty or duplicate tokens and namespaces that are no tag.
+pub fn set_users(users: Vec<UserSettings>) -> anyhow::Result<()> {
+    let _ = USERS.set(checked_users(users)?);
+    Ok(())
+}
+
+fn checked_users(users: Vec<UserSettings>) -> anyhow::Result<Vec<User>> {
+    let mut tokens = HashSet::new();
+    let mut checked = Vec::new();
+    for user in users {
+        if user.token.trim().is_empty() {
+            return Err(anyhow!("User {:?} has no token", user.name));
+        }
+        if !tokens.insert(user.token.clone()) {
+            return Err(anyhow!(
+                "User {:?} has the token of another user",
+                user.name
+            ));
+        }
+        let namespace = user.namespace.map(|n| n.trim().to_lowercase());
+        if let Some(namespace) = &namespace {
+            if namespace.is_empty() || namespace.contains(|c: char| c == ',' || c.is_whitespace()) {
+                return Err(anyhow!(
+                    "Namespace {:?} of user {:?} is no tag",
+                    namespace,
+                    user.name
+                ));
+            }
+        }
+        checked.push(User {
+            name: user.name,
+            token: user.token,
+            namespace,
+        });
+    }
+    Ok(checked)
+}
+
+/// answers the connections of `listener` until the process ends
+pub fn serve(listener: TcpListener) -> anyhow::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream.context("Cannot accept connection")?;
+        // a waiting /changes request must not block the others
+        thread::spawn(move || {
+            if let Err(e) = handle(stream) {
+                error!("{:#}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(stream: TcpStream) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let response = match read_request(&mut BufReader::new(&stream)) {
+        Ok(request) => {
+            debug!("{} {}", request.method, request.path);
+            route(&request, USERS.get().map(Vec::as_slice).unwrap_or_default())
+        }
+        Err(e) => Response::text(400, format!("{:#}", e)),
+    };
+    response.write(&stream).context("Cannot write response")
+}
+
+fn read_request(reader: &mut impl BufRead) -> anyhow::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(anyhow!("Invalid request line {:?}", line.trim()));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut length = 0;
+    let (mut token, mut content_type, mut host, mut origin) = (None, None, None, None);
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid Content-Length {:?}", value.trim()))?;
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                token = value.trim().strip_prefix("Bearer ").map(String::from);
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("origin") {
+                origin = Some(value.trim().to_string());
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(anyhow!("Request too large: {} bytes", length));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).context("Incomplete body")?;
+    Ok(Request {
+        method,
+        path,
+        token,
+        content_type,
+        host,
+        origin,
+        body: String::from_utf8(body).context("Body is not UTF-8")?,
+    })
+}
+
+fn route(request: &Request, users: &[User]) -> Response {
+    let hosts = HOSTS.get().map(Vec::as_slice).unwrap_or_default();
+    if !is_known_host(request.host.as_deref(), hosts) {
+        return Response::text(403, "Unknown host");
+    }
+    let path = request.path.split('?').next().unwrap_or_default();
+    if (request.method.as_str(), path) == ("GET", "/") {
+        return Response::new(200, "text/html; charset=utf-8", INDEX);
+    }
+    let Some(scope) = authenticate(request, users) else {
+        return Response::text(401, "Token missing or unknown");
+    };
+    match (request.method.as_str(), path) {
+        ("POST", "/rpc") if !is_json(request) => {
+            Response::text(415, "Content-Type must be application/json")
+        }
+        ("POST", "/rpc") if !is_same_origin(request) => {
+            Response::text(403, "Cross-origin requests are not allowed")
+        }
+        ("POST", "/rpc") => {
+            let response = rpc::respond_with(&request.body, |method, params| {
+                rpc::allowed(method, METHODS)?;
+                call(method, params, scope, users)
+            });
+            match response {
+                Some(response) => Response::new(200, "application/json", response.to_string()),
+                None => Response::text(204, ""),
+            }
+        }
+        ("GET", path) if path.starts_with("/open/") => match path["/open/".len()..].parse() {
+            Ok(id) => {
+                open(id, scope, users).unwrap_or_else(|e| Response::text(500, format!("{:#}", e)))
+            }
+            Err(_) => Response::text(404, "Not found"),
+        },
+        ("GET", "/changes") => match scope {
+            Scope::All => changes(request),
+            // deleted bookmarks have no tags to tell their namespace
+            Scope::Namespace(_) => Response::text(403, "No change feed for users with namespace"),
+        },
+        (_, "/" | "/rpc" | "/changes") => Response::text(405, "Method not allowed"),
+        _ => Response::text(404, "Not found"),
+    }
+}
+
+fn changes(request: &Request) -> Response {
+    let feed = || -> anyhow::Result<changes::Feed> {
+        let since = query(request, "since").unwrap_or_default();
+        let limit = query(request, "limit")
+            .map(|limit| limit.parse().context("Invalid limit"))
+            .transpose()?;
+        let wait = query(request, "wait")
+            .map(|wait| wait.parse().context("Invalid wait"))
+            .transpose()?
+            .map_or(Duration::ZERO, |wait| {
+                Duration::from_secs(wait).min(MAX_WAIT)
+            });
+        let start = Instant::now();
+        let mut dal = Dal::default();
+        loop {
+            let feed = changes::feed(&mut dal, &since, limit)?;
+            if !feed.changes.is_empty() || start.elapsed() + POLL > wait {
+                return Ok(feed);
+            }
+            thread::sleep(POLL);
+        }
+    };
+    match feed() {
+        Ok(feed) => Response::new(200, "application/json", json!(feed).to_string()),
+        Err(e) => Response::text(400, format!("{:#}", e)),
+    }
+}
+
+/// forms of other web pages cannot send JSON without a CORS preflight, which is not answered
+fn is_json(request: &Request) -> bool {
+    request.content_type.as_deref().is_some_and(|content_type| {
+        let mime = content_type.split(';').next().unwrap_or_default();
+        mime.trim().eq_ignore_ascii_case("application/json")
+    })
+}
+
+/// localhost, IP addresses and `hosts`, with any port. Requests without `Host` are not sent by
+/// browsers
+fn is_known_host(host: Option<&str>, hosts: &[String]) -> bool {
+    let Some(host) = host else {
+        return true;
+    };
+    let host = host.to_lowercase();
+    let name = match host.strip_prefix('[') {
+        // IPv6
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name == "localhost" || name.parse::<IpAddr>().is_ok() || hosts.iter().any(|h| h == name)
+}
+
+/// requests without `Origin` are not sent by web pages, e.g. by curl
+fn is_same_origin(request: &Request) -> bool {
+    let Some(origin) = &request.origin else {
+        return true;
+    };
+    let Ok(origin) = Url::parse(origin) else {
+        return false;
+    };
+    let authority = match (origin.host_str(), origin.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return false,
+    };
+    request
+        .host
+        .as_deref()
+        .is_some_and(|host| host.eq_ignore_ascii_case(&authority))
+}
+
+/// value of the query parameter `name`
+fn query(request: &Request, name: &str) -> Option<String> {
+    let url = Url::parse(&format!("http://localhost{}", request.path)).ok()?;
+    let value = url.query_pairs().find(|(key, _)| key == name)?.1;
+    Some(value.into_owned())
+}
+
+/// scope of the token of the header or the `token` query parameter, e.g. of /open links.
+/// None if users are configured and none has the token
+fn authenticate<'a>(request: &Request, users: &'a [User]) -> Option<Scope<'a>> {
+    if users.is_empty() {
+        return Some(Scope::All);
+    }
+    let token = request.token.clone().or_else(|| query(request, "token"))?;
+    scope(&token, users)
+}
+
+fn scope<'a>(token: &str, users: &'a [User]) -> Option<Scope<'a>> {
+    let user = users.iter().find(|user| same(&user.token, token))?;
+    debug!("User {}", user.name);
+    Some(match &user.namespace {
+        Some(namespace) => Scope::Namespace(namespace),
+        None => Scope::All,
+    })
+}
+
+/// access of `token` for the other servers: None if unauthenticated, Some(false) if restricted
+/// to a namespace and Some(true) for all bookmarks
+#[cfg(feature = "grpc")]
+pub(crate) fn access(token: Option<&str>) -> Option<bool> {
+    let users = USERS.get().map(Vec::as_slice).unwrap_or_default();
+    if users.is_empty() {
+        return Some(true);
+    }
+    scope(token?, users).map(|scope| matches!(scope, Scope::All))
+}
+
+/// comparison in constant time, the position of a difference does not leak
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+/// whether a bookmark with the tag string `tags` is in `scope`: it has no namespace tag of
+/// another user
+fn visible(tags: &str, scope: Scope, users: &[User]) -> bool {
+    let Scope::Namespace(own) = scope else {
+        return true;
+    };
+    tags.split(',')
+        .filter(|tag| !tag.is_empty() && *tag != own)
+        .all(|tag| {
+            !users
+                .iter()
+                .any(|user| user.namespace.as_deref() == Some(tag))
+        })
+}
+
+/// the methods of `bkmr rpc` restricted to `scope`: results are filtered, added bookmarks get
+/// the namespace tag
+fn call(method: &str, params: Value, scope: Scope, users: &[User]) -> Result<Value, RpcError> {
+    if method == "add" {
+        check_web_url(&params)?;
+    }
+    let Scope::Namespace(namespace) = scope else {
+        return rpc::call(method, params);
+    };
+    let mut params = match params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    match method {
+        "search" => {
+            // the limit applies to the visible bookmarks
+            let limit = params.get("limit").and_then(Value::as_u64);
+            if let Some(params) = params.as_object_mut() {
+                params.remove("limit");
+            }
+            let bms = rpc::call("search", params)?;
+            let bms = bms
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|bm| visible(bm["tags"].as_str().unwrap_or_default(), scope, users))
+                .take(limit.map_or(usize::MAX, |limit| limit as usize))
+                .cloned()
+                .collect();
+            Ok(Value::Array(bms))
+        }
+        "add" => {
+            check_tags(&params, scope, users)?;
+            match params.get_mut("tags") {
+                Some(Value::Array(tags)) => tags.push(json!(namespace)),
+                None | Some(Value::Null) => params["tags"] = json!([namespace]),
+                // rejected by add
+                Some(_) => {}
+            }
+            rpc::call("add", params)
+        }
+        "tags" => {
+            let prefix = params["prefix"].as_str().unwrap_or_default().to_string();
+            let bms = call("search", json!({}), scope, users)?;
+            let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+            for bm in bms.as_array().into_iter().flatten() {
+                let tags = bm["tags"].as_str().unwrap_or_default().split(',');
+                for tag in tags.filter(|tag| !tag.is_empty() && tag.starts_with(&prefix)) {
+                    *counts.entry(tag.to_string()).or_default() += 1;
+                }
+            }
+            let tags = counts
+                .into_iter()
+                .map(|(tag, count)| json!({"tag": tag, "count": count}))
+                .collect();
+            Ok(Value::Array(tags))
+        }
+        _ => rpc::call(method, params),
+    }
+}
+
+/// only web pages can be added, commands and snippets would run or show on the serving machine
+fn check_web_url(params: &Value) -> Result<(), RpcError> {
+    let url = params
+        .get("url")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    match Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(RpcError::new(
+            rpc::INVALID_PARAMS,
+            format!("Only http(s) URLs can be added over the web: {:?}", url),
+        )),
+    }
+}
+
+/// a namespaced user cannot tag a bookmark into the namespace of another user
+fn check_tags(params: &Value, scope: Scope, users: &[User]) -> Result<(), RpcError> {
+    let tags: Vec<&str> = params
+        .get("tags")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let tags = Tags::create_normalized_tag_string(Some(tags.join(",")));
+    match visible(&tags, scope, users) {
+        true => Ok(()),
+        false => Err(RpcError::new(
+            rpc::INVALID_PARAMS,
+            format!("Tags of another namespace cannot be added: {:?}", tags),
+        )),
+    }
+}
+
+/// web pages are opened by the browser, text is shown, nothing runs on the serving machine
+fn open(id: i32, scope: Scope, users: &[User]) -> anyhow::Result<Response> {
+    let mut dal = Dal::default();
+    let bm = match dal.get_bookmark_by_id(id) {
+        Ok(bm) if visible(&bm.tags, scope, users) => bm,
+        _ => return Ok(Response::text(404, format!("No bookmark {}", id))),
+    };
+    let response = match handler::action(&bm.URL, &bm.get_tags())? {
+        Action::Os(uri) if uri.starts_with("http://") || uri.starts_with("https://") => {
+            Response::redirect(uri)
+        }
+        Action::Print(text) | Action::Markdown(text) | Action::Copy(text) => {
+            Response::text(200, text)
+        }
+        Action::Os(_) | Action::Shell(_) => {
+            return Ok(Response::text(
+                403,
+                format!("Bookmark {} is not opened over the web", id),
+            ))
+        }
+    };
+    dal.record_visit(bm.id)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn rpc_request(body: &str) -> Request {
+        Request {
+            content_type: Some("application/json".to_string()),
+            ..request("POST", "/rpc", body)
+        }
+    }
+
+    fn users() -> Vec<User> {
+        let user = |name: &str, namespace: Option<&str>| User {
+            name: name.to_string(),
+            token: format!("{}-token", name),
+            namespace: namespace.map(String::from),
+        };
+        vec![
+            user("alice", Some("alice")),
+            user("bob", Some("bob")),
+            user("admin", None),
+        ]
+    }
+
+    #[rstest]
+    fn test_read_request() {
+        let raw = "POST /rpc HTTP/1.1\r\nHost: phone\r\ncontent-length: 4\r\n\r\n{}\r\nignored";
+        assert_eq!(
+            read_request(&mut raw.as_bytes()).unwrap(),
+            Request {
+                host: Some("phone".to_string()),
+                ..request("POST", "/rpc", "{}\r\n")
+            }
+        );
+        let raw = "POST /rpc HTTP/1.1\r\nContent-Type: application/json\r\nOrigin: http://phone:9999\r\n\r\n";
+        let parsed = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(parsed.content_type.as_deref(), Some("application/json"));
+        assert_eq!(parsed.origin.as_deref(), Some("http://phone:9999"));
+        let raw = "POST /rpc HTTP/1.1\r\nAuthorization: Bearer s3cr3t\r\n\r\n";
+        assert_eq!(
+            read_request(&mut raw.as_bytes()).unwrap().token.as_deref(),
+            Some("s3cr3t")
+        );
+        let raw = "GET /?q=rust HTTP/1.1\r\n\r\n";
+        assert_eq!(
+            read_request(&mut raw.as_bytes()).unwrap(),
+            request("GET", "/?q=rust", "")
+        );
+        assert!(read_request(&mut "\r\n".as_bytes()).is_err());
+        let raw = format!(
+            "POST /rpc HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert!(read_request(&mut raw.as_bytes()).is_err());
+    }
+
+    #[rstest]
+    #[case(request("GET", "/", ""), 200)]
+    #[case(request("GET", "/?tag=rust", ""), 200)]
+    #[case(request("PUT", "/", ""), 405)]
+    #[case(request("GET", "/rpc", ""), 405)]
+    #[case(request("GET", "/open/x", ""), 404)]
+    #[case(request("GET", "/changes?since=x", ""), 400)]
+    #[case(request("GET", "/changes?wait=soon", ""), 400)]
+    #[case(request("POST", "/changes", ""), 405)]
+    #[case(request("GET", "/favicon.ico", ""), 404)]
+    #[case(rpc_request(r#"{"jsonrpc": "2.0", "method": "tags"}"#), 204)]
+    fn test_route(#[case] request: Request, #[case] status: u16) {
+        assert_eq!(route(&request, &[]).status, status);
+    }
+
+    #[rstest]
+    fn test_route_rpc() {
+        let open = r#"{"jsonrpc": "2.0", "id": 1, "method": "open", "params": {"id": 1}}"#;
+        let response = route(&rpc_request(open), &[]);
+        assert_eq!(response.content_type, "application/json");
+        let response: Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(response["error"]["code"], -32601);
+        let add = r#"{"jsonrpc": "2.0", "id": 1, "method": "add", "params": {"url": "shell::ls"}}"#;
+        let response: Value = serde_json::from_str(&route(&rpc_request(add), &[]).body).unwrap();
+        assert_eq!(response["error"]["code"], rpc::INVALID_PARAMS);
+    }
+
+    #[rstest]
+    #[case(None, true)]
+    #[case(Some("localhost:9999"), true)]
+    #[case(Some("127.0.0.1:9999"), true)]
+    #[case(Some("[::1]:9999"), true)]
+    #[case(Some("192.168.1.5"), true)]
+    #[case(Some("PC.local:9999"), true)]
+    #[case(Some("evil.org:9999"), false)]
+    #[case(Some("localhost.evil.org"), false)]
+    fn test_is_known_host(#[case] host: Option<&str>, #[case] expected: bool) {
+        assert_eq!(is_known_host(host, &["pc.local".to_string()]), expected);
+    }
+
+    #[rstest]
+    fn test_route_unknown_host() {
+        let rpc = Request {
+            host: Some("rebound.evil.org:9999".to_string()),
+            origin: Some("http://rebound.evil.org:9999".to_string()),
+            ..rpc_request(r#"{"jsonrpc": "2.0", "method": "tags"}"#)
+        };
+        assert_eq!(route(&rpc, &[]).status, 403);
+        let open = Request {
+            host: Some("rebound.evil.org".to_string()),
+            ..request("GET", "/open/1", "")
+        };
+        assert_eq!(route(&open, &[]).status, 403);
+    }
+
+    #[rstest]
+    #[case(None, None, None, 415)]
+    #[case(Some("text/plain"), None, None, 415)]
+    #[case(Some("application/x-www-form-urlencoded"), None, None, 415)]
+    #[case(Some("application/json; charset=utf-8"), None, None, 204)]
+    #[case(
+        Some("application/json"),
+        Some("localhost:9999"),
+        Some("http://localhost:9999"),
+        204
+    )]
+    #[case(
+        Some("application/json"),
+        Some("localhost:9999"),
+        Some("https://evil.org"),
+        403
+    )]
+    #[case(
+        Some("application/json"),
+        Some("localhost:9999"),
+        Some("http://localhost"),
+        403
+    )]
+    #[case(Some("application/json"), Some("localhost:9999"), Some("null"), 403)]
+    #[case(Some("application/json"), None, Some("http://localhost:9999"), 403)]
+    fn test_route_rpc_csrf(
+        #[case] content_type: Option<&str>,
+        #[case] host: Option<&str>,
+        #[case] origin: Option<&str>,
+        #[case] status: u16,
+    ) {
+        let request = Request {
+            content_type: content_type.map(String::from),
+            host: host.map(String::from),
+            origin: origin.map(String::from),
+            ..request("POST", "/rpc", r#"{"jsonrpc": "2.0", "method": "tags"}"#)
+        };
+        assert_eq!(route(&request, &[]).status, status);
+    }
+
+    #[rstest]
+    fn test_checked_users() {
+        let settings = |token: &str, namespace: Option<&str>| UserSettings {
+            name: "alice".to_string(),
+            token: token.to_string(),
+            namespace: namespace.map(String::from),
+        };
+        let users = checked_users(vec![settings("t1", Some(" Alice "))]).unwrap();
+        assert_eq!(users[0].namespace.as_deref(), Some("alice"));
+        assert!(checked_users(vec![settings(" ", None)]).is_err());
+        assert!(checked_users(vec![settings("t1", None), settings("t1", None)]).is_err());
+        assert!(checked_users(vec![settings("t1", Some("a,b"))]).is_err());
+    }
+
+    #[rstest]
+    fn test_authenticate() {
+        let users = users();
+        assert_eq!(
+            authenticate(&request("POST", "/rpc", ""), &[]),
+            Some(Scope::All)
+        );
+        assert_eq!(authenticate(&request("POST", "/rpc", ""), &users), None);
+        let alice = Request {
+            token: Some("alice-token".to_string()),
+            ..request("POST", "/rpc", "")
+        };
+        assert_eq!(
+            authenticate(&alice, &users),
+            Some(Scope::Namespace("alice"))
+        );
+        let changes = Request {
+            method: "GET".to_string(),
+            path: "/changes".to_string(),
+            ..alice
+        };
+        assert_eq!(route(&changes, &users).status, 403);
+        let admin = request("GET", "/open/1?token=admin-token", "");
+        assert_eq!(authenticate(&admin, &users), Some(Scope::All));
+        let wrong = request("GET", "/open/1?token=alice-tokem", "");
+        assert_eq!(authenticate(&wrong, &users), None);
+        // the UI itself is public, its data is not
+        assert_eq!(route(&request("GET", "/", ""), &users).status, 200);
+        assert_eq!(route(&request("GET", "/open/1", ""), &users).status, 401);
+        let tags = r#"{"jsonrpc": "2.0", "id": 1, "method": "tags"}"#;
+        assert_eq!(route(&request("POST", "/rpc", tags), &users).status, 401);
+    }
+
+    #[rstest]
+    #[case(",rust,", Scope::Namespace("alice"), true)]
+    #[case(",alice,rust,", Scope::Namespace("alice"), true)]
+    #[case(",bob,rust,", Scope::Namespace("alice"), false)]
+    #[case(",alice,bob,", Scope::Namespace("alice"), false)]
+    #[case(",bob,", Scope::All, true)]
+    fn test_visible(#[case] tags: &str, #[case] scope: Scope, #[case] expected: bool) {
+        assert_eq!(visible(tags, scope, &users()), expected);
+    }
+
+    #[rstest]
+    #[case(json!({"url": "https://a.org"}), true)]
+    #[case(json!({"url": "https://a.org", "tags": ["rust", "alice"]}), true)]
+    #[case(json!({"url": "https://a.org", "tags": ["rust", "bob"]}), false)]
+    #[case(json!({"url": "https://a.org", "tags": [" Bob "]}), false)]
+    fn test_check_tags(#[case] params: Value, #[case] expected: bool) {
+        let result = check_tags(&params, Scope::Namespace("alice"), &users());
+        assert_eq!(result.is_ok(), expected);
+        assert!(check_tags(&params, Scope::All, &users()).is_ok());
+    }
+
+    #[rstest]
+    fn test_write_response() {
+        let mut output = Vec::new();
+        Response::redirect("https://www.sysid.de".to_string())
+            .write(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 303 See Other\r\n"));
+        assert!(output.contains("\r\nLocation: https://www.sysid.de\r\n"));
+        assert!(output.ends_with("Connection: close\r\n\r\n"));
+        let mut output = Vec::new();
+        Response::text(415, "").write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"));
+    }
+}
@@ -0,0 +1,294 @@
+//! `bkmr export --format site`: a static HTML site of web bookmarks, e.g. to publish a link
+//! collection on GitHub Pages. Searching filters the bookmarks of a page in the browser, without
+//! a server.
+//!
+//! Layout of the directory:
+//! - `index.html`: all bookmarks and the tags
+//! - `tags/<tag>.html`: the bookmarks of a tag
+//! - `favicons/<host>.ico`: icons of the sites, downloaded once
+//! - `style.css`, `search.js`
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use reqwest::Url;
+use tracing::debug;
+
+use crate::fetch::load_urls_content;
+use crate::format::xml_escape;
+use crate::models::Bookmark;
+
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; margin: 0 auto; max-width: 52rem; padding: 0.5rem 1rem; }
+input { box-sizing: border-box; font-size: 1rem; padding: 0.4rem; width: 100%; }
+ul { list-style: none; padding: 0; }
+#bookmarks li { border-bottom: 1px solid #ddd; padding: 0.5rem 0; }
+#bookmarks img { height: 16px; margin-right: 0.3rem; vertical-align: middle; width: 16px; }
+.url, .desc { color: #555; font-size: 0.85rem; overflow-wrap: anywhere; }
+.tags a, #tags a { background: #eef; border-radius: 0.6rem; font-size: 0.8rem; margin-right: 0.3rem; padding: 0.1rem 0.5rem; text-decoration: none; }
+#tags li { display: inline-block; margin: 0.2rem 0; }
+footer { color: #777; font-size: 0.8rem; margin-top: 1rem; }
+[hidden] { display: none; }
+";
+
+const SEARCH: &str = "\
+const input = document.getElementById(\"search\");
+input.addEventListener(\"input\", () => {
+  const words = input.value.toLowerCase().split(/\\s+/).filter(Boolean);
+  let shown = 0;
+  for (const item of document.querySelectorAll(\"#bookmarks li\")) {
+    item.hidden = !words.every((word) => item.dataset.search.includes(word));
+    shown += item.hidden ? 0 : 1;
+  }
+  document.getElementById(\"count\").textContent = shown;
+});
+";
+
+/// writes the site of the web bookmarks of `bms` into `dir`, returns the number of bookmarks.
+/// Favicons are downloaded if `favicons` is set, existing ones are kept.
+pub fn export_site(
+    bms: &[Bookmark],
+    dir: &Path,
+    title: &str,
+    favicons: bool,
+) -> anyhow::Result<usize> {
+    let mut bms: Vec<&Bookmark> = bms.iter().filter(|bm| host(bm).is_some()).collect();
+    bms.sort_by_key(|bm| (bm.metadata.to_lowercase(), bm.URL.clone()));
+    let tags_dir = dir.join("tags");
+    let icons_dir = dir.join("favicons");
+    for dir in [dir, tags_dir.as_path(), icons_dir.as_path()] {
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    // pages of tags gone since the last export
+    for entry in fs::read_dir(&tags_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "html") {
+            fs::remove_file(&path)?;
+        }
+    }
+    if favicons {
+        download_favicons(&bms, &icons_dir);
+    }
+    let icons: BTreeSet<String> = fs::read_dir(&icons_dir)?
+        .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().into_owned()))
+        .collect();
+
+    let mut tags: BTreeMap<String, Vec<&Bookmark>> = BTreeMap::new();
+    for bm in &bms {
+        for tag in bm.get_tags() {
+            tags.entry(tag).or_default().push(bm);
+        }
+    }
+    let tag_list: String = tags
+        .iter()
+        .map(|(tag, bms)| {
+            format!(
+                "<li><a href=\"tags/{}.html\">{}</a> {}</li>\n",
+                slug(tag),
+                xml_escape(tag),
+                bms.len()
+            )
+        })
+        .collect();
+    let index = page(
+        title,
+        title,
+        "",
+        &format!("<ul id=\"tags\">\n{}</ul>\n", tag_list),
+        &bms,
+        &icons,
+    );
+    write(&dir.join("index.html"), &index)?;
+    for (tag, tag_bms) in &tags {
+        let back = "<p><a href=\"../index.html\">All bookmarks</a></p>\n";
+        let heading = format!("{}: {}", title, tag);
+        let html = page(title, &heading, "../", back, tag_bms, &icons);
+        write(&tags_dir.join(format!("{}.html", slug(tag))), &html)?;
+    }
+    write(&dir.join("style.css"), STYLE)?;
+    write(&dir.join("search.js"), SEARCH)?;
+    Ok(bms.len())
+}
+
+fn write(path: &Path, content: &str) -> anyhow::Result<()> {
+    fs::write(path, content).with_context(|| format!("Cannot write {}", path.display()))
+}
+
+/// host and port of web bookmarks, None for snippets, files and commands
+fn host(bm: &Bookmark) -> Option<String> {
+    let url = Url::parse(&bm.URL).ok()?;
+    let host = match url.scheme() {
+        "http" | "https" => url.host_str()?,
+        _ => return None,
+    };
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+/// file name of a tag page or favicon: characters other than letters, digits, '-', '_' and '.'
+/// are replaced
+fn slug(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// /favicon.ico of the sites without icon yet, pages served instead of an icon are skipped
+fn download_favicons(bms: &[&Bookmark], dir: &Path) {
+    let mut sites = BTreeMap::new();
+    for bm in bms {
+        let Some(host) = host(bm) else { continue };
+        let file = format!("{}.ico", slug(&host));
+        if !dir.join(&file).exists() {
+            let scheme = bm.URL.split(':').next().unwrap_or("https");
+            sites.insert(file, format!("{}://{}/favicon.ico", scheme, host));
+        }
+    }
+    let urls: Vec<&str> = sites.values().map(String::as_str).collect();
+    for ((file, url), content) in sites.iter().zip(load_urls_content(&urls)) {
+        match content {
+            Ok(icon) if !icon.is_empty() && !icon.trim_ascii_start().starts_with(b"<") => {
+                if let Err(e) = fs::write(dir.join(file), icon) {
+                    debug!("{}: {}", file, e);
+                }
+            }
+            Ok(_) => debug!("{}: no icon", url),
+            Err(e) => debug!("{}: {:#}", url, e),
+        }
+    }
+}
+
+fn page(
+    title: &str,
+    heading: &str,
+    root: &str,
+    nav: &str,
+    bms: &[&Bookmark],
+    icons: &BTreeSet<String>,
+) -> String {
+    let items: String = bms.iter().map(|bm| item(bm, root, icons)).collect();
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n<link rel=\"stylesheet\" href=\"{root}style.css\">\n\
+         </head>\n<body>\n<h1>{heading}</h1>\n{nav}\
+         <input type=\"search\" id=\"search\" placeholder=\"Search\" autocomplete=\"off\">\n\
+         <p><span id=\"count\">{count}</span> bookmarks</p>\n\
+         <ul id=\"bookmarks\">\n{items}</ul>\n\
+         <footer>Exported by bkmr</footer>\n<script src=\"{root}search.js\"></script>\n\
+         </body>\n</html>\n",
+        title = xml_escape(title),
+        heading = xml_escape(heading),
+        root = root,
+        nav = nav,
+        count = bms.len(),
+        items = items,
+    )
+}
+
+fn item(bm: &Bookmark, root: &str, icons: &BTreeSet<String>) -> String {
+    let tags = bm.get_tags();
+    let search = format!("{} {} {} {}", bm.metadata, bm.URL, bm.desc, tags.join(" "));
+    let icon = host(bm)
+        .map(|host| format!("{}.ico", slug(&host)))
+        .filter(|file| icons.contains(file))
+        .map(|file| format!("<img src=\"{}favicons/{}\" alt=\"\">", root, file))
+        .unwrap_or_default();
+    let title = match bm.metadata.trim() {
+        "" => &bm.URL,
+        title => title,
+    };
+    let desc = match bm.desc.trim() {
+        "" => String::new(),
+        desc => format!("<div class=\"desc\">{}</div>", xml_escape(desc)),
+    };
+    let tag_links: String = tags
+        .iter()
+        .map(|tag| {
+            format!(
+                "<a href=\"{}tags/{}.html\">{}</a>",
+                root,
+                slug(tag),
+                xml_escape(tag)
+            )
+        })
+        .collect();
+    format!(
+        "<li data-search=\"{}\">{}<a href=\"{}\">{}</a><div class=\"url\">{}</div>{}\
+         <div class=\"tags\">{}</div></li>\n",
+        xml_escape(&search.to_lowercase()),
+        icon,
+        xml_escape(&bm.URL),
+        xml_escape(title),
+        xml_escape(&bm.URL),
+        desc,
+        tag_links
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn bookmark(url: &str, title: &str, tags: &str) -> Bookmark {
+        Bookmark {
+            URL: url.to_string(),
+            metadata: title.to_string(),
+            tags: tags.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    fn test_export_site() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("tags")).unwrap();
+        fs::write(dir.path().join("tags/gone.html"), "").unwrap();
+        fs::create_dir_all(dir.path().join("favicons")).unwrap();
+        fs::write(dir.path().join("favicons/www.sysid.de.ico"), "icon").unwrap();
+        let bms = vec![
+            bookmark("https://www.sysid.de", "sysid <blog>", ",blog,rust,"),
+            bookmark("https://www.rust-lang.org/", "Rust", ",rust,"),
+            bookmark("shell::rm -rf /tmp/x", "cleanup", ",rust,"),
+        ];
+        assert_eq!(export_site(&bms, dir.path(), "My links", false).unwrap(), 2);
+        let index = fs::read_to_string(dir.path().join("index.html")).unwrap();
+        assert!(index.contains("<title>My links</title>"));
+        assert!(index.contains("<a href=\"tags/rust.html\">rust</a> 2</li>"));
+        assert!(index.contains(
+            "<li data-search=\"sysid &lt;blog&gt; https://www.sysid.de  blog rust\">\
+             <img src=\"favicons/www.sysid.de.ico\" alt=\"\"><a href=\"https://www.sysid.de\">sysid &lt;blog&gt;</a>"
+        ));
+        assert!(!index.contains("cleanup"));
+        // sorted by title
+        assert!(index.find("Rust").unwrap() < index.find("sysid").unwrap());
+        let rust = fs::read_to_string(dir.path().join("tags/rust.html")).unwrap();
+        assert!(rust.contains("<h1>My links: rust</h1>"));
+        assert!(rust.contains("<span id=\"count\">2</span>"));
+        assert!(rust.contains("href=\"../style.css\""));
+        assert!(dir.path().join("tags/blog.html").exists());
+        assert!(!dir.path().join("tags/gone.html").exists());
+        assert!(dir.path().join("search.js").exists());
+    }
+
+    #[rstest]
+    #[case("rust", "rust")]
+    #[case("c++", "c__")]
+    #[case("a/b c", "a_b_c")]
+    #[case("localhost:8080", "localhost_8080")]
+    fn test_slug(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(slug(name), expected);
+    }
+}
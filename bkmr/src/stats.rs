@@ -0,0 +1,452 @@
+//! Overview of `bkmr stats`: number of bookmarks per kind, most used tags, additions per month
+//! and size of the database. `bkmr stats top`: the most opened bookmarks of a period, from the log
+//! of opens written by the database on every visit. `bkmr stats heatmap`: additions and opens per
+//! day of the last weeks as a grid like the contribution graph of GitHub. `bkmr stats domains`:
+//! number, age and dead links of the web bookmarks per host.
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime};
+use diesel::result::Error as DieselError;
+use serde::Serialize;
+
+use crate::dal::Dal;
+use crate::handler::{COPY_PREFIX, MARKDOWN_PREFIX, SHELL_PREFIX, SNIP_PREFIX};
+use crate::models::{Bookmark, DayActivity, MonthCount, Opened, TagsFrequency};
+use crate::process::domain;
+use crate::DEAD_TAG;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Stats {
+    pub total: usize,
+    pub kinds: Kinds,
+    /// most frequent first
+    pub top_tags: Vec<TagsFrequency>,
+    /// of the change log, oldest month first
+    pub added_per_month: Vec<MonthCount>,
+    /// bytes
+    pub db_size: i64,
+}
+
+/// number of bookmarks per kind
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Kinds {
+    pub web: usize,
+    pub shell: usize,
+    pub file: usize,
+    /// snip::, markdown:: and copy::
+    pub snippet: usize,
+    /// other schemes, e.g. ssh:: or mailto:
+    pub other: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Web,
+    Shell,
+    File,
+    Snippet,
+    Other,
+}
+
+/// kind of a bookmark by its URI, paths may start with an environment variable
+pub fn kind(uri: &str) -> Kind {
+    let lower = uri.to_lowercase();
+    if uri.starts_with(SHELL_PREFIX) {
+        Kind::Shell
+    } else if [SNIP_PREFIX, MARKDOWN_PREFIX, COPY_PREFIX]
+        .iter()
+        .any(|prefix| uri.starts_with(prefix))
+    {
+        Kind::Snippet
+    } else if lower.starts_with("http://") || lower.starts_with("https://") {
+        Kind::Web
+    } else if lower.starts_with("file:")
+        || ["/", "~", "$", "./", "../"]
+            .iter()
+            .any(|p| uri.starts_with(p))
+    {
+        Kind::File
+    } else {
+        Kind::Other
+    }
+}
+
+/// the overview with the `top` most used tags
+pub fn stats(dal: &mut Dal, top: usize) -> Result<Stats, DieselError> {
+    let bms = dal.get_bookmarks("")?;
+    let mut kinds = Kinds::default();
+    for bm in &bms {
+        match kind(&bm.URL) {
+            Kind::Web => kinds.web += 1,
+            Kind::Shell => kinds.shell += 1,
+            Kind::File => kinds.file += 1,
+            Kind::Snippet => kinds.snippet += 1,
+            Kind::Other => kinds.other += 1,
+        }
+    }
+    let mut top_tags = dal.get_all_tags()?;
+    top_tags.truncate(top);
+    Ok(Stats {
+        total: bms.len(),
+        kinds,
+        top_tags,
+        added_per_month: dal.get_additions_per_month()?,
+        db_size: dal.get_db_size()?,
+    })
+}
+
+/// the `limit` most opened bookmarks in the period before now, with `tag` if given
+pub fn top(
+    dal: &mut Dal,
+    tag: Option<&str>,
+    period: Option<Duration>,
+    limit: i64,
+) -> Result<Vec<Opened>, DieselError> {
+    // periods before the log are its whole time
+    let since = period
+        .and_then(|period| chrono::Utc::now().naive_utc().checked_sub_signed(period))
+        .unwrap_or(DateTime::UNIX_EPOCH.naive_utc());
+    let tag = tag.unwrap_or_default().trim().to_lowercase();
+    dal.get_most_opened(&tag, since, limit)
+}
+
+/// web bookmarks of a host, `www.` left out
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Domain {
+    pub domain: String,
+    pub bookmarks: usize,
+    /// mean days since the last update, the addition if never updated
+    pub average_age_days: i64,
+    /// tagged dead by `bkmr check`
+    pub dead: usize,
+    /// share of dead links among the bookmarks, 0 to 1
+    pub dead_rate: f64,
+}
+
+/// the domains of the web bookmarks among `bms`, most bookmarks first
+pub fn domains(bms: &[Bookmark], now: NaiveDateTime) -> Vec<Domain> {
+    let mut hosts: HashMap<String, Vec<&Bookmark>> = HashMap::new();
+    for bm in bms.iter().filter(|bm| kind(&bm.URL) == Kind::Web) {
+        hosts.entry(domain(&bm.URL)).or_default().push(bm);
+    }
+    let mut domains: Vec<Domain> = hosts
+        .into_iter()
+        .map(|(domain, bms)| {
+            let days: i64 = bms
+                .iter()
+                .map(|bm| (now - bm.last_update_ts).num_days())
+                .sum();
+            let dead = bms
+                .iter()
+                .filter(|bm| bm.get_tags().iter().any(|t| t == DEAD_TAG))
+                .count();
+            Domain {
+                domain,
+                bookmarks: bms.len(),
+                average_age_days: days / bms.len() as i64,
+                dead,
+                dead_rate: dead as f64 / bms.len() as f64,
+            }
+        })
+        .collect();
+    domains.sort_by(|a, b| {
+        b.bookmarks
+            .cmp(&a.bookmarks)
+            .then_with(|| a.domain.cmp(&b.domain))
+    });
+    domains
+}
+
+/// table of `domains` for the terminal
+pub fn format_domains(domains: &[Domain]) -> String {
+    let mut table = String::from("bookmarks  avg age  dead  domain\n");
+    for d in domains {
+        table.push_str(&format!(
+            "{:>9}  {:>6}d  {:>3.0}%  {}\n",
+            d.bookmarks,
+            d.average_age_days,
+            d.dead_rate * 100.0,
+            d.domain
+        ));
+    }
+    table
+}
+
+/// levels of activity in the grid, from none to the most active day
+const SHADES: [char; 5] = ['·', '░', '▒', '▓', '█'];
+
+/// additions and opens of a day
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Day {
+    pub date: NaiveDate,
+    pub added: i32,
+    pub opened: i32,
+}
+
+/// activity per day of whole weeks, Monday to Sunday, up to `to`
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Heatmap {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub days: Vec<Day>,
+}
+
+impl Heatmap {
+    fn new(today: NaiveDate, weeks: u32, activity: &[DayActivity]) -> Heatmap {
+        let from = today
+            - Duration::days(today.weekday().num_days_from_monday() as i64)
+            - Duration::weeks(weeks.saturating_sub(1) as i64);
+        let activity: HashMap<&str, &DayActivity> =
+            activity.iter().map(|a| (a.day.as_str(), a)).collect();
+        let days = from
+            .iter_days()
+            .take_while(|date| *date <= today)
+            .map(|date| {
+                let day = activity.get(date.format("%Y-%m-%d").to_string().as_str());
+                Day {
+                    date,
+                    added: day.map_or(0, |a| a.added),
+                    opened: day.map_or(0, |a| a.opened),
+                }
+            })
+            .collect();
+        Heatmap {
+            from,
+            to: today,
+            days,
+        }
+    }
+}
+
+/// the heatmap of the `weeks` up to `today`, the local date
+pub fn heatmap(dal: &mut Dal, today: NaiveDate, weeks: u32) -> Result<Heatmap, DieselError> {
+    let first = Heatmap::new(today, weeks, &[]).from;
+    // the log is in UTC, a day before covers all time zones
+    let since = (first - Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight exists");
+    Ok(Heatmap::new(today, weeks, &dal.get_activity(since)?))
+}
+
+/// shade of `n` relative to the maximum `max`
+fn shade(n: i32, max: i32) -> char {
+    match n {
+        0 => SHADES[0],
+        _ => SHADES[((4 * n + max - 1) / max).clamp(1, 4) as usize],
+    }
+}
+
+impl fmt::Display for Heatmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = |day: &Day| day.added + day.opened;
+        let max = self.days.iter().map(total).max().unwrap_or_default();
+        let weeks = self.days.len().div_ceil(7);
+        // the month above the week it starts in, if there is room
+        let mut months = String::new();
+        for week in 0..weeks {
+            let monday = self.from + Duration::weeks(week as i64);
+            let starts = week == 0 || monday.day() <= 7;
+            if starts && (months.is_empty() || months.chars().count() < week) {
+                months.push_str(&" ".repeat(week - months.chars().count()));
+                months.push_str(&monday.format("%b").to_string());
+            }
+        }
+        writeln!(f, "    {}", months.trim_end())?;
+        for (weekday, label) in ["Mon", "", "Wed", "", "Fri", "", ""].iter().enumerate() {
+            let row: String = (0..weeks)
+                .map(|week| match self.days.get(week * 7 + weekday) {
+                    Some(day) => shade(total(day), max),
+                    None => ' ',
+                })
+                .collect();
+            writeln!(f, "{:<4}{}", label, row.trim_end())?;
+        }
+        let added: i32 = self.days.iter().map(|day| day.added).sum();
+        let opened: i32 = self.days.iter().map(|day| day.opened).sum();
+        write!(
+            f,
+            "    less {} more   {} added, {} opened from {} to {}",
+            SHADES.iter().collect::<String>(),
+            added,
+            opened,
+            self.from,
+            self.to
+        )
+    }
+}
+
+/// period like `12h`, `30d` or `2w`
+pub fn parse_period(period: &str) -> anyhow::Result<Duration> {
+    let invalid = || anyhow!("Invalid period {:?}, expected e.g. 12h, 30d or 2w", period);
+    let period = period.trim();
+    let (n, unit) = period.split_at(period.len().saturating_sub(1));
+    let n: i64 = n.parse().ok().filter(|n| *n > 0).ok_or_else(invalid)?;
+    match unit {
+        "h" => Duration::try_hours(n),
+        "d" => Duration::try_days(n),
+        "w" => Duration::try_weeks(n),
+        _ => None,
+    }
+    .ok_or_else(invalid)
+}
+
+/// bytes with binary units, e.g. 1.5 MiB
+fn human_size(bytes: i64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{} B", bytes),
+                _ => format!("{:.1} {}", size, unit),
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GiB", size)
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kinds = &self.kinds;
+        writeln!(f, "Bookmarks: {}", self.total)?;
+        for (name, n) in [
+            ("web", kinds.web),
+            ("shell", kinds.shell),
+            ("file", kinds.file),
+            ("snippet", kinds.snippet),
+            ("other", kinds.other),
+        ] {
+            writeln!(f, "  {}: {}", name, n)?;
+        }
+        writeln!(f, "Top tags:")?;
+        for tag in &self.top_tags {
+            writeln!(f, "  {}: {}", tag.n, tag.tag)?;
+        }
+        writeln!(f, "Added per month:")?;
+        for month in &self.added_per_month {
+            writeln!(f, "  {}: {}", month.month, month.n)?;
+        }
+        write!(f, "Database size: {}", human_size(self.db_size))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("https://www.sysid.de", Kind::Web)]
+    #[case("HTTP://example.org", Kind::Web)]
+    #[case("shell::ls -l", Kind::Shell)]
+    #[case("snip::println!()", Kind::Snippet)]
+    #[case("markdown::# title", Kind::Snippet)]
+    #[case("/home/user/notes.md", Kind::File)]
+    #[case("~/notes.md", Kind::File)]
+    #[case("$HOME/dev", Kind::File)]
+    #[case("file:///tmp/x.pdf", Kind::File)]
+    #[case("ssh::host", Kind::Other)]
+    #[case("mailto:a@b.org", Kind::Other)]
+    fn test_kind(#[case] uri: &str, #[case] expected: Kind) {
+        assert_eq!(kind(uri), expected);
+    }
+
+    #[rstest]
+    #[case("12h", Some(Duration::hours(12)))]
+    #[case("30d", Some(Duration::days(30)))]
+    #[case(" 2w", Some(Duration::weeks(2)))]
+    #[case("30", None)]
+    #[case("0d", None)]
+    #[case("d", None)]
+    #[case("3m", None)]
+    #[case("", None)]
+    fn test_parse_period(#[case] period: &str, #[case] expected: Option<Duration>) {
+        assert_eq!(parse_period(period).ok(), expected);
+    }
+
+    #[rstest]
+    fn test_domains() {
+        let now = NaiveDate::from_ymd_opt(2026, 10, 16)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let bookmark = |url: &str, tags: &str, days: i64| Bookmark {
+            URL: url.to_string(),
+            tags: tags.to_string(),
+            last_update_ts: now - Duration::days(days),
+            ..Default::default()
+        };
+        let domains = domains(
+            &[
+                bookmark("https://www.sysid.de/blog", ",blog,", 10),
+                bookmark("https://sysid.de/about", ",dead,", 20),
+                bookmark("http://rust-lang.org", "", 3),
+                bookmark("shell::ls", "", 1),
+            ],
+            now,
+        );
+        assert_eq!(
+            domains,
+            vec![
+                Domain {
+                    domain: "sysid.de".to_string(),
+                    bookmarks: 2,
+                    average_age_days: 15,
+                    dead: 1,
+                    dead_rate: 0.5,
+                },
+                Domain {
+                    domain: "rust-lang.org".to_string(),
+                    bookmarks: 1,
+                    average_age_days: 3,
+                    dead: 0,
+                    dead_rate: 0.0,
+                },
+            ]
+        );
+        assert_eq!(
+            format_domains(&domains[..1]),
+            "bookmarks  avg age  dead  domain\n        2      15d   50%  sysid.de\n"
+        );
+    }
+
+    #[rstest]
+    fn test_heatmap() {
+        // a Thursday
+        let today = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+        let activity = |day: &str, added, opened| DayActivity {
+            day: day.to_string(),
+            added,
+            opened,
+        };
+        let heatmap = Heatmap::new(
+            today,
+            3,
+            &[
+                activity("2026-09-27", 9, 9),
+                activity("2026-09-28", 1, 0),
+                activity("2026-10-05", 2, 2),
+                activity("2026-10-15", 0, 1),
+            ],
+        );
+        assert_eq!(heatmap.from, NaiveDate::from_ymd_opt(2026, 9, 28).unwrap());
+        assert_eq!(heatmap.days.len(), 18);
+        assert_eq!(
+            heatmap.to_string(),
+            "    Sep\nMon ░█·\n    ···\nWed ···\n    ··░\nFri ··\n    ··\n    ··\n    \
+            less ·░▒▓█ more   3 added, 3 opened from 2026-09-28 to 2026-10-15"
+        );
+    }
+
+    #[rstest]
+    #[case(512, "512 B")]
+    #[case(1536, "1.5 KiB")]
+    #[case(3 << 20, "3.0 MiB")]
+    #[case(5 << 30, "5.0 GiB")]
+    fn test_human_size(#[case] bytes: i64, #[case] expected: &str) {
+        assert_eq!(human_size(bytes), expected);
+    }
+}
@@ -0,0 +1,754 @@
+//! Syncing bookmarks between machines through a canonical text export: one JSON line per bookmark,
+//! sorted and keyed by URL, as ids differ between databases.
+//!
+//! Local and remote changes are merged against the export of the last sync (the base): a bookmark
+//! changed on one side only takes that change, changed on both sides is merged as configured,
+//! see [`Conflict`].
+//!
+//! `bkmr sync git` keeps the export in a git repository, the base being the export of the last
+//! commit shared with the remote.
+//! `bkmr sync ssh` exchanges the exports with the bkmr of another machine, the base being the
+//! merged records of the last sync with it.
+//! `bkmr sync cloud` does the same with an encrypted snapshot on S3-compatible or WebDAV storage.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Context};
+use chrono::{NaiveDateTime, Utc};
+use diesel::result::Error as DieselError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::debug;
+
+use crate::cloud::{Age, Storage};
+use crate::dal::Dal;
+use crate::edit;
+use crate::environment::CONFIG;
+use crate::models::Bookmark;
+use crate::process::run_editor;
+use crate::tag::Tags;
+
+/// export file in the sync repository
+pub const EXPORT_FILE: &str = "bookmarks.ndjson";
+
+/// the synced fields of a bookmark, without the local id and visits
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub url: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub desc: String,
+    #[serde(default)]
+    pub flags: i32,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub extra: Map<String, Value>,
+    pub updated: NaiveDateTime,
+}
+
+impl Record {
+    pub fn from_bookmark(bm: &Bookmark) -> Record {
+        Record {
+            url: bm.URL.clone(),
+            title: bm.metadata.clone(),
+            tags: bm.get_tags(),
+            desc: bm.desc.clone(),
+            flags: bm.flags,
+            extra: bm.get_extra(),
+            updated: bm.last_update_ts,
+        }
+    }
+
+    /// the bookmark with id `id`, 0 for new ones
+    pub fn to_bookmark(&self, id: i32) -> Bookmark {
+        Bookmark {
+            id,
+            URL: self.url.clone(),
+            metadata: self.title.clone(),
+            tags: Tags::create_normalized_tag_string(Some(self.tags.join(","))),
+            desc: self.desc.clone(),
+            flags: self.flags,
+            last_update_ts: self.updated,
+            extra: Value::Object(self.extra.clone()).to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// same content, whenever it was updated
+    fn same_content(&self, other: &Record) -> bool {
+        Record {
+            updated: other.updated,
+            ..self.clone()
+        } == *other
+    }
+}
+
+/// records by URL
+pub type Records = BTreeMap<String, Record>;
+
+pub fn records(bms: &[Bookmark]) -> Records {
+    bms.iter()
+        .map(|bm| (bm.URL.clone(), Record::from_bookmark(bm)))
+        .collect()
+}
+
+/// one line per record, sorted by URL so that changes show up as line diffs
+pub fn to_ndjson(records: &Records) -> String {
+    records
+        .values()
+        .map(|r| serde_json::to_string(r).expect("records are serializable") + "\n")
+        .collect()
+}
+
+pub fn parse_ndjson(text: &str) -> anyhow::Result<Records> {
+    let mut records = Records::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(line)
+            .with_context(|| format!("Invalid record in line {}", i + 1))?;
+        records.insert(record.url.clone(), record);
+    }
+    Ok(records)
+}
+
+/// changes to the local bookmarks
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub added: Vec<Record>,
+    pub updated: Vec<Record>,
+    /// URLs of the bookmarks to delete
+    pub deleted: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// how a bookmark changed on both sides is merged
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Conflict {
+    /// the newer version, local if equally new
+    #[default]
+    NewestWins,
+    /// both versions in the editor, the one saved is taken
+    ManualMerge,
+    /// the fields changed on either side: union of tags,
+    /// title, description, flags and extra fields changed on both sides are the local ones
+    FieldMerge,
+}
+
+static CONFLICT: OnceLock<Conflict> = OnceLock::new();
+
+/// sets the conflict resolution of all syncs, only the first call has an effect
+pub fn set_conflict(conflict: Conflict) {
+    let _ = CONFLICT.set(conflict);
+}
+
+pub fn conflict() -> Conflict {
+    *CONFLICT.get().unwrap_or(&Conflict::NewestWins)
+}
+
+impl Conflict {
+    /// the merged version of `local` and `remote`, both changed since `base` (None: added on both sides)
+    pub fn resolve(
+        self,
+        base: Option<&Record>,
+        local: &Record,
+        remote: &Record,
+    ) -> anyhow::Result<Record> {
+        match self {
+            Conflict::NewestWins if remote.updated > local.updated => Ok(remote.clone()),
+            Conflict::NewestWins => Ok(local.clone()),
+            Conflict::ManualMerge => edit_conflict(local, remote),
+            Conflict::FieldMerge => Ok(merge_fields(base, local, remote)),
+        }
+    }
+}
+
+/// the version saved in the editor, updated now
+fn edit_conflict(local: &Record, remote: &Record) -> anyhow::Result<Record> {
+    let local_bm = local.to_bookmark(0);
+    let buffer = edit::render_conflict(&local_bm, &remote.to_bookmark(0))?;
+    let edited = run_editor(&buffer).with_context(|| format!("Sync conflict of {}", local.url))?;
+    let bm = edit::parse_conflict(&edited, &local_bm)?;
+    Ok(Record {
+        updated: Utc::now().naive_utc(),
+        ..Record::from_bookmark(&bm)
+    })
+}
+
+fn merge_fields(base: Option<&Record>, local: &Record, remote: &Record) -> Record {
+    // the remote value if only it changed
+    fn pick<T: PartialEq + Clone>(base: Option<&T>, local: &T, remote: &T) -> T {
+        match base {
+            Some(base) if base == local => remote.clone(),
+            _ => local.clone(),
+        }
+    }
+    // added on either side is kept, removed on either side stays removed
+    let base_tags: BTreeSet<&String> = base.map(|b| b.tags.iter().collect()).unwrap_or_default();
+    let tags: BTreeSet<String> = local
+        .tags
+        .iter()
+        .chain(remote.tags.iter())
+        .filter(|tag| {
+            !base_tags.contains(tag) || (local.tags.contains(tag) && remote.tags.contains(tag))
+        })
+        .cloned()
+        .collect();
+    // each field like the title, a missing field being a removed or not yet added one
+    let keys: BTreeSet<&String> = base
+        .iter()
+        .flat_map(|b| b.extra.keys())
+        .chain(local.extra.keys())
+        .chain(remote.extra.keys())
+        .collect();
+    let mut extra = Map::new();
+    for key in keys {
+        let base_value = base.and_then(|b| b.extra.get(key));
+        let (l, r) = (local.extra.get(key), remote.extra.get(key));
+        if l != base_value && r != base_value && l != r {
+            eprintln!(
+                "Field {:?} of {} changed on both sides, keeping the local one",
+                key, local.url
+            );
+        }
+        if let Some(value) = pick(Some(&base_value), &l, &r) {
+            extra.insert(key.clone(), value.clone());
+        }
+    }
+    Record {
+        url: local.url.clone(),
+        title: pick(base.map(|b| &b.title), &local.title, &remote.title),
+        tags: tags.into_iter().collect(),
+        desc: pick(base.map(|b| &b.desc), &local.desc, &remote.desc),
+        flags: pick(base.map(|b| &b.flags), &local.flags, &remote.flags),
+        extra,
+        updated: local.updated.max(remote.updated),
+    }
+}
+
+/// three-way merge of `local` and `remote` changes since `base`: the merged records and the
+/// changes making `local` the merged ones.
+/// Edits win over deletions, edits on both sides are merged by `conflict`.
+pub fn merge(
+    base: &Records,
+    local: &Records,
+    remote: &Records,
+    conflict: Conflict,
+) -> anyhow::Result<(Records, Changes)> {
+    let mut merged = Records::new();
+    let mut changes = Changes::default();
+    let urls: BTreeSet<&String> = base
+        .keys()
+        .chain(local.keys())
+        .chain(remote.keys())
+        .collect();
+    for url in urls {
+        let changed = |side: &Record| !base.get(url).is_some_and(|b| b.same_content(side));
+        let record = match (local.get(url), remote.get(url)) {
+            (Some(l), Some(r)) if l.same_content(r) => Some(l.clone()),
+            (Some(l), Some(r)) => match (changed(l), changed(r)) {
+                (true, true) => Some(conflict.resolve(base.get(url), l, r)?),
+                (_, false) => Some(l.clone()),
+                (false, true) => Some(r.clone()),
+            },
+            // deleted remotely
+            (Some(l), None) if base.contains_key(url) && !changed(l) => None,
+            (Some(l), None) => Some(l.clone()),
+            // deleted locally
+            (None, Some(r)) if base.contains_key(url) && !changed(r) => None,
+            (None, Some(r)) => Some(r.clone()),
+            (None, None) => None,
+        };
+        match (local.get(url), &record) {
+            (Some(l), Some(r)) if l != r => changes.updated.push(r.clone()),
+            (None, Some(r)) => changes.added.push(r.clone()),
+            (Some(_), None) => changes.deleted.push(url.clone()),
+            _ => {}
+        }
+        if let Some(record) = record {
+            merged.insert(url.clone(), record);
+        }
+    }
+    Ok((merged, changes))
+}
+
+/// applies `changes` to the bookmarks of `bms`, the current ones of `dal`
+pub fn apply(dal: &mut Dal, bms: &[Bookmark], changes: &Changes) -> Result<(), DieselError> {
+    let ids: BTreeMap<&str, i32> = bms.iter().map(|bm| (bm.URL.as_str(), bm.id)).collect();
+    for record in &changes.updated {
+        dal.replace_bookmark(&record.to_bookmark(ids[record.url.as_str()]))?;
+    }
+    for record in &changes.added {
+        dal.import_bookmark(&record.to_bookmark(0))?;
+    }
+    // deleting compacts the ids: highest first keeps the lower ones valid
+    let mut deleted: Vec<i32> = changes
+        .deleted
+        .iter()
+        .map(|url| ids[url.as_str()])
+        .collect();
+    deleted.sort_unstable_by(|a, b| b.cmp(a));
+    for id in deleted {
+        dal.delete_bookmark2(id)?;
+    }
+    Ok(())
+}
+
+/// merges the bookmarks with the export in git repository `dir`: fetches and merges `remote`
+/// (default: origin if the repository has it), commits the merged export and pushes it.
+/// A dry run only returns the changes.
+pub fn sync_git(
+    dal: &mut Dal,
+    dir: &Path,
+    remote: Option<&str>,
+    dry_run: bool,
+) -> anyhow::Result<Changes> {
+    let git = Git { dir };
+    git.run(&["rev-parse", "--git-dir"]).with_context(|| {
+        format!(
+            "No git repository: {}, create it with `git init`",
+            dir.display()
+        )
+    })?;
+    let remote = match remote {
+        Some(remote) => Some(remote.to_string()),
+        None => git
+            .run(&["remote"])?
+            .lines()
+            .find(|r| *r == "origin")
+            .map(String::from),
+    };
+    let branch = git.run(&["symbolic-ref", "--short", "HEAD"])?;
+    let mut theirs = None;
+    if let Some(remote) = &remote {
+        git.run(&["fetch", "--quiet", remote])?;
+        let tracking = format!("{}/{}", remote, branch);
+        if git.succeeds(&["rev-parse", "--verify", "--quiet", &tracking]) {
+            theirs = Some(tracking);
+        }
+    }
+    let has_head = git.succeeds(&["rev-parse", "--verify", "--quiet", "HEAD"]);
+    // the last export both sides had
+    let base_commit = match (&theirs, has_head) {
+        (_, false) => None,
+        (Some(theirs), true) => git.run(&["merge-base", "HEAD", theirs]).ok(),
+        (None, true) => Some("HEAD".to_string()),
+    };
+    let base = match &base_commit {
+        Some(commit) => git.export(commit)?,
+        None => Records::new(),
+    };
+    let remote_records = match &theirs {
+        Some(theirs) => git.export(theirs)?,
+        None => base.clone(),
+    };
+    let bms = dal.get_bookmarks("")?;
+    let (merged, changes) = merge(&base, &records(&bms), &remote_records, conflict())?;
+    debug!("{:?}", changes);
+    dal.transaction_or_dry_run(dry_run, |dal| apply(dal, &bms, &changes))?;
+    if dry_run {
+        return Ok(changes);
+    }
+
+    if let Some(theirs) = &theirs {
+        if !has_head || git.succeeds(&["merge-base", "--is-ancestor", "HEAD", theirs]) {
+            git.run(&["merge", "--quiet", "--ff-only", theirs])?;
+        } else if !git.succeeds(&["merge-base", "--is-ancestor", theirs, "HEAD"]) {
+            // conflicts of the export are resolved by writing the merged one
+            git.run(&[
+                "merge",
+                "--quiet",
+                "--no-commit",
+                "--no-ff",
+                "--allow-unrelated-histories",
+                "-X",
+                "ours",
+                theirs,
+            ])?;
+        }
+    }
+    let path = dir.join(EXPORT_FILE);
+    fs::write(&path, to_ndjson(&merged))
+        .with_context(|| format!("Cannot write {}", path.display()))?;
+    git.run(&["add", EXPORT_FILE])?;
+    let merging = git.succeeds(&["rev-parse", "--verify", "--quiet", "MERGE_HEAD"]);
+    if merging || !git.succeeds(&["diff", "--cached", "--quiet"]) {
+        git.run(&["commit", "--quiet", "-m", "bkmr sync"])?;
+    }
+    if let Some(remote) = &remote {
+        git.run(&["push", "--quiet", remote, &format!("HEAD:{}", branch)])?;
+    }
+    Ok(changes)
+}
+
+/// makes the bookmarks of `dal` equal to `target`, e.g. the merged ones of a peer
+pub fn import(dal: &mut Dal, target: &Records) -> anyhow::Result<Changes> {
+    let bms = dal.get_bookmarks("")?;
+    let local = records(&bms);
+    let (_, changes) = merge(&local, &local, target, Conflict::NewestWins)?;
+    dal.transaction(|dal| apply(dal, &bms, &changes))?;
+    Ok(changes)
+}
+
+/// state of the last sync with `destination` (a peer or storage URL), next to the database
+pub fn state_path(destination: &str) -> PathBuf {
+    let name: String = destination
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '@' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    PathBuf::from(format!("{}.sync", CONFIG.db_url)).join(format!("{}.ndjson", name))
+}
+
+/// merges the bookmarks with those of the peer `destination` (e.g. "user@host"), where `ssh`
+/// runs `bkmr sync export` and `bkmr sync import` of command `bkmr`.
+/// The records of the last sync with the peer, kept in `state`, are the base: those missing
+/// on one side now are its deletions.
+/// Returns the changes to the local bookmarks and to those of the peer, a dry run only these.
+pub fn sync_ssh(
+    dal: &mut Dal,
+    destination: &str,
+    ssh: &str,
+    bkmr: &str,
+    state: &Path,
+    dry_run: bool,
+) -> anyhow::Result<(Changes, Changes)> {
+    let ssh = shlex::split(ssh)
+        .filter(|argv| !argv.is_empty())
+        .ok_or_else(|| anyhow!("Invalid ssh command: {:?}", ssh))?;
+    let peer = Peer {
+        ssh: &ssh,
+        destination,
+        bkmr,
+    };
+    let theirs = parse_ndjson(&peer.run("sync export", None)?)
+        .with_context(|| format!("Export of {}", destination))?;
+    sync_records(dal, &theirs, state, dry_run, |merged| {
+        peer.run("sync import", Some(&to_ndjson(merged)))?;
+        Ok(())
+    })
+}
+
+/// merges the bookmarks with the age encrypted snapshot in `storage`, replacing it by the merged
+/// ones. The records of the last sync with it, kept in `state`, are the base.
+/// Returns the changes to the local bookmarks and to the snapshot, a dry run only these.
+pub fn sync_cloud(
+    dal: &mut Dal,
+    storage: &Storage,
+    age: &Age,
+    state: &Path,
+    dry_run: bool,
+) -> anyhow::Result<(Changes, Changes)> {
+    let snapshot = storage.get()?;
+    let theirs = match &snapshot {
+        Some(snapshot) => {
+            let plain = age
+                .decrypt(&snapshot.content)
+                .with_context(|| format!("Cannot decrypt {}", storage))?;
+            parse_ndjson(&String::from_utf8_lossy(&plain))
+                .with_context(|| format!("Snapshot {}", storage))?
+        }
+        None => Records::new(),
+    };
+    sync_records(dal, &theirs, state, dry_run, |merged| {
+        let encrypted = age.encrypt(to_ndjson(merged).as_bytes())?;
+        storage.put(encrypted, snapshot.as_ref())
+    })
+}
+
+/// merges the bookmarks with `theirs`, `push` replaces these by the merged ones if they changed.
+/// The records of the last sync, kept in `state`, are the base.
+fn sync_records(
+    dal: &mut Dal,
+    theirs: &Records,
+    state: &Path,
+    dry_run: bool,
+    push: impl FnOnce(&Records) -> anyhow::Result<()>,
+) -> anyhow::Result<(Changes, Changes)> {
+    let base = match fs::read_to_string(state) {
+        Ok(text) => parse_ndjson(&text).with_context(|| format!("{}", state.display()))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => Records::new(),
+        Err(e) => return Err(e).with_context(|| format!("Cannot read {}", state.display())),
+    };
+    let bms = dal.get_bookmarks("")?;
+    let (merged, changes) = merge(&base, &records(&bms), theirs, conflict())?;
+    let (_, their_changes) = merge(theirs, theirs, &merged, Conflict::NewestWins)?;
+    debug!("{:?} {:?}", changes, their_changes);
+    dal.transaction_or_dry_run(dry_run, |dal| apply(dal, &bms, &changes))?;
+    if dry_run {
+        return Ok((changes, their_changes));
+    }
+    if !their_changes.is_empty() {
+        push(&merged)?;
+    }
+    if let Some(dir) = state.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    fs::write(state, to_ndjson(&merged))
+        .with_context(|| format!("Cannot write {}", state.display()))?;
+    Ok((changes, their_changes))
+}
+
+struct Peer<'a> {
+    ssh: &'a [String],
+    destination: &'a str,
+    bkmr: &'a str,
+}
+
+impl Peer<'_> {
+    /// stdout of bkmr running `args` on the peer with `input` on its stdin
+    fn run(&self, args: &str, input: Option<&str>) -> anyhow::Result<String> {
+        let command = format!("{} {}", self.bkmr, args);
+        debug!("{:?} {} {}", self.ssh, self.destination, command);
+        let mut child = Command::new(&self.ssh[0])
+            .args(&self.ssh[1..])
+            .arg(self.destination)
+            .arg(&command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Cannot run {}", self.ssh[0]))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(input.unwrap_or_default().as_bytes())?;
+        drop(stdin);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} on {} failed: {}",
+                command,
+                self.destination,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+struct Git<'a> {
+    dir: &'a Path,
+}
+
+impl Git<'_> {
+    /// trimmed stdout, fails with stderr
+    fn run(&self, args: &[&str]) -> anyhow::Result<String> {
+        debug!("git {:?}", args);
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.dir)
+            .args(args)
+            .output()
+            .context("Cannot run git")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn succeeds(&self, args: &[&str]) -> bool {
+        self.run(args).is_ok()
+    }
+
+    /// the export of `commit`, empty if it has none
+    fn export(&self, commit: &str) -> anyhow::Result<Records> {
+        match self.run(&["show", &format!("{}:{}", commit, EXPORT_FILE)]) {
+            Ok(text) => {
+                parse_ndjson(&text).with_context(|| format!("{} of {}", EXPORT_FILE, commit))
+            }
+            Err(_) => Ok(Records::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use rstest::*;
+
+    use super::*;
+
+    fn record(url: &str, title: &str, day: u32) -> Record {
+        Record {
+            url: url.to_string(),
+            title: title.to_string(),
+            tags: vec!["ccc".to_string()],
+            desc: String::new(),
+            flags: 0,
+            extra: Map::new(),
+            updated: NaiveDate::from_ymd_opt(2023, 5, day)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap(),
+        }
+    }
+
+    fn of(records: &[Record]) -> Records {
+        records.iter().map(|r| (r.url.clone(), r.clone())).collect()
+    }
+
+    #[rstest]
+    fn test_ndjson() {
+        let mut a = record("https://a.org", "A", 1);
+        a.extra.insert("author".to_string(), Value::from("x"));
+        let records = of(&[record("https://b.org", "B", 2), a]);
+        let text = to_ndjson(&records);
+        assert!(text.starts_with(r#"{"url":"https://a.org","title":"A","tags":["ccc"]"#));
+        assert_eq!(text.lines().count(), 2);
+        assert_eq!(parse_ndjson(&text).unwrap(), records);
+        assert!(parse_ndjson("{\n").is_err());
+    }
+
+    #[rstest]
+    fn test_merge() {
+        let base = of(&[
+            record("https://kept.org", "Kept", 1),
+            record("https://local-edit.org", "Old", 1),
+            record("https://remote-edit.org", "Old", 1),
+            record("https://both-edit.org", "Old", 1),
+            record("https://local-delete.org", "Old", 1),
+            record("https://remote-delete.org", "Old", 1),
+            record("https://edit-delete.org", "Old", 1),
+        ]);
+        let local = of(&[
+            record("https://kept.org", "Kept", 1),
+            record("https://local-edit.org", "Local", 2),
+            record("https://remote-edit.org", "Old", 1),
+            record("https://both-edit.org", "Local", 3),
+            record("https://remote-delete.org", "Old", 1),
+            record("https://edit-delete.org", "Local", 2),
+            record("https://local-new.org", "New", 2),
+        ]);
+        let remote = of(&[
+            record("https://kept.org", "Kept", 1),
+            record("https://local-edit.org", "Old", 1),
+            record("https://remote-edit.org", "Remote", 2),
+            record("https://both-edit.org", "Remote", 4),
+            record("https://local-delete.org", "Old", 1),
+            record("https://remote-new.org", "New", 2),
+        ]);
+        let (merged, changes) = merge(&base, &local, &remote, Conflict::NewestWins).unwrap();
+        let titles: Vec<(&str, &str)> = merged
+            .values()
+            .map(|r| (r.url.as_str(), r.title.as_str()))
+            .collect();
+        assert_eq!(
+            titles,
+            vec![
+                ("https://both-edit.org", "Remote"),
+                ("https://edit-delete.org", "Local"),
+                ("https://kept.org", "Kept"),
+                ("https://local-edit.org", "Local"),
+                ("https://local-new.org", "New"),
+                ("https://remote-edit.org", "Remote"),
+                ("https://remote-new.org", "New"),
+            ]
+        );
+        assert_eq!(
+            changes.added,
+            vec![record("https://remote-new.org", "New", 2)]
+        );
+        assert_eq!(
+            changes.updated,
+            vec![
+                record("https://both-edit.org", "Remote", 4),
+                record("https://remote-edit.org", "Remote", 2),
+            ]
+        );
+        assert_eq!(changes.deleted, vec!["https://remote-delete.org"]);
+    }
+
+    #[rstest]
+    fn test_resolve() {
+        let local = record("https://a.org", "Local", 2);
+        let remote = record("https://a.org", "Remote", 3);
+        let resolved = Conflict::NewestWins.resolve(None, &local, &remote);
+        assert_eq!(resolved.unwrap(), remote);
+        let resolved = Conflict::NewestWins.resolve(None, &remote, &local);
+        assert_eq!(resolved.unwrap(), remote);
+    }
+
+    #[rstest]
+    fn test_merge_fields() {
+        let mut base = record("https://a.org", "Base", 1);
+        base.tags = vec!["ccc".to_string(), "old".to_string()];
+        base.extra.insert("author".to_string(), Value::from("x"));
+        let mut local = record("https://a.org", "Local", 2);
+        local.tags = vec!["ccc".to_string(), "local".to_string(), "old".to_string()];
+        local.extra = base.extra.clone();
+        local.extra.insert("year".to_string(), Value::from("2023"));
+        let mut remote = record("https://a.org", "Remote", 3);
+        remote.tags = vec!["ccc".to_string(), "remote".to_string()];
+        remote.desc = "remote desc".to_string();
+        let merged = Conflict::FieldMerge
+            .resolve(Some(&base), &local, &remote)
+            .unwrap();
+        assert_eq!(merged.title, "Local");
+        assert_eq!(merged.desc, "remote desc");
+        assert_eq!(merged.tags, vec!["ccc", "local", "remote"]);
+        assert_eq!(merged.extra.keys().collect::<Vec<_>>(), vec!["year"]);
+        assert_eq!(merged.updated, remote.updated);
+        // added on both sides: nothing was removed
+        let merged = Conflict::FieldMerge.resolve(None, &local, &remote).unwrap();
+        assert_eq!(merged.tags, vec!["ccc", "local", "old", "remote"]);
+        assert_eq!(merged.desc, "");
+    }
+
+    #[rstest]
+    fn test_merge_extra_fields() {
+        let mut base = record("https://a.org", "A", 1);
+        base.extra.insert("author".to_string(), Value::from("x"));
+        base.extra.insert("year".to_string(), Value::from("2023"));
+        let mut local = base.clone();
+        local.title = "Local".to_string();
+        local.extra.insert("year".to_string(), Value::from("2024"));
+        let mut remote = base.clone();
+        remote.extra.insert("author".to_string(), Value::from("y"));
+        remote.extra.insert("year".to_string(), Value::from("2025"));
+        remote.extra.insert("lang".to_string(), Value::from("en"));
+        let merged = Conflict::FieldMerge
+            .resolve(Some(&base), &local, &remote)
+            .unwrap();
+        // changed by the remote only
+        assert_eq!(merged.extra["author"], "y");
+        assert_eq!(merged.extra["lang"], "en");
+        // changed on both sides
+        assert_eq!(merged.extra["year"], "2024");
+    }
+
+    #[rstest]
+    fn test_state_path() {
+        let path = state_path("me@host:2222/x");
+        assert!(path.ends_with("me@host_2222_x.ndjson"));
+    }
+
+    #[rstest]
+    fn test_merge_unchanged() {
+        let records = of(&[record("https://a.org", "A", 1)]);
+        let (merged, changes) =
+            merge(&Records::new(), &records, &records, Conflict::NewestWins).unwrap();
+        assert_eq!(merged, records);
+        assert!(changes.is_empty());
+        // the first sync of a machine keeps the bookmarks of both
+        let remote = of(&[record("https://b.org", "B", 1)]);
+        let (merged, changes) =
+            merge(&Records::new(), &records, &remote, Conflict::NewestWins).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(changes.added, vec![record("https://b.org", "B", 1)]);
+    }
+}
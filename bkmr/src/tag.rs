@@ -1,6 +1,5 @@
-use log::debug;
 use std::collections::HashSet;
-use stdext::function_name;
+use tracing::debug;
 
 #[derive(Debug, PartialOrd, PartialEq, Clone, Default)]
 pub struct Tags {
@@ -40,7 +39,7 @@ impl Tags {
             .collect();
         _tags.sort();
         _tags.dedup();
-        debug!("({}:{}) {:?}", function_name!(), line!(), _tags);
+        debug!("{:?}", _tags);
         _tags
     }
 
@@ -71,6 +70,25 @@ impl Tags {
         let intersect = set1.intersection(&set2).collect::<HashSet<_>>();
         intersect.len() > 0
     }
+
+    /// tags all of `bm_tags` have in common, sorted
+    pub fn common_tags(bm_tags: &[Vec<String>]) -> Vec<String> {
+        let mut common: Vec<String> = bm_tags.first().cloned().unwrap_or_default();
+        common.retain(|tag| bm_tags.iter().all(|tags| tags.contains(tag)));
+        common.sort();
+        common
+    }
+
+    /// tags to add and to remove after picking `picked` in a selection preselected with `common`:
+    /// picked tags are added, common tags no longer picked are removed
+    pub fn picked_changes(common: &[String], picked: &[String]) -> (Vec<String>, Vec<String>) {
+        let removed = common
+            .iter()
+            .filter(|tag| !picked.contains(tag))
+            .cloned()
+            .collect();
+        (Self::clean_tags(picked.to_vec()), removed)
+    }
 }
 
 #[cfg(test)]
@@ -88,15 +106,30 @@ fn init() {
 #[cfg(test)]
 mod test {
     use crate::tag::Tags;
-    use log::debug;
     use rstest::*;
-    use stdext::function_name;
+    use tracing::debug;
+
+    #[rstest]
+    fn test_picked_changes() {
+        let bm_tags = vec![
+            Tags::normalize_tag_string(Some("b,a,x".to_string())),
+            Tags::normalize_tag_string(Some("a,b,y".to_string())),
+        ];
+        let common = Tags::common_tags(&bm_tags);
+        assert_eq!(common, vec!["a", "b"]);
+        assert!(Tags::common_tags(&[]).is_empty());
+        let picked = vec!["c".to_string(), "a".to_string()];
+        assert_eq!(
+            Tags::picked_changes(&common, &picked),
+            (vec!["a".to_string(), "c".to_string()], vec!["b".to_string()])
+        );
+    }
 
     #[rstest]
     fn test_default() {
         let tags = Tags::default();
         assert_eq!(tags.tags.len(), 0);
-        debug!("({}:{}) {:?}", function_name!(), line!(), tags);
+        debug!("{:?}", tags);
     }
 
     #[rstest]
@@ -111,7 +144,7 @@ mod test {
         let tags = Tags::new(tag.clone());
         assert_eq!(tags.tag, expected);
         assert_eq!(tags.tags, expected_vec);
-        debug!("({}:{}) {:?}", function_name!(), line!(), tags);
+        debug!("{:?}", tags);
     }
 
     #[rstest]
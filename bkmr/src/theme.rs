@@ -0,0 +1,154 @@
+//! Colors of bookmark listings and of `bkmr tui`, a preset optionally overridden per element.
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Context};
+use termcolor::Color;
+
+use crate::environment::ThemeSettings;
+
+/// colors of the elements of a bookmark
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub title: Color,
+    pub url: Color,
+    pub id: Color,
+    pub date: Color,
+    pub desc: Color,
+    pub tags: Color,
+    /// marks of selected bookmarks and tags
+    pub mark: Color,
+    /// secondary information like counts
+    pub dim: Color,
+}
+
+/// names of the presets
+pub const PRESETS: &[&str] = &["dark", "light", "solarized"];
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+impl Theme {
+    /// for dark terminal backgrounds
+    pub fn dark() -> Theme {
+        Theme {
+            title: Color::Green,
+            url: Color::Yellow,
+            id: Color::White,
+            date: Color::Cyan,
+            desc: Color::White,
+            tags: Color::Blue,
+            mark: Color::Magenta,
+            dim: Color::Ansi256(8),
+        }
+    }
+
+    /// for light terminal backgrounds
+    pub fn light() -> Theme {
+        Theme {
+            title: Color::Blue,
+            url: Color::Magenta,
+            id: Color::Black,
+            date: Color::Cyan,
+            desc: Color::Black,
+            tags: Color::Green,
+            mark: Color::Red,
+            dim: Color::Ansi256(244),
+        }
+    }
+
+    /// Solarized accent colors, readable on its dark and light backgrounds
+    pub fn solarized() -> Theme {
+        Theme {
+            title: Color::Rgb(0x26, 0x8b, 0xd2),
+            url: Color::Rgb(0xb5, 0x89, 0x00),
+            id: Color::Rgb(0x93, 0xa1, 0xa1),
+            date: Color::Rgb(0x2a, 0xa1, 0x98),
+            desc: Color::Rgb(0x83, 0x94, 0x96),
+            tags: Color::Rgb(0x85, 0x99, 0x00),
+            mark: Color::Rgb(0xd3, 0x36, 0x82),
+            dim: Color::Rgb(0x58, 0x6e, 0x75),
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            "solarized" => Some(Theme::solarized()),
+            _ => None,
+        }
+    }
+
+    /// the preset with `colors` by element name, given as color names, ANSI numbers or "r,g,b"
+    pub fn from_settings(
+        preset: Option<&str>,
+        colors: &BTreeMap<String, String>,
+    ) -> anyhow::Result<Theme> {
+        let mut theme = match preset {
+            Some(name) => Theme::preset(name).ok_or_else(|| {
+                anyhow!("Unknown theme {:?}, one of: {}", name, PRESETS.join(", "))
+            })?,
+            None => Theme::dark(),
+        };
+        for (name, value) in colors {
+            let color = Color::from_str(value)
+                .with_context(|| format!("Invalid color {:?} of {:?}", value, name))?;
+            let element = match name.as_str() {
+                "title" => &mut theme.title,
+                "url" => &mut theme.url,
+                "id" => &mut theme.id,
+                "date" => &mut theme.date,
+                "desc" => &mut theme.desc,
+                "tags" => &mut theme.tags,
+                "mark" => &mut theme.mark,
+                "dim" => &mut theme.dim,
+                _ => return Err(anyhow!("Unknown theme color {:?}", name)),
+            };
+            *element = color;
+        }
+        Ok(theme)
+    }
+}
+
+/// sets the theme from the config, only the first call has an effect
+pub fn set_theme(settings: &ThemeSettings) -> anyhow::Result<()> {
+    let theme = Theme::from_settings(settings.preset.as_deref(), &settings.colors)?;
+    let _ = THEME.set(theme);
+    Ok(())
+}
+
+/// the configured theme, `Theme::dark` if none
+pub fn theme() -> &'static Theme {
+    THEME.get_or_init(Theme::dark)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn test_from_settings() {
+        let colors = BTreeMap::from([
+            ("title".to_string(), "red".to_string()),
+            ("tags".to_string(), "208".to_string()),
+            ("url".to_string(), "0,128,255".to_string()),
+        ]);
+        let theme = Theme::from_settings(Some("light"), &colors).unwrap();
+        assert_eq!(theme.title, Color::Red);
+        assert_eq!(theme.tags, Color::Ansi256(208));
+        assert_eq!(theme.url, Color::Rgb(0, 128, 255));
+        assert_eq!(theme.id, Theme::light().id);
+        assert_eq!(
+            Theme::from_settings(None, &BTreeMap::new()).unwrap(),
+            Theme::dark()
+        );
+        assert!(Theme::from_settings(Some("neon"), &BTreeMap::new()).is_err());
+        let invalid = BTreeMap::from([("title".to_string(), "reddish".to_string())]);
+        assert!(Theme::from_settings(None, &invalid).is_err());
+        let unknown = BTreeMap::from([("border".to_string(), "red".to_string())]);
+        assert!(Theme::from_settings(None, &unknown).is_err());
+    }
+}
@@ -0,0 +1,1629 @@
+//! `bkmr tui`: a persistent terminal UI with a searchable list of bookmarks, a detail pane and
+//! keyboard actions, for working in bkmr instead of issuing one-shot commands.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Stdout};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+use tracing::debug;
+
+use crate::dal::Dal;
+use crate::format::{format_ts, opml};
+use crate::fzf::{has_tags, split_tag_query, tag_counts};
+use crate::handler::{MARKDOWN_PREFIX, SNIP_PREFIX};
+use crate::models::Bookmark;
+use crate::process::{domain, edit_bms, open_bms};
+use crate::theme::theme;
+use crate::TRASH_TAG;
+
+type Term = Terminal<CrosstermBackend<Stdout>>;
+
+const EDIT_HELP: &str = "Tab: next field  Ctrl-S: save  Esc: cancel";
+const SIDEBAR_HELP: &str = "Space: select tag  c: clear  Tab: bookmarks  s: hide";
+/// rows moved by PageUp/PageDown
+const PAGE: isize = 10;
+/// pause in typing after which the query is searched
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Normal,
+    /// typing the query
+    Search,
+    /// editing the tags of the selected bookmark, comma separated
+    Tags(String),
+    /// asking whether to run the action on the marked or selected bookmarks
+    Confirm(Action),
+    /// reading the argument of a bulk action
+    Prompt(Prompt, String),
+    /// editing the selected bookmark in the detail pane
+    Edit(Form),
+    /// showing all keys
+    Help,
+}
+
+/// fields of the selected bookmark edited in the detail pane
+#[derive(Debug, PartialEq)]
+struct Form {
+    field: Field,
+    title: String,
+    /// comma separated
+    tags: String,
+    desc: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Title,
+    Tags,
+    Desc,
+}
+
+impl Form {
+    fn new(bm: &Bookmark) -> Self {
+        Form {
+            field: Field::Title,
+            title: bm.metadata.clone(),
+            tags: bm.get_tags().join(","),
+            desc: bm.desc.clone(),
+        }
+    }
+
+    fn value(&mut self) -> &mut String {
+        match self.field {
+            Field::Title => &mut self.title,
+            Field::Tags => &mut self.tags,
+            Field::Desc => &mut self.desc,
+        }
+    }
+
+    fn next(&mut self) {
+        self.field = match self.field {
+            Field::Title => Field::Tags,
+            Field::Tags => Field::Desc,
+            Field::Desc => Field::Title,
+        };
+    }
+
+    fn previous(&mut self) {
+        self.field = match self.field {
+            Field::Title => Field::Desc,
+            Field::Tags => Field::Title,
+            Field::Desc => Field::Tags,
+        };
+    }
+
+    fn apply(self, bm: &mut Bookmark) {
+        bm.metadata = self.title.trim().to_string();
+        bm.set_tags(split_tags(&self.tags));
+        bm.desc = self.desc;
+    }
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',').map(|t| t.trim().to_string()).collect()
+}
+
+/// the part of the screen receiving the keys in `Mode::Normal`
+#[derive(Debug, PartialEq)]
+enum Focus {
+    List,
+    Sidebar,
+}
+
+/// actions which need the terminal or the database, all but `SetTags` and `Update`
+/// run on the marked bookmarks, if none are marked on the selected one
+#[derive(Debug, PartialEq)]
+enum Action {
+    Quit,
+    Open,
+    Edit,
+    Delete,
+    /// tags the bookmarks with `TRASH_TAG`
+    Trash,
+    SetTags(String),
+    Update(Form),
+    AddTags(String),
+    RemoveTags(String),
+    /// writes the bookmarks to a file, OPML if it ends with ".opml" else JSON
+    Export(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prompt {
+    AddTags,
+    RemoveTags,
+    Export,
+}
+
+impl Prompt {
+    fn label(&self) -> &'static str {
+        match self {
+            Prompt::AddTags => "add tags: ",
+            Prompt::RemoveTags => "remove tags: ",
+            Prompt::Export => "export to: ",
+        }
+    }
+
+    fn action(&self, input: String) -> Action {
+        match self {
+            Prompt::AddTags => Action::AddTags(input),
+            Prompt::RemoveTags => Action::RemoveTags(input),
+            Prompt::Export => Action::Export(input),
+        }
+    }
+}
+
+/// commands of the bookmark list bound to keys by `[tui.keys]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Down,
+    Up,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    Search,
+    Open,
+    EditHere,
+    Edit,
+    Tags,
+    Mark,
+    Visual,
+    AddTags,
+    RemoveTags,
+    Delete,
+    Trash,
+    Export,
+    Sidebar,
+    Help,
+    Quit,
+}
+
+impl Command {
+    const ALL: [Command; 21] = [
+        Command::Down,
+        Command::Up,
+        Command::PageDown,
+        Command::PageUp,
+        Command::Top,
+        Command::Bottom,
+        Command::Search,
+        Command::Open,
+        Command::EditHere,
+        Command::Edit,
+        Command::Tags,
+        Command::Mark,
+        Command::Visual,
+        Command::AddTags,
+        Command::RemoveTags,
+        Command::Delete,
+        Command::Trash,
+        Command::Export,
+        Command::Sidebar,
+        Command::Help,
+        Command::Quit,
+    ];
+
+    /// name in `[tui.keys]`
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Down => "down",
+            Command::Up => "up",
+            Command::PageDown => "page-down",
+            Command::PageUp => "page-up",
+            Command::Top => "top",
+            Command::Bottom => "bottom",
+            Command::Search => "search",
+            Command::Open => "open",
+            Command::EditHere => "edit-here",
+            Command::Edit => "edit",
+            Command::Tags => "tags",
+            Command::Mark => "mark",
+            Command::Visual => "visual",
+            Command::AddTags => "add-tags",
+            Command::RemoveTags => "remove-tags",
+            Command::Delete => "delete",
+            Command::Trash => "trash",
+            Command::Export => "export",
+            Command::Sidebar => "sidebar",
+            Command::Help => "help",
+            Command::Quit => "quit",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Command> {
+        Command::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Command::Down => "next bookmark",
+            Command::Up => "previous bookmark",
+            Command::PageDown => "page down",
+            Command::PageUp => "page up",
+            Command::Top => "first bookmark",
+            Command::Bottom => "last bookmark",
+            Command::Search => "search, #tag filters by tag",
+            Command::Open => "open",
+            Command::EditHere => "edit title, tags and description here",
+            Command::Edit => "edit in the editor",
+            Command::Tags => "set tags",
+            Command::Mark => "mark and move down",
+            Command::Visual => "mark a range",
+            Command::AddTags => "add tags",
+            Command::RemoveTags => "remove tags",
+            Command::Delete => "delete",
+            Command::Trash => "move to trash",
+            Command::Export => "export to a file",
+            Command::Sidebar => "show or hide the tags",
+            Command::Help => "this help",
+            Command::Quit => "quit",
+        };
+        f.write_str(label)
+    }
+}
+
+/// keys of the commands unless configured otherwise, besides the arrow keys, PageUp/PageDown,
+/// Home/End, Enter (open) and Tab (tags)
+const DEFAULT_KEYS: [(&str, Command); 21] = [
+    ("j", Command::Down),
+    ("k", Command::Up),
+    ("ctrl-d", Command::PageDown),
+    ("ctrl-u", Command::PageUp),
+    ("gg", Command::Top),
+    ("G", Command::Bottom),
+    ("/", Command::Search),
+    ("o", Command::Open),
+    ("i", Command::EditHere),
+    ("e", Command::Edit),
+    ("t", Command::Tags),
+    ("space", Command::Mark),
+    ("v", Command::Visual),
+    ("+", Command::AddTags),
+    ("-", Command::RemoveTags),
+    ("d", Command::Delete),
+    ("D", Command::Trash),
+    ("x", Command::Export),
+    ("s", Command::Sidebar),
+    ("?", Command::Help),
+    ("q", Command::Quit),
+];
+
+static KEYS: OnceLock<Vec<(String, Command)>> = OnceLock::new();
+
+/// sets the keys of commands on top of the defaults, e.g. `down = "ctrl-n"`,
+/// only the first call has an effect. Fails on unknown commands or keys.
+pub fn set_keys(keys: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let _ = KEYS.set(keybindings(keys)?);
+    Ok(())
+}
+
+fn keys() -> &'static [(String, Command)] {
+    KEYS.get_or_init(|| keybindings(&BTreeMap::new()).expect("default keys"))
+}
+
+fn keybindings(keys: &BTreeMap<String, String>) -> anyhow::Result<Vec<(String, Command)>> {
+    let mut bindings: Vec<(String, Command)> = DEFAULT_KEYS
+        .iter()
+        .map(|(key, command)| (key.to_string(), *command))
+        .collect();
+    for (name, key) in keys {
+        let command = Command::from_name(name).ok_or_else(|| {
+            let names: Vec<&str> = Command::ALL.iter().map(Command::name).collect();
+            anyhow!(
+                "Unknown TUI command {:?}, expected one of {}",
+                name,
+                names.join(", ")
+            )
+        })?;
+        let key = key.trim().to_string();
+        if parse_keys(&key).is_none() {
+            bail!(
+                "Invalid key {:?} of TUI command {}, e.g. x, ctrl-x, alt-x, space or gg",
+                key,
+                name
+            );
+        }
+        // the configured key replaces the default key of the command and the default command of the key
+        bindings.retain(|(k, c)| *c != command && *k != key);
+        bindings.push((key, command));
+    }
+    Ok(bindings)
+}
+
+/// a key with its modifiers, Shift is part of the character
+type Press = (KeyCode, KeyModifiers);
+
+fn press_of(key: &KeyEvent) -> Press {
+    (key.code, key.modifiers - KeyModifiers::SHIFT)
+}
+
+/// keys of a binding: a character, ctrl-<char>, alt-<char>, space or a sequence of characters like gg
+fn parse_keys(keys: &str) -> Option<Vec<Press>> {
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !c.is_whitespace() => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = keys.strip_prefix("ctrl-") {
+        return single(c).map(|c| vec![(KeyCode::Char(c), KeyModifiers::CONTROL)]);
+    }
+    if let Some(c) = keys.strip_prefix("alt-") {
+        return single(c).map(|c| vec![(KeyCode::Char(c), KeyModifiers::ALT)]);
+    }
+    if keys == "space" {
+        return Some(vec![(KeyCode::Char(' '), KeyModifiers::NONE)]);
+    }
+    if keys.is_empty() || keys.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(
+        keys.chars()
+            .map(|c| (KeyCode::Char(c), KeyModifiers::NONE))
+            .collect(),
+    )
+}
+
+/// keys bound to `command`, e.g. for the help
+fn keys_of(command: Command) -> Vec<&'static str> {
+    keys()
+        .iter()
+        .filter(|(_, c)| *c == command)
+        .map(|(key, _)| key.as_str())
+        .collect()
+}
+
+struct App {
+    bms: Vec<Bookmark>,
+    query: String,
+    /// full-text query `bms` were loaded with
+    searched: String,
+    /// positions in `bms` of the bookmarks with the tags of `query`
+    shown: Vec<usize>,
+    list: ListState,
+    mode: Mode,
+    /// result of the last action
+    status: String,
+    /// tags of `bms` with their number of bookmarks
+    tags: Vec<(String, usize)>,
+    tag_list: ListState,
+    /// tags selected in the sidebar, the bookmarks must have all of them
+    selected_tags: BTreeSet<String>,
+    sidebar: bool,
+    focus: Focus,
+    /// ids of the bookmarks marked for bulk actions
+    marked: BTreeSet<i32>,
+    /// visual mode: the row where it started and the marks before
+    visual: Option<(usize, BTreeSet<i32>)>,
+    /// keys typed so far of a sequence like gg
+    pending: Vec<Press>,
+}
+
+impl App {
+    fn new(bms: Vec<Bookmark>) -> Self {
+        let mut app = App {
+            bms: Vec::new(),
+            query: String::new(),
+            searched: String::new(),
+            shown: Vec::new(),
+            list: ListState::default(),
+            mode: Mode::Normal,
+            status: String::new(),
+            tags: Vec::new(),
+            tag_list: ListState::default(),
+            selected_tags: BTreeSet::new(),
+            sidebar: false,
+            focus: Focus::List,
+            marked: BTreeSet::new(),
+            visual: None,
+            pending: Vec::new(),
+        };
+        app.reload(bms);
+        app
+    }
+
+    /// shows the bookmarks with the tags of the query, the selection keeps its row if possible.
+    /// Its words are searched in the database, see `fts_query`
+    fn filter(&mut self) {
+        let (tags, _) = split_tag_query(&self.query);
+        self.shown = (0..self.bms.len())
+            .filter(|i| has_tags(&self.bms[*i], &tags))
+            .filter(|i| {
+                let bm_tags = self.bms[*i].get_tags();
+                self.selected_tags.iter().all(|tag| bm_tags.contains(tag))
+            })
+            .collect();
+        let selected = match self.shown.len() {
+            0 => None,
+            n => Some(self.list.selected().unwrap_or(0).min(n - 1)),
+        };
+        self.list.select(selected);
+    }
+
+    fn reload(&mut self, bms: Vec<Bookmark>) {
+        self.bms = bms;
+        self.tags = tag_counts(&self.bms);
+        let selected = match self.tags.len() {
+            0 => None,
+            n => Some(self.tag_list.selected().unwrap_or(0).min(n - 1)),
+        };
+        self.tag_list.select(selected);
+        self.filter();
+    }
+
+    /// full-text query of the words of the query, each a prefix of the words searched
+    fn fts_query(&self) -> String {
+        let (_, rest) = split_tag_query(&self.query);
+        rest.split_whitespace()
+            .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn selected(&self) -> Option<&Bookmark> {
+        let i = *self.shown.get(self.list.selected()?)?;
+        self.bms.get(i)
+    }
+
+    /// the marked bookmarks, if none are marked the selected one
+    fn targets(&self) -> Vec<Bookmark> {
+        match self.marked.is_empty() {
+            true => self.selected().cloned().into_iter().collect(),
+            false => self
+                .bms
+                .iter()
+                .filter(|bm| self.marked.contains(&bm.id))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(id) = self.selected().map(|bm| bm.id) {
+            if !self.marked.remove(&id) {
+                self.marked.insert(id);
+            }
+        }
+    }
+
+    /// in visual mode the rows between its start and the selection are marked
+    fn extend_visual(&mut self) {
+        let (Some((start, before)), Some(selected)) = (&self.visual, self.list.selected()) else {
+            return;
+        };
+        let rows = (*start).min(selected)..=(*start).max(selected);
+        let ids: Vec<i32> = rows.map(|row| self.bms[self.shown[row]].id).collect();
+        self.marked = before.iter().copied().chain(ids).collect();
+    }
+
+    /// selects or deselects the current tag of the sidebar
+    fn toggle_tag(&mut self) {
+        let Some((tag, _)) = self.tag_list.selected().and_then(|i| self.tags.get(i)) else {
+            return;
+        };
+        if !self.selected_tags.remove(tag) {
+            self.selected_tags.insert(tag.clone());
+        }
+        self.filter();
+    }
+
+    fn move_tag_by(&mut self, rows: isize) {
+        if let Some(selected) = self.tag_list.selected() {
+            let last = self.tags.len() as isize - 1;
+            self.tag_list
+                .select(Some((selected as isize + rows).clamp(0, last) as usize));
+        }
+    }
+
+    fn move_by(&mut self, rows: isize) {
+        if let Some(selected) = self.list.selected() {
+            let last = self.shown.len() as isize - 1;
+            self.list
+                .select(Some((selected as isize + rows).clamp(0, last) as usize));
+        }
+        self.extend_visual();
+    }
+
+    /// command of the keys typed so far, None while they start a longer sequence
+    fn command(&mut self, key: &KeyEvent) -> Option<Command> {
+        self.pending.push(press_of(key));
+        let bound = |pending: &[Press]| {
+            let mut prefix = false;
+            for (keys, command) in keys() {
+                let keys = parse_keys(keys).unwrap_or_default();
+                if keys == pending {
+                    return (Some(*command), false);
+                }
+                prefix |= keys.starts_with(pending);
+            }
+            (None, prefix)
+        };
+        match bound(&self.pending) {
+            (Some(command), _) => {
+                self.pending.clear();
+                Some(command)
+            }
+            (None, true) => None,
+            // no sequence starts so, the key may be bound itself or start another one
+            (None, false) if self.pending.len() > 1 => {
+                self.pending.clear();
+                self.command(key)
+            }
+            (None, false) => {
+                self.pending.clear();
+                None
+            }
+        }
+    }
+
+    fn run_command(&mut self, command: Command) -> Option<Action> {
+        let selected = self.selected().is_some();
+        match command {
+            Command::Quit => return Some(Action::Quit),
+            Command::Mark => {
+                self.toggle_mark();
+                self.move_by(1);
+            }
+            Command::Visual => match self.visual {
+                Some(_) => self.visual = None,
+                None => {
+                    self.visual = self.list.selected().map(|row| (row, self.marked.clone()));
+                    self.extend_visual();
+                }
+            },
+            Command::Down => self.move_by(1),
+            Command::Up => self.move_by(-1),
+            Command::PageDown => self.move_by(PAGE),
+            Command::PageUp => self.move_by(-PAGE),
+            Command::Top => self.move_by(-(self.shown.len() as isize)),
+            Command::Bottom => self.move_by(self.shown.len() as isize),
+            Command::Search => self.mode = Mode::Search,
+            Command::Sidebar => self.sidebar = !self.sidebar,
+            Command::Help => self.mode = Mode::Help,
+            Command::Open if selected => return Some(Action::Open),
+            Command::Edit if selected => return Some(Action::Edit),
+            Command::EditHere => {
+                if let Some(bm) = self.selected() {
+                    self.mode = Mode::Edit(Form::new(bm));
+                }
+            }
+            Command::Delete if selected => {
+                self.visual = None;
+                self.mode = Mode::Confirm(Action::Delete);
+            }
+            Command::Trash if selected => {
+                self.visual = None;
+                self.mode = Mode::Confirm(Action::Trash);
+            }
+            Command::AddTags if selected => {
+                self.mode = Mode::Prompt(Prompt::AddTags, String::new())
+            }
+            Command::RemoveTags if selected => {
+                self.mode = Mode::Prompt(Prompt::RemoveTags, String::new())
+            }
+            Command::Export if selected => {
+                self.mode = Mode::Prompt(Prompt::Export, "bookmarks.json".to_string())
+            }
+            Command::Tags => {
+                if let Some(bm) = self.selected() {
+                    self.mode = Mode::Tags(bm.get_tags().join(","));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// changes the mode or the selection, returns what the caller has to do
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Action::Quit);
+        }
+        match &mut self.mode {
+            Mode::Normal if self.focus == Focus::Sidebar => match key.code {
+                KeyCode::Esc | KeyCode::Tab => self.focus = Focus::List,
+                KeyCode::Down => self.move_tag_by(1),
+                KeyCode::Up => self.move_tag_by(-1),
+                KeyCode::Enter => self.toggle_tag(),
+                KeyCode::Char('c') => {
+                    self.selected_tags.clear();
+                    self.filter();
+                }
+                _ => match self.command(&key)? {
+                    Command::Quit => return Some(Action::Quit),
+                    Command::Sidebar => {
+                        self.sidebar = false;
+                        self.focus = Focus::List;
+                    }
+                    Command::Down => self.move_tag_by(1),
+                    Command::Up => self.move_tag_by(-1),
+                    Command::Mark => self.toggle_tag(),
+                    Command::Help => self.mode = Mode::Help,
+                    _ => {}
+                },
+            },
+            Mode::Normal => match key.code {
+                KeyCode::Esc if self.visual.is_some() => self.visual = None,
+                KeyCode::Esc if !self.marked.is_empty() => self.marked.clear(),
+                KeyCode::Esc => return Some(Action::Quit),
+                KeyCode::Down => self.move_by(1),
+                KeyCode::Up => self.move_by(-1),
+                KeyCode::PageDown => self.move_by(PAGE),
+                KeyCode::PageUp => self.move_by(-PAGE),
+                KeyCode::Home => self.move_by(-(self.shown.len() as isize)),
+                KeyCode::End => self.move_by(self.shown.len() as isize),
+                KeyCode::Tab => {
+                    self.sidebar = true;
+                    self.focus = Focus::Sidebar;
+                }
+                KeyCode::Enter if self.selected().is_some() => return Some(Action::Open),
+                _ => {
+                    let command = self.command(&key)?;
+                    return self.run_command(command);
+                }
+            },
+            Mode::Search => match key.code {
+                KeyCode::Esc => {
+                    self.query.clear();
+                    self.mode = Mode::Normal;
+                    self.filter();
+                }
+                KeyCode::Enter => self.mode = Mode::Normal,
+                KeyCode::Down => self.move_by(1),
+                KeyCode::Up => self.move_by(-1),
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.filter();
+                }
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.filter();
+                }
+                _ => {}
+            },
+            Mode::Tags(tags) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Enter => {
+                    let tags = std::mem::take(tags);
+                    self.mode = Mode::Normal;
+                    return Some(Action::SetTags(tags));
+                }
+                KeyCode::Backspace => {
+                    tags.pop();
+                }
+                KeyCode::Char(c) => tags.push(c),
+                _ => {}
+            },
+            Mode::Edit(form) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    let Mode::Edit(form) = std::mem::replace(&mut self.mode, Mode::Normal) else {
+                        unreachable!()
+                    };
+                    return Some(Action::Update(form));
+                }
+                KeyCode::Tab => form.next(),
+                KeyCode::BackTab => form.previous(),
+                KeyCode::Enter if form.field == Field::Desc => form.desc.push('\n'),
+                KeyCode::Enter => form.next(),
+                KeyCode::Backspace => {
+                    form.value().pop();
+                }
+                KeyCode::Char(c) => form.value().push(c),
+                _ => {}
+            },
+            Mode::Prompt(prompt, input) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Enter => {
+                    let action = prompt.action(std::mem::take(input));
+                    self.mode = Mode::Normal;
+                    return Some(action);
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            },
+            Mode::Help => self.mode = Mode::Normal,
+            Mode::Confirm(_) => {
+                let Mode::Confirm(action) = std::mem::replace(&mut self.mode, Mode::Normal) else {
+                    unreachable!()
+                };
+                if key.code == KeyCode::Char('y') {
+                    return Some(action);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// whether all `words` are in the title, URL, description or tags of `bm` and it has `tags`
+/// runs the TUI on `bms` until it is quit. `load` searches the full-text query typed,
+/// an empty one yields all bookmarks; it also reloads them after changes
+pub fn run(
+    bms: Vec<Bookmark>,
+    load: impl Fn(&str) -> anyhow::Result<Vec<Bookmark>>,
+) -> anyhow::Result<()> {
+    let mut terminal = enter()?;
+    let result = event_loop(&mut terminal, App::new(bms), load);
+    leave(&mut terminal)?;
+    result
+}
+
+fn enter() -> anyhow::Result<Term> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    Ok(Terminal::new(CrosstermBackend::new(io::stdout()))?)
+}
+
+fn leave(terminal: &mut Term) -> anyhow::Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+/// runs `f` on the normal screen, e.g. an editor or a shell command
+fn suspended<T>(terminal: &mut Term, f: impl FnOnce() -> T) -> anyhow::Result<T> {
+    leave(terminal)?;
+    let result = f();
+    enable_raw_mode()?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+    terminal.clear()?;
+    Ok(result)
+}
+
+fn event_loop(
+    terminal: &mut Term,
+    mut app: App,
+    load: impl Fn(&str) -> anyhow::Result<Vec<Bookmark>>,
+) -> anyhow::Result<()> {
+    // when to search the query typed, postponed by each key
+    let mut search_at: Option<Instant> = None;
+    loop {
+        terminal.draw(|f| draw(f, &mut app))?;
+        let due = match search_at {
+            Some(at) => !event::poll(at.saturating_duration_since(Instant::now()))?,
+            None => false,
+        };
+        if due {
+            search_at = None;
+            let query = app.fts_query();
+            match load(&query) {
+                Ok(bms) => {
+                    app.searched = query;
+                    app.status.clear();
+                    app.reload(bms);
+                }
+                // e.g. while a quote is being typed
+                Err(e) => app.status = format!("{:#}", e),
+            }
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let action = app.handle_key(key);
+        if app.fts_query() != app.searched {
+            search_at = Some(Instant::now() + DEBOUNCE);
+        }
+        let Some(action) = action else {
+            continue;
+        };
+        if action == Action::Quit {
+            return Ok(());
+        }
+        let bms = app.targets();
+        let Some(bm) = app.selected().cloned() else {
+            continue;
+        };
+        debug!(
+            "{:?} {:?}",
+            action,
+            bms.iter().map(|bm| bm.id).collect::<Vec<_>>()
+        );
+        let positions = || (1..=bms.len() as i32).collect::<Vec<i32>>();
+        let result = match action {
+            Action::Open => suspended(terminal, || open_bms(positions(), bms.clone()))?
+                .map(|_| format!("Opened {}", bms.len())),
+            Action::Edit => suspended(terminal, || edit_bms(positions(), bms.clone()))?
+                .map(|_| format!("Edited {}", bms.len())),
+            Action::Delete => {
+                // ids are compacted from the deleted ones on, the highest first
+                app.marked.clear();
+                let mut ids: Vec<i32> = bms.iter().map(|bm| bm.id).collect();
+                ids.sort_unstable_by(|a, b| b.cmp(a));
+                Dal::default()
+                    .transaction(|dal| {
+                        ids.into_iter()
+                            .try_for_each(|id| dal.delete_bookmark2(id).map(|_| ()))
+                    })
+                    .map(|_| format!("Deleted {}", bms.len()))
+                    .map_err(anyhow::Error::from)
+            }
+            Action::Trash => update_tags(&bms, &[TRASH_TAG.to_string()], &[])
+                .map(|_| format!("Moved {} to trash, tag {}", bms.len(), TRASH_TAG)),
+            Action::AddTags(tags) => {
+                update_tags(&bms, &split_tags(&tags), &[]).map(|_| format!("Tagged {}", bms.len()))
+            }
+            Action::RemoveTags(tags) => update_tags(&bms, &[], &split_tags(&tags))
+                .map(|_| format!("Untagged {}", bms.len())),
+            Action::Export(path) => export(&bms, Path::new(path.trim()))
+                .map(|_| format!("Exported {} to {}", bms.len(), path.trim())),
+            Action::SetTags(tags) => {
+                let mut bm = bm;
+                bm.set_tags(split_tags(&tags));
+                Dal::default()
+                    .update_bookmark(bm)
+                    .map(|_| "Tags saved".to_string())
+                    .map_err(anyhow::Error::from)
+            }
+            Action::Update(form) => {
+                let mut bm = bm;
+                form.apply(&mut bm);
+                Dal::default()
+                    .update_bookmark(bm)
+                    .map(|_| "Saved".to_string())
+                    .map_err(anyhow::Error::from)
+            }
+            Action::Quit => unreachable!(),
+        };
+        app.status = result.unwrap_or_else(|e| format!("{:#}", e));
+        // ids, visits and contents may have changed
+        app.reload(load(&app.searched)?);
+    }
+}
+
+/// adds `add` to and removes `remove` from the tags of `bms`, all or none
+fn update_tags(bms: &[Bookmark], add: &[String], remove: &[String]) -> anyhow::Result<()> {
+    Dal::default().transaction(|dal| {
+        for bm in bms {
+            let mut bm = bm.clone();
+            let tags = bm
+                .get_tags()
+                .into_iter()
+                .chain(add.iter().cloned())
+                .filter(|tag| !remove.contains(tag))
+                .collect();
+            bm.set_tags(tags);
+            dal.update_bookmark(bm)?;
+        }
+        Ok(())
+    })
+}
+
+fn export(bms: &[Bookmark], path: &Path) -> anyhow::Result<()> {
+    let content = match path.extension().and_then(|e| e.to_str()) {
+        Some("opml") => opml(bms),
+        _ => serde_json::to_string_pretty(bms)?,
+    };
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+        .split(f.size());
+    let main = if app.sidebar {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref())
+            .split(rows[0]);
+        draw_sidebar(f, app, columns[0]);
+        columns[1]
+    } else {
+        rows[0]
+    };
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+        .split(main);
+
+    let items: Vec<ListItem> = app
+        .shown
+        .iter()
+        .map(|i| list_item(&app.bms[*i], app.marked.contains(&app.bms[*i].id)))
+        .collect();
+    let filter = app
+        .selected_tags
+        .iter()
+        .map(|tag| format!("#{}", tag))
+        .chain((!app.query.is_empty()).then(|| format!("/{}", app.query)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut title = match filter.as_str() {
+        "" => format!(" Bookmarks {} ", app.shown.len()),
+        filter => format!(" {} {}/{} ", filter, app.shown.len(), app.bms.len()),
+    };
+    if !app.marked.is_empty() {
+        let visual = if app.visual.is_some() { "VISUAL " } else { "" };
+        title.push_str(&format!("{}{} marked ", visual, app.marked.len()));
+    }
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    f.render_stateful_widget(list, columns[0], &mut app.list);
+
+    if let Mode::Edit(form) = &app.mode {
+        draw_form(f, form, columns[1]);
+    } else {
+        let details = Paragraph::new(app.selected().map(detail_lines).unwrap_or_default())
+            .block(Block::default().borders(Borders::ALL))
+            .wrap(Wrap { trim: false });
+        f.render_widget(details, columns[1]);
+    }
+
+    draw_status(f, app, rows[1]);
+    if app.mode == Mode::Help {
+        draw_help(f, rows[0]);
+    }
+}
+
+/// the most used keys for the status line
+fn short_help() -> String {
+    [
+        Command::Help,
+        Command::Search,
+        Command::EditHere,
+        Command::Quit,
+    ]
+    .iter()
+    .filter_map(|command| {
+        let key = keys_of(*command).first().copied()?;
+        Some(format!("{}: {}", key, command.name().replace('-', " ")))
+    })
+    .chain(["Enter: open".to_string()])
+    .collect::<Vec<_>>()
+    .join("  ")
+}
+
+/// all keys over the middle of `area`, any key closes it
+fn draw_help<B: Backend>(f: &mut Frame<B>, area: Rect) {
+    let key_style = fg(theme().mark);
+    let fixed = [
+        ("Enter", "open"),
+        ("Esc", "end visual mode, clear marks or quit"),
+        ("Tab", "focus the tags: Space selects, c clears"),
+        ("Search", "Enter keeps the query, Esc clears it"),
+        ("Edit here", "Tab: next field, Ctrl-S: save, Esc: cancel"),
+    ];
+    let mut lines: Vec<Line> = Command::ALL
+        .iter()
+        .map(|command| {
+            Line::from(vec![
+                Span::styled(format!("{:>12}  ", keys_of(*command).join(" ")), key_style),
+                Span::raw(command.to_string()),
+            ])
+        })
+        .collect();
+    lines.push(Line::from(""));
+    lines.extend(fixed.iter().map(|(key, text)| {
+        Line::from(vec![
+            Span::styled(format!("{:>12}  ", key), key_style),
+            Span::raw(*text),
+        ])
+    }));
+    let width = area.width.min(64);
+    let height = area.height.min(lines.len() as u16 + 2);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Keys ")),
+        popup,
+    );
+}
+
+/// tags with their counts, the selected ones marked
+fn draw_sidebar<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    let theme = theme();
+    let items: Vec<ListItem> = app
+        .tags
+        .iter()
+        .map(|(tag, n)| {
+            let (mark, style) = match app.selected_tags.contains(tag) {
+                true => ("* ", fg(theme.mark)),
+                false => ("  ", Style::default()),
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{}{}", mark, tag), style),
+                Span::styled(format!(" {}", n), fg(theme.dim)),
+            ]))
+        })
+        .collect();
+    let highlight = match app.focus {
+        Focus::Sidebar => Style::default().add_modifier(Modifier::REVERSED),
+        Focus::List => Style::default(),
+    };
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(" Tags "))
+        .highlight_style(highlight);
+    f.render_stateful_widget(list, area, &mut app.tag_list);
+}
+
+fn list_item(bm: &Bookmark, marked: bool) -> ListItem<'_> {
+    let theme = theme();
+    ListItem::new(Line::from(vec![
+        Span::styled(if marked { "* " } else { "  " }, fg(theme.mark)),
+        Span::styled(format!("{:>4} ", bm.id), fg(theme.dim)),
+        Span::styled(bm.metadata.clone(), fg(theme.title)),
+        Span::raw(" "),
+        Span::styled(domain(&bm.URL), fg(theme.url)),
+        Span::raw(" "),
+        Span::styled(bm.get_tags().join(","), fg(theme.tags)),
+    ]))
+}
+
+/// style with a foreground color of the theme
+fn fg(color: termcolor::Color) -> Style {
+    let color = match color {
+        termcolor::Color::Black => Color::Black,
+        termcolor::Color::Blue => Color::Blue,
+        termcolor::Color::Green => Color::Green,
+        termcolor::Color::Red => Color::Red,
+        termcolor::Color::Cyan => Color::Cyan,
+        termcolor::Color::Magenta => Color::Magenta,
+        termcolor::Color::Yellow => Color::Yellow,
+        // ANSI white is ratatui's gray
+        termcolor::Color::White => Color::Gray,
+        termcolor::Color::Ansi256(n) => Color::Indexed(n),
+        termcolor::Color::Rgb(r, g, b) => Color::Rgb(r, g, b),
+        _ => Color::Reset,
+    };
+    Style::default().fg(color)
+}
+
+/// the full bookmark for the detail pane
+/// the full bookmark for the detail pane, the content of `markdown::` and `snip::` bookmarks
+/// and their descriptions rendered as Markdown
+fn detail_lines(bm: &Bookmark) -> Vec<Line<'_>> {
+    let theme = theme();
+    let mut lines = vec![Line::from(Span::styled(
+        bm.metadata.clone(),
+        fg(theme.title).add_modifier(Modifier::BOLD),
+    ))];
+    let notes = if let Some(text) = bm.URL.strip_prefix(MARKDOWN_PREFIX) {
+        lines.push(Line::from(""));
+        lines.extend(markdown_lines(text));
+        true
+    } else if let Some(code) = bm.URL.strip_prefix(SNIP_PREFIX) {
+        lines.push(Line::from(""));
+        lines.extend(
+            code.lines()
+                .map(|l| Line::styled(l.to_string(), code_style())),
+        );
+        true
+    } else {
+        lines.push(Line::from(Span::styled(bm.URL.clone(), fg(theme.url))));
+        false
+    };
+    lines.push(Line::from(""));
+    if !bm.desc.trim().is_empty() {
+        match notes {
+            true => lines.extend(markdown_lines(bm.desc.trim())),
+            false => lines.extend(bm.desc.trim().lines().map(|l| Line::from(l.to_string()))),
+        }
+        lines.push(Line::from(""));
+    }
+    lines.push(Line::from(vec![
+        Span::raw("Tags:    "),
+        Span::styled(bm.get_tags().join(" "), fg(theme.tags)),
+    ]));
+    lines.push(Line::from(format!(
+        "Updated: {}",
+        format_ts(&bm.last_update_ts)
+    )));
+    if let Some(last_visited) = &bm.last_visited {
+        lines.push(Line::from(format!(
+            "Visited: {} ({} times)",
+            format_ts(last_visited),
+            bm.visits
+        )));
+    }
+    lines
+}
+
+fn code_style() -> Style {
+    fg(theme().date)
+}
+
+/// `text` as formatted lines: headings, lists, quotes, rules, fenced code and inline
+/// emphasis, code and links
+fn markdown_lines(text: &str) -> Vec<Line<'static>> {
+    let theme = theme();
+    let mut lines = Vec::new();
+    let mut fenced = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            continue;
+        }
+        if fenced {
+            lines.push(Line::styled(format!("  {}", line), code_style()));
+            continue;
+        }
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            let mut style = fg(theme.title).add_modifier(Modifier::BOLD);
+            if hashes == 1 {
+                style = style.add_modifier(Modifier::UNDERLINED);
+            }
+            lines.push(Line::from(inline(trimmed[hashes..].trim(), style)));
+            continue;
+        }
+        if ["---", "***", "___"].contains(&trimmed.trim_end()) {
+            lines.push(Line::styled("─".repeat(24), fg(theme.dim)));
+            continue;
+        }
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            let mut spans = vec![Span::styled("│ ", fg(theme.dim))];
+            spans.extend(inline(
+                quote.trim_start(),
+                Style::default().add_modifier(Modifier::ITALIC),
+            ));
+            lines.push(Line::from(spans));
+            continue;
+        }
+        let indent = " ".repeat(line.len() - trimmed.len());
+        let item = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| trimmed.strip_prefix(bullet));
+        if let Some(item) = item {
+            let mut spans = vec![Span::styled(format!("{}• ", indent), fg(theme.mark))];
+            spans.extend(inline(item, Style::default()));
+            lines.push(Line::from(spans));
+            continue;
+        }
+        let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+        if digits > 0 && trimmed[digits..].starts_with(". ") {
+            let mut spans = vec![Span::styled(
+                format!("{}{} ", indent, &trimmed[..digits + 1]),
+                fg(theme.mark),
+            )];
+            spans.extend(inline(&trimmed[digits + 2..], Style::default()));
+            lines.push(Line::from(spans));
+            continue;
+        }
+        lines.push(Line::from(inline(line, Style::default())));
+    }
+    lines
+}
+
+/// spans of a line with `**strong**`, `*emphasis*`, `` `code` `` and `[links](url)`
+fn inline(text: &str, style: Style) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        // no emphasis within snake_case words
+        let in_word = c == '_' && plain.ends_with(|p: char| p.is_alphanumeric());
+        // (the content, length of the whole markup)
+        let markup = match c {
+            '`' => rest[1..].find('`').map(|end| (&rest[1..end + 1], end + 2)),
+            '*' | '_' if in_word => None,
+            '*' | '_' if rest[1..].starts_with(c) => {
+                let delimiter = &rest[..2];
+                rest[2..]
+                    .find(delimiter)
+                    .filter(|end| *end > 0)
+                    .map(|end| (&rest[2..end + 2], end + 4))
+            }
+            '*' | '_' if !rest[1..].starts_with(char::is_whitespace) => rest[1..]
+                .find(c)
+                .filter(|end| *end > 0)
+                .map(|end| (&rest[1..end + 1], end + 2)),
+            '[' => rest.find("](").and_then(|middle| {
+                let end = rest[middle..].find(')')? + middle;
+                Some((&rest[1..middle], end + 1))
+            }),
+            _ => None,
+        };
+        let Some((content, len)) = markup else {
+            plain.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+        if !plain.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut plain), style));
+        }
+        match c {
+            '`' => spans.push(Span::styled(content.to_string(), code_style())),
+            '[' => {
+                let url = &rest[content.len() + 3..len - 1];
+                spans.extend(inline(content, style.add_modifier(Modifier::UNDERLINED)));
+                spans.push(Span::styled(format!(" ({})", url), fg(theme().dim)));
+            }
+            _ if rest[1..].starts_with(c) => {
+                spans.extend(inline(content, style.add_modifier(Modifier::BOLD)))
+            }
+            _ => spans.extend(inline(content, style.add_modifier(Modifier::ITALIC))),
+        }
+        rest = &rest[len..];
+    }
+    if !plain.is_empty() {
+        spans.push(Span::styled(plain, style));
+    }
+    spans
+}
+
+/// the fields being edited, not wrapped so that the cursor can follow the text
+fn draw_form<B: Backend>(f: &mut Frame<B>, form: &Form, area: Rect) {
+    let label = |field: Field, name: &'static str| {
+        let style = match form.field == field {
+            true => Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            false => Style::default().add_modifier(Modifier::BOLD),
+        };
+        Span::styled(name, style)
+    };
+    let mut lines = vec![
+        Line::from(vec![
+            label(Field::Title, "Title:"),
+            Span::raw(" "),
+            Span::raw(form.title.as_str()),
+        ]),
+        Line::from(vec![
+            label(Field::Tags, "Tags:"),
+            Span::raw(" "),
+            Span::raw(form.tags.as_str()),
+        ]),
+        Line::from(label(Field::Desc, "Description:")),
+    ];
+    lines.extend(form.desc.split('\n').map(Line::from));
+    // the cursor is at the end of the edited field
+    let (row, column) = match form.field {
+        Field::Title => (0, "Title: ".len() + form.title.chars().count()),
+        Field::Tags => (1, "Tags: ".len() + form.tags.chars().count()),
+        Field::Desc => {
+            let last = form.desc.rsplit('\n').next().unwrap_or_default();
+            (
+                2 + form.desc.matches('\n').count() + 1,
+                last.chars().count(),
+            )
+        }
+    };
+    let details =
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Edit "));
+    f.render_widget(details, area);
+    let x = (area.x + 1 + column as u16).min(area.x + area.width.saturating_sub(2));
+    let y = (area.y + 1 + row as u16).min(area.y + area.height.saturating_sub(2));
+    f.set_cursor(x, y);
+}
+
+/// help, prompts or the result of the last action
+fn draw_status<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
+    let (line, cursor) = match &app.mode {
+        Mode::Normal if app.focus == Focus::Sidebar => (SIDEBAR_HELP.to_string(), None),
+        Mode::Normal | Mode::Help if app.status.is_empty() => (short_help(), None),
+        Mode::Normal | Mode::Help => (format!("{}  |  {}", app.status, short_help()), None),
+        Mode::Search => (
+            format!("/{}", app.query),
+            Some(app.query.chars().count() + 1),
+        ),
+        Mode::Tags(tags) => (format!("tags: {}", tags), Some(tags.chars().count() + 6)),
+        Mode::Edit(_) => (EDIT_HELP.to_string(), None),
+        Mode::Prompt(prompt, input) => (
+            format!("{}{}", prompt.label(), input),
+            Some(prompt.label().len() + input.chars().count()),
+        ),
+        Mode::Confirm(action) => {
+            let verb = match action {
+                Action::Trash => "Move to trash",
+                _ => "Delete",
+            };
+            let what = match app.targets().as_slice() {
+                [bm] => format!("{:?}", bm.metadata),
+                bms => format!("{} bookmarks", bms.len()),
+            };
+            (format!("{} {}? (y/N)", verb, what), None)
+        }
+    };
+    f.render_widget(Paragraph::new(line), area);
+    if let Some(x) = cursor {
+        f.set_cursor(area.x + x as u16, area.y);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    fn bms() -> Vec<Bookmark> {
+        [("Rust Book", ",rust,doc,"), ("Python Docs", ",py,doc,")]
+            .iter()
+            .enumerate()
+            .map(|(i, (title, tags))| Bookmark {
+                id: i as i32 + 1,
+                URL: format!("https://example.org/{}", i + 1),
+                metadata: title.to_string(),
+                tags: tags.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Option<Action> {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[rstest]
+    #[case("", vec![0, 1], "")]
+    #[case("book", vec![0, 1], r#""book"*"#)]
+    #[case("#doc", vec![0, 1], "")]
+    #[case("#py docs", vec![1], r#""docs"*"#)]
+    #[case("t:go", vec![], "")]
+    #[case(r#"say "hi""#, vec![0, 1], r#""say"* """hi"""*"#)]
+    fn test_filter(#[case] query: &str, #[case] expected: Vec<usize>, #[case] fts: &str) {
+        let mut app = App::new(bms());
+        app.query = query.to_string();
+        app.filter();
+        assert_eq!(app.shown, expected);
+        assert_eq!(app.list.selected(), (!expected.is_empty()).then_some(0));
+        assert_eq!(app.fts_query(), fts);
+    }
+
+    #[rstest]
+    fn test_sidebar() {
+        let mut app = App::new(bms());
+        assert_eq!(
+            app.tags,
+            vec![
+                ("doc".to_string(), 2),
+                ("py".to_string(), 1),
+                ("rust".to_string(), 1),
+            ]
+        );
+        press(&mut app, KeyCode::Tab);
+        assert!(app.sidebar);
+        assert_eq!(app.focus, Focus::Sidebar);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(app.shown, vec![0, 1]);
+        press(&mut app, KeyCode::Char('j'));
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(app.shown, vec![1]);
+        press(&mut app, KeyCode::Char('j'));
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(app.shown, Vec::<usize>::new());
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(app.shown, vec![1]);
+        press(&mut app, KeyCode::Char('c'));
+        assert_eq!(app.shown, vec![0, 1]);
+        press(&mut app, KeyCode::Tab);
+        assert_eq!(app.focus, Focus::List);
+        press(&mut app, KeyCode::Char('s'));
+        assert!(!app.sidebar);
+    }
+
+    #[rstest]
+    fn test_marks() {
+        let mut app = App::new(bms());
+        let ids = |app: &App| app.targets().iter().map(|bm| bm.id).collect::<Vec<_>>();
+        assert_eq!(ids(&app), vec![1]);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(app.selected().unwrap().id, 2);
+        assert_eq!(ids(&app), vec![1]);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(ids(&app), vec![1, 2]);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(ids(&app), vec![1]);
+        assert_eq!(press(&mut app, KeyCode::Esc), None);
+        assert_eq!(ids(&app), vec![2]);
+
+        press(&mut app, KeyCode::Char('k'));
+        press(&mut app, KeyCode::Char('v'));
+        assert_eq!(ids(&app), vec![1]);
+        press(&mut app, KeyCode::Char('j'));
+        assert_eq!(ids(&app), vec![1, 2]);
+        press(&mut app, KeyCode::Char('v'));
+        press(&mut app, KeyCode::Char('k'));
+        assert_eq!(ids(&app), vec![1, 2]);
+
+        press(&mut app, KeyCode::Char('+'));
+        press(&mut app, KeyCode::Char('x'));
+        assert_eq!(
+            press(&mut app, KeyCode::Enter),
+            Some(Action::AddTags("x".to_string()))
+        );
+        press(&mut app, KeyCode::Char('x'));
+        assert_eq!(
+            app.mode,
+            Mode::Prompt(Prompt::Export, "bookmarks.json".to_string())
+        );
+        assert_eq!(press(&mut app, KeyCode::Esc), None);
+        press(&mut app, KeyCode::Char('D'));
+        assert_eq!(press(&mut app, KeyCode::Char('y')), Some(Action::Trash));
+        assert_eq!(ids(&app), vec![1, 2]);
+    }
+
+    #[rstest]
+    fn test_keybindings() {
+        let keys = BTreeMap::from([
+            ("top".to_string(), "g".to_string()),
+            ("delete".to_string(), "x".to_string()),
+        ]);
+        let bindings = keybindings(&keys).unwrap();
+        assert!(bindings.contains(&("g".to_string(), Command::Top)));
+        assert!(bindings.contains(&("x".to_string(), Command::Delete)));
+        assert!(!bindings.iter().any(|(key, _)| key == "gg" || key == "d"));
+        assert!(!bindings.iter().any(|(_, c)| *c == Command::Export));
+
+        let unknown = BTreeMap::from([("jump".to_string(), "J".to_string())]);
+        assert!(keybindings(&unknown).is_err());
+        let invalid = BTreeMap::from([("down".to_string(), "ctrl-".to_string())]);
+        assert!(keybindings(&invalid).is_err());
+    }
+
+    #[rstest]
+    #[case("j", Some(vec![(KeyCode::Char('j'), KeyModifiers::NONE)]))]
+    #[case("ctrl-d", Some(vec![(KeyCode::Char('d'), KeyModifiers::CONTROL)]))]
+    #[case("alt-x", Some(vec![(KeyCode::Char('x'), KeyModifiers::ALT)]))]
+    #[case("space", Some(vec![(KeyCode::Char(' '), KeyModifiers::NONE)]))]
+    #[case("gg", Some(vec![(KeyCode::Char('g'), KeyModifiers::NONE); 2]))]
+    #[case("-", Some(vec![(KeyCode::Char('-'), KeyModifiers::NONE)]))]
+    #[case("", None)]
+    #[case("g g", None)]
+    fn test_parse_keys(#[case] keys: &str, #[case] expected: Option<Vec<Press>>) {
+        assert_eq!(parse_keys(keys), expected);
+    }
+
+    #[rstest]
+    fn test_vim_keys() {
+        let mut app = App::new(bms());
+        press(&mut app, KeyCode::Char('G'));
+        assert_eq!(app.selected().unwrap().id, 2);
+        assert_eq!(press(&mut app, KeyCode::Char('g')), None);
+        assert_eq!(app.selected().unwrap().id, 2);
+        press(&mut app, KeyCode::Char('g'));
+        assert_eq!(app.selected().unwrap().id, 1);
+        // an unbound sequence does not swallow the next key
+        press(&mut app, KeyCode::Char('g'));
+        press(&mut app, KeyCode::Char('j'));
+        assert_eq!(app.selected().unwrap().id, 2);
+        let shifted = KeyEvent::new(KeyCode::Char('?'), KeyModifiers::SHIFT);
+        app.handle_key(shifted);
+        assert_eq!(app.mode, Mode::Help);
+        assert_eq!(press(&mut app, KeyCode::Char('q')), None);
+        assert_eq!(app.mode, Mode::Normal);
+    }
+
+    fn texts(lines: &[Line]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| line.spans.iter().map(|s| s.content.as_ref()).collect())
+            .collect()
+    }
+
+    #[rstest]
+    #[case("plain text", vec!["plain text"])]
+    #[case("a **bold** and *it* word", vec!["a ", "bold", " and ", "it", " word"])]
+    #[case("run `ls -l` now", vec!["run ", "ls -l", " now"])]
+    #[case("see [docs](https://docs.rs)", vec!["see ", "docs", " (https://docs.rs)"])]
+    #[case("snake_case_name", vec!["snake_case_name"])]
+    #[case("2 * 3 * 4", vec!["2 * 3 * 4"])]
+    #[case("__under__ _it_", vec!["under", " ", "it"])]
+    #[case("unclosed `tick", vec!["unclosed `tick"])]
+    fn test_inline(#[case] text: &str, #[case] expected: Vec<&str>) {
+        let spans: Vec<String> = inline(text, Style::default())
+            .into_iter()
+            .map(|s| s.content.to_string())
+            .collect();
+        assert_eq!(spans, expected);
+    }
+
+    #[rstest]
+    fn test_markdown_lines() {
+        let text = "# Title\n\n- one\n  * two\n3. three\n> quote\n---\n```sh\nls *.rs\n```\n## Sub";
+        assert_eq!(
+            texts(&markdown_lines(text)),
+            vec![
+                "Title",
+                "",
+                "• one",
+                "  • two",
+                "3. three",
+                "│ quote",
+                "─".repeat(24).as_str(),
+                "  ls *.rs",
+                "Sub",
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_detail_lines() {
+        let mut bm = bms().remove(0);
+        bm.URL = "markdown::# Notes\n**a**".to_string();
+        bm.desc = "*see* also".to_string();
+        let lines = texts(&detail_lines(&bm));
+        assert_eq!(lines[..6], ["Rust Book", "", "Notes", "a", "", "see also"]);
+        bm.URL = "snip::echo *a*".to_string();
+        let lines = texts(&detail_lines(&bm));
+        assert_eq!(lines[..3], ["Rust Book", "", "echo *a*"]);
+    }
+
+    #[rstest]
+    fn test_handle_key() {
+        let mut app = App::new(bms());
+        assert_eq!(press(&mut app, KeyCode::Char('j')), None);
+        assert_eq!(app.selected().unwrap().id, 2);
+        assert_eq!(press(&mut app, KeyCode::Char('j')), None);
+        assert_eq!(app.selected().unwrap().id, 2);
+
+        press(&mut app, KeyCode::Char('/'));
+        press(&mut app, KeyCode::Char('#'));
+        press(&mut app, KeyCode::Char('r'));
+        press(&mut app, KeyCode::Char('u'));
+        assert_eq!(app.shown, vec![0]);
+        assert_eq!(app.selected().unwrap().id, 1);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.mode, Mode::Normal);
+        assert_eq!(press(&mut app, KeyCode::Enter), Some(Action::Open));
+
+        press(&mut app, KeyCode::Char('t'));
+        assert_eq!(app.mode, Mode::Tags("doc,rust".to_string()));
+        press(&mut app, KeyCode::Char(','));
+        press(&mut app, KeyCode::Char('x'));
+        assert_eq!(
+            press(&mut app, KeyCode::Enter),
+            Some(Action::SetTags("doc,rust,x".to_string()))
+        );
+
+        press(&mut app, KeyCode::Char('i'));
+        press(&mut app, KeyCode::Backspace);
+        press(&mut app, KeyCode::Char('!'));
+        press(&mut app, KeyCode::Tab);
+        press(&mut app, KeyCode::Tab);
+        press(&mut app, KeyCode::Char('a'));
+        press(&mut app, KeyCode::Enter);
+        press(&mut app, KeyCode::Char('b'));
+        let save = KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL);
+        let Some(Action::Update(form)) = app.handle_key(save) else {
+            panic!("no update");
+        };
+        assert_eq!(app.mode, Mode::Normal);
+        let mut bm = app.selected().unwrap().clone();
+        form.apply(&mut bm);
+        assert_eq!(bm.metadata, "Rust Boo!");
+        assert_eq!(bm.get_tags(), vec!["doc", "rust"]);
+        assert_eq!(bm.desc, "a\nb");
+
+        press(&mut app, KeyCode::Char('d'));
+        assert_eq!(press(&mut app, KeyCode::Char('n')), None);
+        press(&mut app, KeyCode::Char('d'));
+        assert_eq!(press(&mut app, KeyCode::Char('y')), Some(Action::Delete));
+        assert_eq!(press(&mut app, KeyCode::Char('q')), Some(Action::Quit));
+    }
+}
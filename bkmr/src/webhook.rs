@@ -0,0 +1,203 @@
+//! Webhooks of the config (`[[webhooks]]`): a POST request per bookmark event, e.g. to n8n,
+//! IFTTT or a chat channel. Changes of a transaction are sent once it commits, dry runs send none.
+use std::fmt;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Context};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::{Method, Url};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::debug;
+
+use crate::environment::WebhookSettings;
+use crate::fetch::{header_map, send_request};
+use crate::format::Template;
+use crate::models::Bookmark;
+
+/// placeholder of the event in payload templates
+const EVENT: &str = "{event}";
+
+static WEBHOOKS: OnceLock<Vec<Webhook>> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    Add,
+    Update,
+    Delete,
+    Open,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Event::Add => "add",
+            Event::Update => "update",
+            Event::Delete => "delete",
+            Event::Open => "open",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+struct Webhook {
+    url: Url,
+    events: Vec<Event>,
+    tags: Vec<String>,
+    payload: Option<String>,
+    headers: HeaderMap,
+}
+
+impl Webhook {
+    fn from_settings(settings: WebhookSettings) -> anyhow::Result<Webhook> {
+        let url = Url::parse(&settings.url)
+            .with_context(|| format!("Invalid webhook URL {:?}", settings.url))?;
+        if let Some(payload) = &settings.payload {
+            Template::json(&payload.replace(EVENT, "add"))
+                .with_context(|| format!("Invalid payload of webhook {}", url))?;
+        }
+        let mut headers = header_map(&settings.headers)?;
+        if !headers.contains_key(CONTENT_TYPE) {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
+        Ok(Webhook {
+            url,
+            events: settings.events,
+            tags: settings.tags,
+            payload: settings.payload,
+            headers,
+        })
+    }
+
+    /// no events or tags configured: all of them
+    fn matches(&self, event: Event, bm: &Bookmark) -> bool {
+        (self.events.is_empty() || self.events.contains(&event))
+            && (self.tags.is_empty() || bm.get_tags().iter().any(|t| self.tags.contains(t)))
+    }
+
+    fn payload(&self, event: Event, bm: &Bookmark) -> anyhow::Result<String> {
+        match &self.payload {
+            Some(payload) => {
+                let template = Template::json(&payload.replace(EVENT, &event.to_string()))?;
+                Ok(template.render(bm))
+            }
+            None => Ok(json!({"event": event.to_string(), "bookmark": bm}).to_string()),
+        }
+    }
+
+    fn send(&self, event: Event, bm: &Bookmark) -> anyhow::Result<()> {
+        let body = self.payload(event, bm)?;
+        let response = send_request(
+            Method::POST,
+            self.url.as_str(),
+            self.headers.clone(),
+            Some(body.into_bytes()),
+        )?;
+        if !response.status.is_success() {
+            return Err(anyhow!("{}", response.status));
+        }
+        Ok(())
+    }
+}
+
+/// sets the webhooks, only the first call has an effect.
+/// Fails if a URL, payload template or header is invalid.
+pub fn set_webhooks(webhooks: Vec<WebhookSettings>) -> anyhow::Result<()> {
+    let webhooks = webhooks
+        .into_iter()
+        .map(Webhook::from_settings)
+        .collect::<anyhow::Result<_>>()?;
+    let _ = WEBHOOKS.set(webhooks);
+    Ok(())
+}
+
+/// whether webhooks are configured, spares looking up bookmarks for events otherwise
+pub fn enabled() -> bool {
+    WEBHOOKS.get().is_some_and(|webhooks| !webhooks.is_empty())
+}
+
+/// sends `event` of `bm` to the matching webhooks, failures are reported but do not fail the change
+pub fn fire(event: Event, bm: &Bookmark) {
+    for webhook in WEBHOOKS.get().into_iter().flatten() {
+        if !webhook.matches(event, bm) {
+            continue;
+        }
+        debug!("{} {} to {}", event, bm.id, webhook.url);
+        if let Err(e) = webhook.send(event, bm) {
+            eprintln!(
+                "Webhook {} failed on {} of {}: {:#}",
+                webhook.url, event, bm.id, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn webhook(events: Vec<Event>, tags: &[&str], payload: Option<&str>) -> Webhook {
+        Webhook::from_settings(WebhookSettings {
+            url: "https://hooks.example.com/bkmr".to_string(),
+            events,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            payload: payload.map(String::from),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[fixture]
+    fn bm() -> Bookmark {
+        Bookmark {
+            id: 7,
+            URL: String::from("https://www.sysid.de"),
+            metadata: String::from("sysid \"blog\""),
+            tags: String::from(",blog,team,"),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    fn test_matches(bm: Bookmark) {
+        assert!(webhook(vec![], &[], None).matches(Event::Delete, &bm));
+        assert!(webhook(vec![Event::Add], &["team"], None).matches(Event::Add, &bm));
+        assert!(!webhook(vec![Event::Add], &["team"], None).matches(Event::Open, &bm));
+        assert!(!webhook(vec![], &["work"], None).matches(Event::Add, &bm));
+    }
+
+    #[rstest]
+    fn test_payload(bm: Bookmark) {
+        let payload = webhook(vec![], &[], None).payload(Event::Add, &bm).unwrap();
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(payload["event"], "add");
+        assert_eq!(payload["bookmark"]["URL"], "https://www.sysid.de");
+        let template = r#"{{"text": "{event}: {title} {url}", "id": {id}}}"#;
+        let payload = webhook(vec![], &[], Some(template))
+            .payload(Event::Update, &bm)
+            .unwrap();
+        assert_eq!(
+            payload,
+            r#"{"text": "update: sysid \"blog\" https://www.sysid.de", "id": 7}"#
+        );
+    }
+
+    #[rstest]
+    fn test_from_settings() {
+        let settings = |url: &str, payload: Option<&str>| WebhookSettings {
+            url: url.to_string(),
+            payload: payload.map(String::from),
+            ..Default::default()
+        };
+        assert!(Webhook::from_settings(settings("not a url", None)).is_err());
+        let unknown_field = Some("{{\"text\": \"{name}\"}}");
+        assert!(Webhook::from_settings(settings("https://a.org", unknown_field)).is_err());
+        let webhook = Webhook::from_settings(settings("https://a.org", None)).unwrap();
+        assert_eq!(webhook.headers[CONTENT_TYPE], "application/json");
+    }
+}
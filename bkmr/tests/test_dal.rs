@@ -1,11 +1,17 @@
 use log::{debug, info};
 use rstest::{fixture, rstest};
 use std::collections::HashSet;
+use std::fs;
 use stdext::function_name;
 // use stdext::function_name;
-use bkmr::dal::Dal;
+use bkmr::changes;
+use bkmr::dal::{get_pool, Dal};
 use bkmr::helper;
-use bkmr::models::NewBookmark;
+use bkmr::stats;
+use bkmr::models::{NewAttachment, NewBookmark};
+use diesel::result::Error as DieselError;
+use diesel::{Connection, RunQueryDsl, SqliteConnection};
+use diesel_migrations::MigrationHarness;
 
 #[fixture]
 pub fn dal() -> Dal {
@@ -34,6 +40,35 @@ fn test_danger() {
     // assert_eq!(bm.unwrap().id, 1);
 }
 
+/// databases created before the `extra` column and the attachments get them when opened
+#[rstest]
+fn test_upgrade_schema() {
+    let path = "/tmp/bkmr_upgrade_test.db";
+    fs::remove_file(path).unwrap_or_default();
+    let mut conn = SqliteConnection::establish(path).unwrap();
+    // the schema of the first release
+    conn.run_next_migration(helper::MIGRATIONS).unwrap();
+    diesel::sql_query("INSERT INTO bookmarks (URL, metadata) VALUES ('https://a.org', 'A');")
+        .execute(&mut conn)
+        .unwrap();
+    drop(conn);
+
+    let mut dal = Dal::new(String::from(path));
+    let bms = dal.get_bookmarks("").unwrap();
+    let bm = bms.iter().find(|bm| bm.URL == "https://a.org").unwrap();
+    assert_eq!(bm.get_extra_value("author"), None);
+    assert!(dal.get_attachments(bm.id).unwrap().is_empty());
+}
+
+#[rstest]
+fn test_get_pool_is_shared(_dal: Dal) {
+    let _dal2 = Dal::new(String::from("../db/bkmr.db"));
+    // both Dal instances hold a connection of the same pool
+    let state = get_pool("../db/bkmr.db").state();
+    assert!(state.connections >= 2);
+    assert!(state.connections - state.idle_connections >= 2);
+}
+
 #[rstest]
 fn test_get_bookmark_by_id(mut dal: Dal) {
     let bm = dal.get_bookmark_by_id(1);
@@ -49,6 +84,20 @@ fn test_get_bookmark_by_id_non_existing(mut dal: Dal) {
     assert_eq!(bm.unwrap().id, 1);
 }
 
+#[rstest]
+fn test_get_last_bookmark(mut dal: Dal) {
+    let bm = dal
+        .insert_bookmark(NewBookmark {
+            URL: String::from("https://www.last.org"),
+            metadata: String::from(""),
+            tags: String::from(",xxx,"),
+            desc: String::from(""),
+            flags: 0,
+        })
+        .unwrap();
+    assert_eq!(dal.get_last_bookmark().unwrap().id, bm[0].id);
+}
+
 #[rstest]
 #[case("xxx", 1)]
 #[case("", 11)]
@@ -95,6 +144,68 @@ fn test_update_bm(mut dal: Dal) {
     assert_eq!(bms.unwrap()[0].URL, "http://www.sysid.de");
 }
 
+#[rstest]
+fn test_update_bm_extra(mut dal: Dal) {
+    let mut bm = dal.get_bookmark_by_id(1).unwrap();
+    assert_eq!(bm.extra, "{}");
+    bm.set_extra_value("project", "bkmr");
+    dal.update_bookmark(bm).unwrap();
+    let bm = dal.get_bookmark_by_id(1).unwrap();
+    assert_eq!(bm.get_extra_value("project"), Some("bkmr".to_string()));
+}
+
+#[rstest]
+fn test_record_visit(mut dal: Dal) {
+    let bm = dal.get_bookmark_by_id(1).unwrap();
+    assert_eq!((bm.visits, bm.last_visited), (0, None));
+    assert_eq!(dal.record_visit(1).unwrap(), 1);
+    assert_eq!(dal.record_visit(1).unwrap(), 1);
+    let visited = dal.get_bookmark_by_id(1).unwrap();
+    assert_eq!(visited.visits, 2);
+    assert!(visited.last_visited.is_some());
+    // a visit is not an update
+    assert_eq!(visited.last_update_ts, bm.last_update_ts);
+    assert_eq!(dal.record_visit(99999).unwrap(), 0);
+}
+
+#[rstest]
+fn test_attachments(mut dal: Dal) {
+    let attachment_id = dal
+        .insert_attachment(NewAttachment {
+            bookmark_id: 5,
+            name: String::from("snapshot.html"),
+            content: b"<html></html>".to_vec(),
+        })
+        .unwrap();
+    let attachments = dal.get_attachments(5).unwrap();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].size, 13);
+    assert_eq!(dal.get_attachment(attachment_id).unwrap().content, b"<html></html>");
+
+    // compaction keeps attachments with their bookmark
+    dal.delete_bookmark2(2).unwrap();
+    assert_eq!(dal.get_attachments(4).unwrap()[0].name, "snapshot.html");
+    dal.delete_bookmark2(4).unwrap();
+    assert!(dal.get_attachment(attachment_id).is_err());
+}
+
+#[rstest]
+fn test_contents(mut dal: Dal) {
+    assert!(dal.get_bookmarks("ownership").unwrap().is_empty());
+    dal.set_content(5, "Rust ownership rules").unwrap();
+    dal.set_content(5, "Ownership and borrowing").unwrap();
+    assert!(dal.get_bookmarks("rules").unwrap().is_empty());
+    assert_eq!(dal.get_bookmarks("borrowing").unwrap()[0].id, 5);
+    // columns of bookmarks only restrict matches in bookmarks
+    assert!(dal.get_bookmarks("metadata:borrowing").unwrap().is_empty());
+
+    // compaction keeps contents with their bookmark
+    dal.delete_bookmark2(2).unwrap();
+    assert_eq!(dal.get_bookmarks("borrowing").unwrap()[0].id, 4);
+    dal.delete_bookmark(4).unwrap();
+    assert!(dal.get_bookmarks("borrowing").unwrap().is_empty());
+}
+
 #[rstest]
 fn test_clean_table(mut dal: Dal) {
     let _bms = dal.clean_table();
@@ -137,6 +248,18 @@ fn test_delete_bm2(mut dal: Dal) {
     assert_eq!(ids.len(), 10);
 }
 
+#[rstest]
+fn test_transaction_rollback(mut dal: Dal) {
+    let result: Result<(), DieselError> = dal.transaction(|dal| {
+        // nested transaction of delete_bookmark2 becomes a savepoint
+        dal.delete_bookmark2(4)?;
+        Err(DieselError::RollbackTransaction)
+    });
+    assert!(result.is_err());
+    assert_eq!(dal.get_bookmarks("").unwrap().len(), 11);
+    assert_eq!(dal.get_bookmarks("asdf2").unwrap().len(), 1);
+}
+
 #[rstest]
 fn test_delete_bm(mut dal: Dal) {
     let _bms = dal.delete_bookmark(1);
@@ -189,3 +312,129 @@ fn test_get_related_tags(mut dal: Dal) {
     let result: HashSet<&str> = tags_str.iter().cloned().collect();
     assert_eq!(result, expected);
 }
+
+#[rstest]
+fn test_changes(mut dal: Dal) {
+    // the log starts with the existing bookmarks
+    let feed = changes::feed(&mut dal, "", None).unwrap();
+    assert_eq!(feed.changes.len(), 11);
+    assert!(feed
+        .changes
+        .iter()
+        .all(|entry| entry.change.op == "add" && entry.bookmark.is_some()));
+
+    let mut bm = dal.get_bookmark_by_id(2).unwrap();
+    bm.metadata = String::from("changed");
+    dal.update_bookmark(bm.clone()).unwrap();
+    dal.record_visit(2).unwrap();
+    bm.URL = String::from("http://www.sysid.de");
+    dal.update_bookmark(bm).unwrap();
+    // compaction of the ids is no change
+    dal.delete_bookmark2(1).unwrap();
+
+    let feed = changes::feed(&mut dal, &feed.next, None).unwrap();
+    let ops: Vec<(&str, &str)> = feed
+        .changes
+        .iter()
+        .map(|entry| (entry.change.op.as_str(), entry.change.URL.as_str()))
+        .collect();
+    assert_eq!(
+        ops,
+        vec![
+            ("update", "http://xxxxx/yyyyy"),
+            ("delete", "http://xxxxx/yyyyy"),
+            ("add", "http://www.sysid.de"),
+            ("delete", "https://www.google.com"),
+        ]
+    );
+    assert_eq!(feed.changes[0].bookmark, None);
+    let added = feed.changes[2].bookmark.as_ref().unwrap();
+    assert_eq!((added.id, added.metadata.as_str()), (1, "changed"));
+
+    let page = changes::feed(&mut dal, "11", Some(1)).unwrap();
+    assert_eq!((page.changes.len(), page.next.as_str()), (1, "12"));
+    let empty = changes::feed(&mut dal, &feed.next, None).unwrap();
+    assert_eq!((empty.changes.len(), empty.next), (0, feed.next));
+}
+
+#[rstest]
+fn test_stats(mut dal: Dal) {
+    let stats = stats::stats(&mut dal, 3).unwrap();
+    assert_eq!(stats.total, 11);
+    assert_eq!(
+        (stats.kinds.web, stats.kinds.shell, stats.kinds.file),
+        (7, 1, 3)
+    );
+    assert_eq!(stats.top_tags.len(), 3);
+    assert!(stats.top_tags[0].n >= stats.top_tags[2].n);
+    // the change log starts with the existing bookmarks
+    let added: i32 = stats.added_per_month.iter().map(|month| month.n).sum();
+    assert_eq!(added, 11);
+    assert!(stats.db_size > 0);
+}
+
+#[rstest]
+fn test_most_opened(mut dal: Dal) {
+    for id in [3, 2, 3] {
+        dal.record_visit(id).unwrap();
+    }
+    let day = Some(chrono::Duration::days(1));
+    let top = stats::top(&mut dal, None, day, 10).unwrap();
+    let opens: Vec<(i32, i64)> = top.iter().map(|o| (o.bookmark.id, o.opens)).collect();
+    assert_eq!(opens, vec![(3, 2), (2, 1)]);
+    assert_eq!(stats::top(&mut dal, None, day, 1).unwrap().len(), 1);
+    let tag = dal.get_bookmark_by_id(2).unwrap().get_tags()[0].clone();
+    let top = stats::top(&mut dal, Some(&tag), day, 10).unwrap();
+    assert!(top.iter().any(|o| o.bookmark.id == 2));
+    assert!(top.iter().all(|o| o.bookmark.get_tags().contains(&tag)));
+
+    // the opens follow changes of the URL and the compaction of ids
+    let mut bm = dal.get_bookmark_by_id(3).unwrap();
+    bm.URL = String::from("http://www.sysid.de");
+    dal.update_bookmark(bm).unwrap();
+    dal.delete_bookmark2(2).unwrap();
+    let top = stats::top(&mut dal, None, None, 10).unwrap();
+    let opens: Vec<(i32, &str, i64)> = top
+        .iter()
+        .map(|o| (o.bookmark.id, o.bookmark.URL.as_str(), o.opens))
+        .collect();
+    assert_eq!(opens, vec![(2, "http://www.sysid.de", 2)]);
+}
+
+#[rstest]
+fn test_dump(mut dal: Dal) {
+    let dump = dal.dump().unwrap();
+    assert!(dump.contains("CREATE TABLE bookmarks"));
+    assert!(dump.contains("INSERT INTO \"bookmarks\" VALUES(1,'https://www.google.com','Google',"));
+    assert!(dump.contains("CREATE TRIGGER bookmarks_ai"));
+    assert!(!dump.contains("bookmarks_fts_data"));
+}
+
+#[rstest]
+fn test_dump_load(mut dal: Dal) {
+    let dump = dal.dump().unwrap();
+    let path = "/tmp/bkmr_dump_test.db";
+    fs::remove_file(path).unwrap_or_default();
+
+    let mut target = Dal::new(String::from(path));
+    assert!(target.is_empty_db().unwrap());
+    target.load(&dump, false).unwrap();
+    assert_eq!(target.get_bookmarks("").unwrap().len(), 11);
+    assert_eq!(target.get_bookmarks("xxx").unwrap().len(), 1);
+
+    // loading again requires replacing the existing content
+    assert!(target.load(&dump, false).is_err());
+    target.load(&dump, true).unwrap();
+    assert_eq!(target.get_bookmarks("").unwrap().len(), 11);
+}
+
+#[rstest]
+fn test_load_force_broken_dump(mut dal: Dal) {
+    let dump = dal.dump().unwrap();
+    // truncated within the bookmarks
+    let broken = &dump[..dump.find("INSERT INTO \"bookmarks\" VALUES(3,").unwrap() + 20];
+    assert!(dal.load(broken, true).is_err());
+    assert_eq!(dal.get_bookmarks("").unwrap().len(), 11);
+    assert_eq!(dal.get_bookmark_by_id(1).unwrap().URL, "https://www.google.com");
+    assert_eq!(dal.get_bookmarks("xxx").unwrap().len(), 1);
+}
@@ -53,5 +53,5 @@ fn bms() -> Vec<Bookmark> {
 #[rstest]
 #[ignore = "Interactive via Makefile"]
 fn test_fzf(bms: Vec<Bookmark>) {
-    let ids = fzf_process(&bms);
+    let ids = fzf_process(&bms, None, |_| Ok(bms.clone()));
 }
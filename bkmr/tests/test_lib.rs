@@ -11,8 +11,12 @@ use std::collections::HashSet;
 use std::env;
 use std::error::Error;
 // use bkmr::fzf;
+use bkmr::fetch::{Canonical, Details, LinkStatus};
 use bkmr::models::{Bookmark, NewBookmark};
-use bkmr::{helper, load_url_details, update_bm, update_bookmarks};
+use bkmr::{
+    add_bookmarks_batch, checked, helper, load_url_details, parse_batch, refresh_bookmarks,
+    refreshed, update_bm, update_bookmarks, with_lang_tag, BatchEntry,
+};
 use stdext::function_name;
 
 mod test_dal;
@@ -72,13 +76,162 @@ fn test_update_bm(
     #[case] expected: String,
 ) {
     // let mut dal = Dal::new(String::from("../db/bkmr.db"));
-    update_bm(id, &tags, &tags_not, &mut dal, force);
+    update_bm(id, &tags, &tags_not, &mut dal, force).unwrap();
 
     let bm = dal.get_bookmark_by_id(id).unwrap();
     assert_eq!(bm.tags, expected);
     println!("bm: {:?}", bm);
 }
 
+#[rstest]
+fn test_update_bookmarks_rolls_back(mut dal: Dal) {
+    // 99999 does not exist, so the update of 1 must be rolled back
+    let result = update_bookmarks(vec![1, 99999], vec!["t1".to_string()], vec![], false, false);
+    assert!(result.is_err());
+    assert_eq!(dal.get_bookmark_by_id(1).unwrap().tags, ",ccc,yyy,");
+}
+
+#[rstest]
+fn test_update_bookmarks_dry_run(mut dal: Dal) {
+    let updated = update_bookmarks(vec![1], vec!["t1".to_string()], vec![], false, true).unwrap();
+    assert_eq!(updated[0].tags, ",ccc,t1,yyy,");
+    assert_eq!(dal.get_bookmark_by_id(1).unwrap().tags, ",ccc,yyy,");
+}
+
+#[rstest]
+fn test_parse_batch() {
+    let input = "https://a.org\n\n# comment\nhttps://b.org|t1,t2\nhttps://c.org||c title\n";
+    assert_eq!(
+        parse_batch(input),
+        vec![
+            BatchEntry {
+                url: "https://a.org".to_string(),
+                ..Default::default()
+            },
+            BatchEntry {
+                url: "https://b.org".to_string(),
+                tags: Some("t1,t2".to_string()),
+                title: None,
+            },
+            BatchEntry {
+                url: "https://c.org".to_string(),
+                tags: None,
+                title: Some("c title".to_string()),
+            },
+        ]
+    );
+}
+
+#[rstest]
+fn test_add_bookmarks_batch(mut dal: Dal) {
+    let n = dal.get_bookmarks("").unwrap().len();
+    let entries = parse_batch("https://a.org|t1,t2|A\nhttps://www.google.com\nhttps://a.org\n");
+    let added = add_bookmarks_batch(entries, true, false).unwrap();
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0].metadata, "A");
+    assert_eq!(added[0].tags, ",t1,t2,");
+    assert_eq!(dal.get_bookmarks("").unwrap().len(), n + 1);
+}
+
+#[rstest]
+#[case("Google", "", None)]
+#[case("", "", None)]
+#[case("Google Search", "", Some(("Google Search", "Example Entry")))]
+#[case("", "search engine", Some(("Google", "search engine")))]
+fn test_refreshed(
+    mut dal: Dal,
+    #[case] title: &str,
+    #[case] desc: &str,
+    #[case] expected: Option<(&str, &str)>,
+) {
+    let bm = dal.get_bookmark_by_id(1).unwrap();
+    let details = Details {
+        title: title.to_string(),
+        description: desc.to_string(),
+        ..Default::default()
+    };
+    let new = refreshed(&bm, details, Canonical::Keep);
+    assert_eq!(
+        new.as_ref().map(|bm| (bm.metadata.as_str(), bm.desc.as_str())),
+        expected
+    );
+}
+
+#[rstest]
+#[case(Canonical::Keep, Some(("https://www.google.com", Some("https://www.google.de/"))))]
+#[case(Canonical::Replace, Some(("https://www.google.de/", None)))]
+#[case(Canonical::Ignore, None)]
+fn test_refreshed_canonical_url(
+    mut dal: Dal,
+    #[case] canonical: Canonical,
+    #[case] expected: Option<(&str, Option<&str>)>,
+) {
+    let bm = dal.get_bookmark_by_id(1).unwrap();
+    let details = Details {
+        canonical_url: Some("https://www.google.de/".to_string()),
+        ..Default::default()
+    };
+    let new = refreshed(&bm, details, canonical);
+    assert_eq!(
+        new.as_ref()
+            .map(|bm| (bm.URL.as_str(), bm.get_extra_value("canonical_url"))),
+        expected.map(|(url, kept)| (url, kept.map(String::from)))
+    );
+}
+
+#[rstest]
+#[case(None, None, None)]
+#[case(Some("a,b"), None, Some("a,b"))]
+#[case(Some("a,b"), Some("de"), Some("a,b,lang:de"))]
+#[case(None, Some("de"), Some(",lang:de"))]
+fn test_with_lang_tag(#[case] tags: Option<&str>, #[case] lang: Option<&str>, #[case] expected: Option<&str>) {
+    assert_eq!(with_lang_tag(tags.map(String::from), lang).as_deref(), expected);
+}
+
+#[rstest]
+fn test_refreshed_lang(mut dal: Dal) {
+    let mut bm = dal.get_bookmark_by_id(1).unwrap();
+    bm.set_tags(vec!["ccc".to_string(), "lang:en".to_string()]);
+    let details = Details {
+        lang: Some("de".to_string()),
+        ..Default::default()
+    };
+    let new = refreshed(&bm, details, Canonical::Keep).unwrap();
+    assert_eq!(new.get_tags(), vec!["ccc", "lang:de"]);
+}
+
+#[rstest]
+fn test_refresh_bookmarks_skips_non_web(mut dal: Dal) {
+    let mut bm = dal.get_bookmark_by_id(1).unwrap();
+    bm.URL = "shell::ls".to_string();
+    assert!(refresh_bookmarks(vec![bm], false).unwrap().is_empty());
+}
+
+#[rstest]
+#[case(LinkStatus::Ok(200), ",ccc,yyy,", Some("200"), None)]
+#[case(
+    LinkStatus::Moved(200, "https://google.com/".to_string()),
+    ",ccc,moved,yyy,",
+    Some("200"),
+    Some("https://google.com/")
+)]
+#[case(LinkStatus::Dead(Some(404), "404 Not Found".to_string()), ",ccc,dead,yyy,", Some("404"), None)]
+#[case(LinkStatus::Dead(None, "timeout".to_string()), ",ccc,dead,yyy,", None, None)]
+fn test_checked(
+    mut dal: Dal,
+    #[case] status: LinkStatus,
+    #[case] tags: &str,
+    #[case] http_status: Option<&str>,
+    #[case] moved_to: Option<&str>,
+) {
+    let mut bm = dal.get_bookmark_by_id(1).unwrap();
+    bm.tags = ",ccc,dead,yyy,".to_string();
+    let new = checked(&bm, &status);
+    assert_eq!(new.tags, tags);
+    assert_eq!(new.get_extra_value("http_status").as_deref(), http_status);
+    assert_eq!(new.get_extra_value("moved_to").as_deref(), moved_to);
+}
+
 // #[rstest]
 // fn test_add_bm(mut dal: Dal) {
 //     let bm = NewBookmark {
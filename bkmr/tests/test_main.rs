@@ -35,3 +35,179 @@ fn test_show_bms() {
         .stderr(predicate::str::contains("Debug mode: debug"))
         .stderr(predicate::str::contains("Google"));
 }
+
+#[rstest]
+fn test_show_bms_json() {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(["--json", "show", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""id": 1,"#));
+}
+
+#[rstest]
+fn test_tags_json() {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(["tags", "--json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""tag": "ccc""#));
+}
+
+#[rstest]
+#[case("bash")]
+#[case("zsh")]
+#[case("fish")]
+fn test_completions_complete_tags(#[case] shell: &str) {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(["completions", shell])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("_bkmr_tags"));
+}
+
+#[rstest]
+#[case(&["search", "-q", "--ids"], "1\n")]
+#[case(&["search", "-q"], "https://www.google.com\n")]
+#[case(&["search", "-q", "--ids", "--sort", "frecency"], "1\n")]
+fn test_search_quiet(#[case] args: &[&str], #[case] expected: &str) {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(args)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(expected));
+}
+
+#[rstest]
+fn test_show_ids_from_stdin() {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(["show", "-", "--format", "{id}"])
+        .write_stdin("1\n2,3\n")
+        .assert()
+        .success()
+        .stdout("1\n2\n3\n");
+}
+
+#[rstest]
+#[case(&["show", "99999"], 3)]
+#[case(&["show", "1,x"], 2)]
+#[case(&["search", "--format", "{unknown}"], 2)]
+#[case(&["search", "--format", "{id} {ts:%Q}"], 2)]
+#[case(&["search", "--sort", "frecency", "-o"], 2)]
+#[case(&["search", "\""], 2)] // invalid FTS query
+#[case(&["meta", "get", "1", "no-such-field"], 3)]
+#[case(&["open", "1", "--", "pod"], 2)]
+#[case(&["open", "1", "-t", "xxx"], 2)]
+#[case(&["open", "--query", "nomatchatall"], 3)]
+#[case(&["refresh", "--empty-title", "1"], 3)]
+#[case(&["check", "99999"], 3)]
+#[case(&["archive", "99999"], 3)]
+#[case(&["attach", "open", "1", "99999"], 3)]
+#[case(&["protocol", "handle", "bkmr://delete/1"], 2)]
+#[case(&["protocol", "handle", "bkmr://add?url=shell::ls"], 2)]
+#[case(&["delete", "1"], 6)] // confirmation impossible without terminal, nothing deleted
+#[case(&["--json", "delete", "1"], 2)] // --json does not confirm
+#[case(&["--json", "open", "1"], 2)]
+fn test_exit_codes(#[case] args: &[&str], #[case] code: i32) {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(args).assert().code(code);
+}
+
+#[rstest]
+fn test_rpc() {
+    let requests = [
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "search", "params": {"tags": ["ccc"], "limit": 1}}"#,
+        r#"{"jsonrpc": "2.0", "id": 2, "method": "tags", "params": {"prefix": "cc"}}"#,
+        r#"{"jsonrpc": "2.0", "id": 3, "method": "open", "params": {"id": 99999}}"#,
+    ];
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.arg("rpc")
+        .write_stdin(requests.join("\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""id":1,"jsonrpc":"2.0","result":[{"#))
+        .stdout(predicate::str::contains(r#""URL":"https://www.google.com""#))
+        .stdout(predicate::str::contains(r#""tag":"ccc"}]"#))
+        .stdout(predicate::str::contains(r#""code":-32000"#));
+}
+
+#[rstest]
+fn test_sync_export() {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(["sync", "export"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"url":"https://www.google.com","title":"Google","tags":["ccc","yyy"]"#,
+        ));
+}
+
+#[rstest]
+fn test_delete_dry_run() {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(["delete", "--dry-run", "1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Would delete: https://www.google.com"));
+}
+
+#[rstest]
+#[case(&["show", "--query", "google", "--format", "{id}"], "1\n")]
+#[case(&["show", "-t", "xxx", "--format", "{id}"], "2\n")]
+#[case(&["show", "-e", "aaa,bbb", "--format", "{id}"], "3\n4\n")]
+fn test_show_query(#[case] args: &[&str], #[case] expected: &str) {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(args).assert().success().stdout(expected.to_string());
+}
+
+#[rstest]
+fn test_show_compact() {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(["show", "--compact", "1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("1. [1] Google"))
+        .stderr(predicate::str::contains("google.com ccc,yyy"));
+}
+
+#[rstest]
+#[case(&["edit", "1"], "true", "Edit aborted: no changes")]
+#[case(&["edit", "1"], "false", "Edit aborted: editor exited with")]
+#[case(&["new"], "true", "Edit aborted: no changes")]
+fn test_edit_aborted(#[case] args: &[&str], #[case] editor: &str, #[case] expected: &str) {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.env_remove("VISUAL")
+        .env("EDITOR", editor)
+        .args(args)
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains(expected));
+}
+
+#[rstest]
+fn test_open_confirm_above() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    fs::write(&config, "[open]\nconfirm_above = 0\n").unwrap();
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(["--config", config.to_str().unwrap(), "open", "1"])
+        .assert()
+        .code(6) // confirmation impossible without terminal, nothing opened
+        .stderr(predicate::str::contains("Google"))
+        .stderr(predicate::str::contains("use --yes in scripts"));
+}
+
+#[rstest]
+#[case(&["open", "--print", "1"], "open by OS: https://www.google.com")]
+#[case(
+    &["open", "--print", "--archived", "1"],
+    "open by OS: https://web.archive.org/web/https://www.google.com"
+)]
+fn test_open_print(#[case] args: &[&str], #[case] expected: &str) {
+    let mut cmd = Command::cargo_bin("bkmr").unwrap();
+    cmd.args(args)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("handler: OS"))
+        .stderr(predicate::str::contains(expected));
+}
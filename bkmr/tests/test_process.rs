@@ -40,10 +40,18 @@ fn test_delete_bms(mut dal: Dal, bms: Vec<Bookmark>) {
     // make sure input is sorted as it would be using the helper
     let ids = helper::ensure_int_vector(&vec!["6".to_string(), "2".to_string(), "3".to_string()]);
     // let ids = helper::ensure_int_vector(&vec!["6".to_string()]);
-    delete_bms(ids.unwrap(), bms).unwrap();
+    delete_bms(ids.unwrap(), bms, false).unwrap();
 
     assert_eq!(dal.get_bookmarks("").unwrap().len(), 8);
     assert_eq!(dal.get_bookmarks("bbbbb").unwrap().len(), 0);
     assert_eq!(dal.get_bookmarks("yyyyy").unwrap().len(), 0);
     assert_eq!(dal.get_bookmarks("11111").unwrap().len(), 0);
 }
+
+#[rstest]
+fn test_delete_bms_dry_run(mut dal: Dal, bms: Vec<Bookmark>) {
+    let n = bms.len();
+    let deleted = delete_bms(vec![2, 3], bms, true).unwrap();
+    assert_eq!(deleted.len(), 2);
+    assert_eq!(dal.get_bookmarks("").unwrap().len(), n);
+}